@@ -1,8 +1,12 @@
-use super::{get_class_list, Converter};
-use crate::parser::{image_download_key, ImageEntry, NDJSONData};
-use chrono::Utc;
+use super::{
+    annotation_source_ids, get_class_list, insert_split_placeholders, pose_keypoint_count,
+    Converter, FileData,
+};
+use crate::options::ConvertOptions;
+use crate::parser::{image_download_key, normalize_split, ImageEntry, NDJSONData};
+use chrono::{Datelike, Utc};
 use serde::Serialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Serialize)]
 struct CocoInfo {
@@ -44,7 +48,7 @@ struct CocoImage {
 
 #[derive(Serialize)]
 struct CocoAnnotation {
-    id: i32,
+    id: i64,
     image_id: i32,
     category_id: i32,
     bbox: [f64; 4],
@@ -55,6 +59,12 @@ struct CocoAnnotation {
     keypoints: Option<Vec<f64>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     num_keypoints: Option<i32>,
+    /// Detection-result confidence, when the source box carried one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<f64>,
+    /// Labeling attributes the source box carried, passed through as is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attributes: Option<serde_json::Value>,
 }
 
 #[derive(Serialize)]
@@ -66,25 +76,117 @@ struct CocoFormat {
     annotations: Vec<CocoAnnotation>,
 }
 
-pub struct CocoConverter;
+pub struct CocoConverter {
+    options: ConvertOptions,
+}
 
 impl CocoConverter {
     pub fn new() -> Self {
-        Self
+        Self {
+            options: ConvertOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ConvertOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// A pixel `[x, y, w, h]` box with its corners rounded per
+    /// `pixel_rounding`, or unchanged when that is unset.
+    fn pixel_bbox(&self, x: f64, y: f64, w: f64, h: f64) -> [f64; 4] {
+        match self.options.pixel_rounding {
+            Some(rounding) => {
+                let [xmin, ymin, xmax, ymax] = rounding.apply([x, y, x + w, y + h]);
+                [xmin, ymin, xmax - xmin, ymax - ymin]
+            }
+            None => [x, y, w, h],
+        }
+    }
+
+    /// Adds `coco_json` as `{dir}/_annotations.coco.json`, or at the root
+    /// when `dir` is empty, along with its captions file when asked for.
+    fn insert_coco_json(
+        &self,
+        files: &mut HashMap<String, FileData>,
+        dir: &str,
+        images: &[&ImageEntry],
+        coco_json: String,
+    ) {
+        let prefix = if dir.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", dir)
+        };
+        if self.options.coco_captions && images.iter().any(|img| !img.captions.is_empty()) {
+            files.insert(
+                format!("{}_captions.coco.json", prefix),
+                create_captions_json(&coco_json, images, &self.options)
+                    .into_bytes()
+                    .into(),
+            );
+        }
+        files.insert(
+            format!("{}_annotations.coco.json", prefix),
+            coco_json.into_bytes().into(),
+        );
     }
 
     fn create_coco_json(
         &self,
         images: &[&ImageEntry],
         data: &NDJSONData,
-        _split: &str,
+        split: &str,
         num_kpts: usize,
     ) -> String {
         let class_names = get_class_list(data);
+        let allowed_classes = self.options.allowed_classes(split);
+        // Prefer the dataset's own dates so repeated exports are identical;
+        // the current time is only a fallback.
         let now = Utc::now();
+        let date_created = data
+            .metadata
+            .date_created
+            .clone()
+            .unwrap_or_else(|| now.to_rfc3339());
+        let year = data
+            .metadata
+            .year
+            .or_else(|| date_created.get(..4).and_then(|y| y.parse().ok()))
+            .unwrap_or_else(|| now.year());
         let task = &data.metadata.task;
         let is_pose = task == "pose";
 
+        // Without dataset licenses every image shares a single "Unknown" one.
+        let licenses = if data.metadata.licenses.is_empty() {
+            vec![CocoLicense {
+                id: 1,
+                name: "Unknown".to_string(),
+                url: String::new(),
+            }]
+        } else {
+            data.metadata
+                .licenses
+                .iter()
+                .map(|license| CocoLicense {
+                    id: license.id,
+                    name: license.name.clone(),
+                    url: if self.options.strip_urls {
+                        String::new()
+                    } else {
+                        license.url.clone()
+                    },
+                })
+                .collect()
+        };
+        let default_license = licenses[0].id;
+        let license_ids: HashSet<i32> = licenses.iter().map(|license| license.id).collect();
+        let license_for = |img: &ImageEntry| {
+            img.license
+                .filter(|id| license_ids.contains(id))
+                .unwrap_or(default_license)
+        };
+
         let mut coco = CocoFormat {
             info: CocoInfo {
                 description: if data.metadata.name.is_empty() {
@@ -92,20 +194,21 @@ impl CocoConverter {
                 } else {
                     data.metadata.name.clone()
                 },
-                url: data.metadata.url.clone(),
+                url: if self.options.strip_urls {
+                    String::new()
+                } else {
+                    data.metadata.url.clone()
+                },
                 version: data.metadata.version.clone(),
-                year: now.format("%Y").to_string().parse().unwrap_or(2024),
+                year,
                 contributor: "YOLO NDJSON Converter".to_string(),
-                date_created: now.to_rfc3339(),
+                date_created,
             },
-            licenses: vec![CocoLicense {
-                id: 1,
-                name: "Unknown".to_string(),
-                url: String::new(),
-            }],
+            licenses,
             categories: class_names
                 .iter()
                 .enumerate()
+                .filter(|(i, _)| allowed_classes.is_none_or(|ids| ids.contains(&(*i as i32))))
                 .map(|(i, name)| CocoCategory {
                     id: i as i32,
                     name: name.clone(),
@@ -122,78 +225,142 @@ impl CocoConverter {
             annotations: Vec::new(),
         };
 
-        let mut annotation_id = 1;
+        // Source annotation ids are kept; the rest are numbered around them.
+        let source_ids: HashSet<i64> = images
+            .iter()
+            .flat_map(|img| annotation_source_ids(img, task).into_iter().flatten())
+            .collect();
+        let mut next_id = 1;
+        let mut annotation_id = |source_id: Option<i64>| {
+            source_id.unwrap_or_else(|| {
+                while source_ids.contains(&next_id) {
+                    next_id += 1;
+                }
+                next_id += 1;
+                next_id - 1
+            })
+        };
 
         for (img_idx, img) in images.iter().enumerate() {
             let img_id = (img_idx + 1) as i32;
 
+            // One file covering every split names images by their path, so
+            // the same file name in two splits stays unambiguous.
+            let file_name = if self.options.coco_single_file {
+                format!(
+                    "{}/{}",
+                    self.options.split_normalization.output_split(&img.split),
+                    img.effective_file_name()
+                )
+            } else {
+                img.effective_file_name().to_string()
+            };
             coco.images.push(CocoImage {
                 id: img_id,
-                file_name: img.effective_file_name().to_string(),
+                file_name,
                 width: img.width,
                 height: img.height,
-                license: 1,
+                license: license_for(img),
                 date_captured: now.to_rfc3339(),
             });
 
             match task.as_str() {
                 "segment" => {
                     for seg in img.get_segment_annotations() {
-                        if seg.points.is_empty() {
+                        if seg.outer().is_empty() {
                             continue;
                         }
-                        let mut abs_points: Vec<f64> = Vec::new();
                         let mut min_x = f64::MAX;
                         let mut min_y = f64::MAX;
                         let mut max_x = f64::MIN;
                         let mut max_y = f64::MIN;
 
-                        for (x, y) in &seg.points {
+                        for (x, y) in seg.outer() {
                             let abs_x = x * img.width as f64;
                             let abs_y = y * img.height as f64;
-                            abs_points.push(abs_x);
-                            abs_points.push(abs_y);
                             min_x = min_x.min(abs_x);
                             min_y = min_y.min(abs_y);
                             max_x = max_x.max(abs_x);
                             max_y = max_y.max(abs_y);
                         }
 
-                        let w = max_x - min_x;
-                        let h = max_y - min_y;
+                        let [min_x, min_y, w, h] =
+                            self.pixel_bbox(min_x, min_y, max_x - min_x, max_y - min_y);
+                        let rings: Vec<Vec<f64>> = seg
+                            .rings
+                            .iter()
+                            .map(|ring| {
+                                let abs_points: Vec<f64> = ring
+                                    .iter()
+                                    .flat_map(|(x, y)| {
+                                        [x * img.width as f64, y * img.height as f64]
+                                    })
+                                    .collect();
+                                match self.options.max_segmentation_points {
+                                    Some(max_points) => {
+                                        simplify_flat_polygon(&abs_points, max_points)
+                                    }
+                                    None => abs_points,
+                                }
+                            })
+                            .collect();
+                        // Holes are cut out of the outer ring, as an even-odd
+                        // fill of the rings would.
+                        let area = rings
+                            .iter()
+                            .enumerate()
+                            .map(|(idx, ring)| {
+                                let area = polygon_area(ring);
+                                if idx == 0 {
+                                    area
+                                } else {
+                                    -area
+                                }
+                            })
+                            .sum::<f64>()
+                            .max(0.0);
 
                         coco.annotations.push(CocoAnnotation {
-                            id: annotation_id,
+                            id: annotation_id(seg.id),
                             image_id: img_id,
                             category_id: seg.class_id,
                             bbox: [min_x, min_y, w, h],
-                            area: w * h,
+                            area,
                             iscrowd: 0,
-                            segmentation: vec![abs_points],
+                            segmentation: rings,
                             keypoints: None,
                             num_keypoints: None,
+                            score: None,
+                            attributes: None,
                         });
-                        annotation_id += 1;
                     }
                 }
                 "pose" => {
                     for pose in img.get_pose_annotations() {
                         let x_min = (pose.bbox_x - pose.bbox_w / 2.0) * img.width as f64;
                         let y_min = (pose.bbox_y - pose.bbox_h / 2.0) * img.height as f64;
-                        let w = pose.bbox_w * img.width as f64;
-                        let h = pose.bbox_h * img.height as f64;
+                        let [x_min, y_min, w, h] = self.pixel_bbox(
+                            x_min,
+                            y_min,
+                            pose.bbox_w * img.width as f64,
+                            pose.bbox_h * img.height as f64,
+                        );
 
                         let mut kps: Vec<f64> = Vec::new();
                         let mut visible_count = 0;
                         for (kp_x, kp_y, kp_v) in &pose.keypoints {
                             let abs_x = kp_x * img.width as f64;
                             let abs_y = kp_y * img.height as f64;
-                            if *kp_v > 0.0 {
+                            // Visibility is an integer flag (0 unlabeled, 1
+                            // occluded, 2 visible), rounded as in YOLO labels;
+                            // COCO counts every labeled keypoint.
+                            let visibility = kp_v.round();
+                            if visibility > 0.0 {
                                 visible_count += 1;
                             }
                             kps.push(abs_x);
                             kps.push(abs_y);
-                            kps.push(*kp_v);
+                            kps.push(visibility);
                         }
 
                         // Pad missing keypoints with 0,0,0 (not labeled)
@@ -204,7 +371,7 @@ impl CocoConverter {
                         }
 
                         coco.annotations.push(CocoAnnotation {
-                            id: annotation_id,
+                            id: annotation_id(pose.id),
                             image_id: img_id,
                             category_id: pose.class_id,
                             bbox: [x_min, y_min, w, h],
@@ -213,8 +380,9 @@ impl CocoConverter {
                             segmentation: Vec::new(),
                             keypoints: Some(kps),
                             num_keypoints: Some(visible_count),
+                            score: None,
+                            attributes: None,
                         });
-                        annotation_id += 1;
                     }
                 }
                 "obb" => {
@@ -236,21 +404,22 @@ impl CocoConverter {
                             max_y = max_y.max(abs_y);
                         }
 
-                        let w = max_x - min_x;
-                        let h = max_y - min_y;
+                        let [min_x, min_y, w, h] =
+                            self.pixel_bbox(min_x, min_y, max_x - min_x, max_y - min_y);
 
                         coco.annotations.push(CocoAnnotation {
-                            id: annotation_id,
+                            id: annotation_id(obb.id),
                             image_id: img_id,
                             category_id: obb.class_id,
                             bbox: [min_x, min_y, w, h],
-                            area: w * h,
+                            area: polygon_area(&abs_points),
                             iscrowd: 0,
                             segmentation: vec![abs_points],
                             keypoints: None,
                             num_keypoints: None,
+                            score: None,
+                            attributes: None,
                         });
-                        annotation_id += 1;
                     }
                 }
                 _ => {
@@ -258,11 +427,15 @@ impl CocoConverter {
                     for bbox in img.get_bboxes() {
                         let x_min = (bbox.x - bbox.width / 2.0) * img.width as f64;
                         let y_min = (bbox.y - bbox.height / 2.0) * img.height as f64;
-                        let w = bbox.width * img.width as f64;
-                        let h = bbox.height * img.height as f64;
+                        let [x_min, y_min, w, h] = self.pixel_bbox(
+                            x_min,
+                            y_min,
+                            bbox.width * img.width as f64,
+                            bbox.height * img.height as f64,
+                        );
 
                         coco.annotations.push(CocoAnnotation {
-                            id: annotation_id,
+                            id: annotation_id(bbox.id),
                             image_id: img_id,
                             category_id: bbox.class_id,
                             bbox: [x_min, y_min, w, h],
@@ -271,57 +444,244 @@ impl CocoConverter {
                             segmentation: Vec::new(),
                             keypoints: None,
                             num_keypoints: None,
+                            score: bbox.score,
+                            attributes: bbox.attributes,
                         });
-                        annotation_id += 1;
                     }
                 }
             }
         }
 
-        serde_json::to_string_pretty(&coco).unwrap_or_default()
+        if is_pose && self.options.coco_keypoints_only {
+            // Only pose annotations with labeled keypoints, and the
+            // categories they use, make it into a keypoints-only export.
+            coco.annotations
+                .retain(|ann| ann.num_keypoints.is_some_and(|count| count > 0));
+            let used: HashSet<i32> = coco.annotations.iter().map(|ann| ann.category_id).collect();
+            coco.categories
+                .retain(|category| used.contains(&category.id));
+        }
+
+        if self.options.sort_coco {
+            sort_by_file_name(&mut coco);
+        }
+
+        self.options.to_json(&coco)
+    }
+}
+
+/// Shoelace area of a polygon in COCO's flattened `[x1, y1, x2, y2, ...]`
+/// layout. COCO's `area` is the mask area whenever there is a segmentation.
+fn polygon_area(flat: &[f64]) -> f64 {
+    let points: Vec<(f64, f64)> = flat.chunks_exact(2).map(|p| (p[0], p[1])).collect();
+    let twice_area: f64 = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|((x1, y1), (x2, y2))| x1 * y2 - x2 * y1)
+        .sum();
+    twice_area.abs() / 2.0
+}
+
+/// Distance from `p` to the segment `a`-`b`.
+fn segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len_sq = dx * dx + dy * dy;
+    let t = if len_sq == 0.0 {
+        0.0
+    } else {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len_sq).clamp(0.0, 1.0)
+    };
+    let (cx, cy) = (a.0 + t * dx, a.1 + t * dy);
+    ((p.0 - cx).powi(2) + (p.1 - cy).powi(2)).sqrt()
+}
+
+/// Douglas-Peucker simplification, keeping the first and last points.
+fn douglas_peucker(points: &[(f64, f64)], tolerance: f64) -> Vec<(f64, f64)> {
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+    let mut ranges = vec![(0, points.len() - 1)];
+
+    while let Some((start, end)) = ranges.pop() {
+        let farthest = (start + 1..end)
+            .map(|i| (i, segment_distance(points[i], points[start], points[end])))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((idx, dist)) = farthest {
+            if dist > tolerance {
+                keep[idx] = true;
+                ranges.push((start, idx));
+                ranges.push((idx, end));
+            }
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter_map(|(point, kept)| kept.then_some(*point))
+        .collect()
+}
+
+/// Reduces a polygon to at most `max_points` points (minimum 3), raising the
+/// simplification tolerance until it fits.
+fn simplify_polygon(points: &[(f64, f64)], max_points: usize) -> Vec<(f64, f64)> {
+    let max_points = max_points.max(3);
+    if points.len() <= max_points {
+        return points.to_vec();
+    }
+
+    let mut tolerance = 0.1;
+    for _ in 0..64 {
+        let simplified = douglas_peucker(points, tolerance);
+        if simplified.len() <= max_points {
+            return simplified;
+        }
+        tolerance *= 2.0;
+    }
+
+    // Only reachable for pathological input; fall back to even sampling.
+    let step = points.len().div_ceil(max_points);
+    points.iter().step_by(step).copied().collect()
+}
+
+/// `simplify_polygon` over COCO's flattened `[x1, y1, x2, y2, ...]` layout.
+fn simplify_flat_polygon(flat: &[f64], max_points: usize) -> Vec<f64> {
+    let points: Vec<(f64, f64)> = flat.chunks_exact(2).map(|p| (p[0], p[1])).collect();
+    if points.len() <= max_points.max(3) {
+        return flat.to_vec();
     }
+    simplify_polygon(&points, max_points)
+        .into_iter()
+        .flat_map(|(x, y)| [x, y])
+        .collect()
+}
+
+/// One warning per segment polygon that exceeds `max_points` and will be
+/// simplified in the COCO export.
+pub fn oversized_segmentations(data: &NDJSONData, max_points: usize) -> Vec<String> {
+    data.images
+        .iter()
+        .flat_map(|img| {
+            img.get_segment_annotations()
+                .into_iter()
+                .filter(|seg| seg.rings.iter().any(|ring| ring.len() > max_points.max(3)))
+                .map(move |seg| {
+                    format!(
+                        "{}/{}: simplified a {}-point class {} polygon to at most {} points",
+                        normalize_split(&img.split),
+                        img.effective_file_name(),
+                        seg.rings.iter().map(Vec::len).max().unwrap_or(0),
+                        seg.class_id,
+                        max_points.max(3)
+                    )
+                })
+        })
+        .collect()
+}
+
+/// COCO captions file for a split: the same `info`, `licenses` and `images`
+/// as the instances file (so image ids agree), with `{id, image_id, caption}`
+/// annotations and no categories.
+fn create_captions_json(
+    coco_json: &str,
+    images: &[&ImageEntry],
+    options: &ConvertOptions,
+) -> String {
+    let Ok(mut captions) = serde_json::from_str::<serde_json::Value>(coco_json) else {
+        return String::new();
+    };
+
+    // Image ids are assigned in input order, before any sorting.
+    let annotations: Vec<serde_json::Value> = images
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, img)| {
+            img.captions
+                .iter()
+                .map(move |caption| (idx as i32 + 1, caption))
+        })
+        .enumerate()
+        .map(|(idx, (image_id, caption))| {
+            serde_json::json!({
+                "id": idx + 1,
+                "image_id": image_id,
+                "caption": caption,
+            })
+        })
+        .collect();
+    if let Some(object) = captions.as_object_mut() {
+        object.remove("categories");
+        object.insert("annotations".to_string(), annotations.into());
+    }
+
+    options.to_json(&captions)
+}
+
+fn has_labeled_keypoints(img: &ImageEntry) -> bool {
+    img.get_pose_annotations()
+        .iter()
+        .any(|pose| pose.keypoints.iter().any(|(_, _, v)| *v > 0.0))
+}
+
+/// Orders `images` by file name and groups `annotations` by image in that
+/// same order. Ids are assigned before sorting, so they match an unsorted
+/// export of the same data.
+fn sort_by_file_name(coco: &mut CocoFormat) {
+    coco.images
+        .sort_by(|a, b| a.file_name.cmp(&b.file_name).then(a.id.cmp(&b.id)));
+    let position: HashMap<i32, usize> = coco
+        .images
+        .iter()
+        .enumerate()
+        .map(|(idx, image)| (image.id, idx))
+        .collect();
+    coco.annotations.sort_by_key(|ann| {
+        (
+            position.get(&ann.image_id).copied().unwrap_or(usize::MAX),
+            ann.id,
+        )
+    });
 }
 
 impl Converter for CocoConverter {
     fn convert(
         &self,
         data: &NDJSONData,
-        downloaded_images: &HashMap<String, Vec<u8>>,
-    ) -> HashMap<String, Vec<u8>> {
-        let mut files: HashMap<String, Vec<u8>> = HashMap::new();
+        downloaded_images: &HashMap<String, FileData>,
+    ) -> HashMap<String, FileData> {
+        let mut files: HashMap<String, FileData> = HashMap::new();
         let task = &data.metadata.task;
 
         // For pose: compute max keypoint count globally (max of metadata and actual data)
         let num_kpts = if task == "pose" {
-            let meta_kpts = data
-                .metadata
-                .kpt_shape
-                .as_ref()
-                .and_then(|s| s.first().copied())
-                .unwrap_or(0) as usize;
-            let data_kpts = data
-                .images
-                .iter()
-                .flat_map(|img| img.get_pose_annotations())
-                .map(|p| p.keypoints.len())
-                .max()
-                .unwrap_or(0);
-            meta_kpts.max(data_kpts)
+            pose_keypoint_count(data)
         } else {
             0
         };
 
-        let splits = [
-            ("train", data.train_images()),
-            ("valid", data.valid_images()),
-            ("test", data.test_images()),
-        ];
+        let splits = data.images_by_split(self.options.split_normalization);
 
+        let mut all_images: Vec<&ImageEntry> = Vec::new();
         for (split, images) in &splits {
             if images.is_empty() {
+                if self.options.empty_split_placeholders {
+                    insert_split_placeholders(&mut files, &[split.to_string()]);
+                }
                 continue;
             }
 
+            let keypointed: Vec<&ImageEntry>;
+            let images = if task == "pose" && self.options.coco_keypoints_only {
+                keypointed = images
+                    .iter()
+                    .copied()
+                    .filter(|img| has_labeled_keypoints(img))
+                    .collect();
+                &keypointed
+            } else {
+                images
+            };
+
             // Add images to {split}/ directory
             for img in images {
                 let image_file = img.effective_file_name();
@@ -332,12 +692,21 @@ impl Converter for CocoConverter {
                 }
             }
 
+            if self.options.coco_single_file {
+                all_images.extend(images.iter().copied());
+                continue;
+            }
+
             // Create JSON at {split}/_annotations.coco.json
             let coco_json = self.create_coco_json(images, data, split, num_kpts);
-            files.insert(
-                format!("{}/_annotations.coco.json", split),
-                coco_json.into_bytes(),
-            );
+            self.insert_coco_json(&mut files, split, images, coco_json);
+        }
+
+        if !all_images.is_empty() {
+            // Split filters don't apply to the combined file; the global
+            // class filter still does.
+            let coco_json = self.create_coco_json(&all_images, data, "", num_kpts);
+            self.insert_coco_json(&mut files, "", &all_images, coco_json);
         }
 
         files
@@ -347,7 +716,8 @@ impl Converter for CocoConverter {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parser::{image_download_key, DatasetMetadata};
+    use crate::options::PixelRounding;
+    use crate::parser::{image_download_key, DatasetLicense, DatasetMetadata};
     use serde_json::json;
 
     #[test]
@@ -362,6 +732,10 @@ mod tests {
                 url: String::new(),
                 class_names: HashMap::from([("0".to_string(), "animal".to_string())]),
                 kpt_shape: None,
+                flip_idx: None,
+                date_created: None,
+                year: None,
+                licenses: Vec::new(),
                 version: "1".to_string(),
             },
             images: vec![
@@ -370,6 +744,10 @@ mod tests {
                     file: "img1.jpg".to_string(),
                     output_file: None,
                     url: String::new(),
+                    mirrors: Vec::new(),
+                    license: None,
+                    skip: false,
+                    captions: Vec::new(),
                     width: 640,
                     height: 480,
                     split: "train".to_string(),
@@ -382,6 +760,10 @@ mod tests {
                     file: "img1.jpg".to_string(),
                     output_file: None,
                     url: String::new(),
+                    mirrors: Vec::new(),
+                    license: None,
+                    skip: false,
+                    captions: Vec::new(),
                     width: 640,
                     height: 480,
                     split: "val".to_string(),
@@ -394,13 +776,13 @@ mod tests {
 
         let converter = CocoConverter::new();
         let mut downloaded_images = HashMap::new();
-        downloaded_images.insert(image_download_key("train", "img1.jpg"), vec![1]);
-        downloaded_images.insert(image_download_key("valid", "img1.jpg"), vec![2]);
+        downloaded_images.insert(image_download_key("train", "img1.jpg"), vec![1].into());
+        downloaded_images.insert(image_download_key("valid", "img1.jpg"), vec![2].into());
 
         let files = converter.convert(&data, &downloaded_images);
 
-        assert_eq!(files.get("train/img1.jpg"), Some(&vec![1]));
-        assert_eq!(files.get("valid/img1.jpg"), Some(&vec![2]));
+        assert_eq!(files.get("train/img1.jpg"), Some(&vec![1].into()));
+        assert_eq!(files.get("valid/img1.jpg"), Some(&vec![2].into()));
     }
 
     #[test]
@@ -415,6 +797,10 @@ mod tests {
                 url: String::new(),
                 class_names: HashMap::from([("0".to_string(), "animal".to_string())]),
                 kpt_shape: None,
+                flip_idx: None,
+                date_created: None,
+                year: None,
+                licenses: Vec::new(),
                 version: "1".to_string(),
             },
             images: vec![ImageEntry {
@@ -422,6 +808,10 @@ mod tests {
                 file: "img1.jpg".to_string(),
                 output_file: Some("img1__abcd1234.jpg".to_string()),
                 url: String::new(),
+                mirrors: Vec::new(),
+                license: None,
+                skip: false,
+                captions: Vec::new(),
                 width: 640,
                 height: 480,
                 split: "train".to_string(),
@@ -433,13 +823,17 @@ mod tests {
 
         let converter = CocoConverter::new();
         let mut downloaded_images = HashMap::new();
-        downloaded_images.insert(image_download_key("train", "img1__abcd1234.jpg"), vec![1]);
+        downloaded_images.insert(
+            image_download_key("train", "img1__abcd1234.jpg"),
+            vec![1].into(),
+        );
 
         let files = converter.convert(&data, &downloaded_images);
 
-        assert_eq!(files.get("train/img1__abcd1234.jpg"), Some(&vec![1]));
+        assert_eq!(files.get("train/img1__abcd1234.jpg"), Some(&vec![1].into()));
         let coco: serde_json::Value =
-            serde_json::from_slice(files.get("train/_annotations.coco.json").unwrap()).unwrap();
+            serde_json::from_slice(files["train/_annotations.coco.json"].as_bytes().unwrap())
+                .unwrap();
         assert_eq!(
             coco.get("images")
                 .and_then(|v| v.as_array())
@@ -449,4 +843,583 @@ mod tests {
             Some("img1__abcd1234.jpg")
         );
     }
+
+    #[test]
+    fn coco_info_uses_dataset_dates_verbatim() {
+        let data = NDJSONData {
+            metadata: DatasetMetadata {
+                r#type: "dataset".to_string(),
+                task: "detect".to_string(),
+                name: "test".to_string(),
+                description: String::new(),
+                bytes: 0,
+                url: String::new(),
+                class_names: HashMap::from([("0".to_string(), "animal".to_string())]),
+                kpt_shape: None,
+                flip_idx: None,
+                date_created: Some("2019-06-01T12:00:00+00:00".to_string()),
+                year: None,
+                licenses: Vec::new(),
+                version: "1".to_string(),
+            },
+            images: vec![],
+        };
+
+        let coco: serde_json::Value =
+            serde_json::from_str(&CocoConverter::new().create_coco_json(&[], &data, "train", 0))
+                .unwrap();
+
+        assert_eq!(coco["info"]["date_created"], "2019-06-01T12:00:00+00:00");
+        assert_eq!(coco["info"]["year"], 2019);
+    }
+
+    #[test]
+    fn sort_coco_orders_images_and_groups_annotations() {
+        let image = |file: &str| ImageEntry {
+            r#type: "image".to_string(),
+            file: file.to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 100,
+            height: 100,
+            split: "train".to_string(),
+            annotations: Some(json!({
+                "bboxes": [[0, 0.5, 0.5, 0.2, 0.2], [0, 0.3, 0.3, 0.1, 0.1]]
+            })),
+        };
+        let images = [image("c.jpg"), image("a.jpg"), image("b.jpg")];
+        let data = NDJSONData {
+            metadata: DatasetMetadata {
+                r#type: "dataset".to_string(),
+                task: "detect".to_string(),
+                name: "test".to_string(),
+                description: String::new(),
+                bytes: 0,
+                url: String::new(),
+                class_names: HashMap::from([("0".to_string(), "animal".to_string())]),
+                kpt_shape: None,
+                flip_idx: None,
+                date_created: None,
+                year: None,
+                licenses: Vec::new(),
+                version: "1".to_string(),
+            },
+            images: images.to_vec(),
+        };
+        let converter = CocoConverter::new().with_options(ConvertOptions {
+            sort_coco: true,
+            ..Default::default()
+        });
+
+        let refs: Vec<&ImageEntry> = images.iter().collect();
+        let coco: serde_json::Value =
+            serde_json::from_str(&converter.create_coco_json(&refs, &data, "train", 0)).unwrap();
+
+        let file_names: Vec<&str> = coco["images"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|img| img["file_name"].as_str().unwrap())
+            .collect();
+        assert_eq!(file_names, vec!["a.jpg", "b.jpg", "c.jpg"]);
+        // Ids follow input order: c.jpg=1, a.jpg=2, b.jpg=3.
+        let image_ids: Vec<i64> = coco["annotations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|ann| ann["image_id"].as_i64().unwrap())
+            .collect();
+        assert_eq!(image_ids, vec![2, 2, 3, 3, 1, 1]);
+    }
+
+    #[test]
+    fn coco_assigns_per_image_licenses() {
+        let image = |file: &str, license: Option<i32>| ImageEntry {
+            r#type: "image".to_string(),
+            file: file.to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license,
+            skip: false,
+            captions: Vec::new(),
+            width: 100,
+            height: 100,
+            split: "train".to_string(),
+            annotations: None,
+        };
+        let images = [
+            image("a.jpg", Some(7)),
+            image("b.jpg", Some(3)),
+            image("c.jpg", None),
+        ];
+        let data = NDJSONData {
+            metadata: DatasetMetadata {
+                r#type: "dataset".to_string(),
+                task: "detect".to_string(),
+                name: "test".to_string(),
+                description: String::new(),
+                bytes: 0,
+                url: String::new(),
+                class_names: HashMap::new(),
+                kpt_shape: None,
+                flip_idx: None,
+                date_created: None,
+                year: None,
+                licenses: vec![
+                    DatasetLicense {
+                        id: 3,
+                        name: "CC BY 4.0".to_string(),
+                        url: "https://creativecommons.org/licenses/by/4.0/".to_string(),
+                    },
+                    DatasetLicense {
+                        id: 7,
+                        name: "CC0".to_string(),
+                        url: String::new(),
+                    },
+                ],
+                version: "1".to_string(),
+            },
+            images: images.to_vec(),
+        };
+
+        let refs: Vec<&ImageEntry> = images.iter().collect();
+        let coco: serde_json::Value =
+            serde_json::from_str(&CocoConverter::new().create_coco_json(&refs, &data, "train", 0))
+                .unwrap();
+
+        let license_ids: Vec<i64> = coco["licenses"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|license| license["id"].as_i64().unwrap())
+            .collect();
+        assert_eq!(license_ids, vec![3, 7]);
+        assert_eq!(coco["licenses"][0]["name"], "CC BY 4.0");
+        let image_licenses: Vec<i64> = coco["images"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|img| img["license"].as_i64().unwrap())
+            .collect();
+        // Images without a license fall back to the first listed one.
+        assert_eq!(image_licenses, vec![7, 3, 3]);
+    }
+
+    #[test]
+    fn coco_simplifies_polygons_over_point_limit() {
+        let polygon: Vec<serde_json::Value> = (0..5000)
+            .flat_map(|i| {
+                let angle = i as f64 / 5000.0 * std::f64::consts::TAU;
+                [
+                    json!(0.5 + 0.3 * angle.cos()),
+                    json!(0.5 + 0.3 * angle.sin()),
+                ]
+            })
+            .collect();
+        let mut segment = vec![json!(0)];
+        segment.extend(polygon);
+        let image = ImageEntry {
+            r#type: "image".to_string(),
+            file: "a.jpg".to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: "train".to_string(),
+            annotations: Some(json!({ "segments": [segment] })),
+        };
+        let data = NDJSONData {
+            metadata: DatasetMetadata {
+                r#type: "dataset".to_string(),
+                task: "segment".to_string(),
+                name: "test".to_string(),
+                description: String::new(),
+                bytes: 0,
+                url: String::new(),
+                class_names: HashMap::from([("0".to_string(), "blob".to_string())]),
+                kpt_shape: None,
+                flip_idx: None,
+                date_created: None,
+                year: None,
+                licenses: Vec::new(),
+                version: "1".to_string(),
+            },
+            images: vec![image.clone()],
+        };
+        let converter = CocoConverter::new().with_options(ConvertOptions {
+            max_segmentation_points: Some(1000),
+            ..Default::default()
+        });
+
+        let coco: serde_json::Value =
+            serde_json::from_str(&converter.create_coco_json(&[&image], &data, "train", 0))
+                .unwrap();
+
+        let flat = coco["annotations"][0]["segmentation"][0]
+            .as_array()
+            .unwrap();
+        assert!(flat.len() / 2 <= 1000);
+        assert!(flat.len() / 2 >= 3);
+        // The box still covers the original polygon.
+        let width = coco["annotations"][0]["bbox"][2].as_f64().unwrap();
+        assert!((width - 0.6 * 640.0).abs() < 1e-6);
+        assert_eq!(oversized_segmentations(&data, 1000).len(), 1);
+        assert!(oversized_segmentations(&data, 5000).is_empty());
+    }
+
+    #[test]
+    fn coco_detection_carries_box_confidence_as_score() {
+        let image = ImageEntry {
+            r#type: "image".to_string(),
+            file: "a.jpg".to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: "train".to_string(),
+            annotations: Some(json!({
+                "boxes": [[0, 0.5, 0.5, 0.2, 0.2, 0.91], [0, 0.3, 0.3, 0.1, 0.1]]
+            })),
+        };
+        let data = NDJSONData {
+            metadata: DatasetMetadata {
+                r#type: "dataset".to_string(),
+                task: "detect".to_string(),
+                name: "test".to_string(),
+                description: String::new(),
+                bytes: 0,
+                url: String::new(),
+                class_names: HashMap::from([("0".to_string(), "cat".to_string())]),
+                kpt_shape: None,
+                flip_idx: None,
+                date_created: None,
+                year: None,
+                licenses: Vec::new(),
+                version: "1".to_string(),
+            },
+            images: vec![image.clone()],
+        };
+
+        let coco: serde_json::Value = serde_json::from_str(&CocoConverter::new().create_coco_json(
+            &[&image],
+            &data,
+            "train",
+            0,
+        ))
+        .unwrap();
+
+        assert_eq!(coco["annotations"][0]["score"], 0.91);
+        assert!(coco["annotations"][1].get("score").is_none());
+    }
+
+    #[test]
+    fn coco_captions_are_written_when_enabled() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","caption":"A cat on a sofa.","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+
+        let plain = CocoConverter::new().convert(&data, &HashMap::new());
+        assert!(!plain.contains_key("train/_captions.coco.json"));
+
+        let files = CocoConverter::new()
+            .with_options(ConvertOptions {
+                coco_captions: true,
+                ..Default::default()
+            })
+            .convert(&data, &HashMap::new());
+        let captions: serde_json::Value =
+            serde_json::from_slice(&files["train/_captions.coco.json"].read().unwrap()).unwrap();
+
+        assert_eq!(
+            captions["annotations"],
+            json!([{"id": 1, "image_id": 1, "caption": "A cat on a sofa."}])
+        );
+        assert_eq!(captions["images"].as_array().unwrap().len(), 2);
+        assert_eq!(captions["images"][0]["file_name"], "a.jpg");
+        assert!(captions.get("categories").is_none());
+    }
+
+    #[test]
+    fn coco_keypoints_only_keeps_keypointed_images_and_categories() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"pose","kpt_shape":[2,3],"class_names":{"0":"person","1":"car"}}
+{"type":"image","file":"a.jpg","width":100,"height":100,"split":"train","annotations":{"pose":[[0,0.5,0.5,0.2,0.2,0.4,0.4,2,0.6,0.6,1],[1,0.2,0.2,0.1,0.1,0,0,0,0,0,0]]}}
+{"type":"image","file":"b.jpg","width":100,"height":100,"split":"train","annotations":{"boxes":[[1,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"c.jpg","width":100,"height":100,"split":"train","annotations":{"pose":[[1,0.5,0.5,0.2,0.2,0,0,0,0,0,0]]}}"#,
+        )
+        .unwrap();
+
+        let files = CocoConverter::new()
+            .with_options(ConvertOptions {
+                coco_keypoints_only: true,
+                ..Default::default()
+            })
+            .convert(&data, &HashMap::new());
+        let coco: serde_json::Value =
+            serde_json::from_slice(&files["train/_annotations.coco.json"].read().unwrap()).unwrap();
+
+        let file_names: Vec<&str> = coco["images"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|image| image["file_name"].as_str().unwrap())
+            .collect();
+        assert_eq!(file_names, ["a.jpg"]);
+        let categories: Vec<&str> = coco["categories"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|category| category["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(categories, ["person"]);
+        let annotations = coco["annotations"].as_array().unwrap();
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations[0]["num_keypoints"], 2);
+    }
+
+    #[test]
+    fn pose_visibility_flags_pass_through_and_count_labeled_keypoints() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"pose","kpt_shape":[3,3],"class_names":{"0":"person"}}
+{"type":"image","file":"a.jpg","width":100,"height":100,"split":"train","annotations":{"pose":[[0,0.5,0.5,0.2,0.2,0.1,0.1,0,0.2,0.2,1,0.3,0.3,2]]}}"#,
+        )
+        .unwrap();
+
+        let coco_files = CocoConverter::new().convert(&data, &HashMap::new());
+        let coco: serde_json::Value =
+            serde_json::from_slice(&coco_files["train/_annotations.coco.json"].read().unwrap())
+                .unwrap();
+        let annotation = &coco["annotations"][0];
+        assert_eq!(annotation["num_keypoints"], 2);
+        let flags: Vec<f64> = annotation["keypoints"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .skip(2)
+            .step_by(3)
+            .map(|v| v.as_f64().unwrap())
+            .collect();
+        assert_eq!(flags, [0.0, 1.0, 2.0]);
+
+        let yolo_files =
+            crate::converter::yolo::YoloConverter::new().convert(&data, &HashMap::new());
+        let label = String::from_utf8(
+            yolo_files["train/labels/a.txt"]
+                .read()
+                .unwrap()
+                .into_owned(),
+        )
+        .unwrap();
+        let yolo_flags: Vec<&str> = label.split(' ').skip(7).step_by(3).collect();
+        assert_eq!(yolo_flags, ["0", "1", "2"]);
+    }
+
+    #[test]
+    fn pixel_rounding_applies_to_coco_boxes_when_set() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":100,"height":100,"split":"train","annotations":{"boxes":[[0,0.503,0.5,0.2,0.217]]}}"#,
+        )
+        .unwrap();
+
+        for (rounding, expected) in [
+            (PixelRounding::Round, [40.0, 39.0, 20.0, 22.0]),
+            (PixelRounding::FloorCeil, [40.0, 39.0, 21.0, 22.0]),
+            (PixelRounding::Truncate, [40.0, 39.0, 20.0, 21.0]),
+        ] {
+            let files = CocoConverter::new()
+                .with_options(ConvertOptions {
+                    pixel_rounding: Some(rounding),
+                    ..Default::default()
+                })
+                .convert(&data, &HashMap::new());
+            let coco: serde_json::Value =
+                serde_json::from_slice(&files["train/_annotations.coco.json"].read().unwrap())
+                    .unwrap();
+            assert_eq!(
+                coco["annotations"][0]["bbox"],
+                json!(expected),
+                "{:?}",
+                rounding
+            );
+        }
+
+        let files = CocoConverter::new().convert(&data, &HashMap::new());
+        let coco: serde_json::Value =
+            serde_json::from_slice(&files["train/_annotations.coco.json"].read().unwrap()).unwrap();
+        let x = coco["annotations"][0]["bbox"][0].as_f64().unwrap();
+        assert!((x - 40.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn compact_json_matches_pretty_and_is_smaller() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let convert = |compact_json| {
+            CocoConverter::new()
+                .with_options(ConvertOptions {
+                    compact_json,
+                    ..Default::default()
+                })
+                .convert(&data, &HashMap::new())["train/_annotations.coco.json"]
+                .read()
+                .unwrap()
+                .into_owned()
+        };
+
+        let pretty = convert(false);
+        let compact = convert(true);
+
+        // Export timestamps differ between the two runs.
+        let parse = |bytes: &[u8]| {
+            let mut coco: serde_json::Value = serde_json::from_slice(bytes).unwrap();
+            coco["info"]["date_created"] = json!(null);
+            coco["images"][0]["date_captured"] = json!(null);
+            coco
+        };
+        assert!(compact.len() < pretty.len());
+        assert_eq!(parse(&compact), parse(&pretty));
+    }
+
+    #[test]
+    fn single_file_mode_prefixes_file_names_with_their_split() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"val","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let converter = CocoConverter::new().with_options(ConvertOptions {
+            coco_single_file: true,
+            ..Default::default()
+        });
+
+        let files = converter.convert(&data, &HashMap::new());
+
+        assert!(!files.contains_key("train/_annotations.coco.json"));
+        let coco: serde_json::Value =
+            serde_json::from_slice(&files["_annotations.coco.json"].read().unwrap()).unwrap();
+        let names: Vec<&str> = coco["images"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|image| image["file_name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["train/a.jpg", "valid/a.jpg"]);
+        assert_eq!(coco["annotations"].as_array().unwrap().len(), 2);
+
+        let per_split = CocoConverter::new().convert(&data, &HashMap::new());
+        let coco: serde_json::Value =
+            serde_json::from_slice(&per_split["valid/_annotations.coco.json"].read().unwrap())
+                .unwrap();
+        assert_eq!(coco["images"][0]["file_name"], "a.jpg");
+    }
+
+    #[test]
+    fn area_is_polygon_area_when_there_is_a_segmentation() {
+        let convert = |task: &str, annotations: &str| {
+            let data = crate::parser::parse_ndjson(&format!(
+                r#"{{"type":"dataset","name":"test","task":"{}","class_names":{{"0":"cat"}}}}
+{{"type":"image","file":"a.jpg","width":100,"height":100,"split":"train","annotations":{}}}"#,
+                task, annotations
+            ))
+            .unwrap();
+            let files = CocoConverter::new().convert(&data, &HashMap::new());
+            let coco: serde_json::Value =
+                serde_json::from_slice(&files["train/_annotations.coco.json"].read().unwrap())
+                    .unwrap();
+            coco["annotations"][0]["area"].as_f64().unwrap()
+        };
+
+        // A 40x20 box covers 800 px; the right triangle inside the same
+        // extent covers half of it.
+        let box_area = convert("detect", r#"{"boxes":[[0,0.5,0.5,0.4,0.2]]}"#);
+        let polygon_area = convert("segment", r#"{"segments":[[0,0.3,0.4,0.7,0.4,0.3,0.6]]}"#);
+
+        assert!((box_area - 800.0).abs() < 1e-9);
+        assert!((polygon_area - 400.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn source_annotation_ids_are_kept() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2],[0,0.5,0.5,0.2,0.2,{"id":1}],[0,0.5,0.5,0.2,0.2,0.9,{"id":42}]]}}"#,
+        )
+        .unwrap();
+
+        let files = CocoConverter::new().convert(&data, &HashMap::new());
+
+        let coco: serde_json::Value =
+            serde_json::from_slice(&files["train/_annotations.coco.json"].read().unwrap()).unwrap();
+        let ids: Vec<i64> = coco["annotations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|ann| ann["id"].as_i64().unwrap())
+            .collect();
+        assert_eq!(ids, [2, 1, 42]);
+        assert_eq!(coco["annotations"][2]["score"], json!(0.9));
+    }
+
+    #[test]
+    fn donut_segment_keeps_its_hole() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"segment","class_names":{"0":"donut"}}
+{"type":"image","file":"a.jpg","width":100,"height":100,"split":"train","annotations":{"segments":[[0,[0.1,0.1,0.9,0.1,0.9,0.9,0.1,0.9],[0.4,0.4,0.6,0.4,0.6,0.6,0.4,0.6]]]}}"#,
+        )
+        .unwrap();
+
+        let files = CocoConverter::new().convert(&data, &HashMap::new());
+        let coco: serde_json::Value =
+            serde_json::from_slice(&files["train/_annotations.coco.json"].read().unwrap()).unwrap();
+
+        let annotation = &coco["annotations"][0];
+        let segmentation = annotation["segmentation"].as_array().unwrap();
+        assert_eq!(segmentation.len(), 2);
+        assert_eq!(segmentation[1][0], json!(40.0));
+        assert_eq!(annotation["bbox"], json!([10.0, 10.0, 80.0, 80.0]));
+        // The 80x80 outer square minus the 20x20 hole.
+        assert!((annotation["area"].as_f64().unwrap() - 6000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn box_attributes_reach_the_coco_annotation() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"car"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2,{"id":3,"attributes":{"color":"red","occluded":true}}],[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+
+        let files = CocoConverter::new().convert(&data, &HashMap::new());
+        let coco: serde_json::Value =
+            serde_json::from_slice(&files["train/_annotations.coco.json"].read().unwrap()).unwrap();
+
+        let annotations = coco["annotations"].as_array().unwrap();
+        assert_eq!(annotations[0]["id"], json!(3));
+        assert_eq!(
+            annotations[0]["attributes"],
+            json!({"color": "red", "occluded": true})
+        );
+        assert!(annotations[1].get("attributes").is_none());
+    }
 }
@@ -0,0 +1,362 @@
+use super::{
+    fill_polygon_even_odd, get_class_names, insert_split_placeholders, Converter, FileData,
+};
+use crate::options::{ConvertOptions, SplitNormalization};
+use crate::parser::{image_download_key, ImageEntry, NDJSONData};
+use image::codecs::png::PngEncoder;
+use image::{ExtendedColorType, ImageEncoder};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Serialize)]
+struct PanopticCategory {
+    id: i32,
+    name: String,
+    supercategory: String,
+    isthing: u8,
+}
+
+#[derive(Serialize)]
+struct PanopticImage {
+    id: i32,
+    file_name: String,
+    width: i32,
+    height: i32,
+}
+
+#[derive(Serialize)]
+struct SegmentInfo {
+    id: u32,
+    category_id: i32,
+    area: u64,
+    bbox: [u32; 4],
+    iscrowd: u8,
+}
+
+#[derive(Serialize)]
+struct PanopticAnnotation {
+    image_id: i32,
+    file_name: String,
+    segments_info: Vec<SegmentInfo>,
+}
+
+#[derive(Serialize)]
+struct PanopticFormat {
+    images: Vec<PanopticImage>,
+    annotations: Vec<PanopticAnnotation>,
+    categories: Vec<PanopticCategory>,
+}
+
+/// COCO panoptic export: per split, `_annotations.panoptic.json` and one PNG
+/// per image under `panoptic/`, where each pixel's `R + 256 * G + 256^2 * B`
+/// is the id of the segment covering it and 0 is unlabeled.
+pub struct CocoPanopticConverter {
+    options: ConvertOptions,
+}
+
+impl CocoPanopticConverter {
+    pub fn new() -> Self {
+        Self {
+            options: ConvertOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ConvertOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// Largest mask rendered, in pixels. Rendering holds 7 bytes per pixel, so
+/// this caps a mask at about 350 MB however large an image claims to be.
+const MAX_MASK_PIXELS: u64 = 50_000_000;
+
+/// Why `img` can't get a mask, if its dimensions rule one out.
+fn mask_size_error(img: &ImageEntry) -> Option<String> {
+    if img.width <= 0 || img.height <= 0 {
+        return Some("it has no dimensions".to_string());
+    }
+    let pixels = img.width as u64 * img.height as u64;
+    (pixels > MAX_MASK_PIXELS).then(|| {
+        format!(
+            "{}x{} is over the {} pixel limit for masks",
+            img.width, img.height, MAX_MASK_PIXELS
+        )
+    })
+}
+
+/// Paints the image's segments in order, later ones over earlier ones, and
+/// returns the encoded PNG with the info of every segment left visible.
+/// Segment ids count from 1 within the image.
+fn render_segments(img: &ImageEntry) -> Result<(Vec<u8>, Vec<SegmentInfo>), String> {
+    if let Some(error) = mask_size_error(img) {
+        return Err(error);
+    }
+    let (width, height) = (img.width as u32, img.height as u32);
+    let mut ids = vec![0u32; width as usize * height as usize];
+    let segments = img.get_segment_annotations();
+    for (idx, seg) in segments.iter().enumerate() {
+        let rings: Vec<Vec<(f64, f64)>> = seg
+            .rings
+            .iter()
+            .map(|ring| {
+                ring.iter()
+                    .map(|(x, y)| (x * f64::from(width), y * f64::from(height)))
+                    .collect()
+            })
+            .collect();
+        fill_polygon_even_odd(&rings, width, height, |x, y| {
+            ids[(y * width + x) as usize] = idx as u32 + 1;
+        });
+    }
+
+    // Area and extent of each segment as painted, after overlaps.
+    let mut extents: Vec<Option<(u64, [u32; 4])>> = vec![None; segments.len()];
+    let mut rgb = Vec::with_capacity(ids.len() * 3);
+    for (pixel, id) in ids.iter().enumerate() {
+        rgb.extend_from_slice(&[*id as u8, (*id >> 8) as u8, (*id >> 16) as u8]);
+        if *id == 0 {
+            continue;
+        }
+        let (x, y) = (pixel as u32 % width, pixel as u32 / width);
+        let extent = &mut extents[*id as usize - 1];
+        match extent {
+            Some((area, [min_x, min_y, max_x, max_y])) => {
+                *area += 1;
+                *min_x = (*min_x).min(x);
+                *min_y = (*min_y).min(y);
+                *max_x = (*max_x).max(x);
+                *max_y = (*max_y).max(y);
+            }
+            None => *extent = Some((1, [x, y, x, y])),
+        }
+    }
+
+    let segments_info = segments
+        .iter()
+        .zip(extents)
+        .enumerate()
+        .filter_map(|(idx, (seg, extent))| {
+            let (area, [min_x, min_y, max_x, max_y]) = extent?;
+            Some(SegmentInfo {
+                id: idx as u32 + 1,
+                category_id: seg.class_id,
+                area,
+                bbox: [min_x, min_y, max_x - min_x + 1, max_y - min_y + 1],
+                iscrowd: 0,
+            })
+        })
+        .collect();
+
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .write_image(&rgb, width, height, ExtendedColorType::Rgb8)
+        .map_err(|e| format!("failed to encode panoptic PNG: {}", e))?;
+    Ok((png, segments_info))
+}
+
+/// `photo.jpg` becomes `photo.png`, or `photo_1.png` and so on when an
+/// image of another type took that name already.
+fn png_name(file_name: &str, used: &mut HashSet<String>) -> String {
+    let stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem);
+    let mut name = format!("{}.png", stem);
+    let mut suffix = 0;
+    while !used.insert(name.clone()) {
+        suffix += 1;
+        name = format!("{}_{}.png", stem, suffix);
+    }
+    name
+}
+
+/// A warning for each image that got no panoptic mask, because it has no
+/// dimensions, is too large to render or failed to encode.
+pub fn missing_mask_warnings(
+    files: &HashMap<String, FileData>,
+    data: &NDJSONData,
+    splits: SplitNormalization,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for (split, images) in data.images_by_split(splits) {
+        let Some(panoptic) = files
+            .get(&format!("{}/_annotations.panoptic.json", split))
+            .and_then(|file| file.read().ok())
+            .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok())
+        else {
+            continue;
+        };
+        let masked: HashSet<i64> = panoptic["annotations"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|annotation| annotation["image_id"].as_i64())
+            .collect();
+        // Image ids count from 1 in split order, as in the converter.
+        for (idx, img) in images.iter().enumerate() {
+            if !masked.contains(&(idx as i64 + 1)) {
+                warnings.push(format!(
+                    "'{}/{}' has no panoptic mask: {}",
+                    split,
+                    img.effective_file_name(),
+                    mask_size_error(img).unwrap_or_else(|| "it failed to render".to_string())
+                ));
+            }
+        }
+    }
+    warnings
+}
+
+impl Converter for CocoPanopticConverter {
+    fn convert(
+        &self,
+        data: &NDJSONData,
+        downloaded_images: &HashMap<String, FileData>,
+    ) -> HashMap<String, FileData> {
+        let mut files: HashMap<String, FileData> = HashMap::new();
+        let mut class_names: Vec<(i32, String)> = get_class_names(data).into_iter().collect();
+        class_names.sort();
+
+        let splits = data.images_by_split(self.options.split_normalization);
+
+        for (split, images) in &splits {
+            if images.is_empty() {
+                if self.options.empty_split_placeholders {
+                    insert_split_placeholders(&mut files, &[split.to_string()]);
+                }
+                continue;
+            }
+
+            let mut panoptic = PanopticFormat {
+                images: Vec::new(),
+                annotations: Vec::new(),
+                categories: class_names
+                    .iter()
+                    .map(|(id, name)| PanopticCategory {
+                        id: *id,
+                        name: name.clone(),
+                        supercategory: String::new(),
+                        isthing: 1,
+                    })
+                    .collect(),
+            };
+
+            let mut mask_names = HashSet::new();
+            for (img_idx, img) in images.iter().enumerate() {
+                let img_id = (img_idx + 1) as i32;
+                let image_file = img.effective_file_name();
+                panoptic.images.push(PanopticImage {
+                    id: img_id,
+                    file_name: image_file.to_string(),
+                    width: img.width,
+                    height: img.height,
+                });
+
+                // Images left without a mask are reported by
+                // `missing_mask_warnings`.
+                if let Ok((png, segments_info)) = render_segments(img) {
+                    let mask_name = png_name(image_file, &mut mask_names);
+                    files.insert(format!("{}/panoptic/{}", split, mask_name), png.into());
+                    panoptic.annotations.push(PanopticAnnotation {
+                        image_id: img_id,
+                        file_name: mask_name,
+                        segments_info,
+                    });
+                }
+
+                if let Some(image_data) =
+                    downloaded_images.get(&image_download_key(split, image_file))
+                {
+                    files.insert(format!("{}/{}", split, image_file), image_data.clone());
+                }
+            }
+
+            files.insert(
+                format!("{}/_annotations.panoptic.json", split),
+                self.options.to_json(&panoptic).into_bytes().into(),
+            );
+        }
+
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_segments_get_their_own_ids_in_png_and_json() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"segment","class_names":{"0":"cat","1":"dog"}}
+{"type":"image","file":"a.jpg","width":10,"height":10,"split":"train","annotations":{"segments":[[0,0.0,0.0,0.5,0.0,0.5,0.5,0.0,0.5],[1,0.5,0.5,1.0,0.5,1.0,1.0,0.5,1.0]]}}"#,
+        )
+        .unwrap();
+
+        let files = CocoPanopticConverter::new().convert(&data, &HashMap::new());
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&files["train/_annotations.panoptic.json"].read().unwrap())
+                .unwrap();
+        let annotation = &json["annotations"][0];
+        assert_eq!(annotation["file_name"], "a.png");
+        assert_eq!(
+            annotation["segments_info"],
+            serde_json::json!([
+                {"id": 1, "category_id": 0, "area": 25, "bbox": [0, 0, 5, 5], "iscrowd": 0},
+                {"id": 2, "category_id": 1, "area": 25, "bbox": [5, 5, 5, 5], "iscrowd": 0}
+            ])
+        );
+
+        let png = image::load_from_memory(&files["train/panoptic/a.png"].read().unwrap())
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(png.get_pixel(2, 2).0, [1, 0, 0]);
+        assert_eq!(png.get_pixel(7, 7).0, [2, 0, 0]);
+        assert_eq!(png.get_pixel(7, 2).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn masks_of_same_stem_images_get_distinct_names() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"segment","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":4,"height":4,"split":"train","annotations":{"segments":[[0,0.0,0.0,0.5,0.0,0.5,0.5]]}}
+{"type":"image","file":"a.png","width":4,"height":4,"split":"train","annotations":{"segments":[[0,0.5,0.5,1.0,0.5,1.0,1.0]]}}"#,
+        )
+        .unwrap();
+
+        let files = CocoPanopticConverter::new().convert(&data, &HashMap::new());
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&files["train/_annotations.panoptic.json"].read().unwrap())
+                .unwrap();
+        assert_eq!(json["annotations"][0]["file_name"], "a.png");
+        assert_eq!(json["annotations"][1]["file_name"], "a_1.png");
+        assert!(files.contains_key("train/panoptic/a.png"));
+        assert!(files.contains_key("train/panoptic/a_1.png"));
+    }
+
+    #[test]
+    fn images_without_a_mask_are_reported() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"segment","class_names":{"0":"cat"}}
+{"type":"image","file":"ok.jpg","width":4,"height":4,"split":"train"}
+{"type":"image","file":"flat.jpg","width":0,"height":0,"split":"train"}
+{"type":"image","file":"huge.jpg","width":100000,"height":100000,"split":"train"}"#,
+        )
+        .unwrap();
+
+        let files = CocoPanopticConverter::new().convert(&data, &HashMap::new());
+        let warnings = missing_mask_warnings(&files, &data, SplitNormalization::Canonical);
+
+        assert_eq!(
+            warnings,
+            [
+                "'train/flat.jpg' has no panoptic mask: it has no dimensions",
+                "'train/huge.jpg' has no panoptic mask: 100000x100000 is over the 50000000 pixel limit for masks",
+            ]
+        );
+        assert!(files.contains_key("train/panoptic/ok.png"));
+        assert!(!files.contains_key("train/panoptic/huge.png"));
+    }
+}
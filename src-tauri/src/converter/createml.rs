@@ -1,4 +1,5 @@
-use super::{get_class_names, Converter};
+use super::{get_class_names, insert_split_placeholders, Converter, FileData};
+use crate::options::ConvertOptions;
 use crate::parser::{image_download_key, ImageEntry, NDJSONData};
 use serde::Serialize;
 use std::collections::HashMap;
@@ -31,11 +32,28 @@ struct CreateMlClassification {
     label: String,
 }
 
-pub struct CreateMlConverter;
+pub struct CreateMlConverter {
+    options: ConvertOptions,
+}
 
 impl CreateMlConverter {
     pub fn new() -> Self {
-        Self
+        Self {
+            options: ConvertOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ConvertOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    fn image_url(&self, img: &ImageEntry) -> String {
+        if self.options.strip_urls {
+            String::new()
+        } else {
+            img.url.clone()
+        }
     }
 
     fn create_createml_json(
@@ -69,13 +87,13 @@ impl CreateMlConverter {
 
                 CreateMlImage {
                     image: img.effective_file_name().to_string(),
-                    image_url: img.url.clone(),
+                    image_url: self.image_url(img),
                     annotations,
                 }
             })
             .collect();
 
-        serde_json::to_string_pretty(&result).unwrap_or_default()
+        self.options.to_json(&result)
     }
 
     fn create_createml_obb_json(
@@ -121,13 +139,13 @@ impl CreateMlConverter {
 
                 CreateMlImage {
                     image: img.effective_file_name().to_string(),
-                    image_url: img.url.clone(),
+                    image_url: self.image_url(img),
                     annotations,
                 }
             })
             .collect();
 
-        serde_json::to_string_pretty(&result).unwrap_or_default()
+        self.options.to_json(&result)
     }
 
     fn create_createml_classification_json(
@@ -151,7 +169,7 @@ impl CreateMlConverter {
             })
             .collect();
 
-        serde_json::to_string_pretty(&result).unwrap_or_default()
+        self.options.to_json(&result)
     }
 }
 
@@ -159,20 +177,19 @@ impl Converter for CreateMlConverter {
     fn convert(
         &self,
         data: &NDJSONData,
-        downloaded_images: &HashMap<String, Vec<u8>>,
-    ) -> HashMap<String, Vec<u8>> {
-        let mut files: HashMap<String, Vec<u8>> = HashMap::new();
+        downloaded_images: &HashMap<String, FileData>,
+    ) -> HashMap<String, FileData> {
+        let mut files: HashMap<String, FileData> = HashMap::new();
         let class_names = get_class_names(data);
         let task = &data.metadata.task;
 
-        let splits = [
-            ("train", data.train_images()),
-            ("valid", data.valid_images()),
-            ("test", data.test_images()),
-        ];
+        let splits = data.images_by_split(self.options.split_normalization);
 
         for (split, images) in &splits {
             if images.is_empty() {
+                if self.options.empty_split_placeholders {
+                    insert_split_placeholders(&mut files, &[split.to_string()]);
+                }
                 continue;
             }
 
@@ -181,7 +198,7 @@ impl Converter for CreateMlConverter {
                 "obb" => self.create_createml_obb_json(images, &class_names),
                 _ => self.create_createml_json(images, &class_names),
             };
-            files.insert(format!("{}.json", split), json.into_bytes());
+            files.insert(format!("{}.json", split), json.into_bytes().into());
 
             // Add images
             for img in images {
@@ -216,6 +233,10 @@ mod tests {
                 url: String::new(),
                 class_names: HashMap::from([("0".to_string(), "animal".to_string())]),
                 kpt_shape: None,
+                flip_idx: None,
+                date_created: None,
+                year: None,
+                licenses: Vec::new(),
                 version: "1".to_string(),
             },
             images: vec![
@@ -224,6 +245,10 @@ mod tests {
                     file: "img1.jpg".to_string(),
                     output_file: None,
                     url: String::new(),
+                    mirrors: Vec::new(),
+                    license: None,
+                    skip: false,
+                    captions: Vec::new(),
                     width: 640,
                     height: 480,
                     split: "train".to_string(),
@@ -236,6 +261,10 @@ mod tests {
                     file: "img1.jpg".to_string(),
                     output_file: None,
                     url: String::new(),
+                    mirrors: Vec::new(),
+                    license: None,
+                    skip: false,
+                    captions: Vec::new(),
                     width: 640,
                     height: 480,
                     split: "val".to_string(),
@@ -248,13 +277,13 @@ mod tests {
 
         let converter = CreateMlConverter::new();
         let mut downloaded_images = HashMap::new();
-        downloaded_images.insert(image_download_key("train", "img1.jpg"), vec![1]);
-        downloaded_images.insert(image_download_key("valid", "img1.jpg"), vec![2]);
+        downloaded_images.insert(image_download_key("train", "img1.jpg"), vec![1].into());
+        downloaded_images.insert(image_download_key("valid", "img1.jpg"), vec![2].into());
 
         let files = converter.convert(&data, &downloaded_images);
 
-        assert_eq!(files.get("train/img1.jpg"), Some(&vec![1]));
-        assert_eq!(files.get("valid/img1.jpg"), Some(&vec![2]));
+        assert_eq!(files.get("train/img1.jpg"), Some(&vec![1].into()));
+        assert_eq!(files.get("valid/img1.jpg"), Some(&vec![2].into()));
     }
 
     #[test]
@@ -269,6 +298,10 @@ mod tests {
                 url: String::new(),
                 class_names: HashMap::from([("0".to_string(), "animal".to_string())]),
                 kpt_shape: None,
+                flip_idx: None,
+                date_created: None,
+                year: None,
+                licenses: Vec::new(),
                 version: "1".to_string(),
             },
             images: vec![ImageEntry {
@@ -276,6 +309,10 @@ mod tests {
                 file: "img1.jpg".to_string(),
                 output_file: Some("img1__abcd1234.jpg".to_string()),
                 url: String::new(),
+                mirrors: Vec::new(),
+                license: None,
+                skip: false,
+                captions: Vec::new(),
                 width: 640,
                 height: 480,
                 split: "train".to_string(),
@@ -287,13 +324,16 @@ mod tests {
 
         let converter = CreateMlConverter::new();
         let mut downloaded_images = HashMap::new();
-        downloaded_images.insert(image_download_key("train", "img1__abcd1234.jpg"), vec![1]);
+        downloaded_images.insert(
+            image_download_key("train", "img1__abcd1234.jpg"),
+            vec![1].into(),
+        );
 
         let files = converter.convert(&data, &downloaded_images);
 
-        assert_eq!(files.get("train/img1__abcd1234.jpg"), Some(&vec![1]));
+        assert_eq!(files.get("train/img1__abcd1234.jpg"), Some(&vec![1].into()));
         let data: serde_json::Value =
-            serde_json::from_slice(files.get("train.json").unwrap()).unwrap();
+            serde_json::from_slice(files["train.json"].as_bytes().unwrap()).unwrap();
         assert_eq!(
             data.as_array()
                 .and_then(|arr| arr.first())
@@ -1,26 +1,74 @@
 pub mod coco;
+pub mod coco_panoptic;
 pub mod createml;
 pub mod pascal_voc;
 pub mod yolo;
 
-use crate::parser::NDJSONData;
-use std::collections::HashMap;
+use crate::options::{AnnotationKind, ConvertOptions, SplitNormalization, OUTPUT_SPLITS};
+use crate::parser::{image_entry_download_key, normalize_split, ImageEntry, NDJSONData};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+
+/// Contents of an output entry: either held in memory, or spooled to a temp
+/// file by the downloader and only read back when the archive is written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileData {
+    Bytes(Vec<u8>),
+    OnDisk(PathBuf),
+}
+
+impl FileData {
+    #[cfg(test)]
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            FileData::Bytes(bytes) => Some(bytes),
+            FileData::OnDisk(_) => None,
+        }
+    }
+
+    pub fn read(&self) -> std::io::Result<Cow<'_, [u8]>> {
+        match self {
+            FileData::Bytes(bytes) => Ok(Cow::Borrowed(bytes)),
+            FileData::OnDisk(path) => std::fs::read(path).map(Cow::Owned),
+        }
+    }
+}
+
+impl From<Vec<u8>> for FileData {
+    fn from(bytes: Vec<u8>) -> Self {
+        FileData::Bytes(bytes)
+    }
+}
 
 pub trait Converter {
     fn convert(
         &self,
         data: &NDJSONData,
-        downloaded_images: &HashMap<String, Vec<u8>>,
-    ) -> HashMap<String, Vec<u8>>;
+        downloaded_images: &HashMap<String, FileData>,
+    ) -> HashMap<String, FileData>;
 }
 
-pub fn get_converter(format: &str) -> Option<Box<dyn Converter + Send + Sync>> {
+pub fn get_converter(
+    format: &str,
+    options: &ConvertOptions,
+) -> Option<Box<dyn Converter + Send + Sync>> {
+    let options = options.clone();
     match format.to_lowercase().as_str() {
-        "yolo" => Some(Box::new(yolo::YoloConverter::new())),
-        "yolo_darknet" => Some(Box::new(yolo::YoloConverter::new_darknet())),
-        "coco" => Some(Box::new(coco::CocoConverter::new())),
-        "pascal_voc" | "voc" => Some(Box::new(pascal_voc::PascalVocConverter::new())),
-        "createml" => Some(Box::new(createml::CreateMlConverter::new())),
+        "yolo" => Some(Box::new(yolo::YoloConverter::new().with_options(options))),
+        "yolo_darknet" => Some(Box::new(
+            yolo::YoloConverter::new_darknet().with_options(options),
+        )),
+        "coco" => Some(Box::new(coco::CocoConverter::new().with_options(options))),
+        "coco_panoptic" => Some(Box::new(
+            coco_panoptic::CocoPanopticConverter::new().with_options(options),
+        )),
+        "pascal_voc" | "voc" => Some(Box::new(
+            pascal_voc::PascalVocConverter::new().with_options(options),
+        )),
+        "createml" => Some(Box::new(
+            createml::CreateMlConverter::new().with_options(options),
+        )),
         _ => None,
     }
 }
@@ -50,10 +98,680 @@ pub fn get_class_list(data: &NDJSONData) -> Vec<String> {
         .collect()
 }
 
+/// Removes annotations of classes excluded by the global or per-split class
+/// filter, and of ignored classes. Class ids are left untouched so every
+/// split shares one id space.
+pub fn apply_class_filters(data: &mut NDJSONData, options: &ConvertOptions) {
+    if options.class_filter.is_none()
+        && options.split_class_filters.is_empty()
+        && options.ignore_classes.is_empty()
+    {
+        return;
+    }
+
+    for image in &mut data.images {
+        if let Some(allowed) = options.allowed_classes(&image.split) {
+            image.retain_classes(|id| allowed.contains(&id));
+        }
+        if !options.ignore_classes.is_empty() {
+            image.retain_classes(|id| !options.ignore_classes.contains(&id));
+        }
+    }
+}
+
+/// Boxes whose pixel width or height rounds to zero on their image. Trainers
+/// drop these silently, so the export warns about them. Images without known
+/// dimensions are not counted.
+pub fn count_subpixel_boxes(data: &NDJSONData) -> usize {
+    data.images
+        .iter()
+        .filter(|image| image.width > 0 && image.height > 0)
+        .map(|image| {
+            let (width, height) = (f64::from(image.width), f64::from(image.height));
+            let boxes = image
+                .get_bboxes()
+                .into_iter()
+                .map(|bbox| (bbox.width, bbox.height))
+                .chain(
+                    image
+                        .get_pose_annotations()
+                        .into_iter()
+                        .map(|pose| (pose.bbox_w, pose.bbox_h)),
+                );
+            boxes
+                .filter(|(w, h)| (w * width).round() == 0.0 || (h * height).round() == 0.0)
+                .count()
+        })
+        .sum()
+}
+
+/// Calls `paint(x, y)` for every pixel of a `width` x `height` image whose
+/// center lies inside the polygon, under the even-odd rule, so rings inside
+/// the outer ring cut holes. Ring points are in pixels.
+pub fn fill_polygon_even_odd(
+    rings: &[Vec<(f64, f64)>],
+    width: u32,
+    height: u32,
+    mut paint: impl FnMut(u32, u32),
+) {
+    let edges: Vec<((f64, f64), (f64, f64))> = rings
+        .iter()
+        .filter(|ring| ring.len() >= 3)
+        .flat_map(|ring| {
+            ring.iter()
+                .copied()
+                .zip(ring.iter().copied().cycle().skip(1))
+        })
+        .collect();
+    let mut crossings = Vec::new();
+    for y in 0..height {
+        let center_y = f64::from(y) + 0.5;
+        crossings.clear();
+        for &((x1, y1), (x2, y2)) in &edges {
+            if (y1 <= center_y) != (y2 <= center_y) {
+                crossings.push(x1 + (center_y - y1) / (y2 - y1) * (x2 - x1));
+            }
+        }
+        crossings.sort_by(f64::total_cmp);
+        for span in crossings.chunks_exact(2) {
+            // Pixels whose center x is in [start, end).
+            let start = (span[0] - 0.5).ceil().max(0.0) as u32;
+            let end = ((span[1] - 0.5).ceil().max(0.0) as u32).min(width);
+            for x in start..end {
+                paint(x, y);
+            }
+        }
+    }
+}
+
+/// A split and, when sampling by class, the class of an image.
+type SampleGroup = (String, Option<i32>);
+
+/// Keeps a deterministic `fraction` of each split's images, chosen by a hash
+/// of `seed` and each image, so the same seed picks the same sample. With
+/// `by_class`, images are sampled within groups sharing their first
+/// annotation's class to keep the class mix. Every non-empty group keeps at
+/// least one image. Returns `(kept, total)` per split.
+pub fn sample_images(
+    data: &mut NDJSONData,
+    fraction: f64,
+    seed: u64,
+    by_class: bool,
+) -> BTreeMap<String, (usize, usize)> {
+    // (rank, image index) per (split, class group).
+    let mut groups: BTreeMap<SampleGroup, Vec<(u64, usize)>> = BTreeMap::new();
+    for (image_idx, image) in data.images.iter().enumerate() {
+        let split = normalize_split(&image.split).to_string();
+        let class = by_class
+            .then(|| image.class_ids().first().copied())
+            .flatten();
+        let position = format!("{}|{}|{}|{}", seed, split, image.file, image_idx);
+        let rank = crate::manifest::fnv1a_64(position.as_bytes());
+        groups
+            .entry((split, class))
+            .or_default()
+            .push((rank, image_idx));
+    }
+
+    let mut counts: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    let mut kept = HashSet::new();
+    for ((split, _), mut members) in groups {
+        members.sort_unstable();
+        let keep = ((members.len() as f64 * fraction).round() as usize).clamp(1, members.len());
+        kept.extend(members[..keep].iter().map(|(_, image_idx)| *image_idx));
+        let count = counts.entry(split).or_default();
+        count.0 += keep;
+        count.1 += members.len();
+    }
+
+    let mut image_idx = 0;
+    data.images.retain(|_| {
+        image_idx += 1;
+        kept.contains(&(image_idx - 1))
+    });
+    counts
+}
+
+/// Drops every annotation not of one of `kinds`. If the dataset's task
+/// makes labels from a dropped kind, the task becomes that of the first of
+/// `kinds`.
+pub fn restrict_annotation_kinds(data: &mut NDJSONData, kinds: &[AnnotationKind]) {
+    let Some(first) = kinds.first() else {
+        return;
+    };
+    if !kinds.iter().any(|kind| kind.task() == data.metadata.task) {
+        data.metadata.task = first.task().to_string();
+    }
+
+    for image in &mut data.images {
+        let Some(serde_json::Value::Object(annotations)) = &mut image.annotations else {
+            continue;
+        };
+        annotations.retain(|key, _| kinds.iter().any(|kind| kind.keys().contains(&key.as_str())));
+    }
+}
+
+/// Clears the annotations and captions of a split's images, so files that
+/// cover every split hold none of its labels. Returns how many images had
+/// labels to clear.
+pub fn clear_split_annotations(data: &mut NDJSONData, split: &str) -> usize {
+    let mut cleared = 0;
+    for image in &mut data.images {
+        if normalize_split(&image.split) == split {
+            if image.annotations.is_some() || !image.captions.is_empty() {
+                cleared += 1;
+            }
+            image.annotations = None;
+            image.captions.clear();
+        }
+    }
+    cleared
+}
+
+/// Removes every file under a split's directory except its images, leaving
+/// the split images-only. Panoptic masks are PNGs but still labels.
+pub fn remove_split_labels(files: &mut HashMap<String, FileData>, split: &str) {
+    let prefix = format!("{}/", split);
+    let masks = format!("{}/panoptic/", split);
+    files.retain(|path, _| {
+        !path.starts_with(&prefix) || (crate::verify::is_image(path) && !path.starts_with(&masks))
+    });
+}
+
+/// Removes images left without annotations, returning how many went.
+/// Classification images are kept, since their label is the class itself.
+pub fn drop_empty_images(data: &mut NDJSONData) -> usize {
+    if data.metadata.task == "classify" {
+        return 0;
+    }
+    let before = data.images.len();
+    data.images.retain(|image| !image.class_ids().is_empty());
+    before - data.images.len()
+}
+
+/// Moves the dataset onto the ids of an external class registry, matching
+/// classes by name, so datasets with different local ids agree. Every
+/// registry class joins the class list. Classes the registry lacks, or ids
+/// without a name, are an error unless `assign_unknown` is set, in which case
+/// they get new ids after the registry's. A registry giving one id to two
+/// names is an error too.
+pub fn apply_class_registry(
+    data: &mut NDJSONData,
+    registry: &HashMap<String, i32>,
+    assign_unknown: bool,
+) -> Result<(), String> {
+    let mut registry_names: Vec<(&String, &i32)> = registry.iter().collect();
+    registry_names.sort();
+    let mut names_by_id: HashMap<i32, &String> = HashMap::new();
+    for (name, id) in registry_names {
+        if let Some(first) = names_by_id.insert(*id, name) {
+            return Err(format!(
+                "Class registry gives id {} to both '{}' and '{}'",
+                id, first, name
+            ));
+        }
+    }
+
+    let local_names = get_class_names(data);
+    let mut local_ids: Vec<i32> = local_names.keys().copied().collect();
+    local_ids.extend(data.images.iter().flat_map(|image| image.class_ids()));
+    local_ids.sort_unstable();
+    local_ids.dedup();
+
+    let mut next_id = registry.values().max().map_or(0, |max| max + 1);
+    let mut mapping = HashMap::new();
+    let mut class_names: HashMap<String, String> = registry
+        .iter()
+        .map(|(name, id)| (id.to_string(), name.clone()))
+        .collect();
+    for local_id in local_ids {
+        let name = local_names.get(&local_id);
+        let registry_id = match name.and_then(|name| registry.get(name)) {
+            Some(id) => *id,
+            None if assign_unknown => {
+                next_id += 1;
+                next_id - 1
+            }
+            None => {
+                return Err(match name {
+                    Some(name) => format!("Class '{}' is not in the class registry", name),
+                    None => format!(
+                        "Class id {} has no name to look up in the class registry",
+                        local_id
+                    ),
+                })
+            }
+        };
+        if let Some(name) = name {
+            class_names.insert(registry_id.to_string(), name.clone());
+        }
+        mapping.insert(local_id, registry_id);
+    }
+
+    data.metadata.class_names = class_names;
+    for image in &mut data.images {
+        image.map_class_ids(|old| mapping[&old]);
+    }
+    Ok(())
+}
+
+/// Renumbers the class ids in use, named or annotated, to `0..K` in their
+/// original order, rewriting class names and annotations. Returns the
+/// original id of each new one.
+pub fn remap_class_ids(data: &mut NDJSONData) -> BTreeMap<i32, i32> {
+    let mut used: Vec<i32> = get_class_names(data).into_keys().collect();
+    used.extend(data.images.iter().flat_map(|image| image.class_ids()));
+    used.sort_unstable();
+    used.dedup();
+    let mapping: HashMap<i32, i32> = used
+        .iter()
+        .enumerate()
+        .map(|(new, &old)| (old, new as i32))
+        .collect();
+
+    data.metadata.class_names = data
+        .metadata
+        .class_names
+        .drain()
+        .map(|(id, name)| match id.parse::<i32>() {
+            Ok(old) => (mapping[&old].to_string(), name),
+            Err(_) => (id, name),
+        })
+        .collect();
+    for image in &mut data.images {
+        image.map_class_ids(|old| mapping[&old]);
+    }
+    mapping.into_iter().map(|(old, new)| (new, old)).collect()
+}
+
+/// Keeps at most `max_per_class` annotations of each class, dropping the
+/// excess of over-represented classes. Only the kind of annotation the task
+/// makes labels from is counted and capped. Which instances survive is
+/// decided by a hash of `seed` and the instance's position, so the same seed
+/// always keeps the same ones. Returns the number dropped per class.
+pub fn cap_annotations_per_class(
+    data: &mut NDJSONData,
+    max_per_class: usize,
+    seed: u64,
+) -> BTreeMap<i32, usize> {
+    let keys = AnnotationKind::for_task(&data.metadata.task).keys();
+    // (rank, image index, annotation key, item index) per class.
+    let mut instances: HashMap<i32, Vec<(u64, usize, &'static str, usize)>> = HashMap::new();
+    for (image_idx, image) in data.images.iter().enumerate() {
+        let Some(serde_json::Value::Object(annotations)) = &image.annotations else {
+            continue;
+        };
+        for &key in keys {
+            let Some(serde_json::Value::Array(items)) = annotations.get(key) else {
+                continue;
+            };
+            for (item_idx, item) in items.iter().enumerate() {
+                let class_id = if key == "classification" {
+                    item.as_i64()
+                } else {
+                    item.get(0).and_then(|id| id.as_i64())
+                };
+                let Some(class_id) = class_id else {
+                    continue;
+                };
+                let position = format!("{}|{}|{}|{}", seed, image.file, key, item_idx);
+                let rank = crate::manifest::fnv1a_64(position.as_bytes());
+                instances
+                    .entry(class_id as i32)
+                    .or_default()
+                    .push((rank, image_idx, key, item_idx));
+            }
+        }
+    }
+
+    let mut dropped_counts = BTreeMap::new();
+    let mut dropped = HashSet::new();
+    for (class_id, mut class_instances) in instances {
+        if class_instances.len() <= max_per_class {
+            continue;
+        }
+        class_instances.sort_unstable();
+        dropped_counts.insert(class_id, class_instances.len() - max_per_class);
+        dropped.extend(
+            class_instances[max_per_class..]
+                .iter()
+                .map(|(_, image_idx, key, item_idx)| (*image_idx, *key, *item_idx)),
+        );
+    }
+    if dropped.is_empty() {
+        return dropped_counts;
+    }
+
+    for (image_idx, image) in data.images.iter_mut().enumerate() {
+        let Some(serde_json::Value::Object(annotations)) = &mut image.annotations else {
+            continue;
+        };
+        for (key, value) in annotations.iter_mut() {
+            let Some(&key) = keys.iter().find(|known| *known == key) else {
+                continue;
+            };
+            if let serde_json::Value::Array(items) = value {
+                let mut item_idx = 0;
+                items.retain(|_| {
+                    let keep = !dropped.contains(&(image_idx, key, item_idx));
+                    item_idx += 1;
+                    keep
+                });
+            }
+        }
+    }
+
+    dropped_counts
+}
+
+/// Fills in `class_0..class_N` when the dataset declares no class names but
+/// its annotations reference class ids, so exports don't end up with `nc: 0`
+/// or no categories. Returns whether names were synthesized.
+pub fn synthesize_missing_class_names(data: &mut NDJSONData) -> bool {
+    if !data.metadata.class_names.is_empty() {
+        return false;
+    }
+    let Some(max_id) = data
+        .images
+        .iter()
+        .flat_map(|img| img.class_ids())
+        .filter(|id| *id >= 0)
+        .max()
+    else {
+        return false;
+    };
+
+    data.metadata.class_names = (0..=max_id)
+        .map(|id| (id.to_string(), format!("class_{}", id)))
+        .collect();
+    true
+}
+
+/// Keypoints per pose instance: the larger of the declared `kpt_shape[0]` and
+/// the longest keypoint list actually present, so stale metadata can't truncate.
+pub fn pose_keypoint_count(data: &NDJSONData) -> usize {
+    let meta_kpts = data
+        .metadata
+        .kpt_shape
+        .as_ref()
+        .and_then(|s| s.first().copied())
+        .unwrap_or(0) as usize;
+    let data_kpts = data
+        .images
+        .iter()
+        .flat_map(|img| img.get_pose_annotations())
+        .map(|p| p.keypoints.len())
+        .max()
+        .unwrap_or(0);
+    meta_kpts.max(data_kpts)
+}
+
+/// Checks that a pose dataset's `flip_idx` has one in-range entry per
+/// keypoint. Datasets without `flip_idx`, or that aren't pose, always pass.
+pub fn validate_flip_idx(data: &NDJSONData) -> Result<(), String> {
+    let Some(flip_idx) = data.metadata.flip_idx.as_ref() else {
+        return Ok(());
+    };
+    if data.metadata.task != "pose" {
+        return Ok(());
+    }
+
+    let num_kpts = pose_keypoint_count(data);
+    if flip_idx.len() != num_kpts {
+        return Err(format!(
+            "flip_idx has {} entries but the dataset has {} keypoints",
+            flip_idx.len(),
+            num_kpts
+        ));
+    }
+    if let Some(bad) = flip_idx
+        .iter()
+        .find(|&&idx| idx < 0 || idx as usize >= num_kpts)
+    {
+        return Err(format!(
+            "flip_idx entry {} is out of range for {} keypoints",
+            bad, num_kpts
+        ));
+    }
+    Ok(())
+}
+
+/// Values per keypoint from `kpt_shape[1]`: 2 for `x y`, otherwise 3 for
+/// `x y visibility`.
+pub fn pose_keypoint_dims(data: &NDJSONData) -> usize {
+    match data.metadata.kpt_shape.as_ref().and_then(|s| s.get(1)) {
+        Some(2) => 2,
+        _ => 3,
+    }
+}
+
+/// Lowercases a class name and collapses every run of non-alphanumeric
+/// characters into a single `_`, e.g. `Dog & Cat` -> `dog_cat`.
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('_') {
+            slug.push('_');
+        }
+    }
+    slug.trim_end_matches('_').to_string()
+}
+
+/// Assigns a unique folder slug to each named class and to each of
+/// `class_ids`, which covers ids used without a name. Classes whose slug is
+/// empty fall back to `class_{id}`, and later ids that collide, fallbacks
+/// included, get an `_{id}` suffix.
+pub fn class_folder_slugs(
+    class_names: &HashMap<i32, String>,
+    class_ids: impl IntoIterator<Item = i32>,
+) -> HashMap<i32, String> {
+    let mut ids: Vec<i32> = class_names.keys().copied().chain(class_ids).collect();
+    ids.sort_unstable();
+    ids.dedup();
+
+    let mut used = HashSet::new();
+    let mut slugs = HashMap::new();
+    for id in ids {
+        let base = class_names
+            .get(&id)
+            .map(|name| slugify(name))
+            .filter(|slug| !slug.is_empty())
+            .unwrap_or_else(|| format!("class_{}", id));
+        let mut slug = base.clone();
+        let mut counter = 2;
+        while !used.insert(slug.clone()) {
+            slug = if counter == 2 {
+                format!("{}_{}", base, id)
+            } else {
+                format!("{}_{}_{}", base, id, counter)
+            };
+            counter += 1;
+        }
+        slugs.insert(id, slug);
+    }
+    slugs
+}
+
+/// JSON object mapping each class folder slug back to its original name.
+pub fn class_folder_map_json(
+    class_names: &HashMap<i32, String>,
+    slugs: &HashMap<i32, String>,
+) -> Vec<u8> {
+    let map: std::collections::BTreeMap<&str, &str> = slugs
+        .iter()
+        .filter_map(|(id, slug)| {
+            class_names
+                .get(id)
+                .map(|name| (slug.as_str(), name.as_str()))
+        })
+        .collect();
+    serde_json::to_vec_pretty(&map).unwrap_or_default()
+}
+
+/// Adds an empty `.keep` entry to each of `dirs`, so split directories that
+/// have no images still exist once the archive is extracted.
+pub fn insert_split_placeholders(files: &mut HashMap<String, FileData>, dirs: &[String]) {
+    for dir in dirs {
+        files.insert(format!("{}/.keep", dir), Vec::new().into());
+    }
+}
+
+/// Source ids of the annotations `task` exports from `img`, in label order,
+/// for annotations that carried one.
+pub fn annotation_source_ids(img: &ImageEntry, task: &str) -> Vec<Option<i64>> {
+    match task {
+        "segment" => img.get_segment_annotations().iter().map(|a| a.id).collect(),
+        "pose" => img.get_pose_annotations().iter().map(|a| a.id).collect(),
+        "obb" => img.get_obb_annotations().iter().map(|a| a.id).collect(),
+        _ => img.get_bboxes().iter().map(|a| a.id).collect(),
+    }
+}
+
+/// Errors when two exported annotations carry the same source id, since the
+/// ids are written as they are and COCO needs them unique.
+pub fn check_unique_annotation_ids(data: &NDJSONData) -> Result<(), String> {
+    let mut seen: HashMap<i64, &ImageEntry> = HashMap::new();
+    for img in &data.images {
+        for id in annotation_source_ids(img, &data.metadata.task)
+            .into_iter()
+            .flatten()
+        {
+            if let Some(first) = seen.insert(id, img) {
+                return Err(format!(
+                    "Annotation id {} is used more than once, in '{}' and '{}'",
+                    id,
+                    first.effective_file_name(),
+                    img.effective_file_name()
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds a `{split}/raw/{stem}.json` per image holding its parsed annotations
+/// in one normalized schema, whatever the export format: `boxes`,
+/// `segments`, `poses`, `obbs` and `classifications`, with coordinates as
+/// fractions of the image size. Classify exports use one folder per class
+/// under `{split}/`, so their sidecars go to `raw/{split}/{stem}.json`
+/// instead, where no trainer reads `raw` as a class. Images in
+/// `withheld_split` get none, since their labels are held back.
+pub fn insert_raw_sidecars(
+    files: &mut HashMap<String, FileData>,
+    data: &NDJSONData,
+    splits: SplitNormalization,
+    withheld_split: Option<&str>,
+) {
+    for img in &data.images {
+        if withheld_split == Some(normalize_split(&img.split)) {
+            continue;
+        }
+        let file = img.effective_file_name();
+        let stem = file.rsplit_once('.').map_or(file, |(stem, _)| stem);
+        let split = splits.output_split(&img.split);
+        let sidecar = serde_json::json!({
+            "file": file,
+            "split": split,
+            "width": img.width,
+            "height": img.height,
+            "boxes": img.get_bboxes(),
+            "segments": img.get_segment_annotations(),
+            "poses": img.get_pose_annotations(),
+            "obbs": img.get_obb_annotations(),
+            "classifications": img.get_classifications(),
+        });
+        let path = if data.metadata.task == "classify" {
+            format!("raw/{}/{}.json", split, stem)
+        } else {
+            format!("{}/raw/{}.json", split, stem)
+        };
+        files.insert(
+            path,
+            serde_json::to_vec_pretty(&sidecar)
+                .unwrap_or_default()
+                .into(),
+        );
+    }
+}
+
+/// Text for `split_summary.txt`: the image count and share of each output
+/// split, the standard ones first, then a total line.
+pub fn split_summary(data: &NDJSONData, splits: SplitNormalization) -> String {
+    let mut counts: BTreeMap<(usize, &str), usize> = BTreeMap::new();
+    for img in &data.images {
+        let split = splits.output_split(&img.split);
+        let rank = OUTPUT_SPLITS
+            .iter()
+            .position(|known| *known == split)
+            .unwrap_or(OUTPUT_SPLITS.len());
+        *counts.entry((rank, split)).or_default() += 1;
+    }
+    let total = data.images.len();
+    let percent = |count: usize| {
+        if total == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / total as f64
+        }
+    };
+
+    let mut summary = String::from("split\timages\tpercent\n");
+    for ((_, split), count) in &counts {
+        summary.push_str(&format!("{}\t{}\t{:.1}%\n", split, count, percent(*count)));
+    }
+    summary.push_str(&format!("total\t{}\t{:.1}%\n", total, percent(total)));
+    summary
+}
+
+/// Images and labels that don't pair up in an export, as `split/file` names.
+#[derive(Debug, Default, PartialEq)]
+pub struct Orphans {
+    /// Downloaded images with no annotation for the dataset's task.
+    pub images: Vec<String>,
+    /// Annotated images whose image file is missing from the export.
+    pub labels: Vec<String>,
+}
+
+fn has_task_annotations(img: &ImageEntry, task: &str) -> bool {
+    match task {
+        "pose" => !img.get_pose_annotations().is_empty(),
+        "segment" => !img.get_segment_annotations().is_empty(),
+        "obb" => !img.get_obb_annotations().is_empty(),
+        "classify" => !img.get_classifications().is_empty(),
+        _ => !img.get_bboxes().is_empty(),
+    }
+}
+
+/// Pairs every image entry's annotations with its downloaded file to find
+/// partial exports.
+pub fn find_orphans(
+    data: &NDJSONData,
+    downloaded_images: &HashMap<String, FileData>,
+    splits: SplitNormalization,
+) -> Orphans {
+    let mut orphans = Orphans::default();
+    for img in &data.images {
+        let name = format!(
+            "{}/{}",
+            splits.output_split(&img.split),
+            img.effective_file_name()
+        );
+        let downloaded = downloaded_images.contains_key(&image_entry_download_key(img));
+        match (has_task_annotations(img, &data.metadata.task), downloaded) {
+            (false, true) => orphans.images.push(name),
+            (true, false) => orphans.labels.push(name),
+            _ => {}
+        }
+    }
+    orphans
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parser::{DatasetMetadata, NDJSONData};
+    use crate::parser::{image_download_key, DatasetMetadata, ImageEntry, NDJSONData};
 
     fn make_metadata_with_classes(class_names: HashMap<String, String>) -> NDJSONData {
         NDJSONData {
@@ -66,6 +784,10 @@ mod tests {
                 url: String::new(),
                 class_names,
                 kpt_shape: None,
+                flip_idx: None,
+                date_created: None,
+                year: None,
+                licenses: Vec::new(),
                 version: "1".to_string(),
             },
             images: vec![],
@@ -74,20 +796,23 @@ mod tests {
 
     #[test]
     fn get_converter_returns_known_formats() {
-        assert!(get_converter("yolo").is_some());
-        assert!(get_converter("YOLO").is_some());
-        assert!(get_converter("coco").is_some());
-        assert!(get_converter("pascal_voc").is_some());
-        assert!(get_converter("voc").is_some());
-        assert!(get_converter("createml").is_some());
-        assert!(get_converter("yolo_darknet").is_some());
+        let options = ConvertOptions::default();
+        assert!(get_converter("yolo", &options).is_some());
+        assert!(get_converter("YOLO", &options).is_some());
+        assert!(get_converter("coco", &options).is_some());
+        assert!(get_converter("pascal_voc", &options).is_some());
+        assert!(get_converter("voc", &options).is_some());
+        assert!(get_converter("createml", &options).is_some());
+        assert!(get_converter("coco_panoptic", &options).is_some());
+        assert!(get_converter("yolo_darknet", &options).is_some());
     }
 
     #[test]
     fn get_converter_returns_none_for_unknown() {
-        assert!(get_converter("unknown_format").is_none());
-        assert!(get_converter("").is_none());
-        assert!(get_converter("xml").is_none());
+        let options = ConvertOptions::default();
+        assert!(get_converter("unknown_format", &options).is_none());
+        assert!(get_converter("", &options).is_none());
+        assert!(get_converter("xml", &options).is_none());
     }
 
     #[test]
@@ -102,4 +827,769 @@ mod tests {
 
         assert_eq!(class_list, vec!["cat", "dog", "bird"]);
     }
+
+    #[test]
+    fn synthesized_class_names_cover_max_referenced_id() {
+        let mut data = make_metadata_with_classes(HashMap::new());
+        data.images.push(ImageEntry {
+            r#type: "image".to_string(),
+            file: "img1.jpg".to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: "train".to_string(),
+            annotations: Some(serde_json::json!({
+                "boxes": [[0, 0.5, 0.5, 0.2, 0.2], [3, 0.2, 0.2, 0.1, 0.1]]
+            })),
+        });
+
+        assert!(synthesize_missing_class_names(&mut data));
+        assert_eq!(
+            get_class_list(&data),
+            vec!["class_0", "class_1", "class_2", "class_3"]
+        );
+
+        let files = get_converter("yolo", &ConvertOptions::default())
+            .unwrap()
+            .convert(&data, &HashMap::new());
+        let yaml = String::from_utf8(files["data.yaml"].read().unwrap().into_owned()).unwrap();
+        assert!(yaml.contains("nc: 4\n"));
+        assert!(yaml.contains("class_3"));
+    }
+
+    #[test]
+    fn declared_class_names_are_not_synthesized() {
+        let mut data =
+            make_metadata_with_classes(HashMap::from([("0".to_string(), "cat".to_string())]));
+        assert!(!synthesize_missing_class_names(&mut data));
+        assert_eq!(get_class_list(&data), vec!["cat"]);
+    }
+
+    fn image_with_boxes(file: &str, boxes: serde_json::Value) -> ImageEntry {
+        ImageEntry {
+            r#type: "image".to_string(),
+            file: file.to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: "train".to_string(),
+            annotations: Some(serde_json::json!({ "boxes": boxes })),
+        }
+    }
+
+    fn balanced_dataset() -> NDJSONData {
+        let mut data = make_metadata_with_classes(HashMap::new());
+        for i in 0..10 {
+            let mut boxes = vec![serde_json::json!([0, 0.5, 0.5, 0.1, 0.1]); 10];
+            if i < 5 {
+                boxes.push(serde_json::json!([1, 0.2, 0.2, 0.1, 0.1]));
+            }
+            data.images
+                .push(image_with_boxes(&format!("img{}.jpg", i), boxes.into()));
+        }
+        data
+    }
+
+    fn count_class(data: &NDJSONData, class_id: i32) -> usize {
+        data.images
+            .iter()
+            .flat_map(|img| img.class_ids())
+            .filter(|id| *id == class_id)
+            .count()
+    }
+
+    #[test]
+    fn cap_annotations_per_class_trims_over_represented_class() {
+        let mut data = balanced_dataset();
+
+        let dropped = cap_annotations_per_class(&mut data, 20, 7);
+
+        assert_eq!(dropped, BTreeMap::from([(0, 80)]));
+        assert_eq!(count_class(&data, 0), 20);
+        assert_eq!(count_class(&data, 1), 5);
+    }
+
+    #[test]
+    fn cap_annotations_per_class_is_deterministic_per_seed() {
+        let mut first = balanced_dataset();
+        let mut second = balanced_dataset();
+        let mut other_seed = balanced_dataset();
+
+        cap_annotations_per_class(&mut first, 20, 7);
+        cap_annotations_per_class(&mut second, 20, 7);
+        cap_annotations_per_class(&mut other_seed, 20, 8);
+
+        let kept = |data: &NDJSONData| -> Vec<Option<serde_json::Value>> {
+            data.images
+                .iter()
+                .map(|img| img.annotations.clone())
+                .collect()
+        };
+        assert_eq!(kept(&first), kept(&second));
+        assert_ne!(kept(&first), kept(&other_seed));
+    }
+
+    #[test]
+    fn cap_counts_only_the_kind_the_task_labels() {
+        let mut data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"segment","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"annotations":{"boxes":[[0,0.5,0.5,0.2,0.2],[0,0.2,0.2,0.1,0.1]],"segments":[[0,0.4,0.4,0.6,0.4,0.6,0.6],[0,0.1,0.1,0.3,0.1,0.3,0.3]]}}"#,
+        )
+        .unwrap();
+
+        assert!(cap_annotations_per_class(&mut data, 2, 7).is_empty());
+
+        let dropped = cap_annotations_per_class(&mut data, 1, 7);
+        assert_eq!(dropped, BTreeMap::from([(0, 1)]));
+        assert_eq!(data.images[0].get_segment_annotations().len(), 1);
+        assert_eq!(data.images[0].get_bboxes().len(), 2);
+    }
+
+    #[test]
+    fn strip_urls_removes_urls_from_every_format() {
+        let mut data =
+            make_metadata_with_classes(HashMap::from([("0".to_string(), "cat".to_string())]));
+        data.metadata.url = "https://internal.example/datasets/cats".to_string();
+        data.images.push(ImageEntry {
+            r#type: "image".to_string(),
+            file: "img1.jpg".to_string(),
+            output_file: None,
+            url: "https://internal.example/img1.jpg?token=secret".to_string(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: "train".to_string(),
+            annotations: Some(serde_json::json!({
+                "bboxes": [[0, 0.5, 0.5, 0.2, 0.2]]
+            })),
+        });
+        let options = ConvertOptions {
+            strip_urls: true,
+            ..Default::default()
+        };
+
+        for format in ["yolo", "yolo_darknet", "coco", "pascal_voc", "createml"] {
+            let files = get_converter(format, &options)
+                .unwrap()
+                .convert(&data, &HashMap::new());
+            for (path, content) in &files {
+                let text = String::from_utf8_lossy(content.as_bytes().unwrap());
+                assert!(
+                    !text.contains("internal.example"),
+                    "{} output {} leaks a URL",
+                    format,
+                    path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn find_orphans_reports_both_directions() {
+        let mut data =
+            make_metadata_with_classes(HashMap::from([("0".to_string(), "cat".to_string())]));
+        for (file, annotations) in [
+            (
+                "labeled.jpg",
+                Some(serde_json::json!({"bboxes": [[0, 0.5, 0.5, 0.2, 0.2]]})),
+            ),
+            ("unlabeled.jpg", None),
+            (
+                "missing.jpg",
+                Some(serde_json::json!({"bboxes": [[0, 0.5, 0.5, 0.2, 0.2]]})),
+            ),
+        ] {
+            data.images.push(ImageEntry {
+                r#type: "image".to_string(),
+                file: file.to_string(),
+                output_file: None,
+                url: String::new(),
+                mirrors: Vec::new(),
+                license: None,
+                skip: false,
+                captions: Vec::new(),
+                width: 640,
+                height: 480,
+                split: "val".to_string(),
+                annotations,
+            });
+        }
+        let downloaded = HashMap::from([
+            (image_download_key("valid", "labeled.jpg"), vec![1].into()),
+            (image_download_key("valid", "unlabeled.jpg"), vec![2].into()),
+        ]);
+
+        let orphans = find_orphans(&data, &downloaded, SplitNormalization::Canonical);
+
+        assert_eq!(orphans.images, vec!["valid/unlabeled.jpg"]);
+        assert_eq!(orphans.labels, vec!["valid/missing.jpg"]);
+    }
+
+    #[test]
+    fn split_class_filter_removes_class_from_valid_only() {
+        let mut data = make_metadata_with_classes(HashMap::from([
+            ("0".to_string(), "cat".to_string()),
+            ("1".to_string(), "dog".to_string()),
+        ]));
+        for (file, split) in [("a.jpg", "train"), ("b.jpg", "val")] {
+            data.images.push(ImageEntry {
+                r#type: "image".to_string(),
+                file: file.to_string(),
+                output_file: None,
+                url: String::new(),
+                mirrors: Vec::new(),
+                license: None,
+                skip: false,
+                captions: Vec::new(),
+                width: 640,
+                height: 480,
+                split: split.to_string(),
+                annotations: Some(serde_json::json!({
+                    "bboxes": [[0, 0.5, 0.5, 0.2, 0.2], [1, 0.3, 0.3, 0.1, 0.1]]
+                })),
+            });
+        }
+        let options = ConvertOptions {
+            split_class_filters: HashMap::from([("valid".to_string(), vec![0])]),
+            ..Default::default()
+        };
+
+        apply_class_filters(&mut data, &options);
+
+        let yolo = get_converter("yolo", &options)
+            .unwrap()
+            .convert(&data, &HashMap::new());
+        let train = std::str::from_utf8(yolo["train/labels/a.txt"].as_bytes().unwrap()).unwrap();
+        let valid = std::str::from_utf8(yolo["valid/labels/b.txt"].as_bytes().unwrap()).unwrap();
+        assert!(train.lines().any(|line| line.starts_with("1 ")));
+        assert!(!valid.lines().any(|line| line.starts_with("1 ")));
+        assert_eq!(
+            yolo["classes.txt"].as_bytes().unwrap(),
+            b"cat\ndog".as_slice()
+        );
+
+        let coco = get_converter("coco", &options)
+            .unwrap()
+            .convert(&data, &HashMap::new());
+        let train: serde_json::Value =
+            serde_json::from_slice(coco["train/_annotations.coco.json"].as_bytes().unwrap())
+                .unwrap();
+        let valid: serde_json::Value =
+            serde_json::from_slice(coco["valid/_annotations.coco.json"].as_bytes().unwrap())
+                .unwrap();
+        assert_eq!(train["categories"].as_array().unwrap().len(), 2);
+        let valid_categories = valid["categories"].as_array().unwrap();
+        assert_eq!(valid_categories.len(), 1);
+        assert_eq!(valid_categories[0]["id"], 0);
+        assert!(valid["annotations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|ann| ann["category_id"] == 0));
+    }
+
+    #[test]
+    fn slugify_lowercases_and_collapses_separators() {
+        assert_eq!(slugify("Dog & Cat"), "dog_cat");
+        assert_eq!(slugify("  Traffic--Light "), "traffic_light");
+        assert_eq!(slugify("***"), "");
+    }
+
+    #[test]
+    fn class_folder_slugs_disambiguate_collisions() {
+        let class_names = HashMap::from([
+            (0, "Dog & Cat".to_string()),
+            (1, "dog cat".to_string()),
+            (2, "!!".to_string()),
+        ]);
+
+        let slugs = class_folder_slugs(&class_names, []);
+
+        assert_eq!(slugs[&0], "dog_cat");
+        assert_eq!(slugs[&1], "dog_cat_1");
+        assert_eq!(slugs[&2], "class_2");
+    }
+
+    #[test]
+    fn fallback_class_slugs_do_not_merge_with_named_ones() {
+        let class_names = HashMap::from([(1, "class 3".to_string())]);
+
+        let slugs = class_folder_slugs(&class_names, [3, 1]);
+
+        assert_eq!(slugs[&1], "class_3");
+        assert_eq!(slugs[&3], "class_3_3");
+    }
+
+    #[test]
+    fn raw_sidecars_hold_normalized_boxes() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"val","annotations":{"boxes":[[0,0.5,0.4,0.2,0.1]]}}"#,
+        )
+        .unwrap();
+        let mut files = HashMap::new();
+
+        insert_raw_sidecars(&mut files, &data, SplitNormalization::Canonical, None);
+
+        let sidecar: serde_json::Value =
+            serde_json::from_slice(&files["valid/raw/a.json"].read().unwrap()).unwrap();
+        assert_eq!(
+            sidecar["boxes"],
+            serde_json::json!([{"x": 0.5, "y": 0.4, "width": 0.2, "height": 0.1, "class_id": 0}])
+        );
+        assert_eq!(sidecar["file"], "a.jpg");
+        assert_eq!(sidecar["segments"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn classify_raw_sidecars_stay_out_of_class_folders() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"classify","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"val","annotations":{"classification":[0]}}"#,
+        )
+        .unwrap();
+        let options = ConvertOptions {
+            split_normalization: SplitNormalization::SeparateVal,
+            ..Default::default()
+        };
+        let mut files = get_converter("yolo", &options)
+            .unwrap()
+            .convert(&data, &HashMap::new());
+
+        insert_raw_sidecars(&mut files, &data, SplitNormalization::SeparateVal, None);
+
+        assert!(files.contains_key("raw/val/a.json"));
+        assert!(!files.keys().any(|path| path.starts_with("val/raw/")));
+    }
+
+    #[test]
+    fn split_summary_lists_counts_and_percentages() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"test"}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"train"}
+{"type":"image","file":"c.jpg","width":640,"height":480,"split":"val"}
+{"type":"image","file":"d.jpg","width":640,"height":480,"split":"train"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            split_summary(&data, SplitNormalization::Canonical),
+            "split\timages\tpercent\n\
+             train\t2\t50.0%\n\
+             valid\t1\t25.0%\n\
+             test\t1\t25.0%\n\
+             total\t4\t100.0%\n"
+        );
+    }
+
+    #[test]
+    fn remap_compacts_sparse_class_ids() {
+        let mut data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat","5":"dog"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[5,0.5,0.5,0.2,0.2],[0,0.1,0.1,0.1,0.1]]}}"#,
+        )
+        .unwrap();
+
+        let original_ids = remap_class_ids(&mut data);
+
+        assert_eq!(original_ids, BTreeMap::from([(0, 0), (1, 5)]));
+        assert_eq!(get_class_list(&data), ["cat", "dog"]);
+        let files = get_converter("yolo", &ConvertOptions::default())
+            .unwrap()
+            .convert(&data, &HashMap::new());
+        assert_eq!(
+            files["train/labels/a.txt"].as_bytes().unwrap(),
+            b"1 0.500000 0.500000 0.200000 0.200000\n0 0.100000 0.100000 0.100000 0.100000"
+        );
+    }
+
+    #[test]
+    fn ignored_classes_lose_annotations_but_keep_images_and_names() {
+        let mut data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat","1":"crowd"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2],[1,0.3,0.3,0.4,0.4]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[1,0.3,0.3,0.4,0.4]]}}"#,
+        )
+        .unwrap();
+        let options = ConvertOptions {
+            ignore_classes: vec![1],
+            ..Default::default()
+        };
+
+        apply_class_filters(&mut data, &options);
+
+        assert_eq!(data.images.len(), 2);
+        assert_eq!(data.images[0].class_ids(), [0]);
+        assert!(data.images[1].get_bboxes().is_empty());
+        assert_eq!(get_class_list(&data), ["cat", "crowd"]);
+    }
+
+    #[test]
+    fn class_registry_reconciles_swapped_local_ids() {
+        let registry = HashMap::from([
+            ("cat".to_string(), 7),
+            ("dog".to_string(), 3),
+            ("bird".to_string(), 0),
+        ]);
+        let mut first = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"first","class_names":{"0":"cat","1":"dog"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"annotations":{"boxes":[[0,0.5,0.5,0.2,0.2],[1,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let mut second = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"second","class_names":{"0":"dog","1":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"annotations":{"boxes":[[1,0.5,0.5,0.2,0.2],[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+
+        apply_class_registry(&mut first, &registry, false).unwrap();
+        apply_class_registry(&mut second, &registry, false).unwrap();
+
+        assert_eq!(first.images[0].class_ids(), [7, 3]);
+        assert_eq!(second.images[0].class_ids(), [7, 3]);
+        assert_eq!(get_class_list(&first), get_class_list(&second));
+        assert_eq!(get_class_names(&first)[&0], "bird");
+
+        let mut unknown = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"third","class_names":{"0":"cat","1":"fox"}}"#,
+        )
+        .unwrap();
+        let err = apply_class_registry(&mut unknown.clone(), &registry, false).unwrap_err();
+        assert!(err.contains("'fox'"));
+        apply_class_registry(&mut unknown, &registry, true).unwrap();
+        assert_eq!(get_class_names(&unknown)[&8], "fox");
+    }
+
+    #[test]
+    fn duplicate_annotation_ids_are_rejected() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"annotations":{"boxes":[[0,0.5,0.5,0.2,0.2,{"id":7}],[0,0.3,0.3,0.1,0.1]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"annotations":{"boxes":[[0,0.5,0.5,0.2,0.2,{"id":7}]]}}"#,
+        )
+        .unwrap();
+
+        let err = check_unique_annotation_ids(&data).unwrap_err();
+        assert_eq!(
+            err,
+            "Annotation id 7 is used more than once, in 'a.jpg' and 'b.jpg'"
+        );
+
+        let mut unique = data.clone();
+        unique.images.pop();
+        assert_eq!(check_unique_annotation_ids(&unique), Ok(()));
+    }
+
+    #[test]
+    fn class_registry_reusing_an_id_is_rejected() {
+        let registry = HashMap::from([("cat".to_string(), 1), ("dog".to_string(), 1)]);
+        let mut data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat","1":"dog"}}"#,
+        )
+        .unwrap();
+
+        let err = apply_class_registry(&mut data, &registry, false).unwrap_err();
+        assert_eq!(err, "Class registry gives id 1 to both 'cat' and 'dog'");
+    }
+
+    #[test]
+    fn images_emptied_by_class_filters_are_dropped() {
+        let mut data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat","1":"dog"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"annotations":{"boxes":[[0,0.5,0.5,0.2,0.2],[1,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"annotations":{"boxes":[[1,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let options = ConvertOptions {
+            ignore_classes: vec![1],
+            ..Default::default()
+        };
+
+        apply_class_filters(&mut data, &options);
+        let dropped = drop_empty_images(&mut data);
+
+        assert_eq!(dropped, 1);
+        assert_eq!(data.images.len(), 1);
+        assert_eq!(data.images[0].file, "a.jpg");
+    }
+
+    #[test]
+    fn sample_images_keeps_a_seeded_fraction() {
+        let dataset = || {
+            let mut data = make_metadata_with_classes(HashMap::new());
+            for i in 0..100 {
+                data.images.push(image_with_boxes(
+                    &format!("img{}.jpg", i),
+                    serde_json::json!([[i % 2, 0.5, 0.5, 0.1, 0.1]]),
+                ));
+            }
+            data
+        };
+        let files = |data: &NDJSONData| -> Vec<String> {
+            data.images.iter().map(|img| img.file.clone()).collect()
+        };
+        let mut first = dataset();
+        let mut second = dataset();
+        let mut other_seed = dataset();
+
+        let counts = sample_images(&mut first, 0.1, 7, false);
+        sample_images(&mut second, 0.1, 7, false);
+        sample_images(&mut other_seed, 0.1, 8, false);
+
+        assert_eq!(counts.values().next(), Some(&(10, 100)));
+        assert_eq!(files(&first), files(&second));
+        assert_ne!(files(&first), files(&other_seed));
+
+        let mut balanced = dataset();
+        sample_images(&mut balanced, 0.1, 7, true);
+        assert_eq!(count_class(&balanced, 0), 5);
+        assert_eq!(count_class(&balanced, 1), 5);
+    }
+
+    #[test]
+    fn subpixel_boxes_are_counted() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":100,"height":100,"annotations":{"boxes":[[0,0.5,0.5,0.004,0.2],[0,0.5,0.5,0.01,0.01],[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":0,"height":0,"annotations":{"boxes":[[0,0.5,0.5,0.001,0.001]]}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(count_subpixel_boxes(&data), 1);
+    }
+
+    #[test]
+    fn even_odd_fill_leaves_holes_empty() {
+        let rings = vec![
+            vec![(0.0, 0.0), (6.0, 0.0), (6.0, 6.0), (0.0, 6.0)],
+            vec![(2.0, 2.0), (4.0, 2.0), (4.0, 4.0), (2.0, 4.0)],
+        ];
+        let mut painted = HashSet::new();
+
+        fill_polygon_even_odd(&rings, 8, 8, |x, y| {
+            painted.insert((x, y));
+        });
+
+        assert_eq!(painted.len(), 36 - 4);
+        assert!(painted.contains(&(1, 1)));
+        assert!(!painted.contains(&(2, 2)));
+        assert!(!painted.contains(&(6, 6)));
+    }
+
+    #[test]
+    fn withheld_test_split_keeps_images_but_no_labels() {
+        let mut data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"val","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"c.jpg","width":640,"height":480,"split":"test","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let downloaded: HashMap<String, FileData> =
+            [("train", "a.jpg"), ("valid", "b.jpg"), ("test", "c.jpg")]
+                .into_iter()
+                .map(|(split, file)| (image_download_key(split, file), b"jpg".to_vec().into()))
+                .collect();
+        clear_split_annotations(&mut data, "test");
+
+        for format in ["yolo", "pascal_voc", "coco"] {
+            let mut files = get_converter(format, &ConvertOptions::default())
+                .unwrap()
+                .convert(&data, &downloaded);
+            remove_split_labels(&mut files, "test");
+            insert_raw_sidecars(
+                &mut files,
+                &data,
+                SplitNormalization::Canonical,
+                Some("test"),
+            );
+
+            let in_split = |split: &str| -> Vec<&String> {
+                files
+                    .keys()
+                    .filter(|path| path.starts_with(&format!("{}/", split)))
+                    .collect()
+            };
+            let test_files = in_split("test");
+            assert_eq!(test_files.len(), 1, "{}: {:?}", format, test_files);
+            assert!(test_files[0].ends_with("c.jpg"));
+            for split in ["train", "valid"] {
+                assert!(
+                    in_split(split)
+                        .iter()
+                        .any(|path| !crate::verify::is_image(path)),
+                    "{}: {} lost its labels",
+                    format,
+                    split
+                );
+            }
+            assert!(files.contains_key("valid/raw/b.json"), "{}", format);
+            assert!(!files.contains_key("test/raw/c.json"), "{}", format);
+        }
+    }
+
+    #[test]
+    fn separate_val_keeps_val_apart_from_valid() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"val","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"valid","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let downloaded: HashMap<String, FileData> = ["a.jpg", "b.jpg"]
+            .into_iter()
+            .map(|file| (image_download_key("valid", file), b"jpg".to_vec().into()))
+            .collect();
+        let converter = get_converter(
+            "yolo",
+            &ConvertOptions {
+                split_normalization: SplitNormalization::SeparateVal,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let files = converter.convert(&data, &downloaded);
+
+        for path in [
+            "val/images/a.jpg",
+            "val/labels/a.txt",
+            "valid/images/b.jpg",
+            "valid/labels/b.txt",
+        ] {
+            assert!(files.contains_key(path), "missing {}", path);
+        }
+        assert!(!files.contains_key("valid/labels/a.txt"));
+        assert!(!files.contains_key("val/labels/b.txt"));
+        let yaml = String::from_utf8(files["data.yaml"].read().unwrap().into_owned()).unwrap();
+        assert!(
+            yaml.contains("val: [val/images, valid/images]\n"),
+            "{}",
+            yaml
+        );
+    }
+
+    #[test]
+    fn per_split_options_follow_split_normalization() {
+        let canonical = ConvertOptions {
+            split_class_filters: HashMap::from([("val".to_string(), vec![0])]),
+            download_splits: Some(vec!["val".to_string()]),
+            split_image_settings: HashMap::from([(
+                "val".to_string(),
+                crate::options::SplitImageSettings::default(),
+            )]),
+            ..Default::default()
+        };
+        let separate = ConvertOptions {
+            split_normalization: SplitNormalization::SeparateVal,
+            ..canonical.clone()
+        };
+
+        for split in ["val", "valid"] {
+            assert_eq!(canonical.allowed_classes(split), Some([0].as_slice()));
+            assert!(canonical.downloads_split(split));
+            assert!(canonical.image_settings_for(split).is_some());
+        }
+        assert_eq!(separate.allowed_classes("val"), Some([0].as_slice()));
+        assert_eq!(separate.allowed_classes("valid"), None);
+        assert!(separate.downloads_split("val"));
+        assert!(!separate.downloads_split("valid"));
+        assert!(separate.image_settings_for("val").is_some());
+        assert!(separate.image_settings_for("valid").is_none());
+    }
+
+    #[test]
+    fn separate_val_names_every_split_file_val() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"val","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let downloaded: HashMap<String, FileData> = [("train", "a.jpg"), ("val", "b.jpg")]
+            .into_iter()
+            .map(|(split, file)| (image_download_key(split, file), b"jpg".to_vec().into()))
+            .collect();
+        let options = ConvertOptions {
+            split_normalization: SplitNormalization::SeparateVal,
+            ..Default::default()
+        };
+        let convert = |format: &str, options: &ConvertOptions| {
+            get_converter(format, options)
+                .unwrap()
+                .convert(&data, &downloaded)
+        };
+        let text = |files: &HashMap<String, FileData>, path: &str| {
+            String::from_utf8(files[path].read().unwrap().into_owned()).unwrap()
+        };
+
+        let yolo = convert("yolo", &options);
+        assert!(text(&yolo, "data.yaml").contains("train: train/images\nval: val/images\n"));
+        assert!(!yolo.keys().any(|path| path.starts_with("valid/")));
+
+        let darknet = convert("yolo_darknet", &options);
+        assert_eq!(text(&darknet, "val.txt"), "val/b.jpg\n");
+        assert!(darknet.contains_key("val/b.txt"));
+        assert!(!darknet.contains_key("valid.txt"));
+
+        let createml = convert("createml", &options);
+        assert!(text(&createml, "val.json").contains("\"b.jpg\""));
+        assert!(createml.contains_key("val/b.jpg"));
+        assert!(!createml.contains_key("valid.json"));
+
+        let coco = convert(
+            "coco",
+            &ConvertOptions {
+                coco_single_file: true,
+                ..options.clone()
+            },
+        );
+        let single: serde_json::Value =
+            serde_json::from_str(&text(&coco, "_annotations.coco.json")).unwrap();
+        let names: Vec<&str> = single["images"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|image| image["file_name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["train/a.jpg", "val/b.jpg"]);
+
+        let mut files = HashMap::new();
+        insert_raw_sidecars(&mut files, &data, SplitNormalization::SeparateVal, None);
+        assert!(files.contains_key("val/raw/b.json"));
+        assert!(split_summary(&data, SplitNormalization::SeparateVal).contains("\nval\t1\t50.0%\n"));
+    }
+
+    #[test]
+    fn restricting_to_boxes_ignores_segments() {
+        let mut data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"segment","class_names":{"0":"cat","1":"dog"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]],"segments":[[1,0.1,0.1,0.3,0.1,0.3,0.3]]}}"#,
+        )
+        .unwrap();
+
+        restrict_annotation_kinds(&mut data, &[AnnotationKind::Bbox]);
+
+        assert_eq!(data.metadata.task, "detect");
+        let files = get_converter("yolo", &ConvertOptions::default())
+            .unwrap()
+            .convert(&data, &HashMap::new());
+        let label = std::str::from_utf8(files["train/labels/a.txt"].as_bytes().unwrap()).unwrap();
+        assert_eq!(label.trim(), "0 0.500000 0.500000 0.200000 0.200000");
+        assert!(data.images[0].get_segment_annotations().is_empty());
+    }
 }
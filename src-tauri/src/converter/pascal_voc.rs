@@ -1,15 +1,103 @@
-use super::{get_class_names, Converter};
+use super::{
+    class_folder_map_json, class_folder_slugs, get_class_names, insert_split_placeholders,
+    Converter, FileData,
+};
+use crate::options::{ConvertOptions, PixelRounding};
 use crate::parser::{image_download_key, ImageEntry, NDJSONData};
 use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
 use quick_xml::Writer;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::Cursor;
 
-pub struct PascalVocConverter;
+pub struct PascalVocConverter {
+    options: ConvertOptions,
+}
+
+/// Pixel `[xmin, ymin, xmax, ymax]` boxes for an image's annotations, with
+/// their class ids, rounded per `rounding` and clamped to the image. OBB and
+/// segmentation boxes enclose the shape's points; empty polygons are skipped.
+pub(super) fn pixel_boxes(
+    img: &ImageEntry,
+    task: &str,
+    rounding: PixelRounding,
+) -> Vec<(i32, [i32; 4])> {
+    let clamp = |corners: [f64; 4]| {
+        let [xmin, ymin, xmax, ymax] = rounding.apply(corners);
+        [
+            (xmin as i32).max(0),
+            (ymin as i32).max(0),
+            (xmax as i32).min(img.width),
+            (ymax as i32).min(img.height),
+        ]
+    };
+    let enclose = |points: &[(f64, f64)]| {
+        let mut bounds = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+        for (x, y) in points {
+            let abs_x = x * img.width as f64;
+            let abs_y = y * img.height as f64;
+            bounds = [
+                bounds[0].min(abs_x),
+                bounds[1].min(abs_y),
+                bounds[2].max(abs_x),
+                bounds[3].max(abs_y),
+            ];
+        }
+        clamp(bounds)
+    };
+
+    match task {
+        "obb" => img
+            .get_obb_annotations()
+            .iter()
+            .map(|obb| (obb.class_id, enclose(&obb.points)))
+            .collect(),
+        "segment" => img
+            .get_segment_annotations()
+            .iter()
+            .filter(|seg| !seg.outer().is_empty())
+            .map(|seg| (seg.class_id, enclose(seg.outer())))
+            .collect(),
+        _ => img
+            .get_bboxes()
+            .iter()
+            .map(|bbox| {
+                let corners = [
+                    (bbox.x - bbox.width / 2.0) * img.width as f64,
+                    (bbox.y - bbox.height / 2.0) * img.height as f64,
+                    (bbox.x + bbox.width / 2.0) * img.width as f64,
+                    (bbox.y + bbox.height / 2.0) * img.height as f64,
+                ];
+                (bbox.class_id, clamp(corners))
+            })
+            .collect(),
+    }
+}
+
+/// Drops characters XML 1.0 can't contain even escaped (control characters
+/// other than tab and newlines, U+FFFE and U+FFFF), so names from the NDJSON
+/// always produce a well-formed document.
+fn xml_safe_text(value: &str) -> Cow<'_, str> {
+    let allowed = |c: char| {
+        matches!(c, '\t' | '\n' | '\r') || !(c.is_control() || c == '\u{FFFE}' || c == '\u{FFFF}')
+    };
+    if value.chars().all(allowed) {
+        Cow::Borrowed(value)
+    } else {
+        Cow::Owned(value.chars().filter(|&c| allowed(c)).collect())
+    }
+}
 
 impl PascalVocConverter {
     pub fn new() -> Self {
-        Self
+        Self {
+            options: ConvertOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ConvertOptions) -> Self {
+        self.options = options;
+        self
     }
 
     fn create_voc_xml(
@@ -38,8 +126,12 @@ impl PascalVocConverter {
         // filename
         Self::write_element(&mut writer, "filename", image_file);
 
-        // path (just the filename)
-        Self::write_element(&mut writer, "path", image_file);
+        // path (the filename unless voc_path says otherwise)
+        let path = self.options.voc_path.for_image(
+            self.options.split_normalization.output_split(&img.split),
+            image_file,
+        );
+        Self::write_element(&mut writer, "path", &path);
 
         // source
         writer
@@ -64,140 +156,33 @@ impl PascalVocConverter {
             if task == "segment" { "1" } else { "0" },
         );
 
-        if task == "obb" {
-            // OBB: derive axis-aligned bounding boxes from oriented corners
-            for obb in img.get_obb_annotations() {
-                writer
-                    .write_event(Event::Start(BytesStart::new("object")))
-                    .ok();
-
-                let class_name = class_names
-                    .get(&obb.class_id)
-                    .cloned()
-                    .unwrap_or_else(|| format!("class_{}", obb.class_id));
-
-                Self::write_element(&mut writer, "name", &class_name);
-                Self::write_element(&mut writer, "pose", "Unspecified");
-                Self::write_element(&mut writer, "truncated", "0");
-                Self::write_element(&mut writer, "difficult", "0");
-
-                let mut min_x = f64::MAX;
-                let mut min_y = f64::MAX;
-                let mut max_x = f64::MIN;
-                let mut max_y = f64::MIN;
-
-                for (x, y) in &obb.points {
-                    let abs_x = x * img.width as f64;
-                    let abs_y = y * img.height as f64;
-                    min_x = min_x.min(abs_x);
-                    min_y = min_y.min(abs_y);
-                    max_x = max_x.max(abs_x);
-                    max_y = max_y.max(abs_y);
-                }
-
-                let xmin = min_x.round() as i32;
-                let ymin = min_y.round() as i32;
-                let xmax = max_x.round() as i32;
-                let ymax = max_y.round() as i32;
-
-                writer
-                    .write_event(Event::Start(BytesStart::new("bndbox")))
-                    .ok();
-                Self::write_element(&mut writer, "xmin", &xmin.max(0).to_string());
-                Self::write_element(&mut writer, "ymin", &ymin.max(0).to_string());
-                Self::write_element(&mut writer, "xmax", &xmax.min(img.width).to_string());
-                Self::write_element(&mut writer, "ymax", &ymax.min(img.height).to_string());
-                writer.write_event(Event::End(BytesEnd::new("bndbox"))).ok();
-
-                writer.write_event(Event::End(BytesEnd::new("object"))).ok();
-            }
-        } else if task == "segment" {
-            // Segmentation: derive bounding boxes from polygon vertices
-            for seg in img.get_segment_annotations() {
-                if seg.points.is_empty() {
-                    continue;
-                }
-
-                writer
-                    .write_event(Event::Start(BytesStart::new("object")))
-                    .ok();
-
-                let class_name = class_names
-                    .get(&seg.class_id)
-                    .cloned()
-                    .unwrap_or_else(|| format!("class_{}", seg.class_id));
-
-                Self::write_element(&mut writer, "name", &class_name);
-                Self::write_element(&mut writer, "pose", "Unspecified");
-                Self::write_element(&mut writer, "truncated", "0");
-                Self::write_element(&mut writer, "difficult", "0");
-
-                // Compute bounding box from polygon vertices
-                let mut min_x = f64::MAX;
-                let mut min_y = f64::MAX;
-                let mut max_x = f64::MIN;
-                let mut max_y = f64::MIN;
-
-                for (x, y) in &seg.points {
-                    let abs_x = x * img.width as f64;
-                    let abs_y = y * img.height as f64;
-                    min_x = min_x.min(abs_x);
-                    min_y = min_y.min(abs_y);
-                    max_x = max_x.max(abs_x);
-                    max_y = max_y.max(abs_y);
-                }
-
-                let xmin = min_x.round() as i32;
-                let ymin = min_y.round() as i32;
-                let xmax = max_x.round() as i32;
-                let ymax = max_y.round() as i32;
-
-                writer
-                    .write_event(Event::Start(BytesStart::new("bndbox")))
-                    .ok();
-                Self::write_element(&mut writer, "xmin", &xmin.max(0).to_string());
-                Self::write_element(&mut writer, "ymin", &ymin.max(0).to_string());
-                Self::write_element(&mut writer, "xmax", &xmax.min(img.width).to_string());
-                Self::write_element(&mut writer, "ymax", &ymax.min(img.height).to_string());
-                writer.write_event(Event::End(BytesEnd::new("bndbox"))).ok();
-
-                writer.write_event(Event::End(BytesEnd::new("object"))).ok();
-            }
-        } else {
-            // Detection (default): use bounding boxes directly
-            for bbox in img.get_bboxes() {
-                writer
-                    .write_event(Event::Start(BytesStart::new("object")))
-                    .ok();
-
-                let class_name = class_names
-                    .get(&bbox.class_id)
-                    .cloned()
-                    .unwrap_or_else(|| format!("class_{}", bbox.class_id));
-
-                Self::write_element(&mut writer, "name", &class_name);
-                Self::write_element(&mut writer, "pose", "Unspecified");
-                Self::write_element(&mut writer, "truncated", "0");
-                Self::write_element(&mut writer, "difficult", "0");
-
-                // Convert normalized coords to absolute Pascal VOC format
-                // VOC uses [xmin, ymin, xmax, ymax] in pixels
-                let xmin = ((bbox.x - bbox.width / 2.0) * img.width as f64).round() as i32;
-                let ymin = ((bbox.y - bbox.height / 2.0) * img.height as f64).round() as i32;
-                let xmax = ((bbox.x + bbox.width / 2.0) * img.width as f64).round() as i32;
-                let ymax = ((bbox.y + bbox.height / 2.0) * img.height as f64).round() as i32;
-
-                writer
-                    .write_event(Event::Start(BytesStart::new("bndbox")))
-                    .ok();
-                Self::write_element(&mut writer, "xmin", &xmin.max(0).to_string());
-                Self::write_element(&mut writer, "ymin", &ymin.max(0).to_string());
-                Self::write_element(&mut writer, "xmax", &xmax.min(img.width).to_string());
-                Self::write_element(&mut writer, "ymax", &ymax.min(img.height).to_string());
-                writer.write_event(Event::End(BytesEnd::new("bndbox"))).ok();
-
-                writer.write_event(Event::End(BytesEnd::new("object"))).ok();
-            }
+        for (class_id, [xmin, ymin, xmax, ymax]) in
+            pixel_boxes(img, task, self.options.pixel_rounding.unwrap_or_default())
+        {
+            writer
+                .write_event(Event::Start(BytesStart::new("object")))
+                .ok();
+
+            let class_name = class_names
+                .get(&class_id)
+                .cloned()
+                .unwrap_or_else(|| format!("class_{}", class_id));
+
+            Self::write_element(&mut writer, "name", &class_name);
+            Self::write_element(&mut writer, "pose", "Unspecified");
+            Self::write_element(&mut writer, "truncated", "0");
+            Self::write_element(&mut writer, "difficult", "0");
+
+            writer
+                .write_event(Event::Start(BytesStart::new("bndbox")))
+                .ok();
+            Self::write_element(&mut writer, "xmin", &xmin.to_string());
+            Self::write_element(&mut writer, "ymin", &ymin.to_string());
+            Self::write_element(&mut writer, "xmax", &xmax.to_string());
+            Self::write_element(&mut writer, "ymax", &ymax.to_string());
+            writer.write_event(Event::End(BytesEnd::new("bndbox"))).ok();
+
+            writer.write_event(Event::End(BytesEnd::new("object"))).ok();
         }
 
         writer
@@ -210,7 +195,9 @@ impl PascalVocConverter {
 
     fn write_element(writer: &mut Writer<Cursor<Vec<u8>>>, name: &str, value: &str) {
         writer.write_event(Event::Start(BytesStart::new(name))).ok();
-        writer.write_event(Event::Text(BytesText::new(value))).ok();
+        writer
+            .write_event(Event::Text(BytesText::new(&xml_safe_text(value))))
+            .ok();
         writer.write_event(Event::End(BytesEnd::new(name))).ok();
     }
 }
@@ -219,20 +206,33 @@ impl Converter for PascalVocConverter {
     fn convert(
         &self,
         data: &NDJSONData,
-        downloaded_images: &HashMap<String, Vec<u8>>,
-    ) -> HashMap<String, Vec<u8>> {
-        let mut files: HashMap<String, Vec<u8>> = HashMap::new();
+        downloaded_images: &HashMap<String, FileData>,
+    ) -> HashMap<String, FileData> {
+        let mut files: HashMap<String, FileData> = HashMap::new();
         let class_names = get_class_names(data);
         let task = &data.metadata.task;
 
-        let splits = [
-            ("train", data.train_images()),
-            ("valid", data.valid_images()),
-            ("test", data.test_images()),
-        ];
+        let class_slugs = if self.options.slugify_class_names && task == "classify" {
+            let slugs = class_folder_slugs(
+                &class_names,
+                data.images.iter().flat_map(|img| img.get_classifications()),
+            );
+            files.insert(
+                "class_folders.json".to_string(),
+                class_folder_map_json(&class_names, &slugs).into(),
+            );
+            slugs
+        } else {
+            HashMap::new()
+        };
+
+        let splits = data.images_by_split(self.options.split_normalization);
 
         for (split, images) in &splits {
             if images.is_empty() {
+                if self.options.empty_split_placeholders {
+                    insert_split_placeholders(&mut files, &[split.to_string()]);
+                }
                 continue;
             }
 
@@ -242,10 +242,12 @@ impl Converter for PascalVocConverter {
                     let image_file = img.effective_file_name();
                     let classifications = img.get_classifications();
                     if let Some(&class_id) = classifications.first() {
-                        let class_name = class_names
-                            .get(&class_id)
-                            .cloned()
-                            .unwrap_or_else(|| format!("class_{}", class_id));
+                        let class_name = if self.options.slugify_class_names {
+                            class_slugs.get(&class_id).cloned()
+                        } else {
+                            class_names.get(&class_id).cloned()
+                        }
+                        .unwrap_or_else(|| format!("class_{}", class_id));
 
                         if let Some(image_data) =
                             downloaded_images.get(&image_download_key(split, image_file))
@@ -268,7 +270,7 @@ impl Converter for PascalVocConverter {
                         .unwrap_or(image_file);
                     files.insert(
                         format!("{}/{}.xml", split, xml_filename),
-                        xml_content.into_bytes(),
+                        xml_content.into_bytes().into(),
                     );
 
                     if let Some(image_data) =
@@ -287,6 +289,7 @@ impl Converter for PascalVocConverter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::options::VocPath;
     use crate::parser::{image_download_key, DatasetMetadata};
     use serde_json::json;
 
@@ -302,6 +305,10 @@ mod tests {
                 url: String::new(),
                 class_names: HashMap::from([("0".to_string(), "animal".to_string())]),
                 kpt_shape: None,
+                flip_idx: None,
+                date_created: None,
+                year: None,
+                licenses: Vec::new(),
                 version: "1".to_string(),
             },
             images: vec![
@@ -310,6 +317,10 @@ mod tests {
                     file: "img1.jpg".to_string(),
                     output_file: None,
                     url: String::new(),
+                    mirrors: Vec::new(),
+                    license: None,
+                    skip: false,
+                    captions: Vec::new(),
                     width: 640,
                     height: 480,
                     split: "train".to_string(),
@@ -322,6 +333,10 @@ mod tests {
                     file: "img1.jpg".to_string(),
                     output_file: None,
                     url: String::new(),
+                    mirrors: Vec::new(),
+                    license: None,
+                    skip: false,
+                    captions: Vec::new(),
                     width: 640,
                     height: 480,
                     split: "val".to_string(),
@@ -334,13 +349,13 @@ mod tests {
 
         let converter = PascalVocConverter::new();
         let mut downloaded_images = HashMap::new();
-        downloaded_images.insert(image_download_key("train", "img1.jpg"), vec![1]);
-        downloaded_images.insert(image_download_key("valid", "img1.jpg"), vec![2]);
+        downloaded_images.insert(image_download_key("train", "img1.jpg"), vec![1].into());
+        downloaded_images.insert(image_download_key("valid", "img1.jpg"), vec![2].into());
 
         let files = converter.convert(&data, &downloaded_images);
 
-        assert_eq!(files.get("train/img1.jpg"), Some(&vec![1]));
-        assert_eq!(files.get("valid/img1.jpg"), Some(&vec![2]));
+        assert_eq!(files.get("train/img1.jpg"), Some(&vec![1].into()));
+        assert_eq!(files.get("valid/img1.jpg"), Some(&vec![2].into()));
     }
 
     #[test]
@@ -355,6 +370,10 @@ mod tests {
                 url: String::new(),
                 class_names: HashMap::from([("0".to_string(), "heic".to_string())]),
                 kpt_shape: None,
+                flip_idx: None,
+                date_created: None,
+                year: None,
+                licenses: Vec::new(),
                 version: "1".to_string(),
             },
             images: vec![
@@ -363,6 +382,10 @@ mod tests {
                     file: "Frame_98.jpg".to_string(),
                     output_file: None,
                     url: "https://cdn.example/a.jpg".to_string(),
+                    mirrors: Vec::new(),
+                    license: None,
+                    skip: false,
+                    captions: Vec::new(),
                     width: 640,
                     height: 480,
                     split: "train".to_string(),
@@ -375,6 +398,10 @@ mod tests {
                     file: "Frame_98.jpg".to_string(),
                     output_file: Some("Frame_98__abcd1234.jpg".to_string()),
                     url: "https://cdn.example/b.jpg".to_string(),
+                    mirrors: Vec::new(),
+                    license: None,
+                    skip: false,
+                    captions: Vec::new(),
                     width: 640,
                     height: 480,
                     split: "train".to_string(),
@@ -387,17 +414,102 @@ mod tests {
 
         let converter = PascalVocConverter::new();
         let mut downloaded_images = HashMap::new();
-        downloaded_images.insert(image_download_key("train", "Frame_98.jpg"), vec![1]);
+        downloaded_images.insert(image_download_key("train", "Frame_98.jpg"), vec![1].into());
         downloaded_images.insert(
             image_download_key("train", "Frame_98__abcd1234.jpg"),
-            vec![2],
+            vec![2].into(),
         );
 
         let files = converter.convert(&data, &downloaded_images);
 
-        assert_eq!(files.get("train/Frame_98.jpg"), Some(&vec![1]));
-        assert_eq!(files.get("train/Frame_98__abcd1234.jpg"), Some(&vec![2]));
+        assert_eq!(files.get("train/Frame_98.jpg"), Some(&vec![1].into()));
+        assert_eq!(
+            files.get("train/Frame_98__abcd1234.jpg"),
+            Some(&vec![2].into())
+        );
         assert!(files.contains_key("train/Frame_98.xml"));
         assert!(files.contains_key("train/Frame_98__abcd1234.xml"));
     }
+
+    #[test]
+    fn pixel_rounding_modes_set_voc_box_corners() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":100,"height":100,"split":"train","annotations":{"boxes":[[0,0.503,0.5,0.2,0.217]]}}"#,
+        )
+        .unwrap();
+
+        for (rounding, expected) in [
+            (None, [40, 39, 60, 61]),
+            (Some(PixelRounding::Round), [40, 39, 60, 61]),
+            (Some(PixelRounding::FloorCeil), [40, 39, 61, 61]),
+            (Some(PixelRounding::Truncate), [40, 39, 60, 60]),
+        ] {
+            let files = PascalVocConverter::new()
+                .with_options(ConvertOptions {
+                    pixel_rounding: rounding,
+                    ..Default::default()
+                })
+                .convert(&data, &HashMap::new());
+            let xml: String = String::from_utf8(files["train/a.xml"].read().unwrap().into_owned())
+                .unwrap()
+                .split_whitespace()
+                .collect();
+            let [xmin, ymin, xmax, ymax] = expected;
+            let bndbox = format!(
+                "<xmin>{}</xmin><ymin>{}</ymin><xmax>{}</xmax><ymax>{}</ymax>",
+                xmin, ymin, xmax, ymax
+            );
+            assert!(xml.contains(&bndbox), "{:?}: {}", rounding, xml);
+        }
+    }
+
+    #[test]
+    fn control_characters_in_class_names_are_dropped() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"ca\u0007t\u0000"}}
+{"type":"image","file":"a.jpg","width":100,"height":100,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+
+        let files = PascalVocConverter::new().convert(&data, &HashMap::new());
+        let xml = String::from_utf8(files["train/a.xml"].read().unwrap().into_owned()).unwrap();
+
+        assert!(xml.contains("<name>cat</name>"), "{}", xml);
+        assert!(!xml.chars().any(|c| c.is_control() && !c.is_whitespace()));
+        let mut reader = quick_xml::Reader::from_str(&xml);
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Eof => break,
+                _ => continue,
+            }
+        }
+    }
+
+    #[test]
+    fn voc_path_option_sets_the_path_element() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":100,"height":100,"split":"val","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+
+        for (voc_path, expected) in [
+            (VocPath::FileName, "<path>a.jpg</path>"),
+            (VocPath::Split, "<path>valid/a.jpg</path>"),
+            (
+                VocPath::Root("/data/voc/".to_string()),
+                "<path>/data/voc/valid/a.jpg</path>",
+            ),
+        ] {
+            let files = PascalVocConverter::new()
+                .with_options(ConvertOptions {
+                    voc_path,
+                    ..Default::default()
+                })
+                .convert(&data, &HashMap::new());
+            let xml = String::from_utf8(files["valid/a.xml"].read().unwrap().into_owned()).unwrap();
+            assert!(xml.contains(expected), "{}", xml);
+        }
+    }
 }
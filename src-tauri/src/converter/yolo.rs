@@ -1,15 +1,43 @@
-use super::{get_class_list, get_class_names, Converter};
+use super::pascal_voc::pixel_boxes;
+use super::{
+    annotation_source_ids, class_folder_map_json, class_folder_slugs, get_class_list,
+    get_class_names, insert_split_placeholders, pose_keypoint_count, pose_keypoint_dims,
+    validate_flip_idx, Converter, FileData,
+};
+use crate::options::{ConvertOptions, PixelRounding};
 use crate::parser::{image_download_key, ImageEntry, NDJSONData};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 pub struct YoloConverter {
     darknet: bool,
+    options: ConvertOptions,
 }
 
 fn quote_yaml_scalar(value: &str) -> String {
     serde_json::to_string(value).unwrap_or_else(|_| "\"\"".to_string())
 }
 
+/// Lists `id=name` for each distinct class id used in a YOLO label, in id
+/// order. YOLO label files can't hold comments, so this is written as a
+/// companion file.
+fn create_label_legend(label_content: &str, class_names: &HashMap<i32, String>) -> String {
+    let class_ids: BTreeSet<i32> = label_content
+        .lines()
+        .filter_map(|line| line.split_whitespace().next()?.parse().ok())
+        .collect();
+    class_ids
+        .iter()
+        .map(|id| {
+            let name = class_names
+                .get(id)
+                .cloned()
+                .unwrap_or_else(|| format!("class_{}", id));
+            format!("{}={}", id, name)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 fn sanitize_path_segment(segment: &str) -> String {
     let sanitized = segment
         .chars()
@@ -28,13 +56,112 @@ fn sanitize_path_segment(segment: &str) -> String {
     }
 }
 
+/// Pixel `class xmin ymin xmax ymax` lines for an image, using the same
+/// boxes the Pascal VOC export writes, for spot-checking YOLO labels.
+fn create_absolute_label(img: &ImageEntry, task: &str, rounding: PixelRounding) -> String {
+    pixel_boxes(img, task, rounding)
+        .iter()
+        .map(|(class_id, [xmin, ymin, xmax, ymax])| {
+            format!("{} {} {} {} {}\n", class_id, xmin, ymin, xmax, ymax)
+        })
+        .collect()
+}
+
+/// Flattens a polygon with holes into the single ring a YOLO label holds.
+/// Each hole is walked from the outer ring's last point and back again, a
+/// zero-width seam that leaves the hole empty under an even-odd fill.
+fn bridge_rings(rings: &[Vec<(f64, f64)>]) -> Vec<(f64, f64)> {
+    let Some((outer, holes)) = rings.split_first() else {
+        return Vec::new();
+    };
+    let mut points = outer.clone();
+    let Some(&anchor) = outer.last() else {
+        return points;
+    };
+    for hole in holes.iter().filter(|hole| !hole.is_empty()) {
+        points.extend(hole);
+        points.push(hole[0]);
+        points.push(anchor);
+    }
+    points
+}
+
+/// How far outside `[0, 1]` a source coordinate may stray, e.g. from float
+/// round-off, and still be clamped into range when written.
+const CLAMP_TOLERANCE: f64 = 1e-4;
+
+/// A normalized coordinate as written to a label. Values within
+/// `CLAMP_TOLERANCE` of `[0, 1]` are clamped into range; anything further out,
+/// or non-finite, is written unchanged for `check_label_ranges` to reject.
+fn label_coord(value: f64) -> String {
+    if (-CLAMP_TOLERANCE..=1.0 + CLAMP_TOLERANCE).contains(&value) {
+        format!("{:.6}", value.clamp(0.0, 1.0))
+    } else {
+        format!("{:.6}", value)
+    }
+}
+
+/// Self-check over generated YOLO labels: every normalized coordinate must be
+/// finite and within `[0, 1]`. Pose visibility flags are exempt. A violation
+/// means a label would train on garbage, so it is reported as an error naming
+/// the file and line.
+pub fn check_label_ranges(
+    files: &HashMap<String, FileData>,
+    data: &NDJSONData,
+) -> Result<(), String> {
+    let kpt_dims = pose_keypoint_dims(data);
+    let is_pose = data.metadata.task == "pose";
+    let labels = files.iter().filter(|(path, _)| {
+        path.contains('/')
+            && path.ends_with(".txt")
+            && !path.ends_with(".legend.txt")
+            && !path.ends_with(".abs.txt")
+    });
+
+    for (path, file) in labels {
+        let bytes = file
+            .read()
+            .map_err(|e| format!("Failed to read label '{}': {}", path, e))?;
+        for (line_idx, line) in String::from_utf8_lossy(&bytes).lines().enumerate() {
+            for (idx, token) in line.split_whitespace().enumerate().skip(1) {
+                if is_pose && kpt_dims == 3 && idx > 4 && (idx - 5) % 3 == 2 {
+                    continue;
+                }
+                let in_range = token
+                    .parse::<f64>()
+                    .is_ok_and(|value| value.is_finite() && (0.0..=1.0).contains(&value));
+                if !in_range {
+                    return Err(format!(
+                        "YOLO label '{}' line {} has coordinate '{}' outside [0, 1]",
+                        path,
+                        line_idx + 1,
+                        token
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 impl YoloConverter {
     pub fn new() -> Self {
-        Self { darknet: false }
+        Self {
+            darknet: false,
+            options: ConvertOptions::default(),
+        }
     }
 
     pub fn new_darknet() -> Self {
-        Self { darknet: true }
+        Self {
+            darknet: true,
+            options: ConvertOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ConvertOptions) -> Self {
+        self.options = options;
+        self
     }
 
     fn create_data_yaml(&self, data: &NDJSONData) -> String {
@@ -45,10 +172,58 @@ impl YoloConverter {
         yaml.push_str("# Generated by YOLO NDJSON Converter\n");
         yaml.push_str(&format!("# Version: {}\n", env!("CARGO_PKG_VERSION")));
         yaml.push_str("# https://github.com/amanharshx/YOLO-Ndjson-Zip\n\n");
-        yaml.push_str("path: .\n");
-        yaml.push_str("train: train/images\n");
-        yaml.push_str("val: valid/images\n");
-        yaml.push_str("test: test/images\n");
+        // Ultralytics reads `task` to pick the model head; anything the
+        // converter treats as detection is written as `detect`.
+        let yaml_task = match task.as_str() {
+            "segment" | "pose" | "obb" | "classify" => task.as_str(),
+            _ => "detect",
+        };
+        yaml.push_str(&format!("task: {}\n", yaml_task));
+        // A named path lets Ultralytics resolve the dataset under its datasets
+        // directory, which is what `download:` unpacks into.
+        match self.options.yaml_dataset_name.as_deref() {
+            Some(name) if !name.trim().is_empty() => {
+                yaml.push_str(&format!("path: {}\n", quote_yaml_scalar(name.trim())));
+            }
+            _ => yaml.push_str("path: .\n"),
+        }
+        // Only splits the dataset has are listed, so a train-only export
+        // doesn't point Ultralytics at missing directories. With no images at
+        // all there is nothing to go on and every split is listed. An export
+        // keeping `val/` apart from `valid/` lists both under `val`.
+        let splits = self.options.split_normalization;
+        let present: BTreeSet<&str> = data
+            .images
+            .iter()
+            .map(|img| splits.output_split(&img.split))
+            .collect();
+        let keys: [(&str, &[&str]); 3] = [
+            ("train", &["train"]),
+            ("val", &["val", "valid"]),
+            ("test", &["test"]),
+        ];
+        for (key, dirs) in keys {
+            let paths: Vec<String> = dirs
+                .iter()
+                .filter(|dir| {
+                    if present.is_empty() {
+                        **dir != "val"
+                    } else {
+                        present.contains(*dir)
+                    }
+                })
+                .map(|dir| match yaml_task {
+                    // Classify splits hold class folders directly, with no images/ level.
+                    "classify" => dir.to_string(),
+                    _ => format!("{}/images", dir),
+                })
+                .collect();
+            match paths.as_slice() {
+                [] => {}
+                [path] => yaml.push_str(&format!("{}: {}\n", key, path)),
+                _ => yaml.push_str(&format!("{}: [{}]\n", key, paths.join(", "))),
+            }
+        }
         yaml.push_str(&format!("nc: {}\n", class_names.len()));
         yaml.push_str("names:\n");
 
@@ -57,23 +232,34 @@ impl YoloConverter {
         }
 
         if task == "pose" {
-            let meta_kpts = data
-                .metadata
-                .kpt_shape
-                .as_ref()
-                .and_then(|s| s.first().copied())
-                .unwrap_or(0) as usize;
-            let data_kpts = data
-                .images
-                .iter()
-                .flat_map(|img| img.get_pose_annotations())
-                .map(|p| p.keypoints.len())
-                .max()
-                .unwrap_or(0);
-            let num_kpts = meta_kpts.max(data_kpts);
+            let num_kpts = pose_keypoint_count(data);
             if num_kpts > 0 {
-                // Always dimension 3 (x, y, visibility)
-                yaml.push_str(&format!("kpt_shape: [{}, 3]\n", num_kpts));
+                yaml.push_str(&format!(
+                    "kpt_shape: [{}, {}]\n",
+                    num_kpts,
+                    pose_keypoint_dims(data)
+                ));
+            }
+            if let Some(flip_idx) = &data.metadata.flip_idx {
+                // Mismatched lists are rejected before conversion; skip them
+                // here too rather than write a config Ultralytics refuses.
+                if validate_flip_idx(data).is_ok() {
+                    let indices = flip_idx
+                        .iter()
+                        .map(|idx| idx.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    yaml.push_str(&format!("flip_idx: [{}]\n", indices));
+                }
+            }
+        }
+
+        if let Some(download) = self.options.yaml_download.as_deref() {
+            if !download.trim().is_empty() {
+                yaml.push_str(&format!(
+                    "download: {}\n",
+                    quote_yaml_scalar(download.trim())
+                ));
             }
         }
 
@@ -85,37 +271,45 @@ impl YoloConverter {
             .iter()
             .map(|bbox| {
                 format!(
-                    "{} {:.6} {:.6} {:.6} {:.6}",
-                    bbox.class_id, bbox.x, bbox.y, bbox.width, bbox.height
+                    "{} {} {} {} {}",
+                    bbox.class_id,
+                    label_coord(bbox.x),
+                    label_coord(bbox.y),
+                    label_coord(bbox.width),
+                    label_coord(bbox.height)
                 )
             })
             .collect::<Vec<_>>()
             .join("\n")
     }
 
-    fn create_pose_label(&self, img: &ImageEntry, num_kpts: usize) -> String {
+    fn create_pose_label(&self, img: &ImageEntry, num_kpts: usize, kpt_dims: usize) -> String {
         img.get_pose_annotations()
             .iter()
             .map(|pose| {
                 let mut parts = vec![
                     pose.class_id.to_string(),
-                    format!("{:.6}", pose.bbox_x),
-                    format!("{:.6}", pose.bbox_y),
-                    format!("{:.6}", pose.bbox_w),
-                    format!("{:.6}", pose.bbox_h),
+                    label_coord(pose.bbox_x),
+                    label_coord(pose.bbox_y),
+                    label_coord(pose.bbox_w),
+                    label_coord(pose.bbox_h),
                 ];
 
                 for (kp_x, kp_y, kp_v) in &pose.keypoints {
-                    parts.push(format!("{:.6}", kp_x));
-                    parts.push(format!("{:.6}", kp_y));
-                    parts.push(format!("{}", kp_v.round() as i32));
+                    parts.push(label_coord(*kp_x));
+                    parts.push(label_coord(*kp_y));
+                    if kpt_dims == 3 {
+                        parts.push(format!("{}", kp_v.round() as i32));
+                    }
                 }
 
                 // Pad missing keypoints with 0 0 0 (not labeled)
                 for _ in pose.keypoints.len()..num_kpts {
                     parts.push("0.000000".to_string());
                     parts.push("0.000000".to_string());
-                    parts.push("0".to_string());
+                    if kpt_dims == 3 {
+                        parts.push("0".to_string());
+                    }
                 }
 
                 parts.join(" ")
@@ -129,9 +323,15 @@ impl YoloConverter {
             .iter()
             .map(|seg| {
                 let mut parts = vec![seg.class_id.to_string()];
-                for (x, y) in &seg.points {
-                    parts.push(format!("{:.6}", x));
-                    parts.push(format!("{:.6}", y));
+                let points = bridge_rings(&seg.rings);
+                // Polygons that already end on their first point aren't
+                // closed twice.
+                let closing = points
+                    .first()
+                    .filter(|first| self.options.close_polygons && points.last() != Some(first));
+                for (x, y) in points.iter().chain(closing) {
+                    parts.push(label_coord(*x));
+                    parts.push(label_coord(*y));
                 }
                 parts.join(" ")
             })
@@ -139,14 +339,40 @@ impl YoloConverter {
             .join("\n")
     }
 
+    /// Detection labels derived from each polygon's bounding rectangle, so a
+    /// segment export can also train a detector.
+    fn create_segment_bbox_label(&self, img: &ImageEntry) -> String {
+        img.get_segment_annotations()
+            .iter()
+            .filter(|seg| !seg.outer().is_empty())
+            .map(|seg| {
+                let (min_x, min_y, max_x, max_y) = seg.outer().iter().fold(
+                    (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+                    |(min_x, min_y, max_x, max_y), &(x, y)| {
+                        (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+                    },
+                );
+                format!(
+                    "{} {} {} {} {}",
+                    seg.class_id,
+                    label_coord((min_x + max_x) / 2.0),
+                    label_coord((min_y + max_y) / 2.0),
+                    label_coord(max_x - min_x),
+                    label_coord(max_y - min_y)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
     fn create_obb_label(&self, img: &ImageEntry) -> String {
         img.get_obb_annotations()
             .iter()
             .map(|obb| {
                 let mut parts = vec![obb.class_id.to_string()];
                 for (x, y) in &obb.points {
-                    parts.push(format!("{:.6}", x));
-                    parts.push(format!("{:.6}", y));
+                    parts.push(label_coord(*x));
+                    parts.push(label_coord(*y));
                 }
                 parts.join(" ")
             })
@@ -155,13 +381,33 @@ impl YoloConverter {
     }
 }
 
+impl YoloConverter {
+    /// Line for an image in a Darknet `{split}.txt` list: relative to the
+    /// archive root, or under `image_root` when one is configured.
+    fn darknet_list_entry(&self, split: &str, image_file: &str) -> String {
+        let relative = format!("{}/{}", split, image_file);
+        match self
+            .options
+            .image_root
+            .as_deref()
+            .filter(|root| !root.is_empty())
+        {
+            Some(root) if root.ends_with('/') || root.ends_with('\\') => {
+                format!("{}{}", root, relative)
+            }
+            Some(root) => format!("{}/{}", root, relative),
+            None => relative,
+        }
+    }
+}
+
 impl Converter for YoloConverter {
     fn convert(
         &self,
         data: &NDJSONData,
-        downloaded_images: &HashMap<String, Vec<u8>>,
-    ) -> HashMap<String, Vec<u8>> {
-        let mut files: HashMap<String, Vec<u8>> = HashMap::new();
+        downloaded_images: &HashMap<String, FileData>,
+    ) -> HashMap<String, FileData> {
+        let mut files: HashMap<String, FileData> = HashMap::new();
         let task = &data.metadata.task;
 
         if self.darknet {
@@ -169,66 +415,80 @@ impl Converter for YoloConverter {
             let class_list = get_class_list(data);
             files.insert(
                 "_darknet.labels".to_string(),
-                class_list.join("\n").into_bytes(),
+                class_list.join("\n").into_bytes().into(),
             );
         } else {
             // Standard YOLO mode
             files.insert(
                 "data.yaml".to_string(),
-                self.create_data_yaml(data).into_bytes(),
+                self.create_data_yaml(data).into_bytes().into(),
             );
             let class_list = get_class_list(data);
             files.insert(
                 "classes.txt".to_string(),
-                class_list.join("\n").into_bytes(),
+                class_list.join("\n").into_bytes().into(),
             );
         }
 
         // For pose: compute max keypoint count (max of metadata and actual data)
         let num_kpts = if task == "pose" {
-            let meta_kpts = data
-                .metadata
-                .kpt_shape
-                .as_ref()
-                .and_then(|s| s.first().copied())
-                .unwrap_or(0) as usize;
-            let data_kpts = data
-                .images
-                .iter()
-                .flat_map(|img| img.get_pose_annotations())
-                .map(|p| p.keypoints.len())
-                .max()
-                .unwrap_or(0);
-            meta_kpts.max(data_kpts)
+            pose_keypoint_count(data)
         } else {
             0
         };
+        let kpt_dims = pose_keypoint_dims(data);
+
+        let class_names = get_class_names(data);
+        let class_slugs = if self.options.slugify_class_names && task == "classify" {
+            let slugs = class_folder_slugs(
+                &class_names,
+                data.images.iter().flat_map(|img| img.get_classifications()),
+            );
+            files.insert(
+                "class_folders.json".to_string(),
+                class_folder_map_json(&class_names, &slugs).into(),
+            );
+            slugs
+        } else {
+            HashMap::new()
+        };
 
         // Process images by split
-        let splits = [
-            ("train", data.train_images()),
-            ("valid", data.valid_images()),
-            ("test", data.test_images()),
-        ];
+        let splits = data.images_by_split(self.options.split_normalization);
 
         for (split, images) in splits {
+            if images.is_empty() && self.options.empty_split_placeholders {
+                let dirs = if task == "classify" || self.darknet {
+                    vec![split.to_string()]
+                } else {
+                    vec![format!("{}/images", split), format!("{}/labels", split)]
+                };
+                insert_split_placeholders(&mut files, &dirs);
+            }
+            let mut darknet_list = Vec::new();
             for img in images {
                 let image_file = img.effective_file_name();
                 // Create label file
                 let label_content = match task.as_str() {
-                    "pose" => self.create_pose_label(img, num_kpts),
+                    "pose" => self.create_pose_label(img, num_kpts, kpt_dims),
                     "segment" => self.create_segment_label(img),
                     "obb" => self.create_obb_label(img),
                     "classify" => {
                         // For classification, we use folder structure
                         let classifications = img.get_classifications();
                         if let Some(&class_id) = classifications.first() {
-                            let class_names = get_class_names(data);
-                            let class_name = class_names
-                                .get(&class_id)
-                                .cloned()
-                                .unwrap_or_else(|| format!("class_{}", class_id));
-                            let class_dir = sanitize_path_segment(&class_name);
+                            let class_dir = if self.options.slugify_class_names {
+                                class_slugs
+                                    .get(&class_id)
+                                    .cloned()
+                                    .unwrap_or_else(|| format!("class_{}", class_id))
+                            } else {
+                                let class_name = class_names
+                                    .get(&class_id)
+                                    .cloned()
+                                    .unwrap_or_else(|| format!("class_{}", class_id));
+                                sanitize_path_segment(&class_name)
+                            };
 
                             if let Some(image_data) =
                                 downloaded_images.get(&image_download_key(split, image_file))
@@ -250,12 +510,60 @@ impl Converter for YoloConverter {
                     .map(|(name, _)| name)
                     .unwrap_or(image_file);
 
+                let label_dir = if self.darknet {
+                    split.to_string()
+                } else {
+                    format!("{}/labels", split)
+                };
+                if task == "segment" && self.options.segment_bbox_labels {
+                    files.insert(
+                        format!("{}/labels_det/{}.txt", split, label_filename),
+                        self.create_segment_bbox_label(img).into_bytes().into(),
+                    );
+                }
+                if self.options.absolute_label_sidecars {
+                    files.insert(
+                        format!("{}/{}.abs.txt", label_dir, label_filename),
+                        create_absolute_label(
+                            img,
+                            task,
+                            self.options.pixel_rounding.unwrap_or_default(),
+                        )
+                        .into_bytes()
+                        .into(),
+                    );
+                }
+                // Label lines have no id field, so source annotation ids go in
+                // a companion mapping line index to id.
+                let source_ids: BTreeMap<usize, i64> = annotation_source_ids(img, task)
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(line, id)| Some((line, id?)))
+                    .collect();
+                if !source_ids.is_empty() {
+                    files.insert(
+                        format!("{}/{}.ids.json", label_dir, label_filename),
+                        serde_json::to_vec_pretty(&source_ids)
+                            .unwrap_or_default()
+                            .into(),
+                    );
+                }
+                if self.options.label_legends {
+                    files.insert(
+                        format!("{}/{}.legend.txt", label_dir, label_filename),
+                        create_label_legend(&label_content, &class_names)
+                            .into_bytes()
+                            .into(),
+                    );
+                }
+
                 if self.darknet {
                     // Darknet: flat structure, images + labels side by side in {split}/
                     files.insert(
                         format!("{}/{}.txt", split, label_filename),
-                        label_content.into_bytes(),
+                        label_content.into_bytes().into(),
                     );
+                    darknet_list.push(self.darknet_list_entry(split, image_file));
                     if let Some(image_data) =
                         downloaded_images.get(&image_download_key(split, image_file))
                     {
@@ -265,7 +573,7 @@ impl Converter for YoloConverter {
                     // Standard YOLO: {split}/labels/ and {split}/images/
                     files.insert(
                         format!("{}/labels/{}.txt", split, label_filename),
-                        label_content.into_bytes(),
+                        label_content.into_bytes().into(),
                     );
                     if let Some(image_data) =
                         downloaded_images.get(&image_download_key(split, image_file))
@@ -277,6 +585,12 @@ impl Converter for YoloConverter {
                     }
                 }
             }
+            if !darknet_list.is_empty() {
+                files.insert(
+                    format!("{}.txt", split),
+                    (darknet_list.join("\n") + "\n").into_bytes().into(),
+                );
+            }
         }
 
         files
@@ -305,6 +619,10 @@ mod tests {
                 url: String::new(),
                 class_names,
                 kpt_shape,
+                flip_idx: None,
+                date_created: None,
+                year: None,
+                licenses: Vec::new(),
                 version: "1".to_string(),
             },
             images,
@@ -323,6 +641,281 @@ mod tests {
         assert!(yaml.contains(r#"0: "cat: {evil: true}\n# injected""#));
     }
 
+    #[test]
+    fn create_data_yaml_writes_task_line_for_each_task() {
+        let converter = YoloConverter::new();
+        for (task, expected) in [
+            ("detect", "task: detect\n"),
+            ("segment", "task: segment\n"),
+            ("pose", "task: pose\n"),
+            ("obb", "task: obb\n"),
+            ("classify", "task: classify\n"),
+            ("unknown", "task: detect\n"),
+        ] {
+            let data = make_data(task, HashMap::new(), None, vec![]);
+            let yaml = converter.create_data_yaml(&data);
+            assert!(yaml.contains(expected), "{}: {}", task, yaml);
+        }
+    }
+
+    #[test]
+    fn create_data_yaml_points_classify_at_class_folder_roots() {
+        let converter = YoloConverter::new();
+
+        let classify =
+            converter.create_data_yaml(&make_data("classify", HashMap::new(), None, vec![]));
+        assert!(classify.contains("train: train\nval: valid\ntest: test\n"));
+        assert!(!classify.contains("/images"));
+
+        let segment =
+            converter.create_data_yaml(&make_data("segment", HashMap::new(), None, vec![]));
+        assert!(segment.contains("train: train/images\nval: valid/images\ntest: test/images\n"));
+    }
+
+    #[test]
+    fn create_data_yaml_omits_absent_splits() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+
+        let yaml = YoloConverter::new().create_data_yaml(&data);
+
+        assert!(yaml.contains("train: train/images\n"));
+        assert!(!yaml.contains("val:"));
+        assert!(!yaml.contains("test:"));
+    }
+
+    #[test]
+    fn create_data_yaml_writes_pose_flip_idx() {
+        let mut data = make_data("pose", HashMap::new(), Some(vec![3, 3]), vec![]);
+        data.metadata.flip_idx = Some(vec![0, 2, 1]);
+
+        let yaml = YoloConverter::new().create_data_yaml(&data);
+
+        assert!(yaml.contains("kpt_shape: [3, 3]\nflip_idx: [0, 2, 1]\n"));
+        assert!(validate_flip_idx(&data).is_ok());
+    }
+
+    #[test]
+    fn flip_idx_length_must_match_keypoint_count() {
+        let mut data = make_data("pose", HashMap::new(), Some(vec![3, 3]), vec![]);
+        data.metadata.flip_idx = Some(vec![1, 0]);
+
+        let err = validate_flip_idx(&data).unwrap_err();
+
+        assert_eq!(
+            err,
+            "flip_idx has 2 entries but the dataset has 3 keypoints"
+        );
+        assert!(!YoloConverter::new()
+            .create_data_yaml(&data)
+            .contains("flip_idx"));
+    }
+
+    #[test]
+    fn create_data_yaml_is_minimal_by_default() {
+        let yaml = YoloConverter::new().create_data_yaml(&make_data(
+            "detect",
+            HashMap::new(),
+            None,
+            vec![],
+        ));
+
+        assert!(yaml.contains("path: .\n"));
+        assert!(!yaml.contains("download:"));
+    }
+
+    #[test]
+    fn create_data_yaml_writes_dataset_name_and_download() {
+        let converter = YoloConverter::new().with_options(ConvertOptions {
+            yaml_dataset_name: Some("my-dataset".to_string()),
+            yaml_download: Some("https://example.com/my-dataset.zip".to_string()),
+            ..Default::default()
+        });
+
+        let yaml = converter.create_data_yaml(&make_data("detect", HashMap::new(), None, vec![]));
+
+        assert!(yaml.contains("path: \"my-dataset\"\n"));
+        assert!(yaml.ends_with("download: \"https://example.com/my-dataset.zip\"\n"));
+    }
+
+    #[test]
+    fn label_legend_lists_exactly_the_classes_present() {
+        let class_names = HashMap::from([
+            ("0".to_string(), "cat".to_string()),
+            ("1".to_string(), "dog".to_string()),
+            ("2".to_string(), "bird".to_string()),
+        ]);
+        let image = ImageEntry {
+            r#type: "image".to_string(),
+            file: "img1.jpg".to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: "train".to_string(),
+            annotations: Some(json!({
+                "bboxes": [[2, 0.5, 0.5, 0.2, 0.2], [0, 0.3, 0.3, 0.1, 0.1], [2, 0.1, 0.1, 0.1, 0.1]]
+            })),
+        };
+        let data = make_data("detect", class_names, None, vec![image]);
+        let options = ConvertOptions {
+            label_legends: true,
+            ..Default::default()
+        };
+
+        let files = YoloConverter::new()
+            .with_options(options.clone())
+            .convert(&data, &HashMap::new());
+        let legend = files["train/labels/img1.legend.txt"].as_bytes().unwrap();
+        assert_eq!(std::str::from_utf8(legend).unwrap(), "0=cat\n2=bird");
+
+        let darknet = YoloConverter::new_darknet()
+            .with_options(options)
+            .convert(&data, &HashMap::new());
+        assert!(darknet.contains_key("train/img1.legend.txt"));
+
+        let plain = YoloConverter::new().convert(&data, &HashMap::new());
+        assert!(!plain.keys().any(|path| path.ends_with(".legend.txt")));
+    }
+
+    #[test]
+    fn darknet_list_files_are_relative_by_default_and_rooted_on_request() {
+        let image = |file: &str, split: &str| ImageEntry {
+            r#type: "image".to_string(),
+            file: file.to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: split.to_string(),
+            annotations: Some(json!({"bboxes": [[0, 0.5, 0.5, 0.2, 0.2]]})),
+        };
+        let class_names = HashMap::from([("0".to_string(), "cat".to_string())]);
+        let data = make_data(
+            "detect",
+            class_names,
+            None,
+            vec![
+                image("a.jpg", "train"),
+                image("b.png", "train"),
+                image("c.jpg", "val"),
+            ],
+        );
+
+        let relative = YoloConverter::new_darknet().convert(&data, &HashMap::new());
+        let list = |files: &HashMap<String, FileData>, path: &str| {
+            String::from_utf8(files[path].read().unwrap().into_owned()).unwrap()
+        };
+        assert_eq!(list(&relative, "train.txt"), "train/a.jpg\ntrain/b.png\n");
+        assert_eq!(list(&relative, "valid.txt"), "valid/c.jpg\n");
+        assert!(!relative.contains_key("test.txt"));
+
+        let rooted = YoloConverter::new_darknet()
+            .with_options(ConvertOptions {
+                image_root: Some("/data/cats".to_string()),
+                ..Default::default()
+            })
+            .convert(&data, &HashMap::new());
+        assert_eq!(
+            list(&rooted, "train.txt"),
+            "/data/cats/train/a.jpg\n/data/cats/train/b.png\n"
+        );
+
+        let plain = YoloConverter::new().convert(&data, &HashMap::new());
+        assert!(!plain.contains_key("train.txt"));
+    }
+
+    #[test]
+    fn segment_bbox_labels_mirror_polygon_labels() {
+        let image = ImageEntry {
+            r#type: "image".to_string(),
+            file: "img1.jpg".to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: "train".to_string(),
+            annotations: Some(json!({
+                "segments": [
+                    [3, 0.1, 0.2, 0.5, 0.2, 0.3, 0.6],
+                    [1, 0.6, 0.6, 0.8, 0.7, 0.7, 0.9]
+                ]
+            })),
+        };
+        let data = make_data("segment", HashMap::new(), None, vec![image]);
+        let converter = YoloConverter::new().with_options(ConvertOptions {
+            segment_bbox_labels: true,
+            ..Default::default()
+        });
+
+        let files = converter.convert(&data, &HashMap::new());
+
+        let seg = std::str::from_utf8(files["train/labels/img1.txt"].as_bytes().unwrap()).unwrap();
+        let det =
+            std::str::from_utf8(files["train/labels_det/img1.txt"].as_bytes().unwrap()).unwrap();
+        let class_ids = |label: &str| -> Vec<String> {
+            label
+                .lines()
+                .map(|line| line.split_whitespace().next().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(class_ids(seg), vec!["3", "1"]);
+        assert_eq!(class_ids(det), class_ids(seg));
+        assert_eq!(
+            det.lines().next().unwrap(),
+            "3 0.300000 0.400000 0.400000 0.400000"
+        );
+    }
+
+    #[test]
+    fn empty_split_placeholders_mark_missing_split_dirs() {
+        let image = ImageEntry {
+            r#type: "image".to_string(),
+            file: "img1.jpg".to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: "train".to_string(),
+            annotations: Some(json!({"bboxes": [[0, 0.5, 0.5, 0.2, 0.2]]})),
+        };
+        let data = make_data("detect", HashMap::new(), None, vec![image]);
+
+        let files = YoloConverter::new()
+            .with_options(ConvertOptions {
+                empty_split_placeholders: true,
+                ..Default::default()
+            })
+            .convert(&data, &HashMap::new());
+
+        assert_eq!(files["valid/images/.keep"], FileData::Bytes(Vec::new()));
+        assert!(files.contains_key("valid/labels/.keep"));
+        assert!(files.contains_key("test/images/.keep"));
+        assert!(!files.contains_key("train/images/.keep"));
+
+        let plain = YoloConverter::new().convert(&data, &HashMap::new());
+        assert!(!plain.keys().any(|path| path.ends_with(".keep")));
+    }
+
     #[test]
     fn classify_paths_sanitize_class_name_segments() {
         let mut class_names = HashMap::new();
@@ -332,6 +925,10 @@ mod tests {
             file: "img1.jpg".to_string(),
             output_file: None,
             url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
             width: 640,
             height: 480,
             split: "train".to_string(),
@@ -342,13 +939,61 @@ mod tests {
         let data = make_data("classify", class_names, None, vec![image]);
         let converter = YoloConverter::new();
         let mut downloaded_images = HashMap::new();
-        downloaded_images.insert(image_download_key("train", "img1.jpg"), vec![1, 2, 3]);
+        downloaded_images.insert(
+            image_download_key("train", "img1.jpg"),
+            vec![1, 2, 3].into(),
+        );
 
         let files = converter.convert(&data, &downloaded_images);
 
         assert!(files.contains_key("train/dogs_cats/img1.jpg"));
     }
 
+    #[test]
+    fn classify_slugify_uses_slug_folders_and_keeps_original_names() {
+        let mut class_names = HashMap::new();
+        class_names.insert("0".to_string(), "Dog & Cat".to_string());
+        let image = ImageEntry {
+            r#type: "image".to_string(),
+            file: "img1.jpg".to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: "train".to_string(),
+            annotations: Some(json!({
+                "classification": [0]
+            })),
+        };
+        let data = make_data("classify", class_names, None, vec![image]);
+        let converter = YoloConverter::new().with_options(ConvertOptions {
+            slugify_class_names: true,
+            ..Default::default()
+        });
+        let mut downloaded_images = HashMap::new();
+        downloaded_images.insert(
+            image_download_key("train", "img1.jpg"),
+            vec![1, 2, 3].into(),
+        );
+
+        let files = converter.convert(&data, &downloaded_images);
+
+        assert!(files.contains_key("train/dog_cat/img1.jpg"));
+        assert_eq!(
+            files.get("classes.txt"),
+            Some(&b"Dog & Cat".to_vec().into())
+        );
+        let yaml = std::str::from_utf8(files["data.yaml"].as_bytes().unwrap()).unwrap();
+        assert!(yaml.contains(r#"0: "Dog & Cat""#));
+        let mapping: serde_json::Value =
+            serde_json::from_slice(files["class_folders.json"].as_bytes().unwrap()).unwrap();
+        assert_eq!(mapping["dog_cat"], "Dog & Cat");
+    }
+
     #[test]
     fn convert_uses_split_aware_download_keys() {
         let mut class_names = HashMap::new();
@@ -359,6 +1004,10 @@ mod tests {
             file: "img1.jpg".to_string(),
             output_file: None,
             url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
             width: 640,
             height: 480,
             split: "train".to_string(),
@@ -371,6 +1020,10 @@ mod tests {
             file: "img1.jpg".to_string(),
             output_file: None,
             url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
             width: 640,
             height: 480,
             split: "val".to_string(),
@@ -382,13 +1035,13 @@ mod tests {
         let data = make_data("detect", class_names, None, vec![train_image, valid_image]);
         let converter = YoloConverter::new();
         let mut downloaded_images = HashMap::new();
-        downloaded_images.insert(image_download_key("train", "img1.jpg"), vec![1]);
-        downloaded_images.insert(image_download_key("valid", "img1.jpg"), vec![2]);
+        downloaded_images.insert(image_download_key("train", "img1.jpg"), vec![1].into());
+        downloaded_images.insert(image_download_key("valid", "img1.jpg"), vec![2].into());
 
         let files = converter.convert(&data, &downloaded_images);
 
-        assert_eq!(files.get("train/images/img1.jpg"), Some(&vec![1]));
-        assert_eq!(files.get("valid/images/img1.jpg"), Some(&vec![2]));
+        assert_eq!(files.get("train/images/img1.jpg"), Some(&vec![1].into()));
+        assert_eq!(files.get("valid/images/img1.jpg"), Some(&vec![2].into()));
     }
 
     #[test]
@@ -407,6 +1060,10 @@ mod tests {
                     file: "Frame_98.jpg".to_string(),
                     output_file: None,
                     url: "https://cdn.example/a.jpg".to_string(),
+                    mirrors: Vec::new(),
+                    license: None,
+                    skip: false,
+                    captions: Vec::new(),
                     width: 640,
                     height: 480,
                     split: "train".to_string(),
@@ -419,6 +1076,10 @@ mod tests {
                     file: "Frame_98.jpg".to_string(),
                     output_file: Some("Frame_98__abcd1234.jpg".to_string()),
                     url: "https://cdn.example/b.jpg".to_string(),
+                    mirrors: Vec::new(),
+                    license: None,
+                    skip: false,
+                    captions: Vec::new(),
                     width: 640,
                     height: 480,
                     split: "train".to_string(),
@@ -431,18 +1092,21 @@ mod tests {
 
         let converter = YoloConverter::new();
         let mut downloaded_images = HashMap::new();
-        downloaded_images.insert(image_download_key("train", "Frame_98.jpg"), vec![1]);
+        downloaded_images.insert(image_download_key("train", "Frame_98.jpg"), vec![1].into());
         downloaded_images.insert(
             image_download_key("train", "Frame_98__abcd1234.jpg"),
-            vec![2],
+            vec![2].into(),
         );
 
         let files = converter.convert(&data, &downloaded_images);
 
-        assert_eq!(files.get("train/images/Frame_98.jpg"), Some(&vec![1]));
+        assert_eq!(
+            files.get("train/images/Frame_98.jpg"),
+            Some(&vec![1].into())
+        );
         assert_eq!(
             files.get("train/images/Frame_98__abcd1234.jpg"),
-            Some(&vec![2])
+            Some(&vec![2].into())
         );
         assert!(files.contains_key("train/labels/Frame_98.txt"));
         assert!(files.contains_key("train/labels/Frame_98__abcd1234.txt"));
@@ -465,6 +1129,10 @@ mod tests {
                     file: "tiger.jpg".to_string(),
                     output_file: None,
                     url: String::new(),
+                    mirrors: Vec::new(),
+                    license: None,
+                    skip: false,
+                    captions: Vec::new(),
                     width: 100,
                     height: 100,
                     split: "train".to_string(),
@@ -477,6 +1145,10 @@ mod tests {
                     file: "human.jpg".to_string(),
                     output_file: None,
                     url: String::new(),
+                    mirrors: Vec::new(),
+                    license: None,
+                    skip: false,
+                    captions: Vec::new(),
                     width: 100,
                     height: 100,
                     split: "train".to_string(),
@@ -492,13 +1164,13 @@ mod tests {
 
         // Tiger: 3 kpts, no padding needed
         let tiger_label =
-            std::str::from_utf8(files.get("train/labels/tiger.txt").unwrap()).unwrap();
+            std::str::from_utf8(files["train/labels/tiger.txt"].as_bytes().unwrap()).unwrap();
         let tiger_parts: Vec<&str> = tiger_label.split_whitespace().collect();
         assert_eq!(tiger_parts.len(), 1 + 4 + 3 * 3); // class + bbox + 3 kpts * 3
 
         // Human: 2 kpts + 1 padded = 3 kpts total
         let human_label =
-            std::str::from_utf8(files.get("train/labels/human.txt").unwrap()).unwrap();
+            std::str::from_utf8(files["train/labels/human.txt"].as_bytes().unwrap()).unwrap();
         let human_parts: Vec<&str> = human_label.split_whitespace().collect();
         assert_eq!(human_parts.len(), 1 + 4 + 3 * 3); // same length as tiger
 
@@ -508,7 +1180,7 @@ mod tests {
         assert_eq!(human_parts[human_parts.len() - 1], "0");
 
         // data.yaml should have kpt_shape: [3, 3]
-        let yaml = std::str::from_utf8(files.get("data.yaml").unwrap()).unwrap();
+        let yaml = std::str::from_utf8(files["data.yaml"].as_bytes().unwrap()).unwrap();
         assert!(yaml.contains("kpt_shape: [3, 3]"));
     }
 
@@ -527,6 +1199,10 @@ mod tests {
                 file: "img.jpg".to_string(),
                 output_file: None,
                 url: String::new(),
+                mirrors: Vec::new(),
+                license: None,
+                skip: false,
+                captions: Vec::new(),
                 width: 100,
                 height: 100,
                 split: "train".to_string(),
@@ -539,11 +1215,89 @@ mod tests {
         let converter = YoloConverter::new();
         let files = converter.convert(&data, &HashMap::new());
 
-        let yaml = std::str::from_utf8(files.get("data.yaml").unwrap()).unwrap();
+        let yaml = std::str::from_utf8(files["data.yaml"].as_bytes().unwrap()).unwrap();
         // Should use 3 (actual max) not 2 (stale metadata)
         assert!(yaml.contains("kpt_shape: [3, 3]"));
     }
 
+    #[test]
+    fn pose_labels_omit_visibility_for_two_dim_keypoints() {
+        let mut class_names = HashMap::new();
+        class_names.insert("0".to_string(), "person".to_string());
+
+        let data = make_data(
+            "pose",
+            class_names,
+            Some(vec![2, 2]),
+            vec![ImageEntry {
+                r#type: "image".to_string(),
+                file: "img.jpg".to_string(),
+                output_file: None,
+                url: String::new(),
+                mirrors: Vec::new(),
+                license: None,
+                skip: false,
+                captions: Vec::new(),
+                width: 100,
+                height: 100,
+                split: "train".to_string(),
+                annotations: Some(json!({
+                    "pose": [[0, 0.5, 0.5, 0.8, 0.8, 0.1, 0.2, 2, 0.3, 0.4, 1]]
+                })),
+            }],
+        );
+
+        let converter = YoloConverter::new();
+        let files = converter.convert(&data, &HashMap::new());
+
+        let label = std::str::from_utf8(files["train/labels/img.txt"].as_bytes().unwrap()).unwrap();
+        assert_eq!(
+            label,
+            "0 0.500000 0.500000 0.800000 0.800000 0.100000 0.200000 0.300000 0.400000"
+        );
+        let yaml = std::str::from_utf8(files["data.yaml"].as_bytes().unwrap()).unwrap();
+        assert!(yaml.contains("kpt_shape: [2, 2]"));
+    }
+
+    #[test]
+    fn pose_labels_keep_visibility_for_three_dim_keypoints() {
+        let mut class_names = HashMap::new();
+        class_names.insert("0".to_string(), "person".to_string());
+
+        let data = make_data(
+            "pose",
+            class_names,
+            Some(vec![2, 3]),
+            vec![ImageEntry {
+                r#type: "image".to_string(),
+                file: "img.jpg".to_string(),
+                output_file: None,
+                url: String::new(),
+                mirrors: Vec::new(),
+                license: None,
+                skip: false,
+                captions: Vec::new(),
+                width: 100,
+                height: 100,
+                split: "train".to_string(),
+                annotations: Some(json!({
+                    "pose": [[0, 0.5, 0.5, 0.8, 0.8, 0.1, 0.2, 2, 0.3, 0.4, 1]]
+                })),
+            }],
+        );
+
+        let converter = YoloConverter::new();
+        let files = converter.convert(&data, &HashMap::new());
+
+        let label = std::str::from_utf8(files["train/labels/img.txt"].as_bytes().unwrap()).unwrap();
+        assert_eq!(
+            label,
+            "0 0.500000 0.500000 0.800000 0.800000 0.100000 0.200000 2 0.300000 0.400000 1"
+        );
+        let yaml = std::str::from_utf8(files["data.yaml"].as_bytes().unwrap()).unwrap();
+        assert!(yaml.contains("kpt_shape: [2, 3]"));
+    }
+
     #[test]
     fn obb_labels_have_correct_format() {
         let mut class_names = HashMap::new();
@@ -558,6 +1312,10 @@ mod tests {
                 file: "dental.jpg".to_string(),
                 output_file: None,
                 url: String::new(),
+                mirrors: Vec::new(),
+                license: None,
+                skip: false,
+                captions: Vec::new(),
                 width: 640,
                 height: 640,
                 split: "train".to_string(),
@@ -570,7 +1328,8 @@ mod tests {
         let converter = YoloConverter::new();
         let files = converter.convert(&data, &HashMap::new());
 
-        let label = std::str::from_utf8(files.get("train/labels/dental.txt").unwrap()).unwrap();
+        let label =
+            std::str::from_utf8(files["train/labels/dental.txt"].as_bytes().unwrap()).unwrap();
         let parts: Vec<&str> = label.split_whitespace().collect();
         // OBB: class_id x1 y1 x2 y2 x3 y3 x4 y4
         assert_eq!(parts.len(), 9);
@@ -579,7 +1338,162 @@ mod tests {
         assert_eq!(parts[2], "0.600000");
 
         // data.yaml should NOT have kpt_shape
-        let yaml = std::str::from_utf8(files.get("data.yaml").unwrap()).unwrap();
+        let yaml = std::str::from_utf8(files["data.yaml"].as_bytes().unwrap()).unwrap();
         assert!(!yaml.contains("kpt_shape"));
     }
+
+    #[test]
+    fn absolute_sidecars_match_voc_boxes() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat","1":"dog"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.25,0.5],[1,0.05,0.9,0.2,0.4]]}}"#,
+        )
+        .unwrap();
+        let options = ConvertOptions {
+            absolute_label_sidecars: true,
+            ..Default::default()
+        };
+
+        let files = YoloConverter::new()
+            .with_options(options.clone())
+            .convert(&data, &HashMap::new());
+        let sidecar =
+            String::from_utf8(files["train/labels/a.abs.txt"].read().unwrap().into_owned())
+                .unwrap();
+        assert_eq!(sidecar, "0 240 120 400 360\n1 0 336 96 480\n");
+
+        let voc = crate::converter::pascal_voc::PascalVocConverter::new()
+            .with_options(options)
+            .convert(&data, &HashMap::new());
+        let xml: String = String::from_utf8(voc["train/a.xml"].read().unwrap().into_owned())
+            .unwrap()
+            .split_whitespace()
+            .collect();
+        for line in sidecar.lines() {
+            let coords: Vec<&str> = line.split(' ').skip(1).collect();
+            let bndbox = format!(
+                "<xmin>{}</xmin><ymin>{}</ymin><xmax>{}</xmax><ymax>{}</ymax>",
+                coords[0], coords[1], coords[2], coords[3]
+            );
+            assert!(xml.contains(&bndbox), "{} not in {}", bndbox, xml);
+        }
+    }
+
+    #[test]
+    fn label_range_check_accepts_clean_labels_and_rejects_bad_ones() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"pose","kpt_shape":[1,3],"class_names":{"0":"person"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"pose":[[0,0.5,0.5,0.2,0.2,0.4,0.4,2]]}}"#,
+        )
+        .unwrap();
+        let mut files = YoloConverter::new().convert(&data, &HashMap::new());
+
+        assert_eq!(check_label_ranges(&files, &data), Ok(()));
+
+        for bad in ["0 0.5 1.2 0.2 0.2 0.4 0.4 2", "0 0.5 NaN 0.2 0.2 0.4 0.4 2"] {
+            files.insert(
+                "train/labels/a.txt".to_string(),
+                bad.as_bytes().to_vec().into(),
+            );
+            let err = check_label_ranges(&files, &data).unwrap_err();
+            assert!(err.contains("train/labels/a.txt"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn coordinates_slightly_out_of_range_are_clamped() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"detect","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,1.00002,-0.00003,0.2]]}}"#,
+        )
+        .unwrap();
+        let files = YoloConverter::new().convert(&data, &HashMap::new());
+
+        let label =
+            String::from_utf8(files["train/labels/a.txt"].read().unwrap().into_owned()).unwrap();
+        assert_eq!(label, "0 0.500000 1.000000 0.000000 0.200000");
+        assert_eq!(check_label_ranges(&files, &data), Ok(()));
+    }
+
+    #[test]
+    fn coordinates_far_out_of_range_are_written_unchanged() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"detect","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,1.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let files = YoloConverter::new().convert(&data, &HashMap::new());
+
+        let label =
+            String::from_utf8(files["train/labels/a.txt"].read().unwrap().into_owned()).unwrap();
+        assert_eq!(label, "0 0.500000 1.500000 0.200000 0.200000");
+        let err = check_label_ranges(&files, &data).unwrap_err();
+        assert!(err.contains("'1.500000'"), "{}", err);
+    }
+
+    #[test]
+    fn close_polygons_repeats_the_first_point() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"segment","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":100,"height":100,"split":"train","annotations":{"segments":[[0,0.1,0.1,0.5,0.1,0.5,0.5],[0,0.2,0.2,0.4,0.2,0.4,0.4,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let converter = YoloConverter::new().with_options(ConvertOptions {
+            close_polygons: true,
+            ..Default::default()
+        });
+
+        let files = converter.convert(&data, &HashMap::new());
+
+        let label =
+            String::from_utf8(files["train/labels/a.txt"].read().unwrap().into_owned()).unwrap();
+        assert_eq!(
+            label,
+            "0 0.100000 0.100000 0.500000 0.100000 0.500000 0.500000 0.100000 0.100000\n\
+             0 0.200000 0.200000 0.400000 0.200000 0.400000 0.400000 0.200000 0.200000"
+        );
+    }
+
+    #[test]
+    fn source_annotation_ids_go_in_a_companion_file() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2],[0,0.4,0.4,0.2,0.2,{"id":42}]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+
+        let files = YoloConverter::new().convert(&data, &HashMap::new());
+
+        let ids: serde_json::Value =
+            serde_json::from_slice(&files["train/labels/a.ids.json"].read().unwrap()).unwrap();
+        assert_eq!(ids, serde_json::json!({"1": 42}));
+        assert!(!files.contains_key("train/labels/b.ids.json"));
+        assert_eq!(
+            files["train/labels/a.txt"].as_bytes().unwrap(),
+            b"0 0.500000 0.500000 0.200000 0.200000\n0 0.400000 0.400000 0.200000 0.200000"
+        );
+    }
+
+    #[test]
+    fn segment_holes_are_bridged_into_one_ring() {
+        let rings = vec![
+            vec![(0.1, 0.1), (0.9, 0.1), (0.9, 0.9)],
+            vec![(0.4, 0.4), (0.6, 0.4), (0.6, 0.6)],
+        ];
+
+        assert_eq!(
+            bridge_rings(&rings),
+            [
+                (0.1, 0.1),
+                (0.9, 0.1),
+                (0.9, 0.9),
+                (0.4, 0.4),
+                (0.6, 0.4),
+                (0.6, 0.6),
+                (0.4, 0.4),
+                (0.9, 0.9),
+            ]
+        );
+    }
 }
@@ -0,0 +1,401 @@
+use crate::converter::FileData;
+use crate::manifest::fnv1a_64;
+use crate::options::ConvertOptions;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+fn split_dir(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+/// Collapses byte-identical images that share a directory in a converted
+/// export into one stored file, and repoints the annotations at it.
+///
+/// Only formats whose annotations name their image file can share one, so
+/// YOLO/Darknet (labels paired to images by file name) and classify exports
+/// are left alone and `None` is returned. Otherwise returns how many
+/// duplicate files were removed.
+pub fn dedup_identical_images(
+    files: &mut HashMap<String, FileData>,
+    downloaded_images: &HashMap<String, FileData>,
+    format: &str,
+    task: &str,
+    options: &ConvertOptions,
+) -> Option<usize> {
+    let format = format.to_lowercase();
+    if task == "classify" || !matches!(format.as_str(), "coco" | "createml" | "pascal_voc" | "voc")
+    {
+        return None;
+    }
+
+    let downloaded_hashes: HashSet<u64> = downloaded_images
+        .values()
+        .filter_map(|file| file.read().ok().map(|bytes| fnv1a_64(&bytes)))
+        .collect();
+
+    // Canonical (first by path) image per directory and content hash, and
+    // the duplicates to fold into it. Only paths are kept, so spooled images
+    // are read back one at a time.
+    let mut paths: Vec<String> = files.keys().cloned().collect();
+    paths.sort();
+    let mut canonical: HashMap<(String, u64), String> = HashMap::new();
+    let mut renames: BTreeMap<String, HashMap<String, String>> = BTreeMap::new();
+    let mut duplicates = Vec::new();
+
+    for path in paths {
+        let Ok(bytes) = files[&path].read() else {
+            continue;
+        };
+        let hash = fnv1a_64(&bytes);
+        if !downloaded_hashes.contains(&hash) {
+            continue;
+        }
+        let (dir, name) = split_dir(&path);
+        match canonical.get(&(dir.to_string(), hash)) {
+            Some(kept) => {
+                // The hash only narrows the search; identical bytes decide.
+                if files[kept]
+                    .read()
+                    .is_ok_and(|kept_bytes| kept_bytes == bytes)
+                {
+                    let kept_name = split_dir(kept).1.to_string();
+                    renames
+                        .entry(dir.to_string())
+                        .or_default()
+                        .insert(name.to_string(), kept_name);
+                    duplicates.push(path.clone());
+                }
+            }
+            None => {
+                canonical.insert((dir.to_string(), hash), path.clone());
+            }
+        }
+    }
+
+    for path in &duplicates {
+        files.remove(path);
+    }
+    for (dir, renames) in &renames {
+        rewrite_image_references(files, &format, dir, renames, options);
+    }
+
+    Some(duplicates.len())
+}
+
+/// Repoints every file of a `format` export that names images in `dir`
+/// after `renames` (old name to new path, both relative to `dir`):
+/// per-split and single-file COCO annotations and captions, CreateML files,
+/// and VOC `<filename>`/`<path>`.
+pub(crate) fn rewrite_image_references(
+    files: &mut HashMap<String, FileData>,
+    format: &str,
+    dir: &str,
+    renames: &HashMap<String, String>,
+    options: &ConvertOptions,
+) {
+    match format {
+        "coco" => {
+            // A single file covering every split names images by their path.
+            let rooted: HashMap<String, String> = renames
+                .iter()
+                .map(|(from, to)| (format!("{}/{}", dir, from), format!("{}/{}", dir, to)))
+                .collect();
+            for name in ["_annotations.coco.json", "_captions.coco.json"] {
+                let path = format!("{}/{}", dir, name);
+                rewrite_json_names(files, &path, "images", "file_name", renames, options);
+                rewrite_json_names(files, name, "images", "file_name", &rooted, options);
+            }
+        }
+        "createml" => rewrite_json_names(
+            files,
+            &format!("{}.json", dir),
+            "",
+            "image",
+            renames,
+            options,
+        ),
+        "pascal_voc" | "voc" => rewrite_voc_names(files, dir, renames),
+        _ => {}
+    }
+}
+
+/// Renames image references in a JSON annotation file. `array_key` selects
+/// the array of image objects, or the document root when empty.
+fn rewrite_json_names(
+    files: &mut HashMap<String, FileData>,
+    path: &str,
+    array_key: &str,
+    name_key: &str,
+    renames: &HashMap<String, String>,
+    options: &ConvertOptions,
+) {
+    let Some(mut json) = files
+        .get(path)
+        .and_then(|file| file.read().ok())
+        .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok())
+    else {
+        return;
+    };
+
+    let items = if array_key.is_empty() {
+        json.as_array_mut()
+    } else {
+        json.get_mut(array_key).and_then(|v| v.as_array_mut())
+    };
+    let mut renamed_any = false;
+    for item in items.into_iter().flatten() {
+        let renamed = item
+            .get(name_key)
+            .and_then(|v| v.as_str())
+            .and_then(|name| renames.get(name));
+        if let Some(renamed) = renamed.cloned() {
+            item[name_key] = serde_json::Value::String(renamed);
+            renamed_any = true;
+        }
+    }
+
+    if renamed_any {
+        files.insert(path.to_string(), options.to_json(&json).into_bytes().into());
+    }
+}
+
+/// Replaces the text of the first `<tag>` in `xml` with what `rename`
+/// returns for it, or `None` when it returns nothing.
+fn replace_tag_text(
+    xml: &str,
+    tag: &str,
+    rename: impl Fn(&str) -> Option<String>,
+) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    let text = quick_xml::escape::unescape(&xml[start..end]).ok()?;
+    let renamed = rename(&text).filter(|renamed| *renamed != text)?;
+    Some(format!(
+        "{}{}{}",
+        &xml[..start],
+        quick_xml::escape::escape(renamed.as_str()),
+        &xml[end..]
+    ))
+}
+
+/// Repoints every VOC XML under `dir` whose `<filename>` or `<path>` names a
+/// renamed image. `<filename>` is a bare name, while `<path>` is either a
+/// bare name or ends in `{dir}/{name}` after some root.
+fn rewrite_voc_names(
+    files: &mut HashMap<String, FileData>,
+    dir: &str,
+    renames: &HashMap<String, String>,
+) {
+    let prefix = format!("{}/", dir);
+    let xml_paths: Vec<String> = files
+        .keys()
+        .filter(|path| path.starts_with(&prefix) && path.ends_with(".xml"))
+        .cloned()
+        .collect();
+    let bare_name = |to: &String| to.rsplit('/').next().unwrap_or(to).to_string();
+
+    for path in xml_paths {
+        let Ok(xml) = files[&path]
+            .read()
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        else {
+            continue;
+        };
+        let mut rewritten = xml.clone();
+        if let Some(renamed) = replace_tag_text(&rewritten, "filename", |name| {
+            renames.get(name).map(bare_name)
+        }) {
+            rewritten = renamed;
+        }
+        if let Some(renamed) = replace_tag_text(&rewritten, "path", |image_path| {
+            if let Some(to) = renames.get(image_path) {
+                return Some(bare_name(to));
+            }
+            let (root, name) = image_path.rsplit_once('/')?;
+            let to = renames.get(name)?;
+            (root == dir || root.ends_with(&format!("/{}", dir)))
+                .then(|| format!("{}/{}", root, to))
+        }) {
+            rewritten = renamed;
+        }
+        if rewritten != xml {
+            files.insert(path, rewritten.into_bytes().into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converter::get_converter;
+    use crate::parser::{image_download_key, parse_ndjson};
+
+    const CONTENT: &str = r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","url":"https://a.example/a.jpg?sig=1","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"train","url":"https://b.example/a.jpg?sig=2","annotations":{"boxes":[[0,0.3,0.3,0.1,0.1]]}}
+{"type":"image","file":"c.jpg","width":640,"height":480,"split":"train","url":"https://c.example/c.jpg","annotations":{"boxes":[[0,0.3,0.3,0.1,0.1]]}}"#;
+
+    fn downloaded() -> HashMap<String, FileData> {
+        HashMap::from([
+            (
+                image_download_key("train", "a.jpg"),
+                b"same".to_vec().into(),
+            ),
+            (
+                image_download_key("train", "b.jpg"),
+                b"same".to_vec().into(),
+            ),
+            (
+                image_download_key("train", "c.jpg"),
+                b"other".to_vec().into(),
+            ),
+        ])
+    }
+
+    fn convert(format: &str) -> HashMap<String, FileData> {
+        let data = parse_ndjson(CONTENT).unwrap();
+        get_converter(format, &ConvertOptions::default())
+            .unwrap()
+            .convert(&data, &downloaded())
+    }
+
+    #[test]
+    fn coco_identical_images_share_one_file() {
+        let mut files = convert("coco");
+
+        let removed = dedup_identical_images(
+            &mut files,
+            &downloaded(),
+            "coco",
+            "detect",
+            &ConvertOptions::default(),
+        );
+
+        assert_eq!(removed, Some(1));
+        assert!(files.contains_key("train/a.jpg"));
+        assert!(!files.contains_key("train/b.jpg"));
+        assert!(files.contains_key("train/c.jpg"));
+        let coco: serde_json::Value =
+            serde_json::from_slice(&files["train/_annotations.coco.json"].read().unwrap()).unwrap();
+        let names: Vec<&str> = coco["images"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|img| img["file_name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["a.jpg", "a.jpg", "c.jpg"]);
+        assert_eq!(coco["annotations"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn voc_identical_images_point_at_kept_file() {
+        let mut files = convert("pascal_voc");
+
+        let removed = dedup_identical_images(
+            &mut files,
+            &downloaded(),
+            "pascal_voc",
+            "detect",
+            &ConvertOptions::default(),
+        );
+
+        assert_eq!(removed, Some(1));
+        assert!(!files.contains_key("train/b.jpg"));
+        let xml = String::from_utf8(files["train/b.xml"].read().unwrap().into_owned()).unwrap();
+        assert!(xml.contains("<filename>a.jpg</filename>"));
+    }
+
+    #[test]
+    fn every_file_naming_images_follows_the_kept_file() {
+        let content = CONTENT.replace(
+            r#""split":"train","url""#,
+            r#""split":"train","caption":"a cat","url""#,
+        );
+        let data = parse_ndjson(&content).unwrap();
+        let options = ConvertOptions {
+            coco_single_file: true,
+            coco_captions: true,
+            compact_json: true,
+            voc_path: crate::options::VocPath::Root("/data".to_string()),
+            ..Default::default()
+        };
+        let mut coco = get_converter("coco", &options)
+            .unwrap()
+            .convert(&data, &downloaded());
+        let mut voc = get_converter("pascal_voc", &options)
+            .unwrap()
+            .convert(&data, &downloaded());
+
+        dedup_identical_images(&mut coco, &downloaded(), "coco", "detect", &options);
+        dedup_identical_images(&mut voc, &downloaded(), "pascal_voc", "detect", &options);
+
+        for name in ["_annotations.coco.json", "_captions.coco.json"] {
+            let bytes = coco[name].read().unwrap().into_owned();
+            assert!(!bytes.contains(&b'\n'), "{} ignored compact_json", name);
+            let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            let names: Vec<&str> = json["images"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|img| img["file_name"].as_str().unwrap())
+                .collect();
+            assert_eq!(
+                names,
+                ["train/a.jpg", "train/a.jpg", "train/c.jpg"],
+                "{}",
+                name
+            );
+        }
+        let xml = String::from_utf8(voc["train/b.xml"].read().unwrap().into_owned()).unwrap();
+        assert!(xml.contains("<path>/data/train/a.jpg</path>"), "{}", xml);
+    }
+
+    #[test]
+    fn spooled_identical_images_are_deduplicated() {
+        let dir = tempfile::tempdir().unwrap();
+        let spooled: HashMap<String, FileData> = downloaded()
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (key, file))| {
+                let path = dir.path().join(format!("{}.img", idx));
+                std::fs::write(&path, file.read().unwrap()).unwrap();
+                (key, FileData::OnDisk(path))
+            })
+            .collect();
+        let data = parse_ndjson(CONTENT).unwrap();
+        let mut files = get_converter("coco", &ConvertOptions::default())
+            .unwrap()
+            .convert(&data, &spooled);
+
+        let removed = dedup_identical_images(
+            &mut files,
+            &spooled,
+            "coco",
+            "detect",
+            &ConvertOptions::default(),
+        );
+
+        assert_eq!(removed, Some(1));
+        assert!(files.contains_key("train/a.jpg"));
+        assert!(!files.contains_key("train/b.jpg"));
+        assert!(matches!(files["train/a.jpg"], FileData::OnDisk(_)));
+    }
+
+    #[test]
+    fn yolo_exports_are_left_alone() {
+        let mut files = convert("yolo");
+        let before = files.len();
+
+        assert_eq!(
+            dedup_identical_images(
+                &mut files,
+                &downloaded(),
+                "yolo",
+                "detect",
+                &ConvertOptions::default()
+            ),
+            None
+        );
+        assert_eq!(files.len(), before);
+    }
+}
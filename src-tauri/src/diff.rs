@@ -0,0 +1,122 @@
+use crate::converter::get_class_names;
+use crate::manifest::ExportManifest;
+use crate::options::SplitNormalization;
+use crate::parser::{parse_ndjson_with, NDJSONData, ParseSettings};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// What changed between two versions of an NDJSON dataset. Images are named
+/// `split/file`.
+#[derive(Debug, Default, Serialize)]
+pub struct DiffReport {
+    pub added_images: Vec<String>,
+    pub removed_images: Vec<String>,
+    /// Images in both versions whose dimensions or annotations differ.
+    pub changed_images: Vec<String>,
+    pub unchanged_images: usize,
+    pub class_changes: Vec<ClassChange>,
+}
+
+/// A class id whose name was added, removed or renamed.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ClassChange {
+    pub id: i32,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Compares two datasets image by image, using the same annotation hash as
+/// delta exports.
+pub fn diff_datasets(before: &NDJSONData, after: &NDJSONData) -> DiffReport {
+    let old = ExportManifest::for_images(&before.images, SplitNormalization::Canonical).images;
+    let new = ExportManifest::for_images(&after.images, SplitNormalization::Canonical).images;
+
+    let mut report = DiffReport::default();
+    for (key, hash) in &new {
+        match old.get(key) {
+            None => report.added_images.push(key.clone()),
+            Some(old_hash) if old_hash != hash => report.changed_images.push(key.clone()),
+            Some(_) => report.unchanged_images += 1,
+        }
+    }
+    report.removed_images = old
+        .keys()
+        .filter(|key| !new.contains_key(*key))
+        .cloned()
+        .collect();
+
+    let old_names = get_class_names(before);
+    let new_names = get_class_names(after);
+    let ids: BTreeSet<i32> = old_names.keys().chain(new_names.keys()).copied().collect();
+    report.class_changes = ids
+        .into_iter()
+        .filter(|id| old_names.get(id) != new_names.get(id))
+        .map(|id| ClassChange {
+            id,
+            before: old_names.get(&id).cloned(),
+            after: new_names.get(&id).cloned(),
+        })
+        .collect();
+    report
+}
+
+/// Parses two NDJSON documents and diffs them.
+pub fn diff_ndjson(before: &str, after: &str) -> Result<DiffReport, String> {
+    let settings = ParseSettings::default();
+    let (before, _) = parse_ndjson_with(before, &settings)
+        .map_err(|e| format!("Failed to parse the first NDJSON: {}", e))?;
+    let (after, _) = parse_ndjson_with(after, &settings)
+        .map_err(|e| format!("Failed to parse the second NDJSON: {}", e))?;
+    Ok(diff_datasets(&before, &after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEFORE: &str = r#"{"type":"dataset","name":"test","class_names":{"0":"cat","1":"dog"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[1,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"c.jpg","width":640,"height":480,"split":"val","annotations":{"boxes":[[1,0.3,0.3,0.1,0.1]]}}"#;
+
+    #[test]
+    fn reports_added_removed_and_changed_images() {
+        let after = r#"{"type":"dataset","name":"test","class_names":{"0":"cat","1":"puppy","2":"bird"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[1,0.5,0.5,0.25,0.2]]}}
+{"type":"image","file":"d.jpg","width":640,"height":480,"split":"val","annotations":{"boxes":[[2,0.3,0.3,0.1,0.1]]}}"#;
+
+        let report = diff_ndjson(BEFORE, after).unwrap();
+
+        assert_eq!(report.added_images, ["valid/d.jpg"]);
+        assert_eq!(report.removed_images, ["valid/c.jpg"]);
+        assert_eq!(report.changed_images, ["train/b.jpg"]);
+        assert_eq!(report.unchanged_images, 1);
+        assert_eq!(
+            report.class_changes,
+            [
+                ClassChange {
+                    id: 1,
+                    before: Some("dog".to_string()),
+                    after: Some("puppy".to_string()),
+                },
+                ClassChange {
+                    id: 2,
+                    before: None,
+                    after: Some("bird".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn identical_datasets_have_no_changes() {
+        let report = diff_ndjson(BEFORE, BEFORE).unwrap();
+
+        assert!(report.added_images.is_empty());
+        assert!(report.removed_images.is_empty());
+        assert!(report.changed_images.is_empty());
+        assert!(report.class_changes.is_empty());
+        assert_eq!(report.unchanged_images, 3);
+    }
+}
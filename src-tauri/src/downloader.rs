@@ -1,16 +1,24 @@
+use crate::converter::FileData;
 use crate::parser::{image_entry_download_key, normalize_split, ImageEntry};
 use futures::stream::{self, StreamExt};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
 use reqwest::Client;
 use serde::Serialize;
 use std::collections::HashMap;
-use std::net::IpAddr;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
 use tauri::ipc::Channel;
-use tokio::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, OnceCell, Semaphore, SemaphorePermit};
+use tokio::time::Instant;
 use url::{Host, Url};
 
-const MAX_DOWNLOAD_BYTES: usize = 50 * 1024 * 1024; // 50 MiB per image
+pub const MAX_DOWNLOAD_BYTES: usize = 50 * 1024 * 1024; // 50 MiB per image
+/// Size reserved against the in-flight byte budget for a response without a
+/// Content-Length.
+const ASSUMED_DOWNLOAD_BYTES: u64 = 4 * 1024 * 1024;
 
 #[derive(Clone, Serialize)]
 pub struct ProgressEvent {
@@ -18,27 +26,582 @@ pub struct ProgressEvent {
     pub current: u32,
     pub total: u32,
     pub item: Option<String>,
+    /// Progress of the whole conversion, so the UI can show one bar that
+    /// doesn't reset between phases.
+    pub overall_percent: u8,
+    /// Part of the phase in progress, e.g. `images` or `labels` while
+    /// writing the export. `current` and `total` then count within it.
+    pub stage: Option<String>,
+    /// Estimated seconds until the phase finishes, once its throughput is
+    /// known.
+    pub eta_seconds: Option<u64>,
+}
+
+/// Share of the overall bar each phase covers, as `(phase, start, end)`
+/// percentages. Downloading dominates real conversions.
+const PHASE_WEIGHTS: [(&str, f64, f64); 6] = [
+    ("parsing", 0.0, 5.0),
+    ("downloading", 5.0, 80.0),
+    ("converting", 80.0, 90.0),
+    ("zipping", 90.0, 100.0),
+    ("writing", 90.0, 100.0),
+    ("complete", 100.0, 100.0),
+];
+
+impl ProgressEvent {
+    pub fn new(phase: &str, current: u32, total: u32, item: Option<String>) -> Self {
+        let (start, end) = PHASE_WEIGHTS
+            .iter()
+            .find(|(name, _, _)| *name == phase)
+            .map_or((0.0, 0.0), |(_, start, end)| (*start, *end));
+        let fraction = if total == 0 {
+            0.0
+        } else {
+            (f64::from(current) / f64::from(total)).min(1.0)
+        };
+
+        Self {
+            phase: phase.to_string(),
+            current,
+            total,
+            item,
+            overall_percent: (start + (end - start) * fraction).floor() as u8,
+            stage: None,
+            eta_seconds: None,
+        }
+    }
+
+    pub fn with_eta(mut self, eta_seconds: Option<u64>) -> Self {
+        self.eta_seconds = eta_seconds;
+        self
+    }
+
+    /// Narrows the event to a stage of its phase, counting `current` of
+    /// `total` within the stage. The overall percentage is unchanged.
+    pub fn with_stage(mut self, stage: &str, current: u32, total: u32) -> Self {
+        self.stage = Some(stage.to_string());
+        self.current = current;
+        self.total = total;
+        self
+    }
+}
+
+/// How far back `EtaEstimator` looks when measuring throughput.
+const ETA_WINDOW: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Estimates time left in a phase from its throughput over the last
+/// `ETA_WINDOW`. Samples are dropped whenever the phase changes, so one
+/// phase's rate never carries into the next.
+#[derive(Default)]
+pub struct EtaEstimator {
+    phase: String,
+    samples: std::collections::VecDeque<(std::time::Instant, u32)>,
+}
+
+impl EtaEstimator {
+    /// Records that `current` of `total` items are done at `now` and returns
+    /// the seconds left at the recent rate, or `None` before there is a rate.
+    pub fn estimate(
+        &mut self,
+        phase: &str,
+        current: u32,
+        total: u32,
+        now: std::time::Instant,
+    ) -> Option<u64> {
+        if self.phase != phase {
+            self.phase = phase.to_string();
+            self.samples.clear();
+        }
+        self.samples.push_back((now, current));
+        while self.samples.len() > 2
+            && self
+                .samples
+                .front()
+                .is_some_and(|(at, _)| now.duration_since(*at) > ETA_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+
+        let (start, start_count) = *self.samples.front()?;
+        let elapsed = now.duration_since(start).as_secs_f64();
+        let done = current.saturating_sub(start_count);
+        if done == 0 || elapsed <= 0.0 {
+            return None;
+        }
+        let remaining = f64::from(total.saturating_sub(current));
+        Some((remaining * elapsed / f64::from(done)).ceil() as u64)
+    }
 }
 
 pub struct Downloader {
     client: Client,
     concurrency: usize,
+    spool_dir: Option<PathBuf>,
+    deadline: Option<Instant>,
+    user_agent: Option<String>,
+    referer: Option<Referer>,
+    allowed_hosts: Vec<String>,
+    dns: Arc<DnsCache>,
+    connection: ConnectionSettings,
+    byte_budget: Option<ByteBudget>,
+    allow_private_hosts: bool,
+}
+
+/// Caps the bytes of in-memory downloads in flight at once. Each download
+/// reserves its Content-Length, or an assumed size, before reading its body
+/// and grows the reservation as the bytes it reads pass it, so memory stays
+/// bounded however many requests run concurrently.
+struct ByteBudget {
+    limit: u32,
+    permits: Semaphore,
+    peak: AtomicU64,
+}
+
+impl ByteBudget {
+    fn new(max_bytes: u64) -> Self {
+        let limit = max_bytes.clamp(1, u64::from(u32::MAX)) as u32;
+        Self {
+            limit,
+            permits: Semaphore::new(limit as usize),
+            peak: AtomicU64::new(0),
+        }
+    }
+
+    /// Waits until `bytes` fit in the budget. A single download larger than
+    /// the whole budget takes all of it.
+    async fn reserve(&self, bytes: Option<u64>) -> Option<SemaphorePermit<'_>> {
+        let bytes = bytes
+            .unwrap_or(ASSUMED_DOWNLOAD_BYTES)
+            .clamp(1, u64::from(self.limit)) as u32;
+        let permit = self.permits.acquire_many(bytes).await.ok()?;
+        self.record_peak();
+        Some(permit)
+    }
+
+    /// Grows `permit` to cover the `bytes` read so far. When the budget can't
+    /// spare the difference right away the reservation is given back and the
+    /// whole amount waited for, so downloads growing at the same time can't
+    /// deadlock each holding part of the budget.
+    async fn grow<'a>(&'a self, permit: &mut Option<SemaphorePermit<'a>>, bytes: u64) {
+        let wanted = bytes.clamp(1, u64::from(self.limit)) as u32;
+        let held = permit
+            .as_ref()
+            .map_or(0, |permit| permit.num_permits() as u32);
+        if wanted <= held {
+            return;
+        }
+        match (
+            self.permits.try_acquire_many(wanted - held),
+            permit.as_mut(),
+        ) {
+            (Ok(extra), Some(permit)) => permit.merge(extra),
+            (Ok(extra), None) => *permit = Some(extra),
+            (Err(_), _) => {
+                *permit = None;
+                *permit = self.permits.acquire_many(wanted).await.ok();
+            }
+        }
+        self.record_peak();
+    }
+
+    fn record_peak(&self) {
+        let in_flight = u64::from(self.limit) - self.permits.available_permits() as u64;
+        self.peak.fetch_max(in_flight, Ordering::SeqCst);
+    }
+}
+
+/// Connection reuse and protocol settings for the HTTP client. HTTP/2 is
+/// always negotiated with servers that offer it over TLS.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionSettings {
+    /// Speak HTTP/2 from the first byte, without negotiating. Only for hosts
+    /// known to support it; HTTP/1-only servers fail every request.
+    pub http2_prior_knowledge: bool,
+    /// How long an idle pooled connection is kept. `None` keeps the client
+    /// default of 90 seconds.
+    pub pool_idle_timeout: Option<std::time::Duration>,
+    /// Idle connections kept per host. `None` uses the download concurrency.
+    pub pool_max_idle_per_host: Option<usize>,
+}
+
+/// Redirects followed before a request fails, as reqwest's default policy.
+const MAX_REDIRECTS: usize = 10;
+
+/// Follows redirects only to HTTP(S) URLs on `allowed_hosts`, so a redirect
+/// can't take a download off the allowlist.
+fn redirect_policy(allowed_hosts: Vec<String>) -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error("Too many redirects");
+        }
+        let url = attempt.url();
+        if !matches!(url.scheme(), "http" | "https") {
+            return attempt.error("Only HTTP/HTTPS redirects are allowed");
+        }
+        let host = url
+            .host_str()
+            .unwrap_or_default()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        if host_allowed(&host, &allowed_hosts) {
+            attempt.follow()
+        } else {
+            attempt.error(format!(
+                "Redirect to host '{}' is not in the allowed hosts",
+                host
+            ))
+        }
+    })
+}
+
+fn build_client(
+    concurrency: usize,
+    settings: &ConnectionSettings,
+    dns: &Arc<DnsCache>,
+    allowed_hosts: &[String],
+) -> Result<Client, String> {
+    let mut builder = Client::builder()
+        .dns_resolver(Arc::new(CachedResolver(Arc::clone(dns))))
+        .redirect(redirect_policy(allowed_hosts.to_vec()))
+        .pool_max_idle_per_host(settings.pool_max_idle_per_host.unwrap_or(concurrency))
+        .timeout(std::time::Duration::from_secs(30));
+    if let Some(timeout) = settings.pool_idle_timeout {
+        builder = builder.pool_idle_timeout(timeout);
+    }
+    if settings.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+    builder
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))
+}
+
+/// Default number of host lookups in flight at once.
+pub const DEFAULT_DNS_CONCURRENCY: usize = 4;
+
+type HostCells = HashMap<String, Arc<OnceCell<Vec<IpAddr>>>>;
+
+/// Resolves each host once per conversion, with at most a fixed number of
+/// lookups in flight, so many images on the same few hosts don't flood the
+/// system resolver. The HTTP client connects through it too, so requests go
+/// to the addresses the download checks saw. Failed lookups aren't cached
+/// and are retried.
+pub struct DnsCache {
+    resolved: std::sync::Mutex<HostCells>,
+    permits: Semaphore,
+    lookups: AtomicU32,
+}
+
+impl DnsCache {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            resolved: std::sync::Mutex::new(HashMap::new()),
+            permits: Semaphore::new(max_concurrent.max(1)),
+            lookups: AtomicU32::new(0),
+        }
+    }
+
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, String> {
+        let cell = {
+            let mut resolved = self.resolved.lock().unwrap_or_else(|e| e.into_inner());
+            Arc::clone(resolved.entry(host.to_string()).or_default())
+        };
+        cell.get_or_try_init(|| async {
+            let _permit = self
+                .permits
+                .acquire()
+                .await
+                .map_err(|_| "Failed to resolve download host".to_string())?;
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            let addrs: Vec<IpAddr> = tokio::net::lookup_host((host, 0))
+                .await
+                .map_err(|_| "Failed to resolve download host".to_string())?
+                .map(|addr| addr.ip())
+                .collect();
+            Ok(addrs)
+        })
+        .await
+        .cloned()
+    }
+}
+
+/// Hands the HTTP client's lookups to a shared [`DnsCache`]. The client
+/// fills in the port itself.
+struct CachedResolver(Arc<DnsCache>);
+
+impl Resolve for CachedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let dns = Arc::clone(&self.0);
+        Box::pin(async move {
+            let addrs = dns.resolve(name.as_str()).await?;
+            let addrs: Addrs = Box::new(addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+/// Referer header sent with every image request, for CDNs with hotlink
+/// protection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Referer {
+    /// The origin of the URL being requested, e.g. `https://cdn.example/`.
+    Origin,
+    Fixed(String),
+}
+
+impl Referer {
+    fn header_value(&self, url: &str) -> Option<String> {
+        match self {
+            Referer::Fixed(value) => Some(value.clone()),
+            Referer::Origin => Url::parse(url)
+                .ok()
+                .map(|parsed| parsed.origin())
+                .filter(|origin| origin.is_tuple())
+                .map(|origin| format!("{}/", origin.ascii_serialization())),
+        }
+    }
 }
 
 impl Downloader {
     pub fn new(concurrency: usize) -> Result<Self, String> {
-        let client = Client::builder()
-            .pool_max_idle_per_host(concurrency)
-            .timeout(std::time::Duration::from_secs(30))
-            .build()
-            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+        let dns = Arc::new(DnsCache::new(DEFAULT_DNS_CONCURRENCY));
+        let connection = ConnectionSettings::default();
+        let client = build_client(concurrency, &connection, &dns, &[])?;
 
         Ok(Self {
             client,
             concurrency,
+            spool_dir: None,
+            deadline: None,
+            user_agent: None,
+            referer: None,
+            allowed_hosts: Vec::new(),
+            dns,
+            connection,
+            byte_budget: None,
+            allow_private_hosts: false,
         })
     }
 
+    /// Stream each download into a file under `dir` instead of keeping the
+    /// bytes in memory. The caller owns `dir` and must keep it alive until the
+    /// returned files have been consumed.
+    pub fn with_spool_dir(mut self, dir: PathBuf) -> Self {
+        self.spool_dir = Some(dir);
+        self
+    }
+
+    /// Stop downloading at `deadline`: queued images are not started and
+    /// in-flight requests are abandoned. Both count as timed out.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Send `user_agent` instead of the HTTP client's default.
+    pub fn with_user_agent(mut self, user_agent: String) -> Self {
+        self.user_agent = Some(user_agent);
+        self
+    }
+
+    pub fn with_referer(mut self, referer: Referer) -> Self {
+        self.referer = Some(referer);
+        self
+    }
+
+    /// Only download from hosts matching one of `hosts`, on top of the
+    /// private-address checks, including after redirects. See
+    /// [`host_allowed`] for the patterns.
+    pub fn with_allowed_hosts(mut self, hosts: Vec<String>) -> Result<Self, String> {
+        self.allowed_hosts = hosts;
+        self.rebuild_client()?;
+        Ok(self)
+    }
+
+    /// Rebuild the HTTP client with `settings`.
+    pub fn with_connection_settings(
+        mut self,
+        settings: &ConnectionSettings,
+    ) -> Result<Self, String> {
+        self.connection = settings.clone();
+        self.rebuild_client()?;
+        Ok(self)
+    }
+
+    /// Resolve at most `max_concurrent` hosts at once.
+    pub fn with_dns_concurrency(mut self, max_concurrent: usize) -> Result<Self, String> {
+        self.dns = Arc::new(DnsCache::new(max_concurrent));
+        self.rebuild_client()?;
+        Ok(self)
+    }
+
+    fn rebuild_client(&mut self) -> Result<(), String> {
+        self.client = build_client(
+            self.concurrency,
+            &self.connection,
+            &self.dns,
+            &self.allowed_hosts,
+        )?;
+        Ok(())
+    }
+
+    /// Hold at most `max_bytes` of in-memory downloads at once. Spooled
+    /// downloads go straight to disk and aren't counted.
+    pub fn with_max_inflight_bytes(mut self, max_bytes: u64) -> Self {
+        self.byte_budget = Some(ByteBudget::new(max_bytes));
+        self
+    }
+
+    fn deadline_passed(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Lets tests download from a local server. The SSRF checks stay on in
+    /// every non-test build.
+    #[cfg(test)]
+    fn allow_private_hosts(mut self) -> Self {
+        self.allow_private_hosts = true;
+        self
+    }
+
+    /// Downloads a single URL into memory, or into `spool_path` when set.
+    async fn fetch(&self, url: &str, spool_path: Option<&Path>) -> Result<FileData, String> {
+        if !self.allow_private_hosts {
+            validate_download_url(url, &self.allowed_hosts, &self.dns).await?;
+        }
+
+        let mut request = self.client.get(url);
+        if let Some(user_agent) = &self.user_agent {
+            request = request.header(reqwest::header::USER_AGENT, user_agent);
+        }
+        if let Some(referer) = self
+            .referer
+            .as_ref()
+            .and_then(|referer| referer.header_value(url))
+        {
+            request = request.header(reqwest::header::REFERER, referer);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        if is_svg(content_type, &[]) {
+            return Err(SVG_UNSUPPORTED.to_string());
+        }
+
+        let file = match spool_path {
+            Some(path) => write_response_with_limit(response, MAX_DOWNLOAD_BYTES, path)
+                .await
+                .map(|()| FileData::OnDisk(path.to_path_buf())),
+            None => {
+                read_response_with_limit(response, MAX_DOWNLOAD_BYTES, self.byte_budget.as_ref())
+                    .await
+                    .map(FileData::Bytes)
+            }
+        }?;
+        let head = file_head(&file);
+        if is_svg(None, &head) {
+            if let FileData::OnDisk(path) = &file {
+                let _ = std::fs::remove_file(path);
+            }
+            return Err(SVG_UNSUPPORTED.to_string());
+        }
+        Ok(file)
+    }
+
+    /// Downloads an NDJSON dataset, rejecting responses whose content type
+    /// says they are something else (e.g. an HTML error page).
+    pub async fn fetch_ndjson(&self, url: &str, max_bytes: usize) -> Result<String, String> {
+        if !self.allow_private_hosts {
+            validate_download_url(url, &self.allowed_hosts, &self.dns).await?;
+        }
+
+        let mut request = self.client.get(url);
+        if let Some(user_agent) = &self.user_agent {
+            request = request.header(reqwest::header::USER_AGENT, user_agent);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        if !is_ndjson_content_type(content_type) {
+            return Err(format!(
+                "Unexpected content type '{}' for NDJSON",
+                content_type.unwrap_or_default()
+            ));
+        }
+
+        let bytes = read_response_with_limit(response, max_bytes, None).await?;
+        String::from_utf8(bytes).map_err(|_| "NDJSON is not valid UTF-8".to_string())
+    }
+
+    /// Sends a HEAD request to one URL, after the same checks as a
+    /// download, and returns the response status.
+    async fn head(&self, url: &str) -> Result<reqwest::StatusCode, String> {
+        if !self.allow_private_hosts {
+            validate_download_url(url, &self.allowed_hosts, &self.dns).await?;
+        }
+
+        let mut request = self.client.head(url);
+        if let Some(user_agent) = &self.user_agent {
+            request = request.header(reqwest::header::USER_AGENT, user_agent);
+        }
+        if let Some(referer) = self
+            .referer
+            .as_ref()
+            .and_then(|referer| referer.header_value(url))
+        {
+            request = request.header(reqwest::header::REFERER, referer);
+        }
+        request
+            .send()
+            .await
+            .map(|response| response.status())
+            .map_err(|e| format!("Request failed: {}", e))
+    }
+
+    /// Checks which of `urls` answer a HEAD request successfully, without
+    /// downloading any body. Results keep the order of `urls`.
+    pub async fn check_urls(&self, urls: &[String]) -> Vec<UrlStatus> {
+        stream::iter(urls)
+            .map(|url| async move {
+                match self.head(url).await {
+                    Ok(status) => UrlStatus {
+                        url: url.clone(),
+                        reachable: status.is_success(),
+                        status: Some(status.as_u16()),
+                        error: None,
+                    },
+                    Err(error) => UrlStatus {
+                        url: url.clone(),
+                        reachable: false,
+                        status: None,
+                        error: Some(error),
+                    },
+                }
+            })
+            .buffered(self.concurrency)
+            .collect()
+            .await
+    }
+
     pub async fn download_all(
         &self,
         images: &[ImageEntry],
@@ -46,92 +609,104 @@ impl Downloader {
     ) -> DownloadResult {
         let images_with_urls: Vec<_> = images
             .iter()
-            .filter(|img| !img.url.is_empty())
             .map(|img| {
                 let split = normalize_split(&img.split);
                 let item_label = format!("{}/{}", split, img.effective_file_name());
                 let download_key = image_entry_download_key(img);
-                (item_label, download_key, img.url.clone())
+                (item_label, download_key, img.download_urls())
             })
+            .filter(|(_, _, urls)| !urls.is_empty())
             .collect();
 
         let total = images_with_urls.len() as u32;
 
         if total == 0 {
-            return DownloadResult {
-                files: HashMap::new(),
-                total: 0,
-                failed: 0,
-            };
+            return DownloadResult::default();
         }
 
-        let _ = channel.send(ProgressEvent {
-            phase: "downloading".to_string(),
-            current: 0,
-            total,
-            item: None,
-        });
+        let _ = channel.send(ProgressEvent::new("downloading", 0, total, None));
 
         let downloaded = Arc::new(Mutex::new(HashMap::new()));
+        let mirrored = Arc::new(Mutex::new(HashMap::new()));
         let counter = Arc::new(AtomicU32::new(0));
         let failed = Arc::new(AtomicU32::new(0));
-        let client = self.client.clone();
+        let timed_out = Arc::new(AtomicU32::new(0));
+        let eta = Arc::new(std::sync::Mutex::new(EtaEstimator::default()));
 
-        stream::iter(images_with_urls)
-            .map(|(item_label, download_key, url)| {
-                let client = client.clone();
+        stream::iter(images_with_urls.into_iter().enumerate())
+            .map(|(index, (item_label, download_key, urls))| {
+                let spool_path = self
+                    .spool_dir
+                    .as_ref()
+                    .map(|dir| dir.join(format!("{}.img", index)));
                 let downloaded = Arc::clone(&downloaded);
+                let mirrored = Arc::clone(&mirrored);
                 let counter = Arc::clone(&counter);
                 let failed = Arc::clone(&failed);
+                let timed_out = Arc::clone(&timed_out);
+                let eta = Arc::clone(&eta);
                 let channel = channel.clone();
 
                 async move {
-                    if let Err(err) = validate_download_url(&url).await {
-                        eprintln!("Skipping download for '{}': {}", item_label, err);
-                        failed.fetch_add(1, Ordering::SeqCst);
-                        let current = counter.fetch_add(1, Ordering::SeqCst) + 1;
-                        let _ = channel.send(ProgressEvent {
-                            phase: "downloading".to_string(),
-                            current,
-                            total,
-                            item: Some(item_label.clone()),
-                        });
-                        return;
-                    }
-
-                    match client.get(&url).send().await {
-                        Ok(response) => {
-                            if response.status().is_success() {
-                                match read_response_with_limit(response, MAX_DOWNLOAD_BYTES).await {
-                                    Ok(bytes) => {
-                                        let mut map = downloaded.lock().await;
-                                        map.insert(download_key.clone(), bytes);
-                                    }
-                                    Err(err) => {
-                                        eprintln!(
-                                            "Skipping download for '{}': {}",
-                                            item_label, err
-                                        );
-                                        failed.fetch_add(1, Ordering::SeqCst);
+                    // Try the primary URL, then each mirror, until one succeeds.
+                    let mut succeeded = false;
+                    let mut out_of_time = false;
+                    for (attempt, url) in urls.iter().enumerate() {
+                        if self.deadline_passed() {
+                            out_of_time = true;
+                            break;
+                        }
+                        let fetch = self.fetch(url, spool_path.as_deref());
+                        let result = match self.deadline {
+                            Some(deadline) => {
+                                match tokio::time::timeout_at(deadline, fetch).await {
+                                    Ok(result) => result,
+                                    Err(_) => {
+                                        out_of_time = true;
+                                        break;
                                     }
                                 }
-                            } else {
-                                failed.fetch_add(1, Ordering::SeqCst);
+                            }
+                            None => fetch.await,
+                        };
+                        match result {
+                            Ok(file) => {
+                                downloaded.lock().await.insert(download_key.clone(), file);
+                                if attempt > 0 {
+                                    mirrored
+                                        .lock()
+                                        .await
+                                        .insert(download_key.clone(), url.clone());
+                                }
+                                succeeded = true;
+                                break;
+                            }
+                            Err(err) => {
+                                eprintln!(
+                                    "Failed to download '{}' (source {} of {}): {}",
+                                    item_label,
+                                    attempt + 1,
+                                    urls.len(),
+                                    err
+                                );
                             }
                         }
-                        Err(e) => {
-                            eprintln!("Failed to download '{}': {}", item_label, e);
-                            failed.fetch_add(1, Ordering::SeqCst);
+                    }
+                    if !succeeded {
+                        failed.fetch_add(1, Ordering::SeqCst);
+                        if out_of_time {
+                            timed_out.fetch_add(1, Ordering::SeqCst);
                         }
                     }
 
                     let current = counter.fetch_add(1, Ordering::SeqCst) + 1;
-                    let _ = channel.send(ProgressEvent {
-                        phase: "downloading".to_string(),
-                        current,
-                        total,
-                        item: Some(item_label),
+                    let eta_seconds = eta.lock().ok().and_then(|mut eta| {
+                        eta.estimate("downloading", current, total, std::time::Instant::now())
                     });
+                    let _ = channel.send(
+                        ProgressEvent::new("downloading", current, total, Some(item_label))
+                            .with_eta(eta_seconds),
+                    );
                 }
             })
             .buffer_unordered(self.concurrency)
@@ -142,6 +717,10 @@ impl Downloader {
             Ok(mutex) => mutex.into_inner(),
             Err(arc) => arc.lock().await.clone(),
         };
+        let mirrored = match Arc::try_unwrap(mirrored) {
+            Ok(mutex) => mutex.into_inner(),
+            Err(arc) => arc.lock().await.clone(),
+        };
 
         let failed_count = match Arc::try_unwrap(failed) {
             Ok(counter) => counter.into_inner(),
@@ -152,17 +731,163 @@ impl Downloader {
             files,
             total,
             failed: failed_count as usize,
+            timed_out: timed_out.load(Ordering::SeqCst) as usize,
+            mirrored,
         }
     }
 }
 
+#[derive(Default)]
 pub struct DownloadResult {
-    pub files: HashMap<String, Vec<u8>>,
+    pub files: HashMap<String, FileData>,
     pub total: u32,
     pub failed: usize,
+    /// Images not downloaded because the deadline passed. Included in
+    /// `failed`.
+    pub timed_out: usize,
+    /// Download key to the mirror URL that served it, for images whose
+    /// primary URL failed.
+    pub mirrored: HashMap<String, String>,
+}
+
+/// Outcome of checking one image URL with a HEAD request.
+#[derive(Debug, Serialize)]
+pub struct UrlStatus {
+    pub url: String,
+    /// True for a 2xx response.
+    pub reachable: bool,
+    /// HTTP status, when the server answered.
+    pub status: Option<u16>,
+    /// Why no response was received, e.g. a blocked host or a DNS failure.
+    pub error: Option<String>,
+}
+
+const SVG_UNSUPPORTED: &str = "Unsupported image type: SVG images can't be used for training";
+
+/// Whether a download is an SVG, by its `image/svg+xml` content type or a
+/// document in `head` whose first element is `<svg`.
+fn is_svg(content_type: Option<&str>, head: &[u8]) -> bool {
+    let svg_type = content_type.is_some_and(|content_type| {
+        content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .eq_ignore_ascii_case("image/svg+xml")
+    });
+    svg_type || starts_with_svg_element(head)
+}
+
+/// Whether the first element of `head` is `<svg`, past a BOM, whitespace, an
+/// XML declaration, comments and a doctype. Binary images that merely contain
+/// `<svg` somewhere don't count.
+fn starts_with_svg_element(head: &[u8]) -> bool {
+    fn after<'a>(bytes: &'a [u8], end: &[u8]) -> Option<&'a [u8]> {
+        bytes
+            .windows(end.len())
+            .position(|window| window == end)
+            .map(|at| &bytes[at + end.len()..])
+    }
+    fn starts_with_ignore_case(bytes: &[u8], prefix: &[u8]) -> bool {
+        bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+    }
+
+    let mut rest = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    loop {
+        rest = rest.trim_ascii_start();
+        let skipped = if rest.starts_with(b"<?") {
+            after(rest, b"?>")
+        } else if rest.starts_with(b"<!--") {
+            after(rest, b"-->")
+        } else if starts_with_ignore_case(rest, b"<!doctype") {
+            // An internal subset may hold `>` of its own.
+            match (
+                rest.iter().position(|&b| b == b'['),
+                rest.iter().position(|&b| b == b'>'),
+            ) {
+                (Some(open), Some(close)) if open < close => {
+                    after(rest, b"]").and_then(|rest| after(rest, b">"))
+                }
+                _ => after(rest, b">"),
+            }
+        } else {
+            break;
+        };
+        match skipped {
+            Some(skipped) => rest = skipped,
+            None => return false,
+        }
+    }
+    starts_with_ignore_case(rest, b"<svg")
+        && rest
+            .get(4)
+            .is_none_or(|&b| b.is_ascii_whitespace() || b == b'>' || b == b'/')
+}
+
+/// The first bytes of a download, enough to sniff an SVG behind an XML
+/// declaration or comment.
+fn file_head(file: &FileData) -> Vec<u8> {
+    const HEAD_BYTES: usize = 1024;
+    match file {
+        FileData::Bytes(bytes) => bytes[..bytes.len().min(HEAD_BYTES)].to_vec(),
+        FileData::OnDisk(path) => {
+            use std::io::Read;
+            let mut head = Vec::with_capacity(HEAD_BYTES);
+            if let Ok(file) = std::fs::File::open(path) {
+                let _ = file.take(HEAD_BYTES as u64).read_to_end(&mut head);
+            }
+            head
+        }
+    }
+}
+
+/// Content types NDJSON is commonly served with. A missing header is
+/// accepted, as static hosts often omit it for unknown extensions.
+fn is_ndjson_content_type(content_type: Option<&str>) -> bool {
+    let Some(content_type) = content_type else {
+        return true;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    matches!(
+        mime.as_str(),
+        "application/x-ndjson"
+            | "application/ndjson"
+            | "application/jsonl"
+            | "application/x-jsonlines"
+            | "application/json"
+            | "application/octet-stream"
+            | "text/plain"
+    )
 }
 
-async fn validate_download_url(url: &str) -> Result<(), String> {
+/// Whether `host` matches an allowlist entry. `*.example.com` matches
+/// subdomains of example.com, `example.com` matches it and its subdomains,
+/// and `*` matches every host. An empty allowlist allows all hosts.
+fn host_allowed(host: &str, allowed_hosts: &[String]) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    allowed_hosts.is_empty()
+        || allowed_hosts.iter().any(|pattern| {
+            let pattern = pattern.trim().trim_end_matches('.').to_ascii_lowercase();
+            if pattern == "*" {
+                return true;
+            }
+            match pattern.strip_prefix("*.") {
+                Some(domain) => host.ends_with(&format!(".{}", domain)),
+                None => host == pattern || host.ends_with(&format!(".{}", pattern)),
+            }
+        })
+}
+
+async fn validate_download_url(
+    url: &str,
+    allowed_hosts: &[String],
+    dns: &DnsCache,
+) -> Result<(), String> {
     let parsed = Url::parse(url).map_err(|_| "Invalid URL".to_string())?;
     match parsed.scheme() {
         "http" | "https" => {}
@@ -172,6 +897,11 @@ async fn validate_download_url(url: &str) -> Result<(), String> {
     let host = parsed
         .host()
         .ok_or_else(|| "URL must include a hostname".to_string())?;
+    let host_name = host.to_string();
+    let host_name = host_name.trim_start_matches('[').trim_end_matches(']');
+    if !host_allowed(host_name, allowed_hosts) {
+        return Err(format!("Host '{}' is not in the allowed hosts", host_name));
+    }
     match host {
         Host::Ipv4(v4) => {
             if is_forbidden_ip(IpAddr::V4(v4)) {
@@ -192,20 +922,11 @@ async fn validate_download_url(url: &str) -> Result<(), String> {
                 return Err("Localhost addresses are not allowed".to_string());
             }
 
-            let port = parsed.port_or_known_default().unwrap_or(80);
-            let mut addrs = tokio::net::lookup_host((domain, port))
-                .await
-                .map_err(|_| "Failed to resolve download host".to_string())?;
-            let mut resolved_any = false;
-
-            for addr in addrs.by_ref() {
-                resolved_any = true;
-                if is_forbidden_ip(addr.ip()) {
-                    return Err("Private or local IPs are not allowed".to_string());
-                }
+            let addrs = dns.resolve(&host_lower).await?;
+            if addrs.iter().any(|ip| is_forbidden_ip(*ip)) {
+                return Err("Private or local IPs are not allowed".to_string());
             }
-
-            if !resolved_any {
+            if addrs.is_empty() {
                 return Err("Failed to resolve download host".to_string());
             }
         }
@@ -238,10 +959,7 @@ fn is_forbidden_ip(ip: IpAddr) -> bool {
     }
 }
 
-async fn read_response_with_limit(
-    response: reqwest::Response,
-    max_bytes: usize,
-) -> Result<Vec<u8>, String> {
+fn check_content_length(response: &reqwest::Response, max_bytes: usize) -> Result<(), String> {
     if let Some(content_length) = response.content_length() {
         if content_length > max_bytes as u64 {
             return Err(format!(
@@ -250,7 +968,22 @@ async fn read_response_with_limit(
             ));
         }
     }
+    Ok(())
+}
 
+/// Reads a response body into memory, charging it against `budget` as it
+/// arrives when one is given.
+async fn read_response_with_limit(
+    response: reqwest::Response,
+    max_bytes: usize,
+    budget: Option<&ByteBudget>,
+) -> Result<Vec<u8>, String> {
+    check_content_length(&response, max_bytes)?;
+
+    let mut reservation = match budget {
+        Some(budget) => budget.reserve(response.content_length()).await,
+        None => None,
+    };
     let mut downloaded = Vec::new();
     let mut stream = response.bytes_stream();
     let mut total_bytes = 0usize;
@@ -265,59 +998,783 @@ async fn read_response_with_limit(
             return Err(format!("Response too large (max {} bytes)", max_bytes));
         }
 
+        if let Some(budget) = budget {
+            budget.grow(&mut reservation, total_bytes as u64).await;
+        }
         downloaded.extend_from_slice(&chunk);
     }
 
     Ok(downloaded)
 }
 
+async fn write_response_with_limit(
+    response: reqwest::Response,
+    max_bytes: usize,
+    path: &Path,
+) -> Result<(), String> {
+    check_content_length(&response, max_bytes)?;
+
+    let result = async {
+        let mut file = tokio::fs::File::create(path)
+            .await
+            .map_err(|e| format!("Failed to create spool file: {}", e))?;
+        let mut stream = response.bytes_stream();
+        let mut total_bytes = 0usize;
+
+        while let Some(chunk_result) = stream.next().await {
+            let chunk = chunk_result.map_err(|e| format!("Failed to read response body: {}", e))?;
+            total_bytes = total_bytes
+                .checked_add(chunk.len())
+                .ok_or_else(|| "Response body size overflow".to_string())?;
+
+            if total_bytes > max_bytes {
+                return Err(format!("Response too large (max {} bytes)", max_bytes));
+            }
+
+            file.write_all(&chunk)
+                .await
+                .map_err(|e| format!("Failed to write spool file: {}", e))?;
+        }
+
+        file.flush()
+            .await
+            .map_err(|e| format!("Failed to write spool file: {}", e))
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+    result
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parser::image_download_key;
+    use tokio::io::AsyncReadExt;
+
+    type Route = (&'static str, u16, &'static [u8], u64);
+
+    /// Serves fixed `(path, status, body, delay_ms)` routes over plain HTTP on
+    /// a random localhost port. Returns its base URL and a log of the raw
+    /// request heads it received.
+    async fn serve(routes: &'static [Route]) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let log = Arc::clone(&log);
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 4096];
+                    let n = socket.read(&mut buf).await.unwrap_or(0);
+                    let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                    log.lock().await.push(request.clone());
+                    let path = request.split_whitespace().nth(1).unwrap_or("/");
+                    let (status, body, delay_ms) = routes
+                        .iter()
+                        .find(|(route, _, _, _)| *route == path)
+                        .map(|(_, status, body, delay_ms)| (*status, *body, *delay_ms))
+                        .unwrap_or((404, b"", 0));
+                    tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
+                    let content_type = match path.rsplit_once('.') {
+                        Some((_, "ndjson")) => "application/x-ndjson",
+                        Some((_, "html")) => "text/html",
+                        Some((_, "svg")) => "image/svg+xml",
+                        _ => "application/octet-stream",
+                    };
+                    let encoding = if body.starts_with(b"\x1f\x8b") {
+                        "Content-Encoding: gzip\r\n"
+                    } else {
+                        ""
+                    };
+                    let head = format!(
+                        "HTTP/1.1 {} Status\r\nContent-Type: {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        content_type,
+                        encoding,
+                        body.len()
+                    );
+                    let _ = socket.write_all(head.as_bytes()).await;
+                    let _ = socket.write_all(body).await;
+                });
+            }
+        });
+        (format!("http://{}", addr), requests)
+    }
+
+    /// Answers every request with a redirect to `location`. Returns its base
+    /// URL.
+    async fn redirect_to(location: String) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = vec![0u8; 4096];
+                let _ = socket.read(&mut buf).await;
+                let head = format!(
+                    "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    location
+                );
+                let _ = socket.write_all(head.as_bytes()).await;
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    fn image_with_mirrors(url: String, mirrors: Vec<String>) -> ImageEntry {
+        image_named("img1.jpg", url, mirrors)
+    }
+
+    fn image_named(file: &str, url: String, mirrors: Vec<String>) -> ImageEntry {
+        ImageEntry {
+            r#type: "image".to_string(),
+            file: file.to_string(),
+            output_file: None,
+            url,
+            mirrors,
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: "train".to_string(),
+            annotations: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn download_falls_back_to_mirror_when_primary_fails() {
+        static ROUTES: [Route; 2] = [
+            ("/primary.jpg", 503, b"unavailable", 0),
+            ("/mirror.jpg", 200, b"mirror bytes", 0),
+        ];
+        let (base, _) = serve(&ROUTES).await;
+        let mirror = format!("{}/mirror.jpg", base);
+        let images = vec![image_with_mirrors(
+            format!("{}/primary.jpg", base),
+            vec![mirror.clone()],
+        )];
+        let downloader = Downloader::new(2).unwrap().allow_private_hosts();
+
+        let result = downloader
+            .download_all(&images, &Channel::new(|_| Ok(())))
+            .await;
+
+        let key = image_download_key("train", "img1.jpg");
+        assert_eq!(result.total, 1);
+        assert_eq!(result.failed, 0);
+        assert_eq!(
+            result.files[&key],
+            FileData::Bytes(b"mirror bytes".to_vec())
+        );
+        assert_eq!(result.mirrored[&key], mirror);
+    }
+
+    #[tokio::test]
+    async fn in_flight_bytes_stay_within_the_budget() {
+        static ROUTES: [Route; 1] = [("/img.jpg", 200, &[0u8; 100], 20)];
+        let (base, _) = serve(&ROUTES).await;
+        let images: Vec<ImageEntry> = (0..6)
+            .map(|i| image_named(&format!("{}.jpg", i), format!("{}/img.jpg", base), vec![]))
+            .collect();
+        let downloader = Downloader::new(6)
+            .unwrap()
+            .allow_private_hosts()
+            .with_max_inflight_bytes(250);
+
+        let result = downloader
+            .download_all(&images, &Channel::new(|_| Ok(())))
+            .await;
+
+        assert_eq!(result.failed, 0);
+        assert_eq!(result.files.len(), 6);
+        let peak = downloader
+            .byte_budget
+            .as_ref()
+            .unwrap()
+            .peak
+            .load(Ordering::SeqCst);
+        assert!(peak > 0 && peak <= 250, "peak {}", peak);
+    }
+
+    #[tokio::test]
+    async fn chunked_responses_are_charged_for_the_bytes_read() {
+        const BODY_BYTES: usize = 6 * 1024 * 1024;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let _ = socket.read(&mut buf).await;
+            let head = "HTTP/1.1 200 OK\r\nContent-Type: image/jpeg\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n";
+            let _ = socket.write_all(head.as_bytes()).await;
+            let chunk = vec![0xffu8; 64 * 1024];
+            for _ in 0..BODY_BYTES / chunk.len() {
+                let _ = socket
+                    .write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+                    .await;
+                let _ = socket.write_all(&chunk).await;
+                let _ = socket.write_all(b"\r\n").await;
+            }
+            let _ = socket.write_all(b"0\r\n\r\n").await;
+        });
+        let downloader = Downloader::new(1)
+            .unwrap()
+            .allow_private_hosts()
+            .with_max_inflight_bytes(16 * 1024 * 1024);
+
+        let file = downloader
+            .fetch(&format!("http://{}/img.jpg", addr), None)
+            .await
+            .unwrap();
+
+        assert_eq!(file.read().unwrap().len(), BODY_BYTES);
+        let peak = downloader
+            .byte_budget
+            .as_ref()
+            .unwrap()
+            .peak
+            .load(Ordering::SeqCst);
+        assert_eq!(peak, BODY_BYTES as u64);
+    }
+
+    #[tokio::test]
+    async fn redirects_are_checked_against_the_allowed_hosts() {
+        static ROUTES: [Route; 1] = [("/img.jpg", 200, b"image bytes", 0)];
+        let (base, requests) = serve(&ROUTES).await;
+        let downloader = Downloader::new(1)
+            .unwrap()
+            .allow_private_hosts()
+            .with_allowed_hosts(vec!["127.0.0.1".to_string()])
+            .unwrap();
+
+        let allowed = redirect_to(format!("{}/img.jpg", base)).await;
+        let file = downloader
+            .fetch(&format!("{}/moved.jpg", allowed), None)
+            .await
+            .unwrap();
+        assert_eq!(file, FileData::Bytes(b"image bytes".to_vec()));
+
+        let elsewhere = base.replace("127.0.0.1", "localhost");
+        let disallowed = redirect_to(format!("{}/img.jpg", elsewhere)).await;
+        assert!(downloader
+            .fetch(&format!("{}/moved.jpg", disallowed), None)
+            .await
+            .is_err());
+        assert_eq!(requests.lock().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn http2_prior_knowledge_sends_the_http2_preface() {
+        static ROUTES: [Route; 0] = [];
+        let (base, requests) = serve(&ROUTES).await;
+        let downloader = Downloader::new(1)
+            .unwrap()
+            .allow_private_hosts()
+            .with_connection_settings(&ConnectionSettings {
+                http2_prior_knowledge: true,
+                ..Default::default()
+            })
+            .unwrap();
+
+        // The plain HTTP/1 test server can't answer, so the request fails.
+        assert!(downloader
+            .fetch(&format!("{}/img.jpg", base), None)
+            .await
+            .is_err());
+
+        let requests = requests.lock().await;
+        assert!(requests[0].starts_with("PRI * HTTP/2.0"), "{:?}", requests);
+    }
+
+    #[test]
+    fn eta_falls_at_a_steady_rate_and_resets_between_phases() {
+        let start = std::time::Instant::now();
+        let at = |secs: u64| start + std::time::Duration::from_secs(secs);
+        let mut eta = EtaEstimator::default();
+
+        assert_eq!(eta.estimate("downloading", 0, 100, at(0)), None);
+        let estimates: Vec<_> = (1..=5)
+            .map(|step| eta.estimate("downloading", step * 10, 100, at(u64::from(step))))
+            .collect();
+
+        // Ten items a second leaves 9, 8, ... seconds.
+        assert_eq!(estimates, [Some(9), Some(8), Some(7), Some(6), Some(5)]);
+        assert_eq!(eta.estimate("zipping", 5, 50, at(6)), None);
+        assert_eq!(eta.estimate("zipping", 30, 50, at(7)), Some(1));
+    }
+
+    #[tokio::test]
+    async fn check_urls_reports_reachable_and_broken_urls() {
+        static ROUTES: [Route; 1] = [("/ok.jpg", 200, b"image bytes", 0)];
+        let (base, requests) = serve(&ROUTES).await;
+        let urls = vec![
+            format!("{}/ok.jpg", base),
+            format!("{}/missing.jpg", base),
+            "http://127.0.0.1:1/closed.jpg".to_string(),
+        ];
+        let downloader = Downloader::new(2).unwrap().allow_private_hosts();
+
+        let statuses = downloader.check_urls(&urls).await;
+
+        let summary: Vec<_> = statuses
+            .iter()
+            .map(|status| (status.url.as_str(), status.reachable, status.status))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (urls[0].as_str(), true, Some(200)),
+                (urls[1].as_str(), false, Some(404)),
+                (urls[2].as_str(), false, None),
+            ]
+        );
+        assert!(statuses[2].error.is_some());
+        assert!(requests
+            .lock()
+            .await
+            .iter()
+            .all(|request| request.starts_with("HEAD ")));
+    }
+
+    #[tokio::test]
+    async fn svg_responses_are_rejected() {
+        static ROUTES: [Route; 5] = [
+            (
+                "/logo.svg",
+                200,
+                b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+                0,
+            ),
+            (
+                "/sniffed.jpg",
+                200,
+                b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+                0,
+            ),
+            (
+                "/commented.jpg",
+                200,
+                b"\xef\xbb\xbf <!-- exported --><!DOCTYPE svg [<!ENTITY a \"b\">]>\n<SVG/>",
+                0,
+            ),
+            ("/photo.jpg", 200, b"\xff\xd8\xff jpeg bytes", 0),
+            (
+                "/embedded.png",
+                200,
+                b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR tEXt<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+                0,
+            ),
+        ];
+        let (base, _) = serve(&ROUTES).await;
+        let downloader = Downloader::new(2).unwrap().allow_private_hosts();
+
+        for path in ["logo.svg", "sniffed.jpg", "commented.jpg"] {
+            let err = downloader
+                .fetch(&format!("{}/{}", base, path), None)
+                .await
+                .unwrap_err();
+            assert!(err.contains("SVG"), "{}: {}", path, err);
+        }
+        for path in ["photo.jpg", "embedded.png"] {
+            assert!(downloader
+                .fetch(&format!("{}/{}", base, path), None)
+                .await
+                .is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn gzip_encoded_responses_are_decoded_before_sniffing() {
+        fn gzip(bytes: &[u8]) -> &'static [u8] {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            std::io::Write::write_all(&mut encoder, bytes).unwrap();
+            encoder.finish().unwrap().leak()
+        }
+        let routes: &'static [Route] = vec![
+            ("/photo.jpg", 200, gzip(b"\xff\xd8\xff jpeg bytes"), 0),
+            ("/logo.jpg", 200, gzip(b"<svg/>"), 0),
+        ]
+        .leak();
+        let (base, _) = serve(routes).await;
+        let downloader = Downloader::new(2).unwrap().allow_private_hosts();
+
+        let photo = downloader
+            .fetch(&format!("{}/photo.jpg", base), None)
+            .await
+            .unwrap();
+        assert_eq!(photo.as_bytes().unwrap(), b"\xff\xd8\xff jpeg bytes");
+        let err = downloader
+            .fetch(&format!("{}/logo.jpg", base), None)
+            .await
+            .unwrap_err();
+        assert!(err.contains("SVG"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn skipped_images_are_not_downloaded_labelled_or_bundled() {
+        static ROUTES: [Route; 2] = [
+            ("/keep.jpg", 200, b"keep bytes", 0),
+            ("/skip.jpg", 200, b"skip bytes", 0),
+        ];
+        let (base, requests) = serve(&ROUTES).await;
+        let content = format!(
+            r#"{{"type":"dataset","name":"test","class_names":{{"0":"cat"}}}}
+{{"type":"image","file":"keep.jpg","width":640,"height":480,"split":"train","url":"{base}/keep.jpg","annotations":{{"boxes":[[0,0.5,0.5,0.2,0.2]]}}}}
+{{"type":"image","file":"skip.jpg","skip":true,"width":640,"height":480,"split":"train","url":"{base}/skip.jpg","annotations":{{"boxes":[[0,0.5,0.5,0.2,0.2]]}}}}"#
+        );
+        let mut data = crate::parser::parse_ndjson(&content).unwrap();
+
+        assert_eq!(data.remove_skipped_images(), 1);
+        let result = Downloader::new(2)
+            .unwrap()
+            .allow_private_hosts()
+            .download_all(&data.images, &Channel::new(|_| Ok(())))
+            .await;
+        let files = crate::converter::get_converter("yolo", &Default::default())
+            .unwrap()
+            .convert(&data, &result.files);
+
+        assert_eq!(result.total, 1);
+        assert!(requests
+            .lock()
+            .await
+            .iter()
+            .all(|request| !request.contains("/skip.jpg")));
+        assert!(files.contains_key("train/images/keep.jpg"));
+        assert!(files.contains_key("train/labels/keep.txt"));
+        assert!(files.keys().all(|path| !path.contains("skip")));
+    }
+
+    #[tokio::test]
+    async fn excluded_splits_keep_labels_but_are_not_downloaded() {
+        static ROUTES: [Route; 2] = [
+            ("/train.jpg", 200, b"train bytes", 0),
+            ("/valid.jpg", 200, b"valid bytes", 0),
+        ];
+        let (base, requests) = serve(&ROUTES).await;
+        let content = format!(
+            r#"{{"type":"dataset","name":"test","class_names":{{"0":"cat"}}}}
+{{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","url":"{base}/train.jpg","annotations":{{"boxes":[[0,0.5,0.5,0.2,0.2]]}}}}
+{{"type":"image","file":"b.jpg","width":640,"height":480,"split":"val","url":"{base}/valid.jpg","annotations":{{"boxes":[[0,0.5,0.5,0.2,0.2]]}}}}"#
+        );
+        let data = crate::parser::parse_ndjson(&content).unwrap();
+        let options = crate::options::ConvertOptions {
+            download_splits: Some(vec!["train".to_string()]),
+            ..Default::default()
+        };
+
+        let result = Downloader::new(2)
+            .unwrap()
+            .allow_private_hosts()
+            .download_all(
+                &options.images_to_fetch(&data.images),
+                &Channel::new(|_| Ok(())),
+            )
+            .await;
+        let files = crate::converter::get_converter("yolo", &options)
+            .unwrap()
+            .convert(&data, &result.files);
+
+        assert_eq!(result.total, 1);
+        assert!(requests
+            .lock()
+            .await
+            .iter()
+            .all(|request| !request.contains("/valid.jpg")));
+        assert!(files.contains_key("train/images/a.jpg"));
+        assert!(files.contains_key("valid/labels/b.txt"));
+        assert!(!files.contains_key("valid/images/b.jpg"));
+    }
+
+    #[tokio::test]
+    async fn fetch_ndjson_accepts_ndjson_and_rejects_html() {
+        static ROUTES: [Route; 2] = [
+            (
+                "/data.ndjson",
+                200,
+                b"{\"type\":\"dataset\",\"name\":\"remote\",\"class_names\":{}}\n",
+                0,
+            ),
+            ("/login.html", 200, b"<html></html>", 0),
+        ];
+        let (base, _) = serve(&ROUTES).await;
+        let downloader = Downloader::new(1).unwrap().allow_private_hosts();
+
+        let content = downloader
+            .fetch_ndjson(&format!("{}/data.ndjson", base), 1024)
+            .await
+            .unwrap();
+        let data = crate::parser::parse_ndjson(&content).unwrap();
+        assert_eq!(data.metadata.name, "remote");
+
+        let err = downloader
+            .fetch_ndjson(&format!("{}/login.html", base), 1024)
+            .await
+            .unwrap_err();
+        assert!(err.contains("text/html"));
+    }
+
+    #[test]
+    fn ndjson_content_types_are_recognized() {
+        assert!(is_ndjson_content_type(Some("application/x-ndjson")));
+        assert!(is_ndjson_content_type(Some("application/jsonl")));
+        assert!(is_ndjson_content_type(Some("text/plain; charset=utf-8")));
+        assert!(is_ndjson_content_type(None));
+        assert!(!is_ndjson_content_type(Some("text/html")));
+    }
+
+    #[tokio::test]
+    async fn download_fails_when_every_mirror_fails() {
+        static ROUTES: [Route; 1] = [("/primary.jpg", 503, b"unavailable", 0)];
+        let (base, _) = serve(&ROUTES).await;
+        let images = vec![image_with_mirrors(
+            format!("{}/primary.jpg", base),
+            vec![format!("{}/missing.jpg", base)],
+        )];
+        let downloader = Downloader::new(2).unwrap().allow_private_hosts();
+
+        let result = downloader
+            .download_all(&images, &Channel::new(|_| Ok(())))
+            .await;
+
+        assert_eq!(result.failed, 1);
+        assert!(result.files.is_empty());
+        assert!(result.mirrored.is_empty());
+    }
+
+    #[tokio::test]
+    async fn deadline_keeps_finished_downloads_and_times_out_the_rest() {
+        static ROUTES: [Route; 2] = [
+            ("/fast.jpg", 200, b"fast bytes", 0),
+            ("/slow.jpg", 200, b"slow bytes", 10_000),
+        ];
+        let (base, _) = serve(&ROUTES).await;
+        let images = vec![
+            image_named("a.jpg", format!("{}/fast.jpg", base), vec![]),
+            image_named("b.jpg", format!("{}/slow.jpg", base), vec![]),
+            image_named("c.jpg", format!("{}/fast.jpg", base), vec![]),
+        ];
+        let started = Instant::now();
+        let downloader = Downloader::new(1)
+            .unwrap()
+            .allow_private_hosts()
+            .with_deadline(started + std::time::Duration::from_millis(500));
+
+        let result = downloader
+            .download_all(&images, &Channel::new(|_| Ok(())))
+            .await;
+
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(result.total, 3);
+        assert!(result
+            .files
+            .contains_key(&image_download_key("train", "a.jpg")));
+        assert_eq!(result.files.len(), 1);
+        assert_eq!(result.timed_out, 2);
+        assert_eq!(result.failed, 2);
+    }
+
+    #[tokio::test]
+    async fn requests_carry_configured_user_agent_and_origin_referer() {
+        static ROUTES: [Route; 1] = [("/img.jpg", 200, b"bytes", 0)];
+        let (base, requests) = serve(&ROUTES).await;
+        let images = vec![image_with_mirrors(format!("{}/img.jpg", base), vec![])];
+        let downloader = Downloader::new(1)
+            .unwrap()
+            .allow_private_hosts()
+            .with_user_agent("Mozilla/5.0 (TestBrowser)".to_string())
+            .with_referer(Referer::Origin);
+
+        let result = downloader
+            .download_all(&images, &Channel::new(|_| Ok(())))
+            .await;
+
+        assert_eq!(result.failed, 0);
+        let request = requests.lock().await[0].to_ascii_lowercase();
+        assert!(request.contains("user-agent: mozilla/5.0 (testbrowser)\r\n"));
+        assert!(request.contains(&format!("referer: {}/\r\n", base)));
+    }
+
+    #[test]
+    fn fixed_referer_is_sent_verbatim() {
+        assert_eq!(
+            Referer::Fixed("https://site.example/gallery".to_string())
+                .header_value("https://cdn.example/a.jpg"),
+            Some("https://site.example/gallery".to_string())
+        );
+        assert_eq!(
+            Referer::Origin.header_value("https://cdn.example:8443/a/b.jpg?x=1"),
+            Some("https://cdn.example:8443/".to_string())
+        );
+    }
+
+    #[test]
+    fn download_urls_put_primary_first_and_skip_blanks_and_repeats() {
+        let image = image_with_mirrors(
+            "https://a.example/1.jpg".to_string(),
+            vec![
+                String::new(),
+                "https://a.example/1.jpg".to_string(),
+                "https://b.example/1.jpg".to_string(),
+            ],
+        );
+        assert_eq!(
+            image.download_urls(),
+            vec!["https://a.example/1.jpg", "https://b.example/1.jpg"]
+        );
+    }
+
+    #[tokio::test]
+    async fn validate_url_rejects_hosts_off_the_allowlist() {
+        let allowed = vec!["cdn.example.com".to_string(), "*.images.test".to_string()];
+
+        let err = validate_download_url("https://1.1.1.1/image.jpg", &allowed, &DnsCache::new(1))
+            .await
+            .unwrap_err();
+        assert!(err.contains("not in the allowed hosts"), "{}", err);
+        assert!(validate_download_url(
+            "https://1.1.1.1/image.jpg",
+            &["1.1.1.1".to_string()],
+            &DnsCache::new(1)
+        )
+        .await
+        .is_ok());
+    }
+
+    #[test]
+    fn allowlist_patterns_match_hosts_and_subdomains() {
+        let allowed = vec!["cdn.example.com".to_string(), "*.images.test".to_string()];
+
+        assert!(host_allowed("cdn.example.com", &allowed));
+        assert!(host_allowed("eu.CDN.example.com", &allowed));
+        assert!(host_allowed("a.images.test", &allowed));
+        assert!(!host_allowed("images.test", &allowed));
+        assert!(!host_allowed("evil-cdn.example.com", &allowed));
+        assert!(!host_allowed("attacker.example", &allowed));
+        assert!(host_allowed("attacker.example", &[]));
+        assert!(host_allowed("attacker.example", &["*".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn dns_cache_resolves_each_host_once() {
+        let dns = Arc::new(DnsCache::new(2));
+
+        let lookups = (0..8).map(|_| {
+            let dns = Arc::clone(&dns);
+            tokio::spawn(async move { dns.resolve("localhost").await })
+        });
+        for lookup in futures::future::join_all(lookups).await {
+            assert!(!lookup.unwrap().unwrap().is_empty());
+        }
+        dns.resolve("127.0.0.1").await.unwrap();
+
+        assert_eq!(dns.lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn requests_resolve_hosts_through_the_dns_cache() {
+        static ROUTES: [Route; 1] = [("/img.jpg", 200, b"image bytes", 0)];
+        let (base, _) = serve(&ROUTES).await;
+        let url = base.replace("127.0.0.1", "localhost");
+        let downloader = Downloader::new(1).unwrap().allow_private_hosts();
+
+        for _ in 0..2 {
+            let file = downloader
+                .fetch(&format!("{}/img.jpg", url), None)
+                .await
+                .unwrap();
+            assert_eq!(file, FileData::Bytes(b"image bytes".to_vec()));
+        }
+
+        assert_eq!(downloader.dns.lookups.load(Ordering::SeqCst), 1);
+    }
 
     #[tokio::test]
     async fn validate_url_accepts_public_ipv4_https() {
-        let result = validate_download_url("https://1.1.1.1/image.jpg").await;
+        let result =
+            validate_download_url("https://1.1.1.1/image.jpg", &[], &DnsCache::new(1)).await;
         assert!(result.is_ok());
     }
 
     #[tokio::test]
     async fn validate_url_accepts_public_ipv4_http() {
-        let result = validate_download_url("http://8.8.8.8/image.jpg").await;
+        let result =
+            validate_download_url("http://8.8.8.8/image.jpg", &[], &DnsCache::new(1)).await;
         assert!(result.is_ok());
     }
 
     #[tokio::test]
     async fn validate_url_rejects_localhost() {
-        let result = validate_download_url("http://127.0.0.1/image.jpg").await;
+        let result =
+            validate_download_url("http://127.0.0.1/image.jpg", &[], &DnsCache::new(1)).await;
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Private or local"));
     }
 
     #[tokio::test]
     async fn validate_url_rejects_private_ip_10() {
-        let result = validate_download_url("http://10.0.0.1/image.jpg").await;
+        let result =
+            validate_download_url("http://10.0.0.1/image.jpg", &[], &DnsCache::new(1)).await;
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Private or local"));
     }
 
     #[tokio::test]
     async fn validate_url_rejects_private_ip_192() {
-        let result = validate_download_url("http://192.168.1.1/image.jpg").await;
+        let result =
+            validate_download_url("http://192.168.1.1/image.jpg", &[], &DnsCache::new(1)).await;
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Private or local"));
     }
 
     #[tokio::test]
     async fn validate_url_rejects_ipv4_mapped_ipv6_loopback() {
-        let result = validate_download_url("http://[::ffff:127.0.0.1]/image.jpg").await;
+        let result = validate_download_url(
+            "http://[::ffff:127.0.0.1]/image.jpg",
+            &[],
+            &DnsCache::new(1),
+        )
+        .await;
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Private or local"));
     }
 
+    #[tokio::test]
+    async fn spooled_download_round_trips_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0.img");
+        let bytes: Vec<u8> = (0..=255u8).cycle().take(70_000).collect();
+        let response = reqwest::Response::from(http::Response::new(bytes.clone()));
+
+        write_response_with_limit(response, MAX_DOWNLOAD_BYTES, &path)
+            .await
+            .unwrap();
+
+        let file = FileData::OnDisk(path);
+        assert_eq!(file.read().unwrap().as_ref(), bytes.as_slice());
+    }
+
+    #[tokio::test]
+    async fn spooled_download_removes_file_over_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0.img");
+        let response = reqwest::Response::from(http::Response::new(vec![0u8; 64]));
+
+        let result = write_response_with_limit(response, 16, &path).await;
+
+        assert!(result.is_err());
+        assert!(!path.exists());
+    }
+
     #[tokio::test]
     async fn validate_url_rejects_localhost_hostname() {
-        let result = validate_download_url("http://localhost/image.jpg").await;
+        let result =
+            validate_download_url("http://localhost/image.jpg", &[], &DnsCache::new(1)).await;
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Localhost"));
     }
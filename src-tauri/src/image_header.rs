@@ -0,0 +1,302 @@
+use crate::converter::FileData;
+use crate::parser::{image_entry_download_key, normalize_split, ImageEntry};
+use std::collections::HashMap;
+
+/// Reads pixel dimensions from a PNG, JPEG, GIF or WebP header without
+/// decoding the image. Returns `None` for unknown or truncated data.
+pub fn sniff_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return png_dimensions(bytes);
+    }
+    if bytes.starts_with(&[0xFF, 0xD8]) {
+        return jpeg_dimensions(bytes);
+    }
+    if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        let width = u16::from_le_bytes([*bytes.get(6)?, *bytes.get(7)?]);
+        let height = u16::from_le_bytes([*bytes.get(8)?, *bytes.get(9)?]);
+        return Some((u32::from(width), u32::from(height)));
+    }
+    if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return webp_dimensions(bytes);
+    }
+    None
+}
+
+fn be_u16(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from(u16::from_be_bytes([
+        *bytes.get(at)?,
+        *bytes.get(at + 1)?,
+    ])))
+}
+
+fn le_u24(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(
+        u32::from(*bytes.get(at)?)
+            | u32::from(*bytes.get(at + 1)?) << 8
+            | u32::from(*bytes.get(at + 2)?) << 16,
+    )
+}
+
+fn png_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    // Signature (8) + IHDR length (4) + "IHDR" (4), then width and height.
+    if bytes.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(bytes.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(bytes.get(20..24)?.try_into().ok()?);
+    Some((width, height))
+}
+
+fn jpeg_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+    loop {
+        // Skip fill bytes preceding a marker.
+        while *bytes.get(pos)? == 0xFF && *bytes.get(pos + 1)? == 0xFF {
+            pos += 1;
+        }
+        if *bytes.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(pos + 1)?;
+        match marker {
+            // Standalone markers carry no length.
+            0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            // Start-of-frame markers (excluding DHT, JPG and DAC).
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                let height = be_u16(bytes, pos + 5)?;
+                let width = be_u16(bytes, pos + 7)?;
+                return Some((width, height));
+            }
+            0xD9 | 0xDA => return None,
+            _ => {
+                let length = be_u16(bytes, pos + 2)? as usize;
+                pos += 2 + length;
+            }
+        }
+    }
+}
+
+fn webp_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    match bytes.get(12..16)? {
+        b"VP8 " => {
+            let width = u32::from(u16::from_le_bytes([*bytes.get(26)?, *bytes.get(27)?]) & 0x3FFF);
+            let height = u32::from(u16::from_le_bytes([*bytes.get(28)?, *bytes.get(29)?]) & 0x3FFF);
+            Some((width, height))
+        }
+        b"VP8L" => {
+            let bits = u32::from_le_bytes(bytes.get(21..25)?.try_into().ok()?);
+            Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+        }
+        b"VP8X" => Some((le_u24(bytes, 24)? + 1, le_u24(bytes, 27)? + 1)),
+        _ => None,
+    }
+}
+
+/// Compares each downloaded image's real dimensions with the declared
+/// `width`/`height`. Mismatches are reported as warnings and, when `correct`
+/// is set, the entry is updated so absolute-coordinate exports use the real
+/// size. Annotations are normalized, so no other rewriting is needed.
+pub fn reconcile_dimensions(
+    images: &mut [ImageEntry],
+    downloaded_images: &HashMap<String, FileData>,
+    correct: bool,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    for image in images.iter_mut() {
+        let Some(file) = downloaded_images.get(&image_entry_download_key(image)) else {
+            continue;
+        };
+        let Ok(bytes) = file.read() else {
+            continue;
+        };
+        let Some((width, height)) = sniff_dimensions(&bytes) else {
+            continue;
+        };
+        if i64::from(width) == i64::from(image.width)
+            && i64::from(height) == i64::from(image.height)
+        {
+            continue;
+        }
+
+        warnings.push(format!(
+            "{}/{}: declared {}x{} but image is {}x{}{}",
+            normalize_split(&image.split),
+            image.effective_file_name(),
+            image.width,
+            image.height,
+            width,
+            height,
+            if correct { " (corrected)" } else { "" }
+        ));
+        if correct {
+            image.width = width as i32;
+            image.height = height as i32;
+        }
+    }
+
+    warnings
+}
+
+/// Drops downloaded images whose header declares more than `max_pixels`
+/// pixels, so a tiny file that would decode to a huge bitmap never reaches
+/// anything that decodes it. Returns one reason per rejected image.
+pub fn reject_oversized_images(
+    images: &[ImageEntry],
+    downloaded_images: &mut HashMap<String, FileData>,
+    max_pixels: u64,
+) -> Vec<String> {
+    let mut rejected = Vec::new();
+
+    for image in images {
+        let key = image_entry_download_key(image);
+        let Some(file) = downloaded_images.get(&key) else {
+            continue;
+        };
+        let Some((width, height)) = file.read().ok().and_then(|bytes| sniff_dimensions(&bytes))
+        else {
+            continue;
+        };
+        let pixels = u64::from(width) * u64::from(height);
+        if pixels > max_pixels {
+            downloaded_images.remove(&key);
+            rejected.push(format!(
+                "{}/{}: image is {}x{} ({} pixels), over the {} pixel limit",
+                normalize_split(&image.split),
+                image.effective_file_name(),
+                width,
+                height,
+                pixels,
+                max_pixels
+            ));
+        }
+    }
+
+    rejected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::image_download_key;
+
+    fn png_header(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n".to_vec();
+        bytes.extend_from_slice(&13u32.to_be_bytes());
+        bytes.extend_from_slice(b"IHDR");
+        bytes.extend_from_slice(&width.to_be_bytes());
+        bytes.extend_from_slice(&height.to_be_bytes());
+        bytes.extend_from_slice(&[8, 2, 0, 0, 0]);
+        bytes
+    }
+
+    fn jpeg_header(width: u16, height: u16) -> Vec<u8> {
+        let mut bytes = vec![0xFF, 0xD8];
+        // APP0 segment that must be skipped before the frame header.
+        bytes.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00]);
+        bytes.extend_from_slice(&[0xFF, 0xC0, 0x00, 0x11, 0x08]);
+        bytes.extend_from_slice(&height.to_be_bytes());
+        bytes.extend_from_slice(&width.to_be_bytes());
+        bytes.extend_from_slice(&[0x03, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        bytes
+    }
+
+    fn image(split: &str, file: &str, width: i32, height: i32) -> ImageEntry {
+        ImageEntry {
+            r#type: "image".to_string(),
+            file: file.to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width,
+            height,
+            split: split.to_string(),
+            annotations: None,
+        }
+    }
+
+    #[test]
+    fn sniff_dimensions_reads_png_and_jpeg_headers() {
+        assert_eq!(sniff_dimensions(&png_header(320, 240)), Some((320, 240)));
+        assert_eq!(sniff_dimensions(&jpeg_header(320, 240)), Some((320, 240)));
+        assert_eq!(sniff_dimensions(b"not an image"), None);
+    }
+
+    #[test]
+    fn sniff_dimensions_reads_webp_lossless_header() {
+        let mut bytes = b"RIFF\0\0\0\0WEBPVP8L\0\0\0\0\x2f".to_vec();
+        let bits: u32 = (320 - 1) | ((240 - 1) << 14);
+        bytes.extend_from_slice(&bits.to_le_bytes());
+        assert_eq!(sniff_dimensions(&bytes), Some((320, 240)));
+    }
+
+    #[test]
+    fn reject_oversized_images_drops_decompression_bombs() {
+        let images = vec![
+            image("train", "bomb.png", 640, 480),
+            image("train", "ok.png", 640, 480),
+        ];
+        let mut files = HashMap::from([
+            (
+                image_download_key("train", "bomb.png"),
+                FileData::from(png_header(50_000, 50_000)),
+            ),
+            (
+                image_download_key("train", "ok.png"),
+                FileData::from(png_header(640, 480)),
+            ),
+        ]);
+
+        let rejected = reject_oversized_images(&images, &mut files, 100_000_000);
+
+        assert_eq!(rejected.len(), 1);
+        assert!(rejected[0].starts_with("train/bomb.png: image is 50000x50000"));
+        assert!(!files.contains_key(&image_download_key("train", "bomb.png")));
+        assert!(files.contains_key(&image_download_key("train", "ok.png")));
+    }
+
+    #[test]
+    fn reconcile_dimensions_warns_without_correcting() {
+        let mut images = vec![image("train", "a.png", 640, 480)];
+        let files = HashMap::from([(
+            image_download_key("train", "a.png"),
+            FileData::from(png_header(320, 240)),
+        )]);
+
+        let warnings = reconcile_dimensions(&mut images, &files, false);
+
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("declared 640x480 but image is 320x240"));
+        assert_eq!((images[0].width, images[0].height), (640, 480));
+    }
+
+    #[test]
+    fn reconcile_dimensions_corrects_metadata_when_enabled() {
+        let mut images = vec![
+            image("train", "a.jpg", 640, 480),
+            image("train", "b.jpg", 320, 240),
+        ];
+        let files = HashMap::from([
+            (
+                image_download_key("train", "a.jpg"),
+                FileData::from(jpeg_header(320, 240)),
+            ),
+            (
+                image_download_key("train", "b.jpg"),
+                FileData::from(jpeg_header(320, 240)),
+            ),
+        ]);
+
+        let warnings = reconcile_dimensions(&mut images, &files, true);
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((images[0].width, images[0].height), (320, 240));
+        assert_eq!((images[1].width, images[1].height), (320, 240));
+    }
+}
@@ -1,28 +1,151 @@
 mod converter;
+mod dedup;
+mod diff;
 mod downloader;
+mod image_header;
+mod local_images;
+mod manifest;
+mod options;
 mod parser;
+mod recompress;
+mod shard;
+mod split_archive;
+mod verify;
 
-use converter::get_converter;
-use downloader::{DownloadResult, Downloader, ProgressEvent};
-use parser::{normalize_split, parse_ndjson, ImageEntry};
+use converter::{
+    apply_class_filters, cap_annotations_per_class, check_unique_annotation_ids, find_orphans,
+    get_converter, synthesize_missing_class_names, validate_flip_idx, FileData, Orphans,
+};
+use downloader::{
+    ConnectionSettings, DownloadResult, Downloader, EtaEstimator, ProgressEvent, Referer,
+};
+use manifest::{ExportManifest, MANIFEST_FILE_NAME};
+use options::{
+    ArchiveComment, ClassIdMode, CompressionPreset, ConvertOptions, DimensionCheck, ImageSource,
+    OutputKind, SplitNormalization,
+};
+use parser::{normalize_split, parse_ndjson_with, ImageEntry, MetadataPolicy, ParseSettings};
 use serde::Serialize;
 use std::collections::{HashMap, HashSet};
-use std::io::Write;
+use std::io::{Seek, Write};
 use std::path::{Path, PathBuf};
 use tauri::ipc::Channel;
+use verify::VerifyReport;
 use zip::write::SimpleFileOptions;
 use zip::ZipWriter;
 
 const MAX_NDJSON_BYTES: u64 = 100 * 1024 * 1024; // 100 MiB
+const DEFAULT_EMBED_SOURCE_MAX_BYTES: u64 = 20 * 1024 * 1024; // 20 MiB
 const MAX_DOWNLOAD_CONCURRENCY: usize = 20;
+const DEFAULT_MAX_FILE_NAME_BYTES: usize = 255;
+/// Room for the `__{hash}` suffix and the longest kept extension, so
+/// shortened names always fit.
+const MIN_MAX_FILE_NAME_BYTES: usize = 2 + 8 + 1 + 16;
 
 #[derive(Debug, Serialize)]
 pub struct ConvertResult {
+    /// Where the export was written: the ZIP, or the directory for
+    /// directory output.
     pub zip_path: String,
     pub file_count: usize,
     pub image_count: usize,
     pub download_total: u32,
     pub failed_downloads: usize,
+    pub mirror_downloads: usize,
+    /// Downloads skipped because the conversion deadline passed.
+    pub timed_out_downloads: usize,
+    /// True when the export holds every image and annotation of the
+    /// dataset: nothing failed, was skipped, capped, sampled out, dropped,
+    /// withheld or cut short.
+    pub complete: bool,
+    /// Why the export isn't complete, when it isn't.
+    pub incomplete_reason: Option<String>,
+    /// Downloaded images with no annotations, as `split/file`.
+    pub orphaned_images: Vec<String>,
+    /// Annotated images whose download is missing, as `split/file`.
+    pub orphaned_labels: Vec<String>,
+    /// Byte-identical images collapsed into a shared file.
+    pub deduplicated_images: usize,
+    /// Images marked `skip` in the NDJSON and left out of the export.
+    pub skipped_images: usize,
+    /// Images left out by `sample_fraction`.
+    pub sampled_out_images: usize,
+    /// Images left out by `drop_empty_images` for having no annotations.
+    pub dropped_empty_images: usize,
+    /// Test images exported without their labels by `withhold_test_labels`.
+    pub withheld_test_labels: usize,
+    /// Boxes under half a pixel wide or tall, which trainers ignore.
+    pub subpixel_boxes: usize,
+    /// Total size of the exported files before compression.
+    pub uncompressed_bytes: u64,
+    /// Size of the written ZIP. Equal to `uncompressed_bytes` for directory
+    /// output.
+    pub archive_bytes: u64,
+    pub warnings: Vec<String>,
+}
+
+/// What an export left out of the dataset on purpose or by failure.
+#[derive(Debug, Default)]
+struct Omissions {
+    failed_downloads: usize,
+    timed_out_downloads: usize,
+    skipped_images: usize,
+    capped_classes: usize,
+    sampled_out_images: usize,
+    dropped_empty_images: usize,
+    withheld_test_labels: usize,
+    delta_export: bool,
+}
+
+impl Omissions {
+    /// Everything left out, if anything, as one sentence.
+    fn reason(&self) -> Option<String> {
+        let mut reasons = Vec::new();
+        if self.failed_downloads > self.timed_out_downloads {
+            reasons.push(format!(
+                "{} images failed to download",
+                self.failed_downloads - self.timed_out_downloads
+            ));
+        }
+        if self.timed_out_downloads > 0 {
+            reasons.push(format!(
+                "{} downloads were cut off by the deadline",
+                self.timed_out_downloads
+            ));
+        }
+        if self.skipped_images > 0 {
+            reasons.push(format!("{} images were marked skip", self.skipped_images));
+        }
+        if self.capped_classes > 0 {
+            reasons.push(format!(
+                "annotations of {} classes were capped",
+                self.capped_classes
+            ));
+        }
+        if self.sampled_out_images > 0 {
+            reasons.push(format!(
+                "{} images were sampled out",
+                self.sampled_out_images
+            ));
+        }
+        if self.dropped_empty_images > 0 {
+            reasons.push(format!(
+                "{} images without annotations were dropped",
+                self.dropped_empty_images
+            ));
+        }
+        if self.withheld_test_labels > 0 {
+            reasons.push(format!(
+                "labels of {} test images were withheld",
+                self.withheld_test_labels
+            ));
+        }
+        if self.delta_export {
+            reasons
+                .push("only images changed since the previous manifest were exported".to_string());
+        }
+        (!reasons.is_empty()).then(|| reasons.join("; "))
+    }
 }
 
 fn normalize_zip_path(path: &str) -> Result<String, String> {
@@ -118,17 +241,50 @@ fn file_name_with_suffix(file_name: &str, suffix: &str) -> String {
     }
 }
 
+/// Shortens `file_name` to at most `max_bytes` bytes by truncating the stem
+/// and appending a hash of the full name, so names that only differ past the
+/// cut stay distinct. The extension is kept. Short names are returned as-is.
+/// `max_bytes` must be at least `MIN_MAX_FILE_NAME_BYTES`.
+fn shorten_file_name(file_name: &str, max_bytes: usize) -> String {
+    if file_name.len() <= max_bytes {
+        return file_name.to_string();
+    }
+
+    let hash = short_stable_hash(file_name);
+    let (stem, ext) = match file_name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && !ext.is_empty() && ext.len() <= 16 => {
+            (stem, Some(ext))
+        }
+        _ => (file_name, None),
+    };
+    let reserved = 2 + hash.len() + ext.map_or(0, |ext| ext.len() + 1);
+    let budget = max_bytes.saturating_sub(reserved);
+    let cut = stem
+        .char_indices()
+        .map(|(idx, c)| idx + c.len_utf8())
+        .take_while(|&end| end <= budget)
+        .last()
+        .unwrap_or(0);
+
+    match ext {
+        Some(ext) => format!("{}__{}.{}", &stem[..cut], hash, ext),
+        None => format!("{}__{}", &stem[..cut], hash),
+    }
+}
+
 fn next_unique_file_name(
     original_file: &str,
     hash_source: &str,
     used_names: &mut HashSet<String>,
+    max_bytes: usize,
 ) -> String {
     let hash = short_stable_hash(hash_source);
     let mut suffix = hash.clone();
     let mut counter = 2usize;
 
     loop {
-        let candidate = file_name_with_suffix(original_file, &suffix);
+        let candidate =
+            shorten_file_name(&file_name_with_suffix(original_file, &suffix), max_bytes);
         if used_names.insert(candidate.clone()) {
             return candidate;
         }
@@ -137,7 +293,10 @@ fn next_unique_file_name(
     }
 }
 
-fn prepare_images_with_unique_output_names(images: &[ImageEntry]) -> Vec<ImageEntry> {
+fn prepare_images_with_unique_output_names(
+    images: &[ImageEntry],
+    max_file_name_bytes: usize,
+) -> Vec<ImageEntry> {
     let mut seen_entries: HashMap<(String, String), usize> = HashMap::new();
     let mut used_names_by_split: HashMap<String, HashSet<String>> = HashMap::new();
     let mut prepared_images = Vec::with_capacity(images.len());
@@ -147,26 +306,25 @@ fn prepare_images_with_unique_output_names(images: &[ImageEntry]) -> Vec<ImageEn
         let used_names = used_names_by_split.entry(split_key.clone()).or_default();
         let dedupe_key = (split_key, image.file.clone());
         let occurrence = seen_entries.entry(dedupe_key).or_insert(0);
+        let base_name = shorten_file_name(&image.file, max_file_name_bytes);
+        let hash_source = if image.url.is_empty() {
+            image.file.as_str()
+        } else {
+            image.url.as_str()
+        };
 
         let mut prepared = image.clone();
-        if *occurrence == 0 {
-            if !used_names.insert(image.file.clone()) {
-                let hash_source = if image.url.is_empty() {
-                    image.file.as_str()
-                } else {
-                    image.url.as_str()
-                };
-                prepared.output_file =
-                    Some(next_unique_file_name(&image.file, hash_source, used_names));
+        if *occurrence == 0 && used_names.insert(base_name.clone()) {
+            if base_name != image.file {
+                prepared.output_file = Some(base_name);
             }
         } else {
-            let hash_source = if image.url.is_empty() {
-                image.file.as_str()
-            } else {
-                image.url.as_str()
-            };
-            prepared.output_file =
-                Some(next_unique_file_name(&image.file, hash_source, used_names));
+            prepared.output_file = Some(next_unique_file_name(
+                &base_name,
+                hash_source,
+                used_names,
+                max_file_name_bytes,
+            ));
         }
         *occurrence += 1;
         prepared_images.push(prepared);
@@ -175,277 +333,2552 @@ fn prepare_images_with_unique_output_names(images: &[ImageEntry]) -> Vec<ImageEn
     prepared_images
 }
 
-#[tauri::command]
-async fn convert_ndjson(
-    file_path: String,
-    format: String,
-    output_path: String,
-    include_images: bool,
-    channel: Channel<ProgressEvent>,
-) -> Result<ConvertResult, String> {
-    let metadata = std::fs::metadata(&file_path)
-        .map_err(|e| format!("Failed to inspect file '{}': {}", &file_path, e))?;
-    if !is_ndjson_size_allowed(metadata.len()) {
-        return Err(format!(
-            "NDJSON file is too large ({} bytes). Maximum allowed is {} bytes.",
-            metadata.len(),
-            MAX_NDJSON_BYTES
-        ));
+/// One image renamed by a filename template, for `file_name_map.json`.
+#[derive(Debug, Serialize)]
+struct TemplatedFileName {
+    split: String,
+    original: String,
+    file: String,
+}
+
+/// Expands a filename template for one image. `{split}`, `{class}` (the
+/// first annotation's class), `{hash}` (of the URL, else the file name) and
+/// `{ext}` are substituted, and `{index}` is the image's position within its
+/// split, zero-padded by `{index:06}`.
+fn expand_file_name_template(
+    template: &str,
+    image: &ImageEntry,
+    index: usize,
+    class_names: &HashMap<i32, String>,
+) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed '{{' in filename template '{}'", template))?;
+        let token = &rest[start + 1..start + end];
+        let value = match token.split_once(':') {
+            Some(("index", width)) => {
+                let width: usize = width
+                    .parse()
+                    .map_err(|_| format!("Invalid index width '{}' in filename template", width))?;
+                format!("{:0width$}", index, width = width)
+            }
+            None if token == "index" => index.to_string(),
+            None if token == "split" => normalize_split(&image.split).to_string(),
+            None if token == "ext" => image
+                .file
+                .rsplit_once('.')
+                .map(|(_, ext)| ext.to_string())
+                .unwrap_or_default(),
+            None if token == "hash" => short_stable_hash(if image.url.is_empty() {
+                &image.file
+            } else {
+                &image.url
+            }),
+            None if token == "class" => match image.class_ids().first() {
+                Some(id) => Some(converter::slugify(
+                    class_names.get(id).map_or("", String::as_str),
+                ))
+                .filter(|slug| !slug.is_empty())
+                .unwrap_or_else(|| format!("class_{}", id)),
+                None => "unlabeled".to_string(),
+            },
+            _ => {
+                return Err(format!(
+                    "Unknown token '{{{}}}' in filename template",
+                    token
+                ))
+            }
+        };
+        expanded.push_str(&value);
+        rest = &rest[start + end + 1..];
     }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
 
-    // Read the NDJSON file
-    let content = std::fs::read_to_string(&file_path)
-        .map_err(|e| format!("Failed to read file '{}': {}", &file_path, e))?;
+/// Renames every image's output file after `template`, keeping names unique
+/// within a split, and returns what each original file became, by output
+/// split.
+fn apply_file_name_template(
+    images: &mut [ImageEntry],
+    template: &str,
+    class_names: &HashMap<i32, String>,
+    max_file_name_bytes: usize,
+    splits: SplitNormalization,
+) -> Result<Vec<TemplatedFileName>, String> {
+    let mut used_names_by_split: HashMap<String, HashSet<String>> = HashMap::new();
+    let mut mapping = Vec::with_capacity(images.len());
 
-    // Parse NDJSON
-    channel
-        .send(ProgressEvent {
-            phase: "parsing".to_string(),
-            current: 0,
-            total: 1,
-            item: Some("Parsing NDJSON...".to_string()),
-        })
-        .ok();
+    for image in images {
+        // Download keys use the canonical split, so names stay unique
+        // across `val` and `valid` even when they are kept apart.
+        let used_names = used_names_by_split
+            .entry(normalize_split(&image.split).to_string())
+            .or_default();
+        let index = used_names.len();
+        let name = shorten_file_name(
+            &expand_file_name_template(template, image, index, class_names)?,
+            max_file_name_bytes,
+        );
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err(format!(
+                "Filename template '{}' gives an invalid name '{}'",
+                template, name
+            ));
+        }
+        let name = if used_names.insert(name.clone()) {
+            name
+        } else {
+            let hash_source = if image.url.is_empty() {
+                image.file.clone()
+            } else {
+                image.url.clone()
+            };
+            next_unique_file_name(&name, &hash_source, used_names, max_file_name_bytes)
+        };
+        mapping.push(TemplatedFileName {
+            split: splits.output_split(&image.split).to_string(),
+            original: image.file.clone(),
+            file: name.clone(),
+        });
+        image.output_file = Some(name);
+    }
 
-    let mut data = parse_ndjson(&content).map_err(|e| format!("Failed to parse NDJSON: {}", e))?;
-    data.images = prepare_images_with_unique_output_names(&data.images);
+    Ok(mapping)
+}
 
-    channel
-        .send(ProgressEvent {
-            phase: "parsing".to_string(),
-            current: 1,
-            total: 1,
-            item: Some(format!("Parsed {} images", data.images.len())),
-        })
-        .ok();
+/// Export entries with images first, then labels and config files, so
+/// writing progress can be reported for each as its own stage. Also returns
+/// the number of images.
+fn writing_order(files: &HashMap<String, FileData>) -> (Vec<(&String, &FileData)>, usize) {
+    let (mut ordered, labels): (Vec<_>, Vec<_>) =
+        files.iter().partition(|(path, _)| verify::is_image(path));
+    let image_count = ordered.len();
+    ordered.extend(labels);
+    (ordered, image_count)
+}
 
-    // Download images if requested
-    let download_result = if include_images {
-        let downloader = Downloader::new(MAX_DOWNLOAD_CONCURRENCY)
-            .map_err(|e| format!("Failed to init downloader: {}", e))?;
-        downloader.download_all(&data.images, &channel).await
+/// Reports writing entry `idx` of `entry_count` in `phase` (`zipping` or
+/// `writing`), every 50 entries and at the start and end of each stage. The
+/// overall count spans both stages; the stage count doesn't.
+fn send_write_progress(
+    channel: &Channel<ProgressEvent>,
+    eta: &mut EtaEstimator,
+    phase: &str,
+    idx: usize,
+    entry_count: usize,
+    image_count: usize,
+    item: String,
+) {
+    let (stage, current, total) = if idx < image_count {
+        ("images", idx + 1, image_count)
     } else {
-        DownloadResult {
-            files: std::collections::HashMap::new(),
-            total: 0,
-            failed: 0,
-        }
+        ("labels", idx + 1 - image_count, entry_count - image_count)
     };
-
-    let image_count = download_result.files.len();
-    let download_total = download_result.total;
-    let failed_downloads = download_result.failed;
-    if include_images && download_total > 0 && image_count == 0 {
-        return Err(
-            "All image downloads failed. Check your network or CDN access and try again."
-                .to_string(),
-        );
+    if idx.is_multiple_of(50) || current == 1 || current == total {
+        let (done, entry_count) = ((idx + 1) as u32, entry_count as u32);
+        let eta_seconds = eta.estimate(phase, done, entry_count, std::time::Instant::now());
+        channel
+            .send(
+                ProgressEvent::new(phase, done, entry_count, Some(item))
+                    .with_stage(stage, current as u32, total as u32)
+                    .with_eta(eta_seconds),
+            )
+            .ok();
     }
+}
 
-    // Get converter
-    let converter = get_converter(&format).ok_or_else(|| format!("Unknown format: {}", format))?;
+fn write_zip<W: Write + Seek>(
+    file: W,
+    files: &HashMap<String, FileData>,
+    comment: Option<&str>,
+    compression: CompressionPreset,
+    channel: &Channel<ProgressEvent>,
+) -> Result<u64, String> {
+    let mut uncompressed_bytes = 0;
+    let mut zip = ZipWriter::new(file);
+    if let Some(comment) = comment {
+        zip.set_comment(comment);
+    }
+    let options = SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .compression_level(compression.deflate_level());
 
-    // Convert
-    channel
-        .send(ProgressEvent {
-            phase: "converting".to_string(),
-            current: 0,
-            total: 1,
-            item: Some("Converting annotations...".to_string()),
-        })
-        .ok();
+    let (ordered, image_count) = writing_order(files);
+    let mut eta = EtaEstimator::default();
+    for (idx, (path, content)) in ordered.iter().enumerate() {
+        let zip_path = normalize_zip_path(path)?;
+        // Spooled images are read back one at a time, so only a single image
+        // is ever resident while zipping.
+        let bytes = content
+            .read()
+            .map_err(|e| format!("Failed to read '{}' for ZIP: {}", zip_path, e))?;
+        // Empty files, like background images' labels, are stored: deflating
+        // nothing still adds a compressed stream.
+        let entry_options = if bytes.is_empty() {
+            options.compression_method(zip::CompressionMethod::Stored)
+        } else {
+            options
+        };
+        zip.start_file(&zip_path, entry_options)
+            .map_err(|e| format!("Failed to add file to ZIP: {}", e))?;
+        zip.write_all(&bytes)
+            .map_err(|e| format!("Failed to write file to ZIP: {}", e))?;
+        uncompressed_bytes += bytes.len() as u64;
 
-    let files = converter.convert(&data, &download_result.files);
+        send_write_progress(
+            channel,
+            &mut eta,
+            "zipping",
+            idx,
+            ordered.len(),
+            image_count,
+            zip_path,
+        );
+    }
 
-    channel
-        .send(ProgressEvent {
-            phase: "converting".to_string(),
-            current: 1,
-            total: 1,
-            item: Some(format!("Converted {} files", files.len())),
-        })
-        .ok();
+    zip.finish()
+        .map_err(|e| format!("Failed to finish ZIP: {}", e))?;
+    Ok(uncompressed_bytes)
+}
 
-    // Create ZIP
-    let total_files = files.len() as u32;
-    channel
-        .send(ProgressEvent {
-            phase: "zipping".to_string(),
-            current: 0,
-            total: total_files,
-            item: Some("Creating ZIP...".to_string()),
-        })
-        .ok();
+/// Text for the ZIP archive comment, if one was asked for.
+fn archive_comment(
+    comment: &ArchiveComment,
+    data: &parser::NDJSONData,
+    format: &str,
+) -> Option<String> {
+    match comment {
+        ArchiveComment::None => None,
+        ArchiveComment::Generated => Some(format!(
+            "{} | format: {} | exported: {} | YOLO NDJSON Converter {}",
+            if data.metadata.name.is_empty() {
+                "dataset"
+            } else {
+                &data.metadata.name
+            },
+            format,
+            chrono::Utc::now().to_rfc3339(),
+            env!("CARGO_PKG_VERSION")
+        )),
+        ArchiveComment::Custom(text) => Some(text.clone()),
+    }
+}
 
-    let output_path = PathBuf::from(&output_path);
-    let file = std::fs::File::create(&output_path).map_err(|e| {
+/// Writes each entry to `{output_dir}/{path}` instead of a ZIP, into a fresh
+/// sibling temp directory that is renamed into place once complete, so
+/// `output_dir` never holds a partial export and no link already in it is
+/// followed. An existing non-empty `output_dir` is refused unless
+/// `replace_existing` is set, in which case it is replaced whole. Paths go
+/// through the same sanitization as ZIP entries, so nothing is written
+/// outside the export.
+fn write_directory(
+    output_dir: &Path,
+    files: &HashMap<String, FileData>,
+    replace_existing: bool,
+    channel: &Channel<ProgressEvent>,
+) -> Result<u64, String> {
+    let existing = std::fs::symlink_metadata(output_dir).ok();
+    if let Some(metadata) = &existing {
+        if !metadata.is_dir() {
+            return Err(format!(
+                "Output '{}' already exists and is not a directory",
+                output_dir.display()
+            ));
+        }
+        let is_empty = std::fs::read_dir(output_dir)
+            .map_err(|e| format!("Failed to read '{}': {}", output_dir.display(), e))?
+            .next()
+            .is_none();
+        if !is_empty && !replace_existing {
+            return Err(format!(
+                "Output directory '{}' is not empty",
+                output_dir.display()
+            ));
+        }
+    }
+    let name = output_dir
+        .file_name()
+        .ok_or_else(|| format!("Output '{}' has no directory name", output_dir.display()))?;
+    let parent = output_dir
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    std::fs::create_dir_all(parent).map_err(|e| {
         format!(
-            "Failed to create output file '{}': {}",
-            output_path.display(),
+            "Failed to create output directory '{}': {}",
+            parent.display(),
             e
         )
     })?;
+    // Dropping the staging directory removes it, with a partial export or
+    // the replaced one inside.
+    let staging = tempfile::Builder::new()
+        .prefix(&format!(".{}.", name.to_string_lossy()))
+        .tempdir_in(parent)
+        .map_err(|e| {
+            format!(
+                "Failed to create a temp directory in '{}': {}",
+                parent.display(),
+                e
+            )
+        })?;
+    let new_dir = staging.path().join("new");
 
-    let zip_result = (|| -> Result<(), String> {
-        let mut zip = ZipWriter::new(file);
-        let options =
-            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
-
-        for (idx, (path, content)) in files.iter().enumerate() {
-            let zip_path = normalize_zip_path(path)?;
-            zip.start_file(&zip_path, options)
-                .map_err(|e| format!("Failed to add file to ZIP: {}", e))?;
-            zip.write_all(content)
-                .map_err(|e| format!("Failed to write file to ZIP: {}", e))?;
-
-            if idx % 50 == 0 || idx == files.len() - 1 {
-                channel
-                    .send(ProgressEvent {
-                        phase: "zipping".to_string(),
-                        current: (idx + 1) as u32,
-                        total: total_files,
-                        item: Some(zip_path),
-                    })
-                    .ok();
-            }
+    let mut written_bytes = 0;
+    let (ordered, image_count) = writing_order(files);
+    let mut eta = EtaEstimator::default();
+    for (idx, (path, content)) in ordered.iter().enumerate() {
+        let relative = normalize_zip_path(path)?;
+        let target = new_dir.join(&relative);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create '{}': {}", parent.display(), e))?;
         }
+        let bytes = content
+            .read()
+            .map_err(|e| format!("Failed to read '{}': {}", relative, e))?;
+        std::fs::write(&target, &bytes)
+            .map_err(|e| format!("Failed to write '{}': {}", target.display(), e))?;
+        written_bytes += bytes.len() as u64;
 
-        zip.finish()
-            .map_err(|e| format!("Failed to finish ZIP: {}", e))?;
-        Ok(())
-    })();
+        send_write_progress(
+            channel,
+            &mut eta,
+            "writing",
+            idx,
+            ordered.len(),
+            image_count,
+            relative,
+        );
+    }
+    std::fs::create_dir_all(&new_dir)
+        .map_err(|e| format!("Failed to create '{}': {}", new_dir.display(), e))?;
 
-    if let Err(err) = zip_result {
-        let _ = std::fs::remove_file(&output_path);
-        return Err(err);
+    // A directory can't be renamed over a non-empty one, so the existing
+    // export is moved aside first and restored if the swap fails.
+    let old_dir = staging.path().join("old");
+    if existing.is_some() {
+        std::fs::rename(output_dir, &old_dir)
+            .map_err(|e| format!("Failed to replace '{}': {}", output_dir.display(), e))?;
+    }
+    if let Err(e) = std::fs::rename(&new_dir, output_dir) {
+        if existing.is_some() {
+            let _ = std::fs::rename(&old_dir, output_dir);
+        }
+        return Err(format!(
+            "Failed to move the export into place at '{}': {}",
+            output_dir.display(),
+            e
+        ));
     }
 
-    channel
-        .send(ProgressEvent {
-            phase: "complete".to_string(),
-            current: 1,
-            total: 1,
-            item: None,
-        })
-        .ok();
+    Ok(written_bytes)
+}
 
-    Ok(ConvertResult {
-        zip_path: output_path.to_string_lossy().to_string(),
-        file_count: files.len(),
-        image_count,
-        download_total,
-        failed_downloads,
+/// Whether both paths exist and resolve to the same file.
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Warning for an output path whose extension doesn't match the ZIP archive
+/// that is written to it, e.g. `export.tar.gz`.
+fn output_extension_warning(output_path: &Path) -> Option<String> {
+    let is_zip = output_path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+    (!is_zip).then(|| {
+        format!(
+            "Output '{}' does not end in .zip but is written as a ZIP archive",
+            output_path.display()
+        )
     })
 }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    tauri::Builder::default()
-        .plugin(tauri_plugin_opener::init())
-        .plugin(tauri_plugin_dialog::init())
-        .plugin(tauri_plugin_updater::Builder::new().build())
-        .plugin(tauri_plugin_process::init())
-        .invoke_handler(tauri::generate_handler![convert_ndjson])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+fn temp_output_path(output_path: &Path) -> PathBuf {
+    let mut name = output_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    output_path.with_file_name(name)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{
-        file_name_with_suffix, is_ndjson_size_allowed, normalize_zip_path,
-        prepare_images_with_unique_output_names, short_stable_hash, MAX_NDJSON_BYTES,
-    };
-    use crate::parser::parse_ndjson;
+/// Writes the archive to a sibling `.tmp` file and renames it over
+/// `output_path` only once it is complete, so the final path never holds a
+/// partial ZIP. The temp file is removed on any error, and a write that fails
+/// with an I/O error on the output file is retried once from scratch, since
+/// exports to network shares hit transient I/O errors. Returns the total
+/// uncompressed size of the entries.
+fn write_zip_atomically(
+    output_path: &Path,
+    files: &HashMap<String, FileData>,
+    comment: Option<&str>,
+    compression: CompressionPreset,
+    channel: &Channel<ProgressEvent>,
+) -> Result<u64, String> {
+    write_zip_atomically_with(output_path, files, comment, compression, channel, |file| {
+        file
+    })
+}
 
-    #[test]
-    fn normalize_zip_path_accepts_simple_paths() {
-        assert_eq!(
-            normalize_zip_path("images/foo.jpg").unwrap(),
-            "images/foo.jpg"
-        );
-        assert_eq!(
-            normalize_zip_path("labels\\foo.txt").unwrap(),
-            "labels/foo.txt"
-        );
-    }
+/// Output file that remembers whether any write to it failed, telling a
+/// transient I/O error apart from one that would fail again, like an
+/// unreadable spooled image.
+struct FailureTracking<W> {
+    inner: W,
+    failed: bool,
+}
 
-    #[test]
-    fn normalize_zip_path_rejects_parent_dirs() {
-        assert!(normalize_zip_path("../evil.txt").is_err());
-        assert!(normalize_zip_path("images/../../evil.txt").is_err());
+impl<W> FailureTracking<W> {
+    fn track<T>(&mut self, result: std::io::Result<T>) -> std::io::Result<T> {
+        self.failed |= result.is_err();
+        result
     }
+}
 
-    #[test]
-    fn normalize_zip_path_rejects_absolute_paths() {
-        assert!(normalize_zip_path("/etc/passwd").is_err());
+impl<W: Write> Write for FailureTracking<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let result = self.inner.write(buf);
+        self.track(result)
     }
 
-    #[test]
-    fn normalize_zip_path_rejects_windows_prefix() {
-        assert!(normalize_zip_path("C:\\evil.txt").is_err());
+    fn flush(&mut self) -> std::io::Result<()> {
+        let result = self.inner.flush();
+        self.track(result)
     }
+}
 
-    #[test]
-    fn normalize_zip_path_rejects_windows_reserved_names() {
-        assert!(normalize_zip_path("CON.txt").is_err());
-        assert!(normalize_zip_path("train/NUL.jpg").is_err());
-        assert!(normalize_zip_path("labels/lpt1").is_err());
+impl<W: Seek> Seek for FailureTracking<W> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let result = self.inner.seek(pos);
+        self.track(result)
     }
+}
 
-    #[test]
-    fn ndjson_size_limit_allows_max_size() {
-        assert!(is_ndjson_size_allowed(MAX_NDJSON_BYTES));
+/// `write_zip_atomically` with each attempt's temp file wrapped by `wrap`.
+fn write_zip_atomically_with<W: Write + Seek>(
+    output_path: &Path,
+    files: &HashMap<String, FileData>,
+    comment: Option<&str>,
+    compression: CompressionPreset,
+    channel: &Channel<ProgressEvent>,
+    mut wrap: impl FnMut(std::fs::File) -> W,
+) -> Result<u64, String> {
+    // Invalid entry paths fail the same way every time, so reject them
+    // before anything is written rather than retrying.
+    for path in files.keys() {
+        normalize_zip_path(path)?;
     }
 
-    #[test]
-    fn ndjson_size_limit_rejects_oversize() {
-        assert!(!is_ndjson_size_allowed(MAX_NDJSON_BYTES + 1));
-    }
+    let temp_path = temp_output_path(output_path);
+    let mut attempt = || {
+        let file = std::fs::File::create(&temp_path).map_err(|e| {
+            (
+                format!(
+                    "Failed to create output file '{}': {}",
+                    temp_path.display(),
+                    e
+                ),
+                false,
+            )
+        })?;
 
-    #[test]
-    fn prepare_images_keeps_first_and_renames_same_split_duplicates() {
-        let content = r#"{"type":"dataset","name":"test","class_names":{}}
-{"type":"image","file":"img1.jpg","width":640,"height":480,"split":"train","url":"https://a.example/img1.jpg"}
-{"type":"image","file":"img1.jpg","width":320,"height":240,"split":"val","url":"https://b.example/img1.jpg"}
-{"type":"image","file":"img1.jpg","width":800,"height":600,"split":"train","url":"https://c.example/img1.jpg"}
-{"type":"image","file":"img2.jpg","width":640,"height":480,"split":"test","url":"https://c.example/img2.jpg"}"#;
+        let mut output = FailureTracking {
+            inner: wrap(file),
+            failed: false,
+        };
+        let result = write_zip(&mut output, files, comment, compression, channel)
+            .map_err(|e| (e, output.failed))
+            .and_then(|uncompressed_bytes| {
+                std::fs::rename(&temp_path, output_path)
+                    .map(|()| uncompressed_bytes)
+                    .map_err(|e| {
+                        (
+                            format!(
+                                "Failed to move ZIP into place at '{}': {}",
+                                output_path.display(),
+                                e
+                            ),
+                            false,
+                        )
+                    })
+            });
 
-        let data = parse_ndjson(content).unwrap();
-        let prepared = prepare_images_with_unique_output_names(&data.images);
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        result
+    };
 
-        assert_eq!(prepared.len(), 4);
-        assert_eq!(prepared[0].file, "img1.jpg");
-        assert_eq!(prepared[0].effective_file_name(), "img1.jpg");
-        assert_eq!(prepared[1].split, "val");
-        assert_eq!(prepared[1].effective_file_name(), "img1.jpg");
-        assert_eq!(
-            prepared[2].effective_file_name(),
-            file_name_with_suffix("img1.jpg", &short_stable_hash("https://c.example/img1.jpg"))
+    // Only a failed write to the output file is worth a second attempt.
+    attempt()
+        .or_else(|(e, io_failed)| {
+            if io_failed {
+                attempt()
+            } else {
+                Err((e, false))
+            }
+        })
+        .map_err(|(e, _)| e)
+}
+
+fn is_remote_input(file_path: &str) -> bool {
+    let lower = file_path.trim_start().to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// An image downloader set up from the download-related options.
+fn configured_downloader(options: &ConvertOptions) -> Result<Downloader, String> {
+    let mut downloader = Downloader::new(MAX_DOWNLOAD_CONCURRENCY)
+        .map_err(|e| format!("Failed to init downloader: {}", e))?;
+    if let Some(user_agent) = options.user_agent.clone().filter(|ua| !ua.is_empty()) {
+        downloader = downloader.with_user_agent(user_agent);
+    }
+    downloader = downloader.with_connection_settings(&ConnectionSettings {
+        http2_prior_knowledge: options.http2_prior_knowledge,
+        pool_idle_timeout: options
+            .pool_idle_timeout_secs
+            .map(std::time::Duration::from_secs),
+        pool_max_idle_per_host: options.pool_max_idle_per_host,
+    })?;
+    if let Some(max_bytes) = options.max_inflight_bytes {
+        downloader = downloader.with_max_inflight_bytes(max_bytes);
+    }
+    if let Some(max_dns) = options.max_concurrent_dns {
+        downloader = downloader.with_dns_concurrency(max_dns)?;
+    }
+    if !options.allowed_hosts.is_empty() {
+        downloader = downloader.with_allowed_hosts(options.allowed_hosts.clone())?;
+    }
+    if options.send_referer {
+        downloader = downloader.with_referer(match options.referer.clone() {
+            Some(referer) if !referer.is_empty() => Referer::Fixed(referer),
+            _ => Referer::Origin,
+        });
+    }
+    Ok(downloader)
+}
+
+/// Rejects option values and combinations no export can honor.
+fn validate_options(options: &ConvertOptions) -> Result<(), String> {
+    if let Some(max_bytes) = options.max_file_name_bytes {
+        if max_bytes < MIN_MAX_FILE_NAME_BYTES {
+            return Err(format!(
+                "Maximum file name length must be at least {} bytes, got {}",
+                MIN_MAX_FILE_NAME_BYTES, max_bytes
+            ));
+        }
+    }
+    if options
+        .annotation_types
+        .as_ref()
+        .is_some_and(|kinds| kinds.is_empty())
+    {
+        return Err("Annotation types must list at least one kind".to_string());
+    }
+    if let Some(fraction) = options.sample_fraction {
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            return Err(format!(
+                "Sample fraction must be above 0 and at most 1, got {}",
+                fraction
+            ));
+        }
+    }
+    if options.max_images_per_dir == Some(0) {
+        return Err("Maximum images per directory must be at least 1".to_string());
+    }
+    if options.class_registry.is_some() && options.class_id_mode == ClassIdMode::Remap {
+        return Err(
+            "A class registry fixes class ids, so it can't be combined with remapping them"
+                .to_string(),
         );
-        assert_eq!(prepared[3].effective_file_name(), "img2.jpg");
+    }
+    // The source is bundled as read, so it would put back what these leave
+    // out of the export.
+    if options.embed_source {
+        if options.withhold_test_labels {
+            return Err(
+                "The embedded source holds the test labels, so it can't be combined with withholding them"
+                    .to_string(),
+            );
+        }
+        if options.strip_urls {
+            return Err(
+                "The embedded source holds the image URLs, so it can't be combined with stripping them"
+                    .to_string(),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Reads the NDJSON input from a local file, whatever its extension
+/// (`.ndjson`, `.jsonl` or none), or downloads it when given an HTTP(S) URL.
+async fn read_ndjson_input(file_path: &str, options: &ConvertOptions) -> Result<String, String> {
+    if is_remote_input(file_path) {
+        let mut downloader =
+            Downloader::new(1).map_err(|e| format!("Failed to init downloader: {}", e))?;
+        if let Some(user_agent) = options.user_agent.clone().filter(|ua| !ua.is_empty()) {
+            downloader = downloader.with_user_agent(user_agent);
+        }
+        return downloader
+            .fetch_ndjson(file_path.trim(), MAX_NDJSON_BYTES as usize)
+            .await
+            .map_err(|e| format!("Failed to download NDJSON '{}': {}", file_path, e));
     }
 
-    #[test]
-    fn prepare_images_uses_counter_when_hash_suffix_collides() {
-        let content = r#"{"type":"dataset","name":"test","class_names":{}}
-{"type":"image","file":"img1.jpg","width":640,"height":480,"split":"train","url":"https://a.example/img1.jpg"}
-{"type":"image","file":"img1.jpg","width":640,"height":480,"split":"train","url":"https://b.example/img1.jpg","annotations":{"boxes":[[0,0.1,0.2,0.3,0.4]]}}
-{"type":"image","file":"img1.jpg","width":640,"height":480,"split":"train","url":"https://b.example/img1.jpg","annotations":{"boxes":[[1,0.2,0.3,0.3,0.4]]}}"#;
+    let metadata = std::fs::metadata(file_path)
+        .map_err(|e| format!("Failed to inspect file '{}': {}", file_path, e))?;
+    if !is_ndjson_size_allowed(metadata.len()) {
+        return Err(format!(
+            "NDJSON file is too large ({} bytes). Maximum allowed is {} bytes.",
+            metadata.len(),
+            MAX_NDJSON_BYTES
+        ));
+    }
 
-        let data = parse_ndjson(content).unwrap();
-        let prepared = prepare_images_with_unique_output_names(&data.images);
+    std::fs::read_to_string(file_path)
+        .map_err(|e| format!("Failed to read file '{}': {}", file_path, e))
+}
 
-        assert_eq!(prepared.len(), 3);
-        let hash = short_stable_hash("https://b.example/img1.jpg");
-        assert_eq!(prepared[0].effective_file_name(), "img1.jpg");
-        assert_eq!(
-            prepared[1].effective_file_name(),
-            file_name_with_suffix("img1.jpg", &hash)
-        );
-        assert_eq!(
-            prepared[2].effective_file_name(),
-            file_name_with_suffix("img1.jpg", &format!("{}__2", hash))
+#[tauri::command]
+async fn convert_ndjson(
+    file_path: String,
+    format: String,
+    output_path: String,
+    include_images: bool,
+    options: Option<ConvertOptions>,
+    channel: Channel<ProgressEvent>,
+) -> Result<ConvertResult, String> {
+    let options = options.unwrap_or_default();
+    validate_options(&options)?;
+    if !is_remote_input(&file_path) && is_same_file(Path::new(&file_path), Path::new(&output_path))
+    {
+        return Err(format!(
+            "Output '{}' is the input NDJSON; choose a different output path",
+            output_path
+        ));
+    }
+    let deadline = options
+        .conversion_deadline_secs
+        .map(|secs| tokio::time::Instant::now() + std::time::Duration::from_secs(secs));
+    let content = read_ndjson_input(&file_path, &options).await?;
+
+    // Parse NDJSON
+    channel
+        .send(ProgressEvent::new(
+            "parsing",
+            0,
+            1,
+            Some("Parsing NDJSON...".to_string()),
+        ))
+        .ok();
+
+    let parse_settings = ParseSettings {
+        lenient_json: options.lenient_json,
+        metadata_policy: options.metadata_policy,
+        default_split: options.default_split.clone(),
+        require_split: options.require_split,
+    };
+    let (mut data, dataset_records) = parse_ndjson_with(&content, &parse_settings)
+        .map_err(|e| format!("Failed to parse NDJSON: {}", e))?;
+    validate_flip_idx(&data).map_err(|e| format!("Invalid pose metadata: {}", e))?;
+    let skipped_images = data.remove_skipped_images();
+    let mut warnings = Vec::new();
+    if dataset_records > 1 {
+        warnings.push(format!(
+            "NDJSON has {} dataset records; {}",
+            dataset_records,
+            match options.metadata_policy {
+                MetadataPolicy::LastWins => "the last one was used",
+                MetadataPolicy::Merge => "their class names were merged",
+            }
+        ));
+    }
+    data.images = prepare_images_with_unique_output_names(
+        &data.images,
+        options
+            .max_file_name_bytes
+            .unwrap_or(DEFAULT_MAX_FILE_NAME_BYTES),
+    );
+    if options.synthesize_class_names && synthesize_missing_class_names(&mut data) {
+        warnings.push(format!(
+            "Dataset has no class names; using {} placeholder names (class_0, class_1, ...)",
+            data.metadata.class_names.len()
+        ));
+    }
+    if let Some(kinds) = &options.annotation_types {
+        converter::restrict_annotation_kinds(&mut data, kinds);
+    }
+    if let Some(registry) = &options.class_registry {
+        converter::apply_class_registry(&mut data, registry, options.registry_assign_unknown)?;
+    }
+    apply_class_filters(&mut data, &options);
+    let class_id_map = (options.class_id_mode == ClassIdMode::Remap)
+        .then(|| converter::remap_class_ids(&mut data));
+
+    // Hash before anything is capped or downloaded so the manifest reflects
+    // the NDJSON as given; a delta export then drops images whose hash is
+    // unchanged. Entries of images that end up not exported are dropped once
+    // the downloads are in.
+    let mut manifest = (options.write_manifest || options.previous_manifest.is_some())
+        .then(|| ExportManifest::for_images(&data.images, options.split_normalization));
+    let mut unchanged_images = Vec::new();
+    if let Some(previous_path) = &options.previous_manifest {
+        let previous = ExportManifest::load(Path::new(previous_path))?;
+        (unchanged_images, data.images) = std::mem::take(&mut data.images)
+            .into_iter()
+            .partition(|image| previous.is_unchanged(image, options.split_normalization));
+    }
+
+    let mut capped_classes = 0;
+    if let Some(max_per_class) = options.max_per_class {
+        let class_names = converter::get_class_names(&data);
+        for (class_id, dropped) in
+            cap_annotations_per_class(&mut data, max_per_class, options.balance_seed)
+        {
+            capped_classes += 1;
+            let name = class_names
+                .get(&class_id)
+                .cloned()
+                .unwrap_or_else(|| format!("class_{}", class_id));
+            warnings.push(format!(
+                "Dropped {} '{}' annotations to stay within {} per class",
+                dropped, name, max_per_class
+            ));
+        }
+    }
+
+    let mut dropped_empty_images = 0;
+    if options.drop_empty_images {
+        dropped_empty_images = converter::drop_empty_images(&mut data);
+        if dropped_empty_images > 0 {
+            warnings.push(format!(
+                "Dropped {} images left without annotations",
+                dropped_empty_images
+            ));
+        }
+    }
+
+    let mut sampled_out_images = 0;
+    if let Some(fraction) = options.sample_fraction {
+        for (split, (kept, total)) in converter::sample_images(
+            &mut data,
+            fraction,
+            options.sample_seed,
+            options.sample_by_class,
+        ) {
+            sampled_out_images += total - kept;
+            warnings.push(format!("Sampled {} of {} {} images", kept, total, split));
+        }
+    }
+
+    if data.images.is_empty() {
+        warnings.push(
+            "Dataset has no images to export; the export holds only dataset configuration files"
+                .to_string(),
+        );
+    }
+
+    channel
+        .send(ProgressEvent::new(
+            "parsing",
+            1,
+            1,
+            Some(format!("Parsed {} images", data.images.len())),
+        ))
+        .ok();
+
+    // Spooled downloads live in this directory until the ZIP has been written;
+    // dropping it at the end of the command removes the temp files.
+    let spool_dir = if include_images
+        && options.stream_images_to_disk
+        && options.image_source == ImageSource::Download
+    {
+        Some(
+            tempfile::tempdir()
+                .map_err(|e| format!("Failed to create download spool directory: {}", e))?,
+        )
+    } else {
+        None
+    };
+
+    if include_images
+        && options.image_source == ImageSource::Download
+        && !data.images.is_empty()
+        && data
+            .images
+            .iter()
+            .all(|image| image.download_urls().is_empty())
+    {
+        let message = "Images were requested but no image in the NDJSON has a URL; \
+                       to bundle images stored on disk, read them from a local folder instead";
+        if options.require_image_urls {
+            return Err(message.to_string());
+        }
+        warnings.push(message.to_string());
+    }
+
+    // Download images if requested
+    let mut download_result = if !include_images {
+        DownloadResult::default()
+    } else if let ImageSource::LocalDir { path, pattern } = &options.image_source {
+        let (result, missing) = local_images::read_local_images(
+            &options.images_to_fetch(&data.images),
+            Path::new(path),
+            pattern,
+            &channel,
+        );
+        warnings.extend(missing);
+        result
+    } else {
+        let mut downloader = configured_downloader(&options)?;
+        if let Some(dir) = &spool_dir {
+            downloader = downloader.with_spool_dir(dir.path().to_path_buf());
+        }
+        if let Some(deadline) = deadline {
+            downloader = downloader.with_deadline(deadline);
+        }
+        downloader
+            .download_all(&options.images_to_fetch(&data.images), &channel)
+            .await
+    };
+
+    if let Some(max_pixels) = options.max_image_pixels {
+        let rejected = image_header::reject_oversized_images(
+            &data.images,
+            &mut download_result.files,
+            max_pixels,
+        );
+        download_result.failed += rejected.len();
+        warnings.extend(rejected);
+    }
+
+    if let Some(threshold) = options.recompress_over_bytes {
+        let quality = options
+            .recompress_quality
+            .unwrap_or(recompress::DEFAULT_RECOMPRESS_QUALITY)
+            .clamp(1, 100);
+        warnings.extend(recompress::recompress_large_images(
+            &mut download_result.files,
+            threshold,
+            quality,
+        ));
+    }
+
+    if !options.split_image_settings.is_empty() {
+        warnings.extend(recompress::reencode_split_images(
+            &mut download_result.files,
+            &mut data.images,
+            &options,
+        ));
+    }
+
+    let image_count = download_result.files.len();
+    let download_total = download_result.total;
+    let failed_downloads = download_result.failed;
+    let mirror_downloads = download_result.mirrored.len();
+    let timed_out_downloads = download_result.timed_out;
+    if include_images && download_total > 0 && image_count == 0 {
+        return Err(match &options.image_source {
+            ImageSource::LocalDir { path, .. } => format!(
+                "No images were found in '{}'. Check the image folder and path pattern.",
+                path
+            ),
+            ImageSource::Download => {
+                "All image downloads failed. Check your network or CDN access and try again."
+                    .to_string()
+            }
+        });
+    }
+
+    if let Some(manifest) = &mut manifest {
+        let exported = data.images.iter().filter(|image| {
+            let withheld = options.withhold_test_labels && normalize_split(&image.split) == "test";
+            let missing = include_images
+                && options.downloads_split(&image.split)
+                && !download_result
+                    .files
+                    .contains_key(&parser::image_entry_download_key(image));
+            !withheld && !missing
+        });
+        manifest.retain_images(
+            unchanged_images.iter().chain(exported),
+            options.split_normalization,
+        );
+    }
+
+    // Images are renamed only once the exported set is final, so `{index}`
+    // has no gaps and `{class}` sees the final class ids. The manifest keeps
+    // the source names, which stay stable between exports.
+    let file_name_map = match options.filename_template.as_deref() {
+        Some(template) => {
+            let source_keys: Vec<String> = data
+                .images
+                .iter()
+                .map(parser::image_entry_download_key)
+                .collect();
+            let class_names = converter::get_class_names(&data);
+            let mapping = apply_file_name_template(
+                &mut data.images,
+                template,
+                &class_names,
+                options
+                    .max_file_name_bytes
+                    .unwrap_or(DEFAULT_MAX_FILE_NAME_BYTES),
+                options.split_normalization,
+            )?;
+            let mut downloaded = std::mem::take(&mut download_result.files);
+            for (image, source_key) in data.images.iter().zip(source_keys) {
+                if let Some(file) = downloaded.remove(&source_key) {
+                    download_result
+                        .files
+                        .insert(parser::image_entry_download_key(image), file);
+                }
+            }
+            Some(mapping)
+        }
+        None => None,
+    };
+
+    if options.dimension_check != DimensionCheck::Off {
+        warnings.extend(image_header::reconcile_dimensions(
+            &mut data.images,
+            &download_result.files,
+            options.dimension_check == DimensionCheck::Correct,
+        ));
+    }
+
+    if let Some(max_points) = options.max_segmentation_points {
+        if format.eq_ignore_ascii_case("coco") && data.metadata.task == "segment" {
+            warnings.extend(converter::coco::oversized_segmentations(&data, max_points));
+        }
+    }
+
+    check_unique_annotation_ids(&data)?;
+
+    // Get converter
+    let converter =
+        get_converter(&format, &options).ok_or_else(|| format!("Unknown format: {}", format))?;
+
+    // Convert
+    channel
+        .send(ProgressEvent::new(
+            "converting",
+            0,
+            1,
+            Some("Converting annotations...".to_string()),
+        ))
+        .ok();
+
+    let withheld_test_labels = if options.withhold_test_labels {
+        converter::clear_split_annotations(&mut data, "test")
+    } else {
+        0
+    };
+    let subpixel_boxes = converter::count_subpixel_boxes(&data);
+    if subpixel_boxes > 0 {
+        warnings.push(format!(
+            "{} boxes are less than a pixel wide or tall and will be ignored by most trainers",
+            subpixel_boxes
+        ));
+    }
+    let mut files = converter.convert(&data, &download_result.files);
+    if matches!(format.to_lowercase().as_str(), "yolo" | "yolo_darknet") {
+        converter::yolo::check_label_ranges(&files, &data)?;
+    }
+    if format.eq_ignore_ascii_case("coco_panoptic") {
+        warnings.extend(converter::coco_panoptic::missing_mask_warnings(
+            &files,
+            &data,
+            options.split_normalization,
+        ));
+    }
+    if options.withhold_test_labels {
+        converter::remove_split_labels(&mut files, "test");
+    }
+    if options.emit_raw_sidecars {
+        converter::insert_raw_sidecars(
+            &mut files,
+            &data,
+            options.split_normalization,
+            options.withhold_test_labels.then_some("test"),
+        );
+    }
+    if let Some(class_id_map) = &class_id_map {
+        files.insert(
+            "class_id_map.json".to_string(),
+            serde_json::to_vec_pretty(class_id_map)
+                .unwrap_or_default()
+                .into(),
+        );
+    }
+    if let Some(file_name_map) = &file_name_map {
+        files.insert(
+            "file_name_map.json".to_string(),
+            serde_json::to_vec_pretty(file_name_map)
+                .unwrap_or_default()
+                .into(),
+        );
+    }
+    if options.embed_source {
+        let max_bytes = options
+            .embed_source_max_bytes
+            .unwrap_or(DEFAULT_EMBED_SOURCE_MAX_BYTES);
+        if content.len() as u64 <= max_bytes {
+            files.insert(
+                "source.ndjson".to_string(),
+                content.as_bytes().to_vec().into(),
+            );
+        } else {
+            warnings.push(format!(
+                "Source NDJSON was not embedded: {} bytes is over the {} byte limit",
+                content.len(),
+                max_bytes
+            ));
+        }
+    }
+    if options.split_summary {
+        files.insert(
+            "split_summary.txt".to_string(),
+            converter::split_summary(&data, options.split_normalization)
+                .into_bytes()
+                .into(),
+        );
+    }
+    let mut deduplicated_images = 0;
+    if options.dedup_identical_images && include_images {
+        match dedup::dedup_identical_images(
+            &mut files,
+            &download_result.files,
+            &format,
+            &data.metadata.task,
+            &options,
+        ) {
+            Some(removed) => deduplicated_images = removed,
+            None => warnings.push(format!(
+                "Identical images were not deduplicated: {} {} exports pair labels with images by file name",
+                format, data.metadata.task
+            )),
+        }
+    }
+    if let Some(max_per_dir) = options.max_images_per_dir.filter(|_| include_images) {
+        if shard::shard_image_dirs(
+            &mut files,
+            &format,
+            &data.metadata.task,
+            max_per_dir,
+            &options,
+        )
+        .is_none()
+        {
+            warnings.push(
+                "Image directories were not sharded: classify exports are organized by class folder"
+                    .to_string(),
+            );
+        }
+    }
+    if let Some(mut manifest) = manifest {
+        manifest.record_files(&files)?;
+        files.insert(MANIFEST_FILE_NAME.to_string(), manifest.to_json().into());
+    }
+    // Without images every label would count as orphaned, so only reconcile
+    // exports that bundle them.
+    let orphans = if include_images {
+        let mut orphans = find_orphans(&data, &download_result.files, options.split_normalization);
+        // Splits excluded from download are expected to have no images.
+        orphans
+            .labels
+            .retain(|name| options.downloads_split(name.split('/').next().unwrap_or_default()));
+        // Withheld test images are unlabeled on purpose.
+        if options.withhold_test_labels {
+            orphans.images.retain(|name| !name.starts_with("test/"));
+        }
+        orphans
+    } else {
+        Orphans::default()
+    };
+
+    channel
+        .send(ProgressEvent::new(
+            "converting",
+            1,
+            1,
+            Some(format!("Converted {} files", files.len())),
+        ))
+        .ok();
+
+    // Create ZIP
+    let total_files = files.len() as u32;
+    let (phase, item) = match options.output_kind {
+        OutputKind::Archive => ("zipping", "Creating ZIP..."),
+        OutputKind::Directory => ("writing", "Writing files..."),
+    };
+    channel
+        .send(ProgressEvent::new(
+            phase,
+            0,
+            total_files,
+            Some(item.to_string()),
+        ))
+        .ok();
+
+    let output_path = PathBuf::from(&output_path);
+    let (uncompressed_bytes, archive_bytes) = match options.output_kind {
+        OutputKind::Archive => {
+            warnings.extend(output_extension_warning(&output_path));
+            let comment = archive_comment(&options.archive_comment, &data, &format);
+            let uncompressed_bytes = write_zip_atomically(
+                &output_path,
+                &files,
+                comment.as_deref(),
+                options.compression_preset,
+                &channel,
+            )?;
+            let archive_bytes = std::fs::metadata(&output_path)
+                .map_err(|e| format!("Failed to read '{}': {}", output_path.display(), e))?
+                .len();
+            (uncompressed_bytes, archive_bytes)
+        }
+        OutputKind::Directory => {
+            let written_bytes =
+                write_directory(&output_path, &files, options.replace_output_dir, &channel)?;
+            (written_bytes, written_bytes)
+        }
+    };
+
+    channel
+        .send(ProgressEvent::new("complete", 1, 1, None))
+        .ok();
+
+    let incomplete_reason = Omissions {
+        failed_downloads,
+        timed_out_downloads,
+        skipped_images,
+        capped_classes,
+        sampled_out_images,
+        dropped_empty_images,
+        withheld_test_labels,
+        delta_export: options.previous_manifest.is_some(),
+    }
+    .reason();
+    Ok(ConvertResult {
+        zip_path: output_path.to_string_lossy().to_string(),
+        file_count: files.len(),
+        image_count,
+        download_total,
+        failed_downloads,
+        mirror_downloads,
+        timed_out_downloads,
+        complete: incomplete_reason.is_none(),
+        incomplete_reason,
+        orphaned_images: orphans.images,
+        orphaned_labels: orphans.labels,
+        deduplicated_images,
+        skipped_images,
+        sampled_out_images,
+        dropped_empty_images,
+        withheld_test_labels,
+        subpixel_boxes,
+        uncompressed_bytes,
+        archive_bytes,
+        warnings,
+    })
+}
+
+/// Re-opens a produced ZIP and checks it is complete for `format`.
+#[tauri::command]
+async fn verify_export(zip_path: String, format: String) -> Result<VerifyReport, String> {
+    verify::verify_export(Path::new(&zip_path), &format)
+}
+
+/// Compares two NDJSON versions of a dataset.
+#[tauri::command]
+async fn diff_ndjson(path_a: String, path_b: String) -> Result<diff::DiffReport, String> {
+    let options = ConvertOptions::default();
+    let before = read_ndjson_input(&path_a, &options).await?;
+    let after = read_ndjson_input(&path_b, &options).await?;
+    diff::diff_ndjson(&before, &after)
+}
+
+/// Sends a HEAD request to every image URL in an NDJSON file, mirrors
+/// included, to find broken links before a full download.
+#[tauri::command]
+async fn check_image_urls(
+    file_path: String,
+    options: Option<ConvertOptions>,
+) -> Result<Vec<downloader::UrlStatus>, String> {
+    let options = options.unwrap_or_default();
+    let content = read_ndjson_input(&file_path, &options).await?;
+    let parse_settings = ParseSettings {
+        lenient_json: options.lenient_json,
+        metadata_policy: options.metadata_policy,
+        ..Default::default()
+    };
+    let (mut data, _) = parse_ndjson_with(&content, &parse_settings)
+        .map_err(|e| format!("Failed to parse NDJSON: {}", e))?;
+    data.remove_skipped_images();
+
+    let mut seen = HashSet::new();
+    let urls: Vec<String> = data
+        .images
+        .iter()
+        .flat_map(|image| image.download_urls())
+        .filter(|url| seen.insert(url.clone()))
+        .collect();
+    Ok(configured_downloader(&options)?.check_urls(&urls).await)
+}
+
+/// Splits a combined export into one ZIP per split in `output_dir`.
+#[tauri::command]
+async fn split_archive(zip_path: String, output_dir: String) -> Result<Vec<String>, String> {
+    split_archive::split_archive(Path::new(&zip_path), Path::new(&output_dir))
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_updater::Builder::new().build())
+        .plugin(tauri_plugin_process::init())
+        .invoke_handler(tauri::generate_handler![
+            convert_ndjson,
+            verify_export,
+            split_archive,
+            diff_ndjson,
+            check_image_urls
+        ])
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        apply_file_name_template, convert_ndjson, file_name_with_suffix, is_ndjson_size_allowed,
+        is_remote_input, normalize_zip_path, output_extension_warning,
+        prepare_images_with_unique_output_names, read_ndjson_input, short_stable_hash,
+        shorten_file_name, temp_output_path, validate_options, write_directory, write_zip,
+        write_zip_atomically, write_zip_atomically_with, Omissions, DEFAULT_MAX_FILE_NAME_BYTES,
+        MAX_NDJSON_BYTES, MIN_MAX_FILE_NAME_BYTES,
+    };
+    use crate::converter::FileData;
+    use crate::options::{
+        AnnotationKind, ClassIdMode, CompressionPreset, ConvertOptions, ImageSource,
+        SplitNormalization,
+    };
+    use crate::parser::parse_ndjson;
+    use std::collections::HashMap;
+    use std::io::{Read, Seek, Write};
+    use std::path::Path;
+    use tauri::ipc::Channel;
+
+    #[test]
+    fn normalize_zip_path_accepts_simple_paths() {
+        assert_eq!(
+            normalize_zip_path("images/foo.jpg").unwrap(),
+            "images/foo.jpg"
+        );
+        assert_eq!(
+            normalize_zip_path("labels\\foo.txt").unwrap(),
+            "labels/foo.txt"
+        );
+    }
+
+    #[test]
+    fn normalize_zip_path_rejects_parent_dirs() {
+        assert!(normalize_zip_path("../evil.txt").is_err());
+        assert!(normalize_zip_path("images/../../evil.txt").is_err());
+    }
+
+    #[test]
+    fn normalize_zip_path_rejects_absolute_paths() {
+        assert!(normalize_zip_path("/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn normalize_zip_path_rejects_windows_prefix() {
+        assert!(normalize_zip_path("C:\\evil.txt").is_err());
+    }
+
+    #[test]
+    fn normalize_zip_path_rejects_windows_reserved_names() {
+        assert!(normalize_zip_path("CON.txt").is_err());
+        assert!(normalize_zip_path("train/NUL.jpg").is_err());
+        assert!(normalize_zip_path("labels/lpt1").is_err());
+    }
+
+    #[test]
+    fn ndjson_size_limit_allows_max_size() {
+        assert!(is_ndjson_size_allowed(MAX_NDJSON_BYTES));
+    }
+
+    #[test]
+    fn ndjson_size_limit_rejects_oversize() {
+        assert!(!is_ndjson_size_allowed(MAX_NDJSON_BYTES + 1));
+    }
+
+    #[test]
+    fn prepare_images_keeps_first_and_renames_same_split_duplicates() {
+        let content = r#"{"type":"dataset","name":"test","class_names":{}}
+{"type":"image","file":"img1.jpg","width":640,"height":480,"split":"train","url":"https://a.example/img1.jpg"}
+{"type":"image","file":"img1.jpg","width":320,"height":240,"split":"val","url":"https://b.example/img1.jpg"}
+{"type":"image","file":"img1.jpg","width":800,"height":600,"split":"train","url":"https://c.example/img1.jpg"}
+{"type":"image","file":"img2.jpg","width":640,"height":480,"split":"test","url":"https://c.example/img2.jpg"}"#;
+
+        let data = parse_ndjson(content).unwrap();
+        let prepared =
+            prepare_images_with_unique_output_names(&data.images, DEFAULT_MAX_FILE_NAME_BYTES);
+
+        assert_eq!(prepared.len(), 4);
+        assert_eq!(prepared[0].file, "img1.jpg");
+        assert_eq!(prepared[0].effective_file_name(), "img1.jpg");
+        assert_eq!(prepared[1].split, "val");
+        assert_eq!(prepared[1].effective_file_name(), "img1.jpg");
+        assert_eq!(
+            prepared[2].effective_file_name(),
+            file_name_with_suffix("img1.jpg", &short_stable_hash("https://c.example/img1.jpg"))
+        );
+        assert_eq!(prepared[3].effective_file_name(), "img2.jpg");
+    }
+
+    #[test]
+    fn prepare_images_uses_counter_when_hash_suffix_collides() {
+        let content = r#"{"type":"dataset","name":"test","class_names":{}}
+{"type":"image","file":"img1.jpg","width":640,"height":480,"split":"train","url":"https://a.example/img1.jpg"}
+{"type":"image","file":"img1.jpg","width":640,"height":480,"split":"train","url":"https://b.example/img1.jpg","annotations":{"boxes":[[0,0.1,0.2,0.3,0.4]]}}
+{"type":"image","file":"img1.jpg","width":640,"height":480,"split":"train","url":"https://b.example/img1.jpg","annotations":{"boxes":[[1,0.2,0.3,0.3,0.4]]}}"#;
+
+        let data = parse_ndjson(content).unwrap();
+        let prepared =
+            prepare_images_with_unique_output_names(&data.images, DEFAULT_MAX_FILE_NAME_BYTES);
+
+        assert_eq!(prepared.len(), 3);
+        let hash = short_stable_hash("https://b.example/img1.jpg");
+        assert_eq!(prepared[0].effective_file_name(), "img1.jpg");
+        assert_eq!(
+            prepared[1].effective_file_name(),
+            file_name_with_suffix("img1.jpg", &hash)
+        );
+        assert_eq!(
+            prepared[2].effective_file_name(),
+            file_name_with_suffix("img1.jpg", &format!("{}__2", hash))
+        );
+    }
+
+    #[test]
+    fn prepare_images_truncates_long_names_and_keeps_them_unique() {
+        let stem_a = format!("{}a", "x".repeat(299));
+        let stem_b = format!("{}b", "x".repeat(299));
+        let content = format!(
+            r#"{{"type":"dataset","name":"test","class_names":{{}}}}
+{{"type":"image","file":"{}.jpg","width":640,"height":480,"split":"train"}}
+{{"type":"image","file":"{}.jpg","width":640,"height":480,"split":"train"}}
+{{"type":"image","file":"short.jpg","width":640,"height":480,"split":"train"}}"#,
+            stem_a, stem_b
+        );
+
+        let data = parse_ndjson(&content).unwrap();
+        let prepared = prepare_images_with_unique_output_names(&data.images, 100);
+
+        let name_a = prepared[0].effective_file_name();
+        let name_b = prepared[1].effective_file_name();
+        assert!(name_a.len() <= 100);
+        assert!(name_b.len() <= 100);
+        assert!(name_a.ends_with(".jpg"));
+        assert_ne!(name_a, name_b);
+        assert_eq!(prepared[2].output_file, None);
+    }
+
+    #[test]
+    fn file_name_template_numbers_images_per_split() {
+        let content = r#"{"type":"dataset","name":"test","class_names":{"0":"Traffic Light"}}
+{"type":"image","file":"IMG_1.JPG","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"IMG_2.png","width":640,"height":480,"split":"val"}
+{"type":"image","file":"IMG_3.jpg","width":640,"height":480,"split":"train"}"#;
+        let mut data = parse_ndjson(content).unwrap();
+        let class_names = HashMap::from([(0, "Traffic Light".to_string())]);
+
+        let mapping = apply_file_name_template(
+            &mut data.images,
+            "{split}_{index:06}_{class}.{ext}",
+            &class_names,
+            DEFAULT_MAX_FILE_NAME_BYTES,
+            SplitNormalization::Canonical,
+        )
+        .unwrap();
+
+        let names: Vec<&str> = data
+            .images
+            .iter()
+            .map(|image| image.effective_file_name())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "train_000000_traffic_light.JPG",
+                "valid_000000_unlabeled.png",
+                "train_000001_unlabeled.jpg",
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&mapping).unwrap()[2],
+            serde_json::json!({
+                "split": "train",
+                "original": "IMG_3.jpg",
+                "file": "train_000001_unlabeled.jpg"
+            })
+        );
+
+        let mut images = data.images.clone();
+        let constant = apply_file_name_template(
+            &mut images,
+            "same.jpg",
+            &class_names,
+            DEFAULT_MAX_FILE_NAME_BYTES,
+            SplitNormalization::Canonical,
+        )
+        .unwrap();
+        assert_ne!(constant[0].file, constant[2].file);
+        let err = apply_file_name_template(
+            &mut images,
+            "{name}.jpg",
+            &class_names,
+            DEFAULT_MAX_FILE_NAME_BYTES,
+            SplitNormalization::Canonical,
+        )
+        .unwrap_err();
+        assert!(err.contains("{name}"));
+    }
+
+    #[tokio::test]
+    async fn file_name_template_numbers_only_sampled_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        let mut content =
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}"#.to_string();
+        for i in 0..20 {
+            content.push_str(&format!(
+                "\n{{\"type\":\"image\",\"file\":\"img{}.jpg\",\"width\":640,\"height\":480,\"split\":\"train\",\"annotations\":{{\"boxes\":[[0,0.5,0.5,0.2,0.2]]}}}}",
+                i
+            ));
+            let image = dir
+                .path()
+                .join("images/train")
+                .join(format!("img{}.jpg", i));
+            std::fs::create_dir_all(image.parent().unwrap()).unwrap();
+            std::fs::write(image, format!("jpeg {}", i)).unwrap();
+        }
+        std::fs::write(&input, content).unwrap();
+        let output = dir.path().join("out.zip");
+
+        convert_ndjson(
+            input.to_string_lossy().to_string(),
+            "yolo".to_string(),
+            output.to_string_lossy().to_string(),
+            true,
+            Some(ConvertOptions {
+                filename_template: Some("{index:03}.{ext}".to_string()),
+                sample_fraction: Some(0.25),
+                image_source: ImageSource::LocalDir {
+                    path: dir.path().join("images").to_string_lossy().to_string(),
+                    pattern: "{split}/{file}".to_string(),
+                },
+                ..Default::default()
+            }),
+            Channel::new(|_| Ok(())),
+        )
+        .await
+        .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        let mut labels: Vec<String> = archive
+            .file_names()
+            .filter_map(|name| name.strip_prefix("train/labels/"))
+            .map(str::to_string)
+            .collect();
+        labels.sort();
+        let expected: Vec<String> = (0..labels.len()).map(|i| format!("{:03}.txt", i)).collect();
+        assert!(!labels.is_empty() && labels.len() < 20, "{:?}", labels);
+        assert_eq!(labels, expected);
+        for label in &labels {
+            let image = format!("train/images/{}", label.replace(".txt", ".jpg"));
+            assert!(archive.by_name(&image).is_ok(), "{} is missing", image);
+        }
+        let mut map = String::new();
+        archive
+            .by_name("file_name_map.json")
+            .unwrap()
+            .read_to_string(&mut map)
+            .unwrap();
+        let map: serde_json::Value = serde_json::from_str(&map).unwrap();
+        assert_eq!(map.as_array().unwrap().len(), labels.len());
+    }
+
+    #[test]
+    fn shorten_file_name_respects_char_boundaries() {
+        let name = format!("{}.png", "é".repeat(200));
+        let short = shorten_file_name(&name, DEFAULT_MAX_FILE_NAME_BYTES);
+
+        assert!(short.len() <= DEFAULT_MAX_FILE_NAME_BYTES);
+        assert!(short.ends_with(".png"));
+        assert_eq!(shorten_file_name("img1.jpg", 255), "img1.jpg");
+    }
+
+    #[test]
+    fn file_name_limits_below_the_hash_suffix_are_rejected() {
+        let too_small = ConvertOptions {
+            max_file_name_bytes: Some(8),
+            ..Default::default()
+        };
+        let err = validate_options(&too_small).unwrap_err();
+        assert!(err.contains("at least"), "{}", err);
+
+        let name = format!("{}.{}", "x".repeat(300), "e".repeat(16));
+        let short = shorten_file_name(&name, MIN_MAX_FILE_NAME_BYTES);
+        assert!(short.len() <= MIN_MAX_FILE_NAME_BYTES, "{}", short);
+        assert!(validate_options(&ConvertOptions {
+            max_file_name_bytes: Some(MIN_MAX_FILE_NAME_BYTES),
+            ..Default::default()
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn empty_annotation_types_are_rejected() {
+        let err = validate_options(&ConvertOptions {
+            annotation_types: Some(Vec::new()),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(err.contains("at least one"), "{}", err);
+        assert!(validate_options(&ConvertOptions {
+            annotation_types: Some(vec![AnnotationKind::Bbox]),
+            ..Default::default()
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn sample_fractions_outside_zero_to_one_are_rejected() {
+        for fraction in [0.0, -0.5, 1.5, f64::NAN] {
+            let err = validate_options(&ConvertOptions {
+                sample_fraction: Some(fraction),
+                ..Default::default()
+            })
+            .unwrap_err();
+            assert!(err.contains("Sample fraction"), "{}", err);
+        }
+        assert!(validate_options(&ConvertOptions {
+            sample_fraction: Some(1.0),
+            ..Default::default()
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn zero_images_per_dir_is_rejected() {
+        let err = validate_options(&ConvertOptions {
+            max_images_per_dir: Some(0),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(err.contains("at least 1"), "{}", err);
+        assert!(validate_options(&ConvertOptions {
+            max_images_per_dir: Some(1),
+            ..Default::default()
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn class_registry_with_remapped_ids_is_rejected() {
+        let err = validate_options(&ConvertOptions {
+            class_registry: Some(HashMap::from([("cat".to_string(), 3)])),
+            class_id_mode: ClassIdMode::Remap,
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(err.contains("class registry"), "{}", err);
+    }
+
+    #[test]
+    fn embedded_source_with_withheld_labels_is_rejected() {
+        let err = validate_options(&ConvertOptions {
+            embed_source: true,
+            withhold_test_labels: true,
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(err.contains("test labels"), "{}", err);
+    }
+
+    #[test]
+    fn embedded_source_with_stripped_urls_is_rejected() {
+        let err = validate_options(&ConvertOptions {
+            embed_source: true,
+            strip_urls: true,
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(err.contains("image URLs"), "{}", err);
+    }
+
+    #[test]
+    fn output_extension_warning_flags_non_zip_paths() {
+        assert_eq!(output_extension_warning(Path::new("out/export.zip")), None);
+        assert_eq!(output_extension_warning(Path::new("out/EXPORT.ZIP")), None);
+        let warning = output_extension_warning(Path::new("out/export.tar.gz")).unwrap();
+        assert!(warning.contains("does not end in .zip"));
+        assert!(output_extension_warning(Path::new("out/export")).is_some());
+    }
+
+    #[test]
+    fn write_zip_reads_spooled_files_back_from_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let spooled = dir.path().join("0.img");
+        let image_bytes: Vec<u8> = (0..=255u8).cycle().take(10_000).collect();
+        std::fs::write(&spooled, &image_bytes).unwrap();
+
+        let mut files = HashMap::new();
+        files.insert(
+            "train/images/img1.jpg".to_string(),
+            FileData::OnDisk(spooled),
+        );
+        files.insert(
+            "train/labels/img1.txt".to_string(),
+            b"0 0.5 0.5 0.2 0.2".to_vec().into(),
+        );
+
+        let zip_path = dir.path().join("out.zip");
+        let file = std::fs::File::create(&zip_path).unwrap();
+        write_zip(
+            file,
+            &files,
+            None,
+            CompressionPreset::default(),
+            &Channel::new(|_| Ok(())),
+        )
+        .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&zip_path).unwrap()).unwrap();
+        let mut round_tripped = Vec::new();
+        archive
+            .by_name("train/images/img1.jpg")
+            .unwrap()
+            .read_to_end(&mut round_tripped)
+            .unwrap();
+        assert_eq!(round_tripped, image_bytes);
+    }
+
+    #[test]
+    fn write_zip_atomically_moves_complete_archive_into_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.zip");
+        let files = HashMap::from([("classes.txt".to_string(), FileData::from(b"cat".to_vec()))]);
+
+        write_zip_atomically(
+            &output,
+            &files,
+            None,
+            CompressionPreset::default(),
+            &Channel::new(|_| Ok(())),
+        )
+        .unwrap();
+
+        assert!(output.exists());
+        assert!(!temp_output_path(&output).exists());
+        assert_eq!(temp_output_path(&output), dir.path().join("out.zip.tmp"));
+    }
+
+    #[test]
+    fn write_zip_atomically_leaves_nothing_behind_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.zip");
+        // An invalid entry path is rejected before anything is written.
+        let files = HashMap::from([("../evil.txt".to_string(), FileData::from(b"evil".to_vec()))]);
+
+        let result = write_zip_atomically(
+            &output,
+            &files,
+            None,
+            CompressionPreset::default(),
+            &Channel::new(|_| Ok(())),
+        );
+
+        assert!(result.is_err());
+        assert!(!output.exists());
+        assert!(!temp_output_path(&output).exists());
+    }
+
+    /// A file whose writes fail when `fail` is set, standing in for a
+    /// network share that drops out.
+    struct FlakyWriter {
+        file: std::fs::File,
+        fail: bool,
+    }
+
+    impl Write for FlakyWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.fail {
+                return Err(std::io::Error::other("share went away"));
+            }
+            self.file.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.file.flush()
+        }
+    }
+
+    impl Seek for FlakyWriter {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.file.seek(pos)
+        }
+    }
+
+    #[test]
+    fn write_zip_atomically_cleans_up_when_both_attempts_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.zip");
+        let files = HashMap::from([("classes.txt".to_string(), FileData::from(b"cat".to_vec()))]);
+        let mut attempts = 0;
+
+        let result = write_zip_atomically_with(
+            &output,
+            &files,
+            None,
+            CompressionPreset::default(),
+            &Channel::new(|_| Ok(())),
+            |file| {
+                attempts += 1;
+                FlakyWriter { file, fail: true }
+            },
+        );
+
+        assert!(result.is_err());
+        assert_eq!(attempts, 2);
+        assert!(!output.exists());
+        assert!(!temp_output_path(&output).exists());
+    }
+
+    #[test]
+    fn write_zip_atomically_does_not_retry_errors_outside_the_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.zip");
+        // A spooled image that has gone missing fails every attempt alike.
+        let files = HashMap::from([(
+            "train/a.jpg".to_string(),
+            FileData::OnDisk(dir.path().join("missing.jpg")),
+        )]);
+        let mut attempts = 0;
+
+        let result = write_zip_atomically_with(
+            &output,
+            &files,
+            None,
+            CompressionPreset::default(),
+            &Channel::new(|_| Ok(())),
+            |file| {
+                attempts += 1;
+                FlakyWriter { file, fail: false }
+            },
+        );
+
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+        assert!(!output.exists());
+        assert!(!temp_output_path(&output).exists());
+    }
+
+    #[test]
+    fn write_zip_atomically_retries_a_failed_write_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.zip");
+        let files = HashMap::from([("classes.txt".to_string(), FileData::from(b"cat".to_vec()))]);
+        let mut attempts = 0;
+
+        let result = write_zip_atomically_with(
+            &output,
+            &files,
+            None,
+            CompressionPreset::default(),
+            &Channel::new(|_| Ok(())),
+            |file| {
+                attempts += 1;
+                FlakyWriter {
+                    file,
+                    fail: attempts == 1,
+                }
+            },
+        );
+
+        assert_eq!(result, Ok(3));
+        assert_eq!(attempts, 2);
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        assert!(archive.by_name("classes.txt").is_ok());
+        assert!(!temp_output_path(&output).exists());
+    }
+
+    #[tokio::test]
+    async fn jsonl_and_extensionless_inputs_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}"#;
+        for name in ["dataset.jsonl", "dataset"] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+
+            let read = read_ndjson_input(path.to_str().unwrap(), &Default::default())
+                .await
+                .unwrap();
+            assert_eq!(parse_ndjson(&read).unwrap().metadata.name, "test");
+        }
+    }
+
+    #[test]
+    fn remote_inputs_are_detected_by_scheme() {
+        assert!(is_remote_input("https://example.com/data.ndjson"));
+        assert!(is_remote_input("HTTP://example.com/data"));
+        assert!(!is_remote_input("/home/user/https/data.jsonl"));
+        assert!(!is_remote_input("C:\\data\\dataset.ndjson"));
+    }
+
+    #[test]
+    fn empty_entries_are_stored_and_recorded_in_the_manifest() {
+        let mut files: HashMap<String, FileData> = HashMap::from([
+            (
+                "train/images/a.jpg".to_string(),
+                FileData::from(b"jpg".to_vec()),
+            ),
+            ("train/labels/a.txt".to_string(), FileData::from(Vec::new())),
+        ]);
+        let mut manifest = crate::manifest::ExportManifest::default();
+        manifest.record_files(&files).unwrap();
+        files.insert(
+            crate::manifest::MANIFEST_FILE_NAME.to_string(),
+            manifest.to_json().into(),
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("out.zip");
+
+        let uncompressed = write_zip(
+            std::fs::File::create(&zip_path).unwrap(),
+            &files,
+            None,
+            CompressionPreset::default(),
+            &Channel::new(|_| Ok(())),
+        )
+        .unwrap();
+
+        let empty = manifest
+            .files
+            .iter()
+            .find(|file| file.path == "train/labels/a.txt")
+            .unwrap();
+        assert_eq!(empty.size, 0);
+        assert_eq!(
+            empty.sha256,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&zip_path).unwrap()).unwrap();
+        let entry = archive.by_name("train/labels/a.txt").unwrap();
+        assert_eq!(entry.size(), 0);
+        assert_eq!(entry.compression(), zip::CompressionMethod::Stored);
+        drop(entry);
+        assert!(uncompressed > 3);
+        let report = crate::verify::verify_export(&zip_path, "yolo").unwrap();
+        assert!(!report
+            .problems
+            .iter()
+            .any(|problem| problem.contains("a.txt")));
+    }
+
+    #[test]
+    fn best_compression_is_smaller_than_fastest_for_labels() {
+        // Label-like text with enough variety that deflate effort matters.
+        let mut seed: u32 = 1;
+        let mut labels = String::new();
+        for _ in 0..20_000 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            labels.push_str(&format!(
+                "{} 0.{:06} 0.{:06} 0.1 0.2\n",
+                seed % 5,
+                seed >> 12,
+                seed % 997
+            ));
+        }
+        let files: HashMap<String, FileData> = HashMap::from([(
+            "train/labels/a.txt".to_string(),
+            FileData::from(labels.into_bytes()),
+        )]);
+
+        let archive_size = |preset| {
+            let mut cursor = std::io::Cursor::new(Vec::new());
+            write_zip(&mut cursor, &files, None, preset, &Channel::new(|_| Ok(()))).unwrap();
+            cursor.into_inner().len()
+        };
+
+        assert!(archive_size(CompressionPreset::Best) < archive_size(CompressionPreset::Fastest));
+    }
+
+    #[test]
+    fn zip_progress_reports_images_and_labels_as_stages() {
+        let files: HashMap<String, FileData> = HashMap::from([
+            (
+                "train/images/a.jpg".to_string(),
+                FileData::from(b"a".to_vec()),
+            ),
+            (
+                "train/images/b.png".to_string(),
+                FileData::from(b"b".to_vec()),
+            ),
+            (
+                "train/labels/a.txt".to_string(),
+                FileData::from(b"0".to_vec()),
+            ),
+            ("data.yaml".to_string(), FileData::from(b"nc: 1".to_vec())),
+            ("classes.txt".to_string(), FileData::from(b"cat".to_vec())),
+        ]);
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = std::sync::Arc::clone(&events);
+        let channel = Channel::new(move |body| {
+            if let tauri::ipc::InvokeResponseBody::Json(json) = body {
+                let event: serde_json::Value = serde_json::from_str(&json).unwrap();
+                log.lock().unwrap().push((
+                    event["stage"].as_str().unwrap().to_string(),
+                    event["current"].as_u64().unwrap(),
+                    event["total"].as_u64().unwrap(),
+                ));
+            }
+            Ok(())
+        });
+
+        write_zip(
+            std::io::Cursor::new(Vec::new()),
+            &files,
+            None,
+            CompressionPreset::default(),
+            &channel,
+        )
+        .unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .map(|(stage, ..)| stage.as_str())
+                .collect::<Vec<_>>(),
+            ["images", "images", "labels", "labels"]
+        );
+        assert_eq!(events[1], ("images".to_string(), 2, 2));
+        assert_eq!(events[3], ("labels".to_string(), 3, 3));
+    }
+
+    #[tokio::test]
+    async fn overall_progress_never_goes_backwards() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        std::fs::write(
+            &input,
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"val","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let percents = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = std::sync::Arc::clone(&percents);
+        let channel = Channel::new(move |body| {
+            if let tauri::ipc::InvokeResponseBody::Json(json) = body {
+                let event: serde_json::Value = serde_json::from_str(&json).unwrap();
+                log.lock()
+                    .unwrap()
+                    .push(event["overall_percent"].as_u64().unwrap());
+            }
+            Ok(())
+        });
+
+        convert_ndjson(
+            input.to_string_lossy().to_string(),
+            "yolo".to_string(),
+            dir.path().join("out.zip").to_string_lossy().to_string(),
+            false,
+            None,
+            channel,
+        )
+        .await
+        .unwrap();
+
+        let percents = percents.lock().unwrap();
+        assert!(percents.len() > 3);
+        assert!(percents.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(percents.first(), Some(&0));
+        assert_eq!(percents.last(), Some(&100));
+    }
+
+    #[tokio::test]
+    async fn metadata_only_dataset_exports_config_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        std::fs::write(
+            &input,
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}"#,
+        )
+        .unwrap();
+        let output = dir.path().join("out.zip");
+
+        let result = convert_ndjson(
+            input.to_string_lossy().to_string(),
+            "yolo".to_string(),
+            output.to_string_lossy().to_string(),
+            true,
+            None,
+            Channel::new(|_| Ok(())),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.image_count, 0);
+        assert!(result.file_count > 0);
+        assert!(result
+            .warnings
+            .iter()
+            .any(|warning| warning.contains("no images")));
+        let archive = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        assert!(archive.file_names().any(|name| name == "data.yaml"));
+
+        let empty = dir.path().join("empty.zip");
+        write_zip_atomically(
+            &empty,
+            &HashMap::new(),
+            None,
+            CompressionPreset::default(),
+            &Channel::new(|_| Ok(())),
+        )
+        .unwrap();
+        assert_eq!(
+            zip::ZipArchive::new(std::fs::File::open(&empty).unwrap())
+                .unwrap()
+                .len(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn directory_output_writes_the_tree_to_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        std::fs::write(
+            &input,
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let output = dir.path().join("export");
+
+        let result = convert_ndjson(
+            input.to_string_lossy().to_string(),
+            "yolo".to_string(),
+            output.to_string_lossy().to_string(),
+            false,
+            Some(crate::options::ConvertOptions {
+                output_kind: crate::options::OutputKind::Directory,
+                ..Default::default()
+            }),
+            Channel::new(|_| Ok(())),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.zip_path, output.to_string_lossy());
+        assert!(output.join("data.yaml").is_file());
+        assert!(output.join("classes.txt").is_file());
+        assert_eq!(
+            std::fs::read_to_string(output.join("train/labels/a.txt")).unwrap(),
+            "0 0.500000 0.500000 0.200000 0.200000"
+        );
+    }
+
+    #[test]
+    fn directory_output_refuses_a_non_empty_target_unless_replacing() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("export");
+        std::fs::create_dir_all(output.join("train")).unwrap();
+        std::fs::write(output.join("stale.txt"), "old").unwrap();
+        let files: HashMap<String, FileData> =
+            HashMap::from([("train/a.txt".to_string(), b"new".to_vec().into())]);
+
+        let err = write_directory(&output, &files, false, &Channel::new(|_| Ok(()))).unwrap_err();
+        assert!(err.contains("is not empty"), "{}", err);
+        assert!(output.join("stale.txt").is_file());
+
+        write_directory(&output, &files, true, &Channel::new(|_| Ok(()))).unwrap();
+        assert!(!output.join("stale.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string(output.join("train/a.txt")).unwrap(),
+            "new"
+        );
+        let leftovers: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(leftovers, ["export"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directory_output_does_not_follow_links_in_the_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = dir.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        let output = dir.path().join("export");
+        std::fs::create_dir(&output).unwrap();
+        std::os::unix::fs::symlink(&outside, output.join("train")).unwrap();
+        let files: HashMap<String, FileData> =
+            HashMap::from([("train/a.txt".to_string(), b"new".to_vec().into())]);
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let channel = Channel::new(move |body| {
+            if let tauri::ipc::InvokeResponseBody::Json(json) = body {
+                sink.lock().unwrap().push(json);
+            }
+            Ok(())
+        });
+
+        write_directory(&output, &files, true, &channel).unwrap();
+
+        assert!(std::fs::read_dir(&outside).unwrap().next().is_none());
+        assert!(!output.join("train").is_symlink());
+        assert!(output.join("train/a.txt").is_file());
+        assert!(events
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|json| json.contains(r#""phase":"writing""#)));
+    }
+
+    #[tokio::test]
+    async fn converting_in_place_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        let content = r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}"#;
+        std::fs::write(&input, content).unwrap();
+        let same_file = dir.path().join(".").join("dataset.ndjson");
+
+        let err = convert_ndjson(
+            input.to_string_lossy().to_string(),
+            "yolo".to_string(),
+            same_file.to_string_lossy().to_string(),
+            false,
+            None,
+            Channel::new(|_| Ok(())),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(err.contains("is the input NDJSON"), "{}", err);
+        assert_eq!(std::fs::read_to_string(&input).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn out_of_range_source_coordinates_fail_the_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        std::fs::write(
+            &input,
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,1.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let output = dir.path().join("out.zip");
+
+        let err = convert_ndjson(
+            input.to_string_lossy().to_string(),
+            "yolo".to_string(),
+            output.to_string_lossy().to_string(),
+            false,
+            None,
+            Channel::new(|_| Ok(())),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(err.contains("outside [0, 1]"), "{}", err);
+        assert!(!output.exists());
+    }
+
+    #[tokio::test]
+    async fn result_reports_uncompressed_and_archive_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        let class_names: Vec<String> = (0..2000)
+            .map(|id| format!("\"{}\":\"repetitive class name {}\"", id, id))
+            .collect();
+        std::fs::write(
+            &input,
+            format!(
+                r#"{{"type":"dataset","name":"test","class_names":{{{}}}}}"#,
+                class_names.join(",")
+            ),
+        )
+        .unwrap();
+        let output = dir.path().join("out.zip");
+
+        let result = convert_ndjson(
+            input.to_string_lossy().to_string(),
+            "yolo".to_string(),
+            output.to_string_lossy().to_string(),
+            false,
+            None,
+            Channel::new(|_| Ok(())),
+        )
+        .await
+        .unwrap();
+
+        assert!(result.uncompressed_bytes > 0);
+        assert_eq!(
+            result.archive_bytes,
+            std::fs::metadata(&output).unwrap().len()
+        );
+        assert!(result.archive_bytes <= result.uncompressed_bytes);
+    }
+
+    #[tokio::test]
+    async fn archive_comment_is_written_to_the_zip() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        std::fs::write(
+            &input,
+            r#"{"type":"dataset","name":"Animals","class_names":{"0":"cat"}}"#,
+        )
+        .unwrap();
+
+        for (name, comment) in [
+            ("generated.zip", crate::options::ArchiveComment::Generated),
+            (
+                "custom.zip",
+                crate::options::ArchiveComment::Custom("provenance: batch 7".to_string()),
+            ),
+        ] {
+            let output = dir.path().join(name);
+            convert_ndjson(
+                input.to_string_lossy().to_string(),
+                "coco".to_string(),
+                output.to_string_lossy().to_string(),
+                false,
+                Some(crate::options::ConvertOptions {
+                    archive_comment: comment,
+                    ..Default::default()
+                }),
+                Channel::new(|_| Ok(())),
+            )
+            .await
+            .unwrap();
+
+            let archive = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+            let text = String::from_utf8_lossy(archive.comment()).into_owned();
+            if name == "custom.zip" {
+                assert_eq!(text, "provenance: batch 7");
+            } else {
+                assert!(
+                    text.starts_with("Animals | format: coco | exported: "),
+                    "{}",
+                    text
+                );
+                assert!(text.ends_with(env!("CARGO_PKG_VERSION")), "{}", text);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn result_says_whether_the_export_is_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        std::fs::write(
+            &input,
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let images = dir.path().join("images");
+        std::fs::create_dir_all(images.join("train")).unwrap();
+        std::fs::write(images.join("train/a.jpg"), b"a").unwrap();
+        let convert = |output: &str| {
+            convert_ndjson(
+                input.to_string_lossy().to_string(),
+                "yolo".to_string(),
+                dir.path().join(output).to_string_lossy().to_string(),
+                true,
+                Some(ConvertOptions {
+                    image_source: ImageSource::LocalDir {
+                        path: images.to_string_lossy().to_string(),
+                        pattern: "{split}/{file}".to_string(),
+                    },
+                    ..Default::default()
+                }),
+                Channel::new(|_| Ok(())),
+            )
+        };
+
+        let partial = convert("partial.zip").await.unwrap();
+        assert!(!partial.complete);
+        assert_eq!(
+            partial.incomplete_reason.as_deref(),
+            Some("1 images failed to download")
+        );
+
+        std::fs::write(images.join("train/b.jpg"), b"b").unwrap();
+        let full = convert("full.zip").await.unwrap();
+        assert!(full.complete);
+        assert_eq!(full.incomplete_reason, None);
+    }
+
+    #[test]
+    fn deadline_timeouts_are_reported_apart_from_failures() {
+        let omissions = Omissions {
+            failed_downloads: 3,
+            timed_out_downloads: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            omissions.reason().as_deref(),
+            Some("1 images failed to download; 2 downloads were cut off by the deadline")
+        );
+        assert_eq!(Omissions::default().reason(), None);
+    }
+
+    #[tokio::test]
+    async fn sampled_dropped_and_withheld_images_make_the_export_incomplete() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        let mut content =
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}"#.to_string();
+        for (file, split) in [
+            ("a", "train"),
+            ("b", "train"),
+            ("c", "train"),
+            ("d", "train"),
+            ("t", "test"),
+        ] {
+            content.push_str(&format!(
+                "\n{{\"type\":\"image\",\"file\":\"{}.jpg\",\"width\":640,\"height\":480,\"split\":\"{}\",\"annotations\":{{\"boxes\":[[0,0.5,0.5,0.2,0.2]]}}}}",
+                file, split
+            ));
+        }
+        content.push_str(
+            r#"
+{"type":"image","file":"e.jpg","width":640,"height":480,"split":"train"}"#,
+        );
+        std::fs::write(&input, content).unwrap();
+
+        let result = convert_ndjson(
+            input.to_string_lossy().to_string(),
+            "yolo".to_string(),
+            dir.path().join("out.zip").to_string_lossy().to_string(),
+            false,
+            Some(ConvertOptions {
+                drop_empty_images: true,
+                sample_fraction: Some(0.5),
+                withhold_test_labels: true,
+                ..Default::default()
+            }),
+            Channel::new(|_| Ok(())),
+        )
+        .await
+        .unwrap();
+
+        assert!(!result.complete);
+        assert_eq!(result.dropped_empty_images, 1);
+        assert_eq!(result.sampled_out_images, 2);
+        assert_eq!(result.withheld_test_labels, 1);
+        assert_eq!(
+            result.incomplete_reason.as_deref(),
+            Some(
+                "2 images were sampled out; 1 images without annotations were dropped; \
+                 labels of 1 test images were withheld"
+            )
+        );
+    }
+
+    /// A 64x48 gradient JPEG at quality 100, which re-encodes smaller.
+    fn large_jpeg() -> Vec<u8> {
+        let img = image::RgbImage::from_fn(64, 48, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 5) as u8, 128])
+        });
+        let mut bytes = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, 100)
+            .encode_image(&img)
+            .unwrap();
+        bytes
+    }
+
+    #[tokio::test]
+    async fn recompressing_local_images_leaves_the_sources_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        std::fs::write(
+            &input,
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":64,"height":48,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let images = dir.path().join("images");
+        std::fs::create_dir_all(images.join("train")).unwrap();
+        let original = large_jpeg();
+        std::fs::write(images.join("train/a.jpg"), &original).unwrap();
+        let output = dir.path().join("out.zip");
+
+        convert_ndjson(
+            input.to_string_lossy().to_string(),
+            "yolo".to_string(),
+            output.to_string_lossy().to_string(),
+            true,
+            Some(ConvertOptions {
+                image_source: ImageSource::LocalDir {
+                    path: images.to_string_lossy().to_string(),
+                    pattern: "{split}/{file}".to_string(),
+                },
+                recompress_over_bytes: Some(1),
+                recompress_quality: Some(50),
+                ..Default::default()
+            }),
+            Channel::new(|_| Ok(())),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(std::fs::read(images.join("train/a.jpg")).unwrap(), original);
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        let mut exported = Vec::new();
+        archive
+            .by_name("train/images/a.jpg")
+            .unwrap()
+            .read_to_end(&mut exported)
+            .unwrap();
+        assert!(exported.len() < original.len());
+    }
+
+    #[tokio::test]
+    async fn manifest_lists_only_images_that_were_exported() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        std::fs::write(
+            &input,
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"c.jpg","width":640,"height":480,"split":"train"}"#,
+        )
+        .unwrap();
+        let images = dir.path().join("images");
+        std::fs::create_dir_all(images.join("train")).unwrap();
+        std::fs::write(images.join("train/a.jpg"), b"a").unwrap();
+        std::fs::write(images.join("train/c.jpg"), b"c").unwrap();
+        let output = dir.path().join("out.zip");
+
+        convert_ndjson(
+            input.to_string_lossy().to_string(),
+            "yolo".to_string(),
+            output.to_string_lossy().to_string(),
+            true,
+            Some(ConvertOptions {
+                image_source: ImageSource::LocalDir {
+                    path: images.to_string_lossy().to_string(),
+                    pattern: "{split}/{file}".to_string(),
+                },
+                write_manifest: true,
+                drop_empty_images: true,
+                ..Default::default()
+            }),
+            Channel::new(|_| Ok(())),
+        )
+        .await
+        .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        let mut manifest = String::new();
+        archive
+            .by_name(crate::manifest::MANIFEST_FILE_NAME)
+            .unwrap()
+            .read_to_string(&mut manifest)
+            .unwrap();
+        let manifest: crate::manifest::ExportManifest = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(manifest.images.keys().collect::<Vec<_>>(), ["train/a.jpg"]);
+    }
+
+    #[tokio::test]
+    async fn url_less_dataset_with_images_requested_is_flagged() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        std::fs::write(
+            &input,
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let convert = |require_image_urls| {
+            convert_ndjson(
+                input.to_string_lossy().to_string(),
+                "yolo".to_string(),
+                dir.path().join("out.zip").to_string_lossy().to_string(),
+                true,
+                Some(ConvertOptions {
+                    require_image_urls,
+                    ..Default::default()
+                }),
+                Channel::new(|_| Ok(())),
+            )
+        };
+
+        let result = convert(false).await.unwrap();
+        assert!(result
+            .warnings
+            .iter()
+            .any(|warning| warning.contains("no image in the NDJSON has a URL")));
+
+        let err = convert(true).await.unwrap_err();
+        assert!(err.contains("local folder"));
+    }
+
+    #[tokio::test]
+    async fn embedded_source_parses_to_the_same_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("dataset.ndjson");
+        let content = r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"val","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#;
+        std::fs::write(&input, content).unwrap();
+        let output = dir.path().join("out.zip");
+
+        convert_ndjson(
+            input.to_string_lossy().to_string(),
+            "coco".to_string(),
+            output.to_string_lossy().to_string(),
+            false,
+            Some(ConvertOptions {
+                embed_source: true,
+                ..Default::default()
+            }),
+            Channel::new(|_| Ok(())),
+        )
+        .await
+        .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        let mut embedded = String::new();
+        archive
+            .by_name("source.ndjson")
+            .unwrap()
+            .read_to_string(&mut embedded)
+            .unwrap();
+        assert_eq!(
+            format!("{:?}", parse_ndjson(&embedded).unwrap()),
+            format!("{:?}", parse_ndjson(content).unwrap())
         );
     }
 }
@@ -0,0 +1,195 @@
+use crate::converter::FileData;
+use crate::downloader::{DownloadResult, ProgressEvent, MAX_DOWNLOAD_BYTES};
+use crate::parser::{image_entry_download_key, normalize_split, ImageEntry};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use tauri::ipc::Channel;
+
+/// Resolves `pattern` for `image` against `base`. `{split}` is the split as
+/// written in the NDJSON and `{file}` the original file name. The expanded
+/// path must stay inside `base`, including after following symlinks.
+fn resolve_local_path(base: &Path, pattern: &str, image: &ImageEntry) -> Result<PathBuf, String> {
+    let relative = pattern
+        .replace("{split}", &image.split)
+        .replace("{file}", &image.file)
+        .replace('\\', "/");
+
+    let mut path = base.to_path_buf();
+    for component in Path::new(&relative).components() {
+        match component {
+            Component::Normal(name) => path.push(name),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(format!("path '{}' escapes the image directory", relative));
+            }
+        }
+    }
+
+    let canonical_base = base.canonicalize().map_err(|e| {
+        format!(
+            "image directory '{}' is not readable: {}",
+            base.display(),
+            e
+        )
+    })?;
+    let canonical = path
+        .canonicalize()
+        .map_err(|_| format!("not found at '{}'", path.display()))?;
+    if !canonical.starts_with(&canonical_base) {
+        return Err(format!("path '{}' escapes the image directory", relative));
+    }
+    if !canonical.is_file() {
+        return Err(format!("'{}' is not a file", path.display()));
+    }
+
+    Ok(canonical)
+}
+
+/// Collects image files from a local directory instead of downloading them.
+/// Files are referenced in place and only read when the ZIP is written.
+/// Returns the same shape as a download plus one warning per missing image.
+pub fn read_local_images(
+    images: &[ImageEntry],
+    base: &Path,
+    pattern: &str,
+    channel: &Channel<ProgressEvent>,
+) -> (DownloadResult, Vec<String>) {
+    let total = images.len() as u32;
+    let mut files = HashMap::new();
+    let mut warnings = Vec::new();
+
+    for (idx, image) in images.iter().enumerate() {
+        let item_label = format!(
+            "{}/{}",
+            normalize_split(&image.split),
+            image.effective_file_name()
+        );
+
+        let resolved = resolve_local_path(base, pattern, image).and_then(|path| {
+            let size = std::fs::metadata(&path)
+                .map_err(|e| format!("failed to inspect '{}': {}", path.display(), e))?
+                .len();
+            if size > MAX_DOWNLOAD_BYTES as u64 {
+                return Err(format!(
+                    "file is too large ({} bytes, limit {} bytes)",
+                    size, MAX_DOWNLOAD_BYTES
+                ));
+            }
+            Ok(path)
+        });
+        match resolved {
+            Ok(path) => {
+                files.insert(image_entry_download_key(image), FileData::OnDisk(path));
+            }
+            Err(err) => warnings.push(format!("{}: local image {}", item_label, err)),
+        }
+
+        let current = idx as u32 + 1;
+        if idx % 50 == 0 || current == total {
+            let _ = channel.send(ProgressEvent::new(
+                "downloading",
+                current,
+                total,
+                Some(item_label),
+            ));
+        }
+    }
+
+    let failed = warnings.len();
+    (
+        DownloadResult {
+            files,
+            total,
+            failed,
+            timed_out: 0,
+            mirrored: HashMap::new(),
+        },
+        warnings,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::image_download_key;
+
+    fn image(split: &str, file: &str) -> ImageEntry {
+        ImageEntry {
+            r#type: "image".to_string(),
+            file: file.to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: split.to_string(),
+            annotations: None,
+        }
+    }
+
+    #[test]
+    fn read_local_images_bundles_files_and_reports_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("train")).unwrap();
+        std::fs::write(dir.path().join("train/a.jpg"), b"jpeg bytes").unwrap();
+
+        let images = vec![image("train", "a.jpg"), image("val", "b.jpg")];
+        let (result, warnings) = read_local_images(
+            &images,
+            dir.path(),
+            "{split}/{file}",
+            &Channel::new(|_| Ok(())),
+        );
+
+        assert_eq!(result.total, 2);
+        assert_eq!(result.failed, 1);
+        let file = &result.files[&image_download_key("train", "a.jpg")];
+        assert_eq!(file.read().unwrap().as_ref(), b"jpeg bytes");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("valid/b.jpg: local image not found"));
+    }
+
+    #[test]
+    fn read_local_images_supports_custom_pattern() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("images")).unwrap();
+        std::fs::write(dir.path().join("images/a.jpg"), b"jpeg bytes").unwrap();
+
+        let images = vec![image("train", "a.jpg")];
+        let (result, warnings) = read_local_images(
+            &images,
+            dir.path(),
+            "images/{file}",
+            &Channel::new(|_| Ok(())),
+        );
+
+        assert!(warnings.is_empty());
+        assert!(result
+            .files
+            .contains_key(&image_download_key("train", "a.jpg")));
+    }
+
+    #[test]
+    fn read_local_images_rejects_traversal() {
+        let root = tempfile::tempdir().unwrap();
+        let base = root.path().join("images");
+        std::fs::create_dir_all(&base).unwrap();
+        std::fs::write(root.path().join("secret.jpg"), b"secret").unwrap();
+
+        let images = vec![
+            image("train", "../secret.jpg"),
+            image("train", "/etc/passwd"),
+        ];
+        let (result, warnings) =
+            read_local_images(&images, &base, "{file}", &Channel::new(|_| Ok(())));
+
+        assert!(result.files.is_empty());
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings
+            .iter()
+            .all(|warning| warning.contains("escapes the image directory")));
+    }
+}
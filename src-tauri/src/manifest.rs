@@ -0,0 +1,187 @@
+use crate::converter::FileData;
+use crate::options::SplitNormalization;
+use crate::parser::ImageEntry;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Record of what an export contained, written as `manifest.json` so a later
+/// export can be reduced to the images that changed since.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ExportManifest {
+    /// Annotation content hash per image, keyed by its output `split/file`.
+    #[serde(default)]
+    pub images: BTreeMap<String, String>,
+    /// Every other file in the export, so it can be checked without
+    /// unpacking it.
+    #[serde(default)]
+    pub files: Vec<ManifestFile>,
+}
+
+/// Uncompressed size and SHA-256 of one export file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+pub(crate) fn fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn manifest_key(image: &ImageEntry, splits: SplitNormalization) -> String {
+    format!(
+        "{}/{}",
+        splits.output_split(&image.split),
+        image.effective_file_name()
+    )
+}
+
+/// Hash of everything that shapes an image's labels: its dimensions and its
+/// annotations. serde_json keeps object keys sorted, so equal annotations
+/// always serialize, and therefore hash, identically.
+pub fn annotation_hash(image: &ImageEntry) -> String {
+    let annotations = serde_json::to_string(&image.annotations).unwrap_or_default();
+    let content = format!("{}x{}|{}", image.width, image.height, annotations);
+    format!("{:016x}", fnv1a_64(content.as_bytes()))
+}
+
+impl ExportManifest {
+    pub fn for_images(images: &[ImageEntry], splits: SplitNormalization) -> Self {
+        Self {
+            images: images
+                .iter()
+                .map(|image| (manifest_key(image, splits), annotation_hash(image)))
+                .collect(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Drops the entries of images not among `images`, e.g. ones sampled out
+    /// or whose download failed, so a later delta export still picks them up.
+    pub fn retain_images<'a>(
+        &mut self,
+        images: impl IntoIterator<Item = &'a ImageEntry>,
+        splits: SplitNormalization,
+    ) {
+        let keep: HashSet<String> = images
+            .into_iter()
+            .map(|image| manifest_key(image, splits))
+            .collect();
+        self.images.retain(|key, _| keep.contains(key));
+    }
+
+    /// Lists the size and SHA-256 of each of `files`, sorted by path.
+    pub fn record_files(&mut self, files: &HashMap<String, FileData>) -> Result<(), String> {
+        let mut entries = files
+            .iter()
+            .filter(|(path, _)| path.as_str() != MANIFEST_FILE_NAME)
+            .map(|(path, data)| {
+                let bytes = data
+                    .read()
+                    .map_err(|e| format!("Failed to read '{}' for the manifest: {}", path, e))?;
+                Ok(ManifestFile {
+                    path: path.clone(),
+                    size: bytes.len() as u64,
+                    sha256: sha256_hex(&bytes),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        self.files = entries;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read manifest '{}': {}", path.display(), e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse manifest '{}': {}", path.display(), e))
+    }
+
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).unwrap_or_default()
+    }
+
+    /// Whether `image` has the same annotation hash it had in this manifest.
+    pub fn is_unchanged(&self, image: &ImageEntry, splits: SplitNormalization) -> bool {
+        self.images
+            .get(&manifest_key(image, splits))
+            .is_some_and(|hash| *hash == annotation_hash(image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn image(file: &str, annotations: serde_json::Value) -> ImageEntry {
+        ImageEntry {
+            r#type: "image".to_string(),
+            file: file.to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: "val".to_string(),
+            annotations: Some(annotations),
+        }
+    }
+
+    #[test]
+    fn annotation_hash_ignores_key_order() {
+        let a = image(
+            "a.jpg",
+            json!({"boxes": [[0, 0.5, 0.5, 0.2, 0.2]], "classification": [0]}),
+        );
+        let b = image(
+            "a.jpg",
+            json!({"classification": [0], "boxes": [[0, 0.5, 0.5, 0.2, 0.2]]}),
+        );
+        assert_eq!(annotation_hash(&a), annotation_hash(&b));
+    }
+
+    #[test]
+    fn delta_keeps_only_changed_and_new_images() {
+        let previous = ExportManifest::for_images(
+            &[
+                image("same.jpg", json!({"boxes": [[0, 0.5, 0.5, 0.2, 0.2]]})),
+                image("edited.jpg", json!({"boxes": [[0, 0.5, 0.5, 0.2, 0.2]]})),
+            ],
+            SplitNormalization::Canonical,
+        );
+        let round_tripped: ExportManifest = serde_json::from_slice(&previous.to_json()).unwrap();
+
+        let current = [
+            image("same.jpg", json!({"boxes": [[0, 0.5, 0.5, 0.2, 0.2]]})),
+            image("edited.jpg", json!({"boxes": [[1, 0.5, 0.5, 0.2, 0.2]]})),
+            image("new.jpg", json!({"boxes": [[0, 0.1, 0.1, 0.1, 0.1]]})),
+        ];
+        let delta: Vec<&str> = current
+            .iter()
+            .filter(|image| !round_tripped.is_unchanged(image, SplitNormalization::Canonical))
+            .map(|image| image.file.as_str())
+            .collect();
+
+        assert_eq!(delta, vec!["edited.jpg", "new.jpg"]);
+        assert!(round_tripped.images.contains_key("valid/same.jpg"));
+    }
+}
@@ -0,0 +1,496 @@
+use crate::parser::{normalize_split, ImageEntry, MetadataPolicy};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// User-selectable conversion settings. Every field defaults to the behavior
+/// the converter had before the option existed, so callers that omit the
+/// options argument get unchanged output.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ConvertOptions {
+    /// Use lowercase, filesystem-safe slugs for classify class folders.
+    pub slugify_class_names: bool,
+    /// Spool downloaded images to a temp directory instead of holding every
+    /// image in memory until the ZIP is written.
+    pub stream_images_to_disk: bool,
+    /// Blank image and dataset URLs in the exported annotations. Images are
+    /// still downloaded from their URLs as usual.
+    pub strip_urls: bool,
+    /// Keep only annotations of these class ids. Ids are never renumbered, so
+    /// the class list stays the same across splits.
+    pub class_filter: Option<Vec<i32>>,
+    /// Per-split overrides of `class_filter`, keyed by split name.
+    pub split_class_filters: HashMap<String, Vec<i32>>,
+    /// What to do when a downloaded image's real size differs from the
+    /// declared `width`/`height`.
+    pub dimension_check: DimensionCheck,
+    /// Where image bytes come from when images are included.
+    pub image_source: ImageSource,
+    /// Reject images whose header declares more pixels than this, counting
+    /// them as failed downloads.
+    pub max_image_pixels: Option<u64>,
+    /// Dataset name written as the YOLO `data.yaml` `path:` instead of `.`.
+    pub yaml_dataset_name: Option<String>,
+    /// URL or script written as the YOLO `data.yaml` `download:` entry.
+    pub yaml_download: Option<String>,
+    /// Retry NDJSON lines that strict JSON rejects as JSON5, for hand-edited
+    /// files with trailing commas or single-quoted strings.
+    pub lenient_json: bool,
+    /// Re-encode JPEG/PNG images larger than this many bytes. Smaller images
+    /// are bundled untouched.
+    pub recompress_over_bytes: Option<usize>,
+    /// JPEG quality (1-100) used when recompressing. PNGs are re-encoded
+    /// losslessly at maximum compression.
+    pub recompress_quality: Option<u8>,
+    /// Write a `{stem}.legend.txt` beside each YOLO label mapping the class
+    /// ids it uses to names.
+    pub label_legends: bool,
+    /// Longest output file name, in bytes, before it is truncated with a
+    /// stable hash. Defaults to 255, the common filesystem limit.
+    pub max_file_name_bytes: Option<usize>,
+    /// For YOLO segment exports, also write bounding-box labels derived from
+    /// the polygons under `{split}/labels_det/`.
+    pub segment_bbox_labels: bool,
+    /// Overall time budget for a conversion, in seconds. Downloads still
+    /// pending when it runs out are skipped and the export is marked
+    /// incomplete.
+    pub conversion_deadline_secs: Option<u64>,
+    /// Order COCO `images` by file name with each image's annotations kept
+    /// contiguous, for consumers that stream the file.
+    pub sort_coco: bool,
+    /// Add a `manifest.json` with a per-image annotation hash to the export.
+    pub write_manifest: bool,
+    /// Path to the `manifest.json` of an earlier export. Only images whose
+    /// annotations changed since are exported, along with an updated
+    /// manifest covering every image.
+    pub previous_manifest: Option<String>,
+    /// Write a `.keep` file into each split directory of a split that has no
+    /// images, for trainers that expect every split to exist.
+    pub empty_split_placeholders: bool,
+    /// User-Agent sent with image downloads instead of the client default.
+    pub user_agent: Option<String>,
+    /// Send a Referer header with image downloads.
+    pub send_referer: bool,
+    /// Referer value to send. When unset, each request uses its URL's origin.
+    pub referer: Option<String>,
+    /// Store byte-identical images in the same directory once, pointing
+    /// every annotation at the shared file. Not available for YOLO or
+    /// classify exports.
+    pub dedup_identical_images: bool,
+    /// When the dataset has no `class_names` but annotations reference class
+    /// ids, name them `class_0..class_N` instead of exporting zero classes.
+    pub synthesize_class_names: bool,
+    /// Splits whose images are downloaded and bundled. Annotations are still
+    /// written for every split. `None` downloads all splits.
+    pub download_splits: Option<Vec<String>>,
+    /// Simplify COCO segment polygons with more points than this, for tools
+    /// that reject long segmentation arrays.
+    pub max_segmentation_points: Option<usize>,
+    /// Keep at most this many annotations per class, dropping the excess of
+    /// over-represented classes to reduce imbalance.
+    pub max_per_class: Option<usize>,
+    /// Seed choosing which annotations `max_per_class` keeps.
+    pub balance_seed: u64,
+    /// Split image directories with more images than this into numbered
+    /// subdirectories of at most this many, moving labels alongside.
+    pub max_images_per_dir: Option<usize>,
+    /// Directory the Darknet export will be extracted to. When set, the
+    /// `{split}.txt` image lists use absolute paths under it instead of
+    /// paths relative to the archive root.
+    pub image_root: Option<String>,
+    /// Also write a COCO captions file, `{split}/_captions.coco.json`, for
+    /// splits whose images have captions.
+    pub coco_captions: bool,
+    /// Write a `{stem}.abs.txt` next to each YOLO label with the boxes in
+    /// pixels, as `class xmin ymin xmax ymax`.
+    pub absolute_label_sidecars: bool,
+    /// Re-encode settings per split, keyed by split name. Images of splits
+    /// without an entry are bundled as they are.
+    pub split_image_settings: HashMap<String, SplitImageSettings>,
+    /// For pose COCO exports, keep only images and categories that have
+    /// annotations with labeled keypoints.
+    pub coco_keypoints_only: bool,
+    /// Whether the export is written as a ZIP or as a directory tree.
+    pub output_kind: OutputKind,
+    /// For directory output, replace an existing non-empty output directory
+    /// instead of refusing to write to it.
+    pub replace_output_dir: bool,
+    /// How fractional pixel coordinates become whole pixels in COCO and VOC
+    /// boxes. When unset, VOC rounds and COCO keeps fractional coordinates.
+    pub pixel_rounding: Option<PixelRounding>,
+    /// Hosts image downloads may use, as `example.com` (the host and its
+    /// subdomains) or `*.example.com` (subdomains only). Empty allows every
+    /// public host.
+    pub allowed_hosts: Vec<String>,
+    /// How several `dataset` records in one NDJSON are combined.
+    pub metadata_policy: MetadataPolicy,
+    /// Also write a `{split}/raw/{stem}.json` per image with its parsed
+    /// annotations, in the same schema for every format (`raw/{split}/`
+    /// for classify exports).
+    pub emit_raw_sidecars: bool,
+    /// Comment stored in the ZIP archive, read by some asset managers for
+    /// provenance.
+    pub archive_comment: ArchiveComment,
+    /// Host lookups allowed in flight at once while downloading. Each host
+    /// is resolved once per conversion. Defaults to 4.
+    pub max_concurrent_dns: Option<usize>,
+    /// Add a `split_summary.txt` with the image count and percentage of each
+    /// split, for sanity-checking the partition.
+    pub split_summary: bool,
+    /// Write COCO and CreateML JSON without indentation, which makes large
+    /// annotation files much smaller.
+    pub compact_json: bool,
+    /// What Pascal VOC `<path>` elements hold.
+    pub voc_path: VocPath,
+    /// Repeat each YOLO segment polygon's first point at its end, for
+    /// consumers that expect closed polygons.
+    pub close_polygons: bool,
+    /// Split for images whose record has none. Defaults to `train`.
+    pub default_split: Option<String>,
+    /// Fail the conversion when an image record has no `split`.
+    pub require_split: bool,
+    /// Most bytes of image downloads held in memory at once, however many
+    /// requests are running. Responses without a size count as 4 MiB.
+    pub max_inflight_bytes: Option<u64>,
+    /// Write one COCO `_annotations.coco.json` at the archive root for all
+    /// splits, with each `file_name` given as `{split}/{file}`.
+    pub coco_single_file: bool,
+    /// Fail instead of warning when images are to be downloaded but no
+    /// image has a URL.
+    pub require_image_urls: bool,
+    /// How class ids with gaps between them are exported.
+    pub class_id_mode: ClassIdMode,
+    /// Bundle the input NDJSON, as read, as `source.ndjson`. Can't be
+    /// combined with `withhold_test_labels` or `strip_urls`, which the
+    /// source would undo.
+    pub embed_source: bool,
+    /// Largest NDJSON `embed_source` bundles, in bytes. Defaults to 20 MiB;
+    /// a larger input is left out with a warning.
+    pub embed_source_max_bytes: Option<u64>,
+    /// Download images over HTTP/2 without negotiating it first, for CDNs
+    /// known to support it. HTTP/2 is negotiated over TLS either way.
+    pub http2_prior_knowledge: bool,
+    /// Seconds an idle download connection is kept for reuse.
+    pub pool_idle_timeout_secs: Option<u64>,
+    /// Idle download connections kept per host. Defaults to the download
+    /// concurrency.
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Drop annotations of these class ids, e.g. crowd regions, while
+    /// keeping their images and the class list unchanged.
+    pub ignore_classes: Vec<i32>,
+    /// Canonical class ids by class name. Annotations are moved onto these
+    /// ids by matching the dataset's class names, and class filters then use
+    /// them too.
+    pub class_registry: Option<HashMap<String, i32>>,
+    /// Give classes missing from `class_registry` new ids after the
+    /// registry's instead of failing.
+    pub registry_assign_unknown: bool,
+    /// Leave out images that have no annotations once class filtering and
+    /// per-class caps have run. Classification exports are unaffected.
+    pub drop_empty_images: bool,
+    /// Names output images after a pattern instead of their source names,
+    /// e.g. `{split}_{index:06}.{ext}`. Supports `{split}`, `{index}`,
+    /// `{class}`, `{hash}` and `{ext}`; `file_name_map.json` records what
+    /// each original became.
+    pub filename_template: Option<String>,
+    /// Export only this fraction (0 to 1) of each split's images, chosen
+    /// deterministically from `sample_seed`.
+    pub sample_fraction: Option<f64>,
+    /// Seed choosing which images `sample_fraction` keeps.
+    pub sample_seed: u64,
+    /// Sample within each class so the sample keeps the dataset's class mix.
+    pub sample_by_class: bool,
+    /// Ship the `test` split as images only: its label and annotation files
+    /// are left out, and files covering every split omit its annotations.
+    pub withhold_test_labels: bool,
+    /// Whether `val` images share `valid/` or get their own `val/`.
+    pub split_normalization: SplitNormalization,
+    /// Export only these kinds of annotation, whatever else images carry.
+    /// When the dataset's task isn't among them, the first kind listed
+    /// decides the labels written instead.
+    pub annotation_types: Option<Vec<AnnotationKind>>,
+    /// Trade-off between archive size and zipping speed.
+    pub compression_preset: CompressionPreset,
+}
+
+/// Rounding applied to pixel box corners.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PixelRounding {
+    /// Round each corner to the nearest pixel.
+    #[default]
+    Round,
+    /// Floor the top-left corner and ceil the bottom-right, so the box never
+    /// shrinks.
+    FloorCeil,
+    /// Drop the fractional part of each corner.
+    Truncate,
+}
+
+impl PixelRounding {
+    /// Rounds `[xmin, ymin, xmax, ymax]`.
+    pub fn apply(self, [xmin, ymin, xmax, ymax]: [f64; 4]) -> [f64; 4] {
+        match self {
+            Self::Round => [xmin.round(), ymin.round(), xmax.round(), ymax.round()],
+            Self::FloorCeil => [xmin.floor(), ymin.floor(), xmax.ceil(), ymax.ceil()],
+            Self::Truncate => [xmin.trunc(), ymin.trunc(), xmax.trunc(), ymax.trunc()],
+        }
+    }
+}
+
+/// Handling of sparse class ids, e.g. a dataset using only ids 0 and 5.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClassIdMode {
+    /// Ids are kept and every gap gets a `class_N` placeholder name.
+    #[default]
+    Dense,
+    /// Used ids are renumbered to `0..K`, with the original id of each new
+    /// one written to `class_id_map.json`.
+    Remap,
+    /// Ids are kept as they are. Exports the same as `Dense`; gaps still
+    /// count towards `nc`.
+    Preserve,
+}
+
+/// A kind of annotation an image can carry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnotationKind {
+    Bbox,
+    Segment,
+    Pose,
+    Obb,
+    Classification,
+}
+
+impl AnnotationKind {
+    /// The kind a dataset task makes labels from. Unknown tasks are treated
+    /// as detection.
+    pub fn for_task(task: &str) -> Self {
+        match task {
+            "segment" => AnnotationKind::Segment,
+            "pose" => AnnotationKind::Pose,
+            "obb" => AnnotationKind::Obb,
+            "classify" => AnnotationKind::Classification,
+            _ => AnnotationKind::Bbox,
+        }
+    }
+
+    /// Keys this kind is stored under in an image's `annotations`.
+    pub fn keys(self) -> &'static [&'static str] {
+        match self {
+            AnnotationKind::Bbox => &["bboxes", "boxes"],
+            AnnotationKind::Segment => &["segments"],
+            AnnotationKind::Pose => &["pose"],
+            AnnotationKind::Obb => &["obb"],
+            AnnotationKind::Classification => &["classification"],
+        }
+    }
+
+    /// The dataset task whose labels are made from this kind.
+    pub fn task(self) -> &'static str {
+        match self {
+            AnnotationKind::Bbox => "detect",
+            AnnotationKind::Segment => "segment",
+            AnnotationKind::Pose => "pose",
+            AnnotationKind::Obb => "obb",
+            AnnotationKind::Classification => "classify",
+        }
+    }
+}
+
+/// Whether `val` images are merged into `valid/` or kept apart in `val/`.
+/// No other split name is rewritten.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitNormalization {
+    /// `val` and `valid` both go to `valid/`.
+    #[default]
+    Canonical,
+    /// `val` images go to `val/`, apart from `valid/` ones.
+    SeparateVal,
+}
+
+/// Every split directory an export can have, in export order. `val` only
+/// appears with [`SplitNormalization::SeparateVal`].
+pub const OUTPUT_SPLITS: [&str; 4] = ["train", "val", "valid", "test"];
+
+impl SplitNormalization {
+    /// Output directory for images whose split is spelled `split`.
+    pub fn output_split(self, split: &str) -> &str {
+        match (self, split) {
+            (Self::SeparateVal, "val") => "val",
+            _ => normalize_split(split),
+        }
+    }
+}
+
+/// Deflate effort used for archive entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionPreset {
+    Fastest,
+    /// The zip library's default level.
+    #[default]
+    Balanced,
+    Best,
+}
+
+impl CompressionPreset {
+    /// Deflate level to request, or `None` for the library default.
+    pub fn deflate_level(self) -> Option<i64> {
+        match self {
+            CompressionPreset::Fastest => Some(1),
+            CompressionPreset::Balanced => None,
+            CompressionPreset::Best => Some(9),
+        }
+    }
+}
+
+/// Contents of the Pascal VOC `<path>` element.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VocPath {
+    /// The image file name alone.
+    #[default]
+    FileName,
+    /// `{split}/{file}`, relative to the archive root.
+    Split,
+    /// `{root}/{split}/{file}`, for the directory the export is extracted to.
+    Root(String),
+}
+
+impl VocPath {
+    pub fn for_image(&self, split: &str, file: &str) -> String {
+        match self {
+            Self::FileName => file.to_string(),
+            Self::Split => format!("{}/{}", split, file),
+            Self::Root(root) => {
+                format!("{}/{}/{}", root.trim_end_matches(['/', '\\']), split, file)
+            }
+        }
+    }
+}
+
+/// ZIP archive comment to write.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveComment {
+    #[default]
+    None,
+    /// Dataset name, format, export time and converter version.
+    Generated,
+    Custom(String),
+}
+
+/// Form of the written export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputKind {
+    /// A single ZIP archive at the output path.
+    #[default]
+    Archive,
+    /// The export's files under the output path, as a directory.
+    Directory,
+}
+
+/// How the images of one split are re-encoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SplitImageSettings {
+    /// Downscale images whose longer side exceeds this many pixels, keeping
+    /// the aspect ratio.
+    pub max_dimension: Option<u32>,
+    /// JPEG quality (1-100) for the re-encode. PNGs are re-encoded
+    /// losslessly at maximum compression.
+    pub quality: Option<u8>,
+}
+
+/// Origin of the image files bundled into the export.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageSource {
+    /// Download every image from its URL.
+    #[default]
+    Download,
+    /// Read images from a local directory. `pattern` is relative to `path`
+    /// and may use the `{split}` and `{file}` placeholders.
+    LocalDir {
+        path: String,
+        #[serde(default = "default_local_pattern")]
+        pattern: String,
+    },
+}
+
+fn default_local_pattern() -> String {
+    "{split}/{file}".to_string()
+}
+
+/// Handling of images whose header dimensions disagree with the NDJSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DimensionCheck {
+    /// Trust the declared dimensions without inspecting the images.
+    #[default]
+    Off,
+    /// Keep the declared dimensions and report each mismatch as a warning.
+    Warn,
+    /// Replace the declared dimensions with the real ones and report them.
+    Correct,
+}
+
+impl ConvertOptions {
+    /// Whether `a` and `b` name the same output split, so per-split options
+    /// follow `split_normalization` like the export's directories do.
+    fn same_split(&self, a: &str, b: &str) -> bool {
+        let splits = self.split_normalization;
+        splits.output_split(a) == splits.output_split(b)
+    }
+
+    /// Class ids allowed in `split`, or `None` when every class is kept.
+    pub fn allowed_classes(&self, split: &str) -> Option<&[i32]> {
+        self.split_class_filters
+            .iter()
+            .find(|(key, _)| self.same_split(key, split))
+            .map(|(_, ids)| ids.as_slice())
+            .or(self.class_filter.as_deref())
+    }
+
+    /// Whether images of `split` should be downloaded.
+    pub fn downloads_split(&self, split: &str) -> bool {
+        self.download_splits
+            .as_ref()
+            .is_none_or(|splits| splits.iter().any(|s| self.same_split(s, split)))
+    }
+
+    /// Re-encode settings for `split`, if any.
+    pub fn image_settings_for(&self, split: &str) -> Option<&SplitImageSettings> {
+        self.split_image_settings
+            .iter()
+            .find(|(name, _)| self.same_split(name, split))
+            .map(|(_, settings)| settings)
+    }
+
+    /// `value` as JSON, pretty-printed unless `compact_json` is set.
+    pub fn to_json(&self, value: &impl Serialize) -> String {
+        if self.compact_json {
+            serde_json::to_string(value).unwrap_or_default()
+        } else {
+            serde_json::to_string_pretty(value).unwrap_or_default()
+        }
+    }
+
+    /// The images to fetch, honoring `download_splits`.
+    pub fn images_to_fetch<'a>(&self, images: &'a [ImageEntry]) -> Cow<'a, [ImageEntry]> {
+        if self.download_splits.is_none() {
+            return Cow::Borrowed(images);
+        }
+        images
+            .iter()
+            .filter(|image| self.downloads_split(&image.split))
+            .cloned()
+            .collect()
+    }
+}
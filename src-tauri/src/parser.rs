@@ -1,13 +1,30 @@
+use crate::options::{SplitNormalization, OUTPUT_SPLITS};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum ParseError {
-    #[error("Failed to parse JSON: {0}")]
-    JsonError(#[from] serde_json::Error),
+    #[error("Failed to parse JSON on line {line}: {source} (`{snippet}`)")]
+    JsonError {
+        /// 1-based line number in the NDJSON.
+        line: usize,
+        /// Start of the offending line.
+        snippet: String,
+        source: serde_json::Error,
+    },
     #[error("No metadata found in NDJSON")]
     NoMetadata,
+    #[error("Dataset records disagree on class {id}: '{first}' vs '{second}'")]
+    ClassNameConflict {
+        id: String,
+        first: String,
+        second: String,
+    },
+    #[error("Image on line {line} has no split")]
+    MissingSplit { line: usize },
+    #[error("Image on line {line} has no file name and no URL to derive one from")]
+    MissingFileName { line: usize },
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 }
@@ -19,6 +36,16 @@ pub struct BoundingBox {
     pub width: f64,
     pub height: f64,
     pub class_id: i32,
+    /// Model confidence, from an optional sixth element of the box.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    /// Source annotation id, from a trailing `{"id": ...}` element.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    /// Free-form labeling attributes, from the trailing element's
+    /// `"attributes"` key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,18 +56,60 @@ pub struct PoseAnnotation {
     pub bbox_w: f64,
     pub bbox_h: f64,
     pub keypoints: Vec<(f64, f64, f64)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SegmentAnnotation {
     pub class_id: i32,
-    pub points: Vec<(f64, f64)>,
+    /// The outer boundary first, then any holes.
+    pub rings: Vec<Vec<(f64, f64)>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+}
+
+impl SegmentAnnotation {
+    /// The outer boundary, ignoring holes.
+    pub fn outer(&self) -> &[(f64, f64)] {
+        self.rings.first().map_or(&[], Vec::as_slice)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ObbAnnotation {
     pub class_id: i32,
     pub points: [(f64, f64); 4],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+}
+
+/// Splits the optional trailing `{"id": ...}` element off an annotation
+/// array, giving its values and its source annotation id.
+fn split_annotation_id(arr: &[serde_json::Value]) -> (&[serde_json::Value], Option<i64>) {
+    match arr.split_last() {
+        Some((serde_json::Value::Object(extra), values)) => {
+            (values, extra.get("id").and_then(|id| id.as_i64()))
+        }
+        _ => (arr, None),
+    }
+}
+
+/// Reads one polygon ring of flattened `x, y` pairs, which needs at least
+/// three points. A trailing unpaired value is ignored.
+fn parse_ring(values: &[serde_json::Value]) -> Option<Vec<(f64, f64)>> {
+    if values.len() < 6 {
+        return None;
+    }
+    values
+        .chunks_exact(2)
+        .map(|pair| Some((pair[0].as_f64()?, pair[1].as_f64()?)))
+        .collect()
+}
+
+/// The `"attributes"` of an annotation array's trailing object, if any.
+fn annotation_attributes(arr: &[serde_json::Value]) -> Option<serde_json::Value> {
+    arr.last()?.as_object()?.get("attributes").cloned()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,10 +130,45 @@ pub struct DatasetMetadata {
     pub class_names: HashMap<String, String>,
     #[serde(default)]
     pub kpt_shape: Option<Vec<i32>>,
+    /// Keypoint index each keypoint swaps with on a horizontal flip.
+    #[serde(default)]
+    pub flip_idx: Option<Vec<i32>>,
+    /// Capture/creation date of the dataset, used verbatim in exports.
+    #[serde(default)]
+    pub date_created: Option<String>,
+    #[serde(default)]
+    pub year: Option<i32>,
+    /// Licenses images can reference by id.
+    #[serde(default)]
+    pub licenses: Vec<DatasetLicense>,
     #[serde(default, deserialize_with = "deserialize_version")]
     pub version: String,
 }
 
+fn deserialize_captions<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    Ok(match value {
+        serde_json::Value::String(s) => vec![s],
+        serde_json::Value::Array(items) => items
+            .into_iter()
+            .filter_map(|item| item.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatasetLicense {
+    pub id: i32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub url: String,
+}
+
 fn default_task() -> String {
     "detect".to_string()
 }
@@ -85,11 +189,26 @@ where
 pub struct ImageEntry {
     #[serde(default)]
     pub r#type: String,
+    /// Output file name. When empty, it is taken from the last segment of
+    /// `url`.
+    #[serde(default)]
     pub file: String,
     #[serde(skip)]
     pub output_file: Option<String>,
     #[serde(default)]
     pub url: String,
+    /// Fallback URLs tried in order when `url` fails to download.
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    /// Id into the dataset's `licenses` list.
+    #[serde(default)]
+    pub license: Option<i32>,
+    /// Marked as bad data by an annotator; excluded from the export.
+    #[serde(default)]
+    pub skip: bool,
+    /// Image captions, from a `caption` string or a list of them.
+    #[serde(default, rename = "caption", deserialize_with = "deserialize_captions")]
+    pub captions: Vec<String>,
     pub width: i32,
     pub height: i32,
     #[serde(default = "default_split")]
@@ -109,6 +228,23 @@ pub fn normalize_split(split: &str) -> &str {
     }
 }
 
+/// Maps common spellings of a dataset task onto the names the converters
+/// match on (`detect`, `segment`, `pose`, `obb`, `classify`). Anything
+/// unrecognized is returned lowercased and treated as detection downstream.
+pub fn normalize_task(task: &str) -> String {
+    let task = task.trim().to_lowercase();
+    match task.as_str() {
+        "detection" | "det" | "object_detection" | "bbox" | "boxes" => "detect".to_string(),
+        "segmentation" | "seg" | "instance_segmentation" | "polygon" | "polygons" => {
+            "segment".to_string()
+        }
+        "keypoints" | "keypoint" | "pose_estimation" | "kpt" => "pose".to_string(),
+        "classification" | "cls" | "image_classification" => "classify".to_string(),
+        "oriented" | "oriented_bbox" | "rotated" => "obb".to_string(),
+        _ => task,
+    }
+}
+
 pub fn image_download_key(split: &str, file: &str) -> String {
     let split = normalize_split(split);
     format!("{}:{}:{}", split.len(), split, file)
@@ -119,10 +255,167 @@ pub fn image_entry_download_key(image: &ImageEntry) -> String {
 }
 
 impl ImageEntry {
+    /// Every URL to try for this image: the primary `url` first, then the
+    /// mirrors, skipping blanks and repeats.
+    pub fn download_urls(&self) -> Vec<String> {
+        let mut urls: Vec<String> = Vec::new();
+        for url in std::iter::once(&self.url).chain(&self.mirrors) {
+            if !url.is_empty() && !urls.contains(url) {
+                urls.push(url.clone());
+            }
+        }
+        urls
+    }
+
     pub fn effective_file_name(&self) -> &str {
         self.output_file.as_deref().unwrap_or(&self.file)
     }
 
+    /// Drops every annotation whose class id fails `keep`, across all
+    /// annotation kinds.
+    pub fn retain_classes(&mut self, keep: impl Fn(i32) -> bool) {
+        let Some(serde_json::Value::Object(annotations)) = &mut self.annotations else {
+            return;
+        };
+
+        for key in ["bboxes", "boxes", "segments", "pose", "obb"] {
+            if let Some(serde_json::Value::Array(items)) = annotations.get_mut(key) {
+                items.retain(|item| {
+                    item.get(0)
+                        .and_then(|id| id.as_i64())
+                        .is_none_or(|id| keep(id as i32))
+                });
+            }
+        }
+
+        if let Some(serde_json::Value::Array(ids)) = annotations.get_mut("classification") {
+            ids.retain(|id| id.as_i64().is_none_or(|id| keep(id as i32)));
+        }
+    }
+
+    /// Divides annotation coordinates given in pixels by the image size, so
+    /// they are normalized like everywhere else. Only coordinates are scaled:
+    /// box scores, pose visibility values and unknown keys are left alone.
+    fn normalize_pixel_coords(&mut self) {
+        let (width, height) = (f64::from(self.width), f64::from(self.height));
+        if width <= 0.0 || height <= 0.0 {
+            return;
+        }
+        let Some(serde_json::Value::Object(annotations)) = &mut self.annotations else {
+            return;
+        };
+        let scale = |value: &mut serde_json::Value, size: f64| {
+            if let Some(coord) = value.as_f64() {
+                *value = serde_json::json!(coord / size);
+            }
+        };
+
+        for (key, items) in annotations.iter_mut() {
+            let Some(items) = items.as_array_mut() else {
+                continue;
+            };
+            for item in items {
+                let Some(values) = item.as_array_mut() else {
+                    continue;
+                };
+                // Values after the class id: how many are x, y pairs, and
+                // for pose the keypoints after them, always x, y, v triples.
+                let (pairs_end, keypoints) = match key.as_str() {
+                    "boxes" | "bboxes" => (5, false),
+                    "pose" => (5, true),
+                    "obb" => (9, false),
+                    "segments" => (values.len(), false),
+                    _ => continue,
+                };
+                for (idx, value) in values.iter_mut().enumerate().take(pairs_end).skip(1) {
+                    let size = if idx % 2 == 1 { width } else { height };
+                    if let Some(ring) = value.as_array_mut() {
+                        // A segment hole ring is its own run of x, y pairs.
+                        for (ring_idx, coord) in ring.iter_mut().enumerate() {
+                            scale(coord, if ring_idx % 2 == 0 { width } else { height });
+                        }
+                    } else {
+                        scale(value, size);
+                    }
+                }
+                if keypoints {
+                    for (idx, value) in values.iter_mut().enumerate().skip(pairs_end) {
+                        match (idx - pairs_end) % 3 {
+                            0 => scale(value, width),
+                            1 => scale(value, height),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replaces the class id of every annotation with `map(id)`.
+    pub fn map_class_ids(&mut self, map: impl Fn(i32) -> i32) {
+        let Some(serde_json::Value::Object(annotations)) = &mut self.annotations else {
+            return;
+        };
+
+        for key in ["bboxes", "boxes", "segments", "pose", "obb"] {
+            if let Some(serde_json::Value::Array(items)) = annotations.get_mut(key) {
+                for id in items.iter_mut().filter_map(|item| item.get_mut(0)) {
+                    if let Some(old) = id.as_i64() {
+                        *id = map(old as i32).into();
+                    }
+                }
+            }
+        }
+
+        if let Some(serde_json::Value::Array(ids)) = annotations.get_mut("classification") {
+            for id in ids.iter_mut() {
+                if let Some(old) = id.as_i64() {
+                    *id = map(old as i32).into();
+                }
+            }
+        }
+    }
+
+    /// Rewrites annotations given as a bare array into the keyed form the
+    /// dataset's task uses, e.g. `[[0, x, y, w, h]]` into `{"boxes": [...]}`.
+    fn key_bare_annotations(&mut self, task: &str) {
+        let Some(serde_json::Value::Array(items)) = &mut self.annotations else {
+            return;
+        };
+        let key = match task {
+            "segment" => "segments",
+            "pose" => "pose",
+            "obb" => "obb",
+            "classify" => "classification",
+            _ => "boxes",
+        };
+        let items = std::mem::take(items);
+        self.annotations = Some(serde_json::json!({ key: items }));
+    }
+
+    /// Every class id referenced by this image's annotations.
+    pub fn class_ids(&self) -> Vec<i32> {
+        let Some(serde_json::Value::Object(annotations)) = &self.annotations else {
+            return Vec::new();
+        };
+
+        let mut ids = Vec::new();
+        for key in ["bboxes", "boxes", "segments", "pose", "obb"] {
+            if let Some(serde_json::Value::Array(items)) = annotations.get(key) {
+                ids.extend(
+                    items
+                        .iter()
+                        .filter_map(|item| item.get(0).and_then(|id| id.as_i64())),
+                );
+            }
+        }
+        if let Some(serde_json::Value::Array(classes)) = annotations.get("classification") {
+            ids.extend(classes.iter().filter_map(|id| id.as_i64()));
+        }
+
+        ids.into_iter().map(|id| id as i32).collect()
+    }
+
     pub fn get_bboxes(&self) -> Vec<BoundingBox> {
         let Some(annotations) = &self.annotations else {
             return Vec::new();
@@ -142,7 +435,8 @@ impl ImageEntry {
         bbox_array
             .iter()
             .filter_map(|bbox_data| {
-                let arr = bbox_data.as_array()?;
+                let attributes = annotation_attributes(bbox_data.as_array()?);
+                let (arr, id) = split_annotation_id(bbox_data.as_array()?);
                 if arr.len() >= 5 {
                     Some(BoundingBox {
                         class_id: arr[0].as_i64()? as i32,
@@ -150,6 +444,9 @@ impl ImageEntry {
                         y: arr[2].as_f64()?,
                         width: arr[3].as_f64()?,
                         height: arr[4].as_f64()?,
+                        score: arr.get(5).and_then(|v| v.as_f64()),
+                        id,
+                        attributes,
                     })
                 } else {
                     None
@@ -195,7 +492,7 @@ impl ImageEntry {
         pose_array
             .iter()
             .filter_map(|pose_data| {
-                let arr = pose_data.as_array()?;
+                let (arr, id) = split_annotation_id(pose_data.as_array()?);
                 if arr.len() < 8 {
                     return None;
                 }
@@ -228,6 +525,7 @@ impl ImageEntry {
                     bbox_w,
                     bbox_h,
                     keypoints,
+                    id,
                 })
             })
             .collect()
@@ -249,23 +547,25 @@ impl ImageEntry {
         seg_array
             .iter()
             .filter_map(|seg_data| {
-                let arr = seg_data.as_array()?;
-                if arr.len() < 7 {
-                    return None;
-                }
-
-                let class_id = arr[0].as_i64()? as i32;
-                let mut points = Vec::new();
-
-                for i in (1..arr.len()).step_by(2) {
-                    if i + 1 < arr.len() {
-                        let x = arr[i].as_f64()?;
-                        let y = arr[i + 1].as_f64()?;
-                        points.push((x, y));
-                    }
-                }
+                let (arr, id) = split_annotation_id(seg_data.as_array()?);
+                let class_id = arr.first()?.as_i64()? as i32;
+
+                // Either `[class, x1, y1, ...]` or, with holes,
+                // `[class, [x1, y1, ...], [x1, y1, ...], ...]`.
+                let rings = if arr.get(1).is_some_and(|v| v.is_array()) {
+                    arr[1..]
+                        .iter()
+                        .map(|ring| parse_ring(ring.as_array()?))
+                        .collect::<Option<Vec<_>>>()?
+                } else {
+                    vec![parse_ring(&arr[1..])?]
+                };
 
-                Some(SegmentAnnotation { class_id, points })
+                Some(SegmentAnnotation {
+                    class_id,
+                    rings,
+                    id,
+                })
             })
             .collect()
     }
@@ -287,7 +587,7 @@ impl ImageEntry {
         obb_array
             .iter()
             .filter_map(|obb_data| {
-                let arr = obb_data.as_array()?;
+                let (arr, id) = split_annotation_id(obb_data.as_array()?);
                 if arr.len() != 9 {
                     return None;
                 }
@@ -300,7 +600,11 @@ impl ImageEntry {
                     (arr[7].as_f64()?, arr[8].as_f64()?),
                 ];
 
-                Some(ObbAnnotation { class_id, points })
+                Some(ObbAnnotation {
+                    class_id,
+                    points,
+                    id,
+                })
             })
             .collect()
     }
@@ -313,56 +617,257 @@ pub struct NDJSONData {
 }
 
 impl NDJSONData {
-    pub fn train_images(&self) -> Vec<&ImageEntry> {
-        self.images
-            .iter()
-            .filter(|img| img.split == "train")
-            .collect()
+    /// Drops images marked `skip` and returns how many were dropped.
+    pub fn remove_skipped_images(&mut self) -> usize {
+        let before = self.images.len();
+        self.images.retain(|image| !image.skip);
+        before - self.images.len()
     }
 
-    pub fn valid_images(&self) -> Vec<&ImageEntry> {
-        self.images
-            .iter()
-            .filter(|img| img.split == "valid" || img.split == "val")
+    /// Images of each output split, in export order. `val` is only listed
+    /// when it has images, since canonical exports never have it.
+    pub fn images_by_split(
+        &self,
+        splits: SplitNormalization,
+    ) -> Vec<(&'static str, Vec<&ImageEntry>)> {
+        OUTPUT_SPLITS
+            .into_iter()
+            .map(|split| {
+                let images: Vec<&ImageEntry> = self
+                    .images
+                    .iter()
+                    .filter(|img| splits.output_split(&img.split) == split)
+                    .collect();
+                (split, images)
+            })
+            .filter(|(split, images)| *split != "val" || !images.is_empty())
             .collect()
     }
+}
 
-    pub fn test_images(&self) -> Vec<&ImageEntry> {
-        self.images
-            .iter()
-            .filter(|img| img.split == "test")
-            .collect()
+#[cfg(test)]
+pub fn parse_ndjson(content: &str) -> Result<NDJSONData, ParseError> {
+    parse_ndjson_with(content, &ParseSettings::default()).map(|(data, _)| data)
+}
+
+/// How a file with more than one `dataset` record, e.g. concatenated
+/// exports, is resolved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataPolicy {
+    /// The last dataset record replaces earlier ones.
+    #[default]
+    LastWins,
+    /// Class names of every record are combined; the same id with two
+    /// different names is an error. Other fields come from the last record.
+    Merge,
+}
+
+/// How `parse_ndjson_with` reads its input.
+#[derive(Debug, Clone, Default)]
+pub struct ParseSettings {
+    /// Retry lines that strict JSON rejects as JSON5.
+    pub lenient_json: bool,
+    pub metadata_policy: MetadataPolicy,
+    /// Split given to images without one, instead of `train`.
+    pub default_split: Option<String>,
+    /// Reject images without an explicit split.
+    pub require_split: bool,
+}
+
+fn merge_metadata(
+    earlier: DatasetMetadata,
+    mut later: DatasetMetadata,
+) -> Result<DatasetMetadata, ParseError> {
+    for (id, name) in earlier.class_names {
+        match later.class_names.get(&id) {
+            Some(other) if *other != name => {
+                return Err(ParseError::ClassNameConflict {
+                    id,
+                    first: name,
+                    second: other.clone(),
+                })
+            }
+            Some(_) => {}
+            None => {
+                later.class_names.insert(id, name);
+            }
+        }
     }
+    Ok(later)
 }
 
-pub fn parse_ndjson(content: &str) -> Result<NDJSONData, ParseError> {
+const SNIPPET_CHARS: usize = 80;
+
+fn json_error(line_number: usize, line: &str, source: serde_json::Error) -> ParseError {
+    let mut snippet: String = line.chars().take(SNIPPET_CHARS).collect();
+    if line.chars().nth(SNIPPET_CHARS).is_some() {
+        snippet.push_str("...");
+    }
+    ParseError::JsonError {
+        line: line_number,
+        snippet,
+        source,
+    }
+}
+
+/// Parses one NDJSON line. In lenient mode a line that strict JSON rejects is
+/// retried as JSON5 (trailing commas, single quotes, comments); if that also
+/// fails the original strict error is returned.
+fn parse_line(line: &str, lenient_json: bool) -> Result<serde_json::Value, serde_json::Error> {
+    match serde_json::from_str(line) {
+        Ok(value) => Ok(value),
+        Err(err) if lenient_json => json5::from_str(line).map_err(|_| err),
+        Err(err) => Err(err),
+    }
+}
+
+/// A single object given on the image record itself, as
+/// `{"label": "cat", "bbox": [x, y, w, h]}` with the class named rather
+/// than numbered. Records with their own `annotations` are left alone.
+fn name_labeled_box(value: &serde_json::Value) -> Option<(String, [f64; 4])> {
+    if value.get("annotations").is_some() {
+        return None;
+    }
+    let label = value.get("label")?.as_str()?;
+    let bbox = value.get("bbox")?.as_array()?;
+    let coords: Vec<f64> = bbox.iter().filter_map(|v| v.as_f64()).collect();
+    let coords: [f64; 4] = coords.try_into().ok()?;
+    Some((label.to_string(), coords))
+}
+
+/// Turns name-labeled objects into `boxes` annotations, resolving each name
+/// to its id in `class_names` and adding names not listed there as new
+/// classes after the highest existing id.
+fn resolve_name_labeled_boxes(
+    metadata: &mut DatasetMetadata,
+    images: &mut [ImageEntry],
+    objects: Vec<(usize, (String, [f64; 4]))>,
+) {
+    let mut ids: HashMap<String, i32> = metadata
+        .class_names
+        .iter()
+        .filter_map(|(id, name)| Some((name.clone(), id.parse().ok()?)))
+        .collect();
+    let mut next_id = ids.values().max().map_or(0, |max| max + 1);
+
+    for (index, (label, [x, y, w, h])) in objects {
+        let class_id = *ids.entry(label.clone()).or_insert_with(|| {
+            let id = next_id;
+            next_id += 1;
+            metadata.class_names.insert(id.to_string(), label);
+            id
+        });
+        images[index].annotations = Some(serde_json::json!({
+            "boxes": [[class_id, x, y, w, h]]
+        }));
+    }
+}
+
+/// The percent-decoded last path segment of `url`, with characters that
+/// aren't valid in file names replaced by `_`. `None` when the URL has no
+/// usable file name.
+fn file_name_from_url(url: &str) -> Option<String> {
+    let parsed = url::Url::parse(url).ok()?;
+    let segment = parsed.path_segments()?.next_back()?;
+    let decoded = percent_encoding::percent_decode_str(segment).decode_utf8_lossy();
+    let name: String = decoded
+        .chars()
+        .map(|c| {
+            if c.is_control() || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let name = name.trim();
+    (!name.is_empty() && name != "." && name != "..").then(|| name.to_string())
+}
+
+/// Parses NDJSON content as `settings` describe. Annotations of a dataset
+/// record with `"coord_units": "pixels"` are normalized by image size. A
+/// `bbox_format` key is not read: boxes are always center x, y, width and
+/// height, so `coord_units` alone says how to scale them. Returns the data
+/// and the number of `dataset` records seen, resolved per the metadata
+/// policy when there are several.
+pub fn parse_ndjson_with(
+    content: &str,
+    settings: &ParseSettings,
+) -> Result<(NDJSONData, usize), ParseError> {
     let mut metadata: Option<DatasetMetadata> = None;
+    let mut dataset_records = 0;
+    let mut pixel_coords = false;
     let mut images: Vec<ImageEntry> = Vec::new();
+    let mut name_labeled = Vec::new();
 
-    for line in content.lines() {
+    for (idx, line) in content.lines().enumerate() {
         let line = line.trim();
         if line.is_empty() {
             continue;
         }
+        let line_error = |err| json_error(idx + 1, line, err);
 
-        let value: serde_json::Value = serde_json::from_str(line)?;
+        let value = parse_line(line, settings.lenient_json).map_err(line_error)?;
 
         if let Some(type_str) = value.get("type").and_then(|v| v.as_str()) {
             match type_str {
                 "dataset" => {
-                    metadata = Some(serde_json::from_value(value)?);
+                    pixel_coords = value
+                        .get("coord_units")
+                        .and_then(|units| units.as_str())
+                        .is_some_and(|units| units.eq_ignore_ascii_case("pixels"));
+                    let record = serde_json::from_value(value).map_err(line_error)?;
+                    dataset_records += 1;
+                    metadata = Some(match (metadata.take(), settings.metadata_policy) {
+                        (Some(earlier), MetadataPolicy::Merge) => merge_metadata(earlier, record)?,
+                        _ => record,
+                    });
                 }
                 "image" => {
-                    images.push(serde_json::from_value(value)?);
+                    if let Some(object) = name_labeled_box(&value) {
+                        name_labeled.push((images.len(), object));
+                    }
+                    // `"split": null` counts as no split at all.
+                    let mut value = value;
+                    let has_split = value.get("split").is_some_and(|split| !split.is_null());
+                    if !has_split {
+                        if let Some(record) = value.as_object_mut() {
+                            record.remove("split");
+                        }
+                    }
+                    let mut image: ImageEntry =
+                        serde_json::from_value(value).map_err(line_error)?;
+                    if image.file.is_empty() {
+                        image.file = file_name_from_url(&image.url)
+                            .ok_or(ParseError::MissingFileName { line: idx + 1 })?;
+                    }
+                    if !has_split {
+                        if settings.require_split {
+                            return Err(ParseError::MissingSplit { line: idx + 1 });
+                        }
+                        if let Some(split) = &settings.default_split {
+                            image.split = split.clone();
+                        }
+                    }
+                    images.push(image);
                 }
                 _ => {}
             }
         }
     }
 
-    let metadata = metadata.ok_or(ParseError::NoMetadata)?;
+    let mut metadata = metadata.ok_or(ParseError::NoMetadata)?;
+    metadata.task = normalize_task(&metadata.task);
+    resolve_name_labeled_boxes(&mut metadata, &mut images, name_labeled);
+    for image in &mut images {
+        image.key_bare_annotations(&metadata.task);
+        if pixel_coords {
+            image.normalize_pixel_coords();
+        }
+    }
 
-    Ok(NDJSONData { metadata, images })
+    Ok((NDJSONData { metadata, images }, dataset_records))
 }
 
 #[cfg(test)]
@@ -382,6 +887,31 @@ mod tests {
         assert_eq!(result.images[0].height, 480);
     }
 
+    #[test]
+    fn missing_file_name_is_decoded_from_the_url() {
+        let content = r#"{"type":"dataset","name":"test","class_names":{}}
+{"type":"image","width":640,"height":480,"url":"https://example.com/photos/caf%C3%A9.jpg?sig=1"}
+{"type":"image","file":"","width":640,"height":480,"url":"https://example.com/a%2Fb%20c.jpg"}
+{"type":"image","file":"kept%20name.jpg","width":640,"height":480,"url":"https://example.com/other.jpg"}"#;
+
+        let result = parse_ndjson(content).unwrap();
+
+        assert_eq!(result.images[0].file, "caf\u{e9}.jpg");
+        assert_eq!(result.images[1].file, "a_b c.jpg");
+        assert_eq!(result.images[2].file, "kept%20name.jpg");
+    }
+
+    #[test]
+    fn image_without_file_or_url_is_an_error() {
+        let content = r#"{"type":"dataset","name":"test","class_names":{}}
+{"type":"image","file":"a.jpg","width":640,"height":480}
+{"type":"image","width":640,"height":480,"url":"https://example.com/"}"#;
+
+        let err = parse_ndjson(content).unwrap_err();
+        assert!(matches!(err, ParseError::MissingFileName { line: 3 }));
+        assert!(err.to_string().contains("line 3"), "{}", err);
+    }
+
     #[test]
     fn parse_string_version() {
         let content = r#"{"type":"dataset","name":"test","class_names":{},"version":"latest"}"#;
@@ -410,7 +940,164 @@ mod tests {
 
         let result = parse_ndjson(content);
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), ParseError::JsonError(_)));
+        assert!(matches!(result.unwrap_err(), ParseError::JsonError { .. }));
+    }
+
+    #[test]
+    fn parse_error_names_the_line_and_its_content() {
+        let content = r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+
+{"type":"image","file":"a.jpg","width":640,"height":480}
+{"type":"image","file":"b.jpg","width":"wide"}"#;
+
+        let err = parse_ndjson(content).unwrap_err();
+
+        assert!(matches!(err, ParseError::JsonError { line: 4, .. }));
+        let message = err.to_string();
+        assert!(message.contains("line 4"), "{}", message);
+        assert!(
+            message.contains(r#"{"type":"image","file":"b.jpg""#),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn multiple_dataset_records_follow_the_metadata_policy() {
+        let content = r#"{"type":"dataset","name":"first","class_names":{"0":"cat","1":"dog"}}
+{"type":"dataset","name":"second","class_names":{"1":"dog","2":"bird"}}"#;
+        let merge = ParseSettings {
+            metadata_policy: MetadataPolicy::Merge,
+            ..Default::default()
+        };
+
+        let (last, records) = parse_ndjson_with(content, &ParseSettings::default()).unwrap();
+        assert_eq!(records, 2);
+        assert_eq!(last.metadata.name, "second");
+        assert_eq!(last.metadata.class_names.len(), 2);
+
+        let (merged, _) = parse_ndjson_with(content, &merge).unwrap();
+        assert_eq!(merged.metadata.name, "second");
+        assert_eq!(merged.metadata.class_names["0"], "cat");
+        assert_eq!(merged.metadata.class_names["1"], "dog");
+        assert_eq!(merged.metadata.class_names["2"], "bird");
+    }
+
+    #[test]
+    fn merging_conflicting_class_names_is_an_error() {
+        let content = r#"{"type":"dataset","name":"first","class_names":{"0":"cat"}}
+{"type":"dataset","name":"second","class_names":{"0":"car"}}"#;
+        let merge = ParseSettings {
+            metadata_policy: MetadataPolicy::Merge,
+            ..Default::default()
+        };
+
+        let err = parse_ndjson_with(content, &merge).unwrap_err();
+
+        assert!(matches!(err, ParseError::ClassNameConflict { ref id, .. } if id == "0"));
+        assert!(parse_ndjson_with(content, &ParseSettings::default()).is_ok());
+    }
+
+    #[test]
+    fn images_without_a_split_follow_the_settings() {
+        let content = r#"{"type":"dataset","name":"test","class_names":{}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"val"}
+{"type":"image","file":"b.jpg","width":640,"height":480}
+{"type":"image","file":"c.jpg","width":640,"height":480,"split":null}"#;
+
+        let (data, _) = parse_ndjson_with(content, &ParseSettings::default()).unwrap();
+        assert_eq!(data.images[1].split, "train");
+        assert_eq!(data.images[2].split, "train");
+
+        let settings = ParseSettings {
+            default_split: Some("test".to_string()),
+            ..Default::default()
+        };
+        let (data, _) = parse_ndjson_with(content, &settings).unwrap();
+        assert_eq!(data.images[0].split, "val");
+        assert_eq!(data.images[1].split, "test");
+        assert_eq!(data.images[2].split, "test");
+
+        let strict = ParseSettings {
+            require_split: true,
+            ..Default::default()
+        };
+        let err = parse_ndjson_with(content, &strict).unwrap_err();
+        assert!(matches!(err, ParseError::MissingSplit { line: 3 }));
+    }
+
+    #[test]
+    fn pixel_coordinates_are_normalized() {
+        let content = r#"{"type":"dataset","name":"test","task":"segment","coord_units":"pixels","kpt_shape":[1,3],"class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":200,"height":100,"annotations":{"segments":[[0,20,10,100,10,100,50]],"pose":[[0,100,50,40,20,60,30,2]]}}"#;
+
+        let result = parse_ndjson(content).unwrap();
+
+        let segment = &result.images[0].get_segment_annotations()[0];
+        assert_eq!(segment.rings[0], vec![(0.1, 0.1), (0.5, 0.1), (0.5, 0.5)]);
+        let pose = &result.images[0].get_pose_annotations()[0];
+        assert_eq!(
+            (pose.bbox_x, pose.bbox_y, pose.bbox_w, pose.bbox_h),
+            (0.5, 0.5, 0.2, 0.2)
+        );
+        assert_eq!(pose.keypoints, vec![(0.3, 0.3, 2.0)]);
+    }
+
+    #[test]
+    fn pixel_box_scores_are_left_alone() {
+        let content = r#"{"type":"dataset","name":"test","coord_units":"pixels","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"annotations":{"boxes":[[0,320,240,64,48,0.9]]}}"#;
+
+        let result = parse_ndjson(content).unwrap();
+
+        let bbox = &result.images[0].get_bboxes()[0];
+        assert_eq!(
+            (bbox.x, bbox.y, bbox.width, bbox.height),
+            (0.5, 0.5, 0.1, 0.1)
+        );
+        assert_eq!(bbox.score, Some(0.9));
+    }
+
+    #[test]
+    fn pixel_keypoints_are_triples_whatever_the_kpt_shape() {
+        let content = r#"{"type":"dataset","name":"test","task":"pose","coord_units":"pixels","kpt_shape":[2,2],"class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":100,"height":200,"annotations":{"pose":[[0,50,100,20,40,10,20,2,30,40,2]]}}"#;
+
+        let result = parse_ndjson(content).unwrap();
+
+        let pose = &result.images[0].get_pose_annotations()[0];
+        assert_eq!(pose.keypoints, vec![(0.1, 0.1, 2.0), (0.3, 0.2, 2.0)]);
+    }
+
+    #[test]
+    fn parse_lenient_accepts_trailing_commas_and_single_quotes() {
+        let content = r#"{"type":"dataset","name":"test","class_names":{"0":"cat",},}
+{'type':'image','file':'img1.jpg','width':640,'height':480,'annotations':{'boxes':[[0,0.1,0.2,0.3,0.4],]}}"#;
+        let lenient = ParseSettings {
+            lenient_json: true,
+            ..Default::default()
+        };
+
+        assert!(parse_ndjson(content).is_err());
+
+        let (result, _) = parse_ndjson_with(content, &lenient).unwrap();
+        assert_eq!(result.metadata.class_names["0"], "cat");
+        assert_eq!(result.images[0].file, "img1.jpg");
+        assert_eq!(result.images[0].width, 640);
+        assert_eq!(result.images[0].get_bboxes()[0].class_id, 0);
+    }
+
+    #[test]
+    fn parse_lenient_still_rejects_invalid_lines() {
+        let content = r#"{"type":"dataset","name":"test","class_names":{}}
+{invalid json"#;
+        let lenient = ParseSettings {
+            lenient_json: true,
+            ..Default::default()
+        };
+
+        let result = parse_ndjson_with(content, &lenient);
+        assert!(matches!(result.unwrap_err(), ParseError::JsonError { .. }));
     }
 
     #[test]
@@ -429,6 +1116,10 @@ mod tests {
             file: "test.jpg".to_string(),
             output_file: None,
             url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
             width: 640,
             height: 480,
             split: "train".to_string(),
@@ -452,6 +1143,10 @@ mod tests {
             file: "test.jpg".to_string(),
             output_file: None,
             url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
             width: 640,
             height: 480,
             split: "train".to_string(),
@@ -467,6 +1162,115 @@ mod tests {
         assert!((bboxes[0].y - 0.35).abs() < f64::EPSILON);
     }
 
+    #[test]
+    fn parse_captions_from_string_or_list() {
+        let content = r#"{"type":"dataset","name":"test","class_names":{}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"caption":"A cat on a sofa."}
+{"type":"image","file":"b.jpg","width":640,"height":480,"caption":["A dog.","A dog in the park."]}
+{"type":"image","file":"c.jpg","width":640,"height":480}"#;
+
+        let result = parse_ndjson(content).unwrap();
+        assert_eq!(result.images[0].captions, vec!["A cat on a sofa."]);
+        assert_eq!(result.images[1].captions.len(), 2);
+        assert!(result.images[2].captions.is_empty());
+    }
+
+    #[test]
+    fn bare_annotation_array_parses_like_keyed_boxes() {
+        let content = r#"{"type":"dataset","name":"test","task":"detect","class_names":{"0":"cat"}}
+{"type":"image","file":"bare.jpg","width":640,"height":480,"annotations":[[0,0.5,0.5,0.2,0.2],[0,0.1,0.2,0.3,0.4]]}
+{"type":"image","file":"keyed.jpg","width":640,"height":480,"annotations":{"bboxes":[[0,0.5,0.5,0.2,0.2],[0,0.1,0.2,0.3,0.4]]}}"#;
+
+        let result = parse_ndjson(content).unwrap();
+        let as_tuples = |image: &ImageEntry| -> Vec<(i32, f64, f64, f64, f64)> {
+            image
+                .get_bboxes()
+                .iter()
+                .map(|b| (b.class_id, b.x, b.y, b.width, b.height))
+                .collect()
+        };
+        assert_eq!(as_tuples(&result.images[0]).len(), 2);
+        assert_eq!(as_tuples(&result.images[0]), as_tuples(&result.images[1]));
+    }
+
+    #[test]
+    fn name_labeled_records_resolve_and_extend_class_names() {
+        let data = parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat","1":"dog"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","label":"dog","bbox":[0.5,0.5,0.2,0.2]}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"train","label":"bird","bbox":[0.4,0.4,0.1,0.1]}"#,
+        )
+        .unwrap();
+
+        let a = data.images[0].get_bboxes();
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].class_id, 1);
+        assert_eq!((a[0].x, a[0].width), (0.5, 0.2));
+        assert_eq!(data.images[1].get_bboxes()[0].class_id, 2);
+        assert_eq!(data.metadata.class_names["2"], "bird");
+        assert_eq!(data.metadata.class_names.len(), 3);
+    }
+
+    #[test]
+    fn task_aliases_are_normalized() {
+        for (alias, task) in [
+            ("segmentation", "segment"),
+            ("keypoints", "pose"),
+            ("Detection", "detect"),
+            ("classification", "classify"),
+            ("obb", "obb"),
+        ] {
+            let content = format!(
+                r#"{{"type":"dataset","name":"test","task":"{}","class_names":{{"0":"cat"}}}}"#,
+                alias
+            );
+            assert_eq!(parse_ndjson(&content).unwrap().metadata.task, task);
+        }
+
+        let data = parse_ndjson(
+            r#"{"type":"dataset","name":"test","task":"segmentation","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":[[0,0.1,0.1,0.5,0.1,0.5,0.5]]}"#,
+        )
+        .unwrap();
+        assert_eq!(data.images[0].get_segment_annotations().len(), 1);
+    }
+
+    #[test]
+    fn bare_annotation_array_follows_task() {
+        let content = r#"{"type":"dataset","name":"test","task":"segment","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"annotations":[[0,0.1,0.1,0.5,0.1,0.3,0.5]]}"#;
+
+        let result = parse_ndjson(content).unwrap();
+        let segments = result.images[0].get_segment_annotations();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].outer().len(), 3);
+    }
+
+    #[test]
+    fn get_bboxes_reads_optional_confidence_score() {
+        let entry = ImageEntry {
+            r#type: "image".to_string(),
+            file: "test.jpg".to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: "train".to_string(),
+            annotations: Some(serde_json::json!({
+                "boxes": [[0, 0.5, 0.5, 0.2, 0.2, 0.87], [1, 0.3, 0.3, 0.1, 0.1]]
+            })),
+        };
+
+        let bboxes = entry.get_bboxes();
+        assert_eq!(bboxes.len(), 2);
+        assert_eq!(bboxes[0].score, Some(0.87));
+        assert_eq!(bboxes[1].score, None);
+    }
+
     #[test]
     fn get_pose_annotations_parses_new_format() {
         // Format: [class_id, bbox_cx, bbox_cy, bbox_w, bbox_h, kp1_x, kp1_y, kp1_v, ...]
@@ -475,6 +1279,10 @@ mod tests {
             file: "test.jpg".to_string(),
             output_file: None,
             url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
             width: 1200,
             height: 800,
             split: "train".to_string(),
@@ -505,6 +1313,10 @@ mod tests {
             file: "test.jpg".to_string(),
             output_file: None,
             url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
             width: 640,
             height: 480,
             split: "train".to_string(),
@@ -518,6 +1330,36 @@ mod tests {
         assert!(poses.is_empty());
     }
 
+    #[test]
+    fn retain_classes_filters_every_annotation_kind() {
+        let mut entry = ImageEntry {
+            r#type: "image".to_string(),
+            file: "test.jpg".to_string(),
+            output_file: None,
+            url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
+            width: 640,
+            height: 480,
+            split: "train".to_string(),
+            annotations: Some(serde_json::json!({
+                "bboxes": [[0, 0.1, 0.2, 0.3, 0.4], [1, 0.5, 0.6, 0.7, 0.8]],
+                "segments": [[1, 0.1, 0.1, 0.2, 0.1, 0.2, 0.2]],
+                "classification": [0, 1]
+            })),
+        };
+
+        entry.retain_classes(|id| id == 0);
+
+        let bboxes = entry.get_bboxes();
+        assert_eq!(bboxes.len(), 1);
+        assert_eq!(bboxes[0].class_id, 0);
+        assert!(entry.get_segment_annotations().is_empty());
+        assert_eq!(entry.get_classifications(), vec![0]);
+    }
+
     #[test]
     fn image_entry_download_key_uses_effective_file_name() {
         let entry = ImageEntry {
@@ -525,6 +1367,10 @@ mod tests {
             file: "img1.jpg".to_string(),
             output_file: Some("img1__abcd1234.jpg".to_string()),
             url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
             width: 640,
             height: 480,
             split: "val".to_string(),
@@ -543,7 +1389,8 @@ mod tests {
 {"type":"image","file":"train2.jpg","width":640,"height":480,"split":"train","url":""}"#;
 
         let data = parse_ndjson(content).unwrap();
-        let train = data.train_images();
+        let splits = data.images_by_split(SplitNormalization::Canonical);
+        let (_, train) = splits.iter().find(|(split, _)| *split == "train").unwrap();
         assert_eq!(train.len(), 2);
         assert!(train.iter().all(|img| img.split == "train"));
     }
@@ -556,11 +1403,28 @@ mod tests {
 {"type":"image","file":"val1.jpg","width":640,"height":480,"split":"val","url":""}"#;
 
         let data = parse_ndjson(content).unwrap();
-        let valid = data.valid_images();
+        let splits = data.images_by_split(SplitNormalization::Canonical);
+        let (_, valid) = splits.iter().find(|(split, _)| *split == "valid").unwrap();
         assert_eq!(valid.len(), 2);
         assert!(valid
             .iter()
             .all(|img| img.split == "valid" || img.split == "val"));
+        assert!(!splits.iter().any(|(split, _)| *split == "val"));
+
+        let separate: Vec<(&str, Vec<&str>)> = data
+            .images_by_split(SplitNormalization::SeparateVal)
+            .into_iter()
+            .map(|(split, images)| (split, images.iter().map(|img| img.file.as_str()).collect()))
+            .collect();
+        assert_eq!(
+            separate,
+            [
+                ("train", vec!["train1.jpg"]),
+                ("val", vec!["val1.jpg"]),
+                ("valid", vec!["valid1.jpg"]),
+                ("test", vec![]),
+            ]
+        );
     }
 
     #[test]
@@ -570,6 +1434,10 @@ mod tests {
             file: "test.jpg".to_string(),
             output_file: None,
             url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
             width: 640,
             height: 640,
             split: "train".to_string(),
@@ -594,6 +1462,10 @@ mod tests {
             file: "test.jpg".to_string(),
             output_file: None,
             url: String::new(),
+            mirrors: Vec::new(),
+            license: None,
+            skip: false,
+            captions: Vec::new(),
             width: 640,
             height: 640,
             split: "train".to_string(),
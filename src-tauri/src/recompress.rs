@@ -0,0 +1,321 @@
+use crate::converter::FileData;
+use crate::options::ConvertOptions;
+use crate::parser::{image_entry_download_key, ImageEntry};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+use std::collections::HashMap;
+use std::io::Cursor;
+
+pub const DEFAULT_RECOMPRESS_QUALITY: u8 = 85;
+/// Widest or tallest image decoded for re-encoding.
+const MAX_DECODE_DIMENSION: u32 = 16_384;
+/// Most memory one decode may allocate.
+const MAX_DECODE_ALLOC: u64 = 512 * 1024 * 1024;
+
+fn file_size(file: &FileData) -> std::io::Result<u64> {
+    match file {
+        FileData::Bytes(bytes) => Ok(bytes.len() as u64),
+        FileData::OnDisk(path) => std::fs::metadata(path).map(|meta| meta.len()),
+    }
+}
+
+/// Re-encodes a JPEG at `quality` or a PNG at maximum compression, keeping the
+/// original format. When `max_dimension` is set, larger images are first
+/// downscaled to fit it. Returns the new bytes and pixel dimensions, or `None`
+/// for other formats.
+fn reencode(
+    bytes: &[u8],
+    quality: u8,
+    max_dimension: Option<u32>,
+) -> Result<Option<(Vec<u8>, u32, u32)>, String> {
+    let format = match image::guess_format(bytes) {
+        Ok(format @ (ImageFormat::Jpeg | ImageFormat::Png)) => format,
+        _ => return Ok(None),
+    };
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DECODE_DIMENSION);
+    limits.max_image_height = Some(MAX_DECODE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let mut decoded = reader
+        .decode()
+        .map_err(|e| format!("failed to decode: {}", e))?;
+    if let Some(max) = max_dimension.filter(|&max| max > 0) {
+        if decoded.width().max(decoded.height()) > max {
+            decoded = decoded.resize(max, max, image::imageops::FilterType::Triangle);
+        }
+    }
+
+    let mut out = Vec::new();
+    match format {
+        ImageFormat::Jpeg => {
+            let rgb = DynamicImage::ImageRgb8(decoded.to_rgb8());
+            rgb.write_with_encoder(JpegEncoder::new_with_quality(&mut out, quality))
+        }
+        _ => decoded.write_with_encoder(PngEncoder::new_with_quality(
+            &mut out,
+            CompressionType::Best,
+            FilterType::Adaptive,
+        )),
+    }
+    .map_err(|e| format!("failed to re-encode: {}", e))?;
+
+    Ok(Some((out, decoded.width(), decoded.height())))
+}
+
+/// Re-encodes every JPEG/PNG larger than `threshold` bytes, leaving smaller
+/// images byte-identical. Dimensions are unchanged, so annotations need no
+/// adjustment. A re-encode that doesn't shrink the file is discarded. The
+/// new bytes replace the entry in memory; files on disk, which may be the
+/// user's own images, are never written. Returns a warning for each image
+/// that could not be processed.
+pub fn recompress_large_images(
+    files: &mut HashMap<String, FileData>,
+    threshold: usize,
+    quality: u8,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    for file in files.values_mut() {
+        let original_size = match file_size(file) {
+            Ok(size) if size > threshold as u64 => size,
+            _ => continue,
+        };
+
+        let result = file
+            .read()
+            .map_err(|e| format!("failed to read: {}", e))
+            .and_then(|bytes| reencode(&bytes, quality, None));
+        let reencoded = match result {
+            Ok(Some((reencoded, _, _))) if (reencoded.len() as u64) < original_size => reencoded,
+            Ok(_) => continue,
+            Err(err) => {
+                warnings.push(format!("Skipped recompressing an image: {}", err));
+                continue;
+            }
+        };
+
+        *file = FileData::Bytes(reencoded);
+    }
+
+    warnings
+}
+
+/// Re-encodes the images of each split that has settings in `settings`,
+/// downscaling to the split's `max_dimension` and updating the image's
+/// declared size to match. Annotations are normalized, so they need no
+/// adjustment. A quality-only re-encode that doesn't shrink the file is
+/// discarded. As with `recompress_large_images`, files on disk are left as
+/// they are. Returns a warning for each image that could not be processed.
+pub fn reencode_split_images(
+    files: &mut HashMap<String, FileData>,
+    images: &mut [ImageEntry],
+    options: &ConvertOptions,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    for image in images.iter_mut() {
+        let Some(settings) = options.image_settings_for(&image.split) else {
+            continue;
+        };
+        let key = image_entry_download_key(image);
+        let Some(file) = files.get_mut(&key) else {
+            continue;
+        };
+        let quality = settings
+            .quality
+            .unwrap_or(DEFAULT_RECOMPRESS_QUALITY)
+            .clamp(1, 100);
+
+        let result = file
+            .read()
+            .map_err(|e| format!("failed to read: {}", e))
+            .and_then(|bytes| {
+                let original_size = bytes.len();
+                reencode(&bytes, quality, settings.max_dimension)
+                    .map(|reencoded| reencoded.map(|result| (result, original_size)))
+            });
+        let (reencoded, width, height) = match result {
+            Ok(Some(((reencoded, width, height), original_size)))
+                if (width as i32, height as i32) != (image.width, image.height)
+                    || reencoded.len() < original_size =>
+            {
+                (reencoded, width, height)
+            }
+            Ok(_) => continue,
+            Err(err) => {
+                warnings.push(format!(
+                    "Skipped re-encoding '{}': {}",
+                    image.effective_file_name(),
+                    err
+                ));
+                continue;
+            }
+        };
+
+        *file = FileData::Bytes(reencoded);
+        image.width = width as i32;
+        image.height = height as i32;
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageEncoder, RgbImage};
+
+    fn quality_100_jpeg() -> Vec<u8> {
+        let img = RgbImage::from_fn(64, 48, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 5) as u8, 128])
+        });
+        let mut bytes = Vec::new();
+        JpegEncoder::new_with_quality(&mut bytes, 100)
+            .write_image(img.as_raw(), 64, 48, image::ExtendedColorType::Rgb8)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn image_under_threshold_is_untouched() {
+        let original = quality_100_jpeg();
+        let mut files = HashMap::from([("a.jpg".to_string(), FileData::from(original.clone()))]);
+
+        let warnings = recompress_large_images(&mut files, original.len(), 50);
+
+        assert!(warnings.is_empty());
+        assert_eq!(files["a.jpg"], FileData::Bytes(original));
+    }
+
+    #[test]
+    fn image_over_threshold_is_reencoded_with_same_dimensions() {
+        let original = quality_100_jpeg();
+        let mut files = HashMap::from([("a.jpg".to_string(), FileData::from(original.clone()))]);
+
+        let warnings = recompress_large_images(&mut files, original.len() - 1, 50);
+
+        assert!(warnings.is_empty());
+        let recompressed = files["a.jpg"].as_bytes().unwrap().to_vec();
+        assert!(recompressed.len() < original.len());
+        assert_eq!(
+            image::guess_format(&recompressed).unwrap(),
+            ImageFormat::Jpeg
+        );
+        let decoded = image::load_from_memory(&recompressed).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (64, 48));
+    }
+
+    #[test]
+    fn images_over_the_decode_limit_are_skipped() {
+        let wide = RgbImage::new(MAX_DECODE_DIMENSION + 1, 1);
+        let mut bytes = Vec::new();
+        PngEncoder::new(&mut bytes)
+            .write_image(
+                wide.as_raw(),
+                wide.width(),
+                1,
+                image::ExtendedColorType::Rgb8,
+            )
+            .unwrap();
+        let mut files = HashMap::from([("a.png".to_string(), FileData::from(bytes.clone()))]);
+
+        let warnings = recompress_large_images(&mut files, 1, 50);
+
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert_eq!(files["a.png"], FileData::Bytes(bytes));
+    }
+
+    #[test]
+    fn non_image_files_over_threshold_are_untouched() {
+        let mut files = HashMap::from([(
+            "notes.txt".to_string(),
+            FileData::from(b"plain text".to_vec()),
+        )]);
+
+        let warnings = recompress_large_images(&mut files, 1, 50);
+
+        assert!(warnings.is_empty());
+        assert_eq!(files["notes.txt"], FileData::Bytes(b"plain text".to_vec()));
+    }
+
+    #[test]
+    fn split_settings_resize_only_their_split() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":64,"height":48,"split":"train"}
+{"type":"image","file":"b.jpg","width":64,"height":48,"split":"val"}"#,
+        )
+        .unwrap();
+        let mut images = data.images;
+        let original = quality_100_jpeg();
+        let mut files = HashMap::new();
+        for image in &images {
+            files.insert(
+                image_entry_download_key(image),
+                FileData::from(original.clone()),
+            );
+        }
+        let options = ConvertOptions {
+            split_image_settings: HashMap::from([(
+                "valid".to_string(),
+                crate::options::SplitImageSettings {
+                    max_dimension: Some(32),
+                    quality: Some(70),
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let warnings = reencode_split_images(&mut files, &mut images, &options);
+
+        assert!(warnings.is_empty());
+        let train = &files[&image_entry_download_key(&images[0])];
+        assert_eq!(train, &FileData::Bytes(original));
+        assert_eq!((images[0].width, images[0].height), (64, 48));
+
+        let valid = image::load_from_memory(
+            files[&image_entry_download_key(&images[1])]
+                .as_bytes()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!((valid.width(), valid.height()), (32, 24));
+        assert_eq!((images[1].width, images[1].height), (32, 24));
+    }
+
+    #[test]
+    fn split_reencode_leaves_files_on_disk_untouched() {
+        let data = crate::parser::parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":64,"height":48,"split":"train"}"#,
+        )
+        .unwrap();
+        let mut images = data.images;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("a.jpg");
+        let original = quality_100_jpeg();
+        std::fs::write(&source, &original).unwrap();
+        let key = image_entry_download_key(&images[0]);
+        let mut files = HashMap::from([(key.clone(), FileData::OnDisk(source.clone()))]);
+        let options = ConvertOptions {
+            split_image_settings: HashMap::from([(
+                "train".to_string(),
+                crate::options::SplitImageSettings {
+                    max_dimension: Some(32),
+                    quality: None,
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let warnings = reencode_split_images(&mut files, &mut images, &options);
+
+        assert!(warnings.is_empty());
+        assert_eq!(std::fs::read(&source).unwrap(), original);
+        let resized = image::load_from_memory(files[&key].as_bytes().unwrap()).unwrap();
+        assert_eq!((resized.width(), resized.height()), (32, 24));
+    }
+}
@@ -0,0 +1,304 @@
+use crate::converter::FileData;
+use crate::dedup::rewrite_image_references;
+use crate::options::{ConvertOptions, OUTPUT_SPLITS};
+use std::collections::HashMap;
+
+fn is_annotation_file(name: &str) -> bool {
+    name.starts_with('.')
+        || name.starts_with('_')
+        || [".txt", ".xml", ".json"]
+            .iter()
+            .any(|ext| name.ends_with(ext))
+}
+
+fn stem(name: &str) -> &str {
+    name.rsplit_once('.').map_or(name, |(stem, _)| stem)
+}
+
+/// Splits image directories holding more than `max_per_dir` images into
+/// numbered subdirectories (`0000/`, `0001/`, ...), in file name order.
+///
+/// Each image's label, legend or XML moves into the matching subdirectory of
+/// its own folder, and every COCO, CreateML, VOC or Darknet reference to it
+/// gains the subdirectory prefix.
+/// Classify exports are organized by class folder and are left alone
+/// (`None`). Otherwise returns the number of subdirectories created.
+pub fn shard_image_dirs(
+    files: &mut HashMap<String, FileData>,
+    format: &str,
+    task: &str,
+    max_per_dir: usize,
+    options: &ConvertOptions,
+) -> Option<usize> {
+    let format = format.to_lowercase();
+    if task == "classify" || max_per_dir == 0 {
+        return None;
+    }
+
+    let mut shard_count = 0;
+    for split in OUTPUT_SPLITS {
+        // (image dir, companion dirs as (dir, suffix) pairs)
+        let (image_dir, companions): (String, Vec<(String, &str)>) = match format.as_str() {
+            "yolo" => (
+                format!("{}/images", split),
+                vec![
+                    (format!("{}/labels", split), ".txt"),
+                    (format!("{}/labels", split), ".legend.txt"),
+                    (format!("{}/labels", split), ".abs.txt"),
+                    (format!("{}/labels", split), ".ids.json"),
+                    (format!("{}/labels_det", split), ".txt"),
+                ],
+            ),
+            "yolo_darknet" => (
+                split.to_string(),
+                vec![
+                    (split.to_string(), ".txt"),
+                    (split.to_string(), ".legend.txt"),
+                    (split.to_string(), ".abs.txt"),
+                    (split.to_string(), ".ids.json"),
+                ],
+            ),
+            "pascal_voc" | "voc" => (split.to_string(), vec![(split.to_string(), ".xml")]),
+            _ => (split.to_string(), Vec::new()),
+        };
+
+        let prefix = format!("{}/", image_dir);
+        let mut images: Vec<String> = files
+            .keys()
+            .filter_map(|path| path.strip_prefix(&prefix))
+            .filter(|name| !name.contains('/') && !is_annotation_file(name))
+            .map(str::to_string)
+            .collect();
+        if images.len() <= max_per_dir {
+            continue;
+        }
+        images.sort();
+
+        let mut renames = HashMap::new();
+        for (idx, name) in images.iter().enumerate() {
+            let shard = format!("{:04}", idx / max_per_dir);
+            let mut moves = vec![(
+                format!("{}/{}", image_dir, name),
+                format!("{}/{}/{}", image_dir, shard, name),
+            )];
+            for (dir, suffix) in &companions {
+                moves.push((
+                    format!("{}/{}{}", dir, stem(name), suffix),
+                    format!("{}/{}/{}{}", dir, shard, stem(name), suffix),
+                ));
+            }
+            for (from, to) in moves {
+                if let Some(file) = files.remove(&from) {
+                    files.insert(to, file);
+                }
+            }
+            renames.insert(name.clone(), format!("{}/{}", shard, name));
+        }
+        shard_count += images.len().div_ceil(max_per_dir);
+
+        if format == "yolo_darknet" {
+            rewrite_list_file(files, split, &renames);
+        } else {
+            rewrite_image_references(files, &format, split, &renames, options);
+        }
+    }
+
+    Some(shard_count)
+}
+
+/// Points the entries of a Darknet `{split}.txt` image list, relative or
+/// under an image root, at the sharded image paths.
+fn rewrite_list_file(
+    files: &mut HashMap<String, FileData>,
+    split: &str,
+    renames: &HashMap<String, String>,
+) {
+    let path = format!("{}.txt", split);
+    let Some(list) = files
+        .get(&path)
+        .and_then(|file| file.read().ok())
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    else {
+        return;
+    };
+
+    let mut rewritten = String::with_capacity(list.len());
+    for line in list.lines() {
+        let renamed = line.rsplit_once('/').and_then(|(dir, name)| {
+            let split_dir = dir == split || dir.ends_with(&format!("/{}", split));
+            renames
+                .get(name)
+                .filter(|_| split_dir)
+                .map(|renamed| format!("{}/{}", dir, renamed))
+        });
+        rewritten.push_str(renamed.as_deref().unwrap_or(line));
+        rewritten.push('\n');
+    }
+    files.insert(path, rewritten.into_bytes().into());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converter::get_converter;
+    use crate::parser::{image_download_key, parse_ndjson};
+
+    fn convert(format: &str, image_count: usize) -> HashMap<String, FileData> {
+        convert_with(format, image_count, &ConvertOptions::default())
+    }
+
+    fn convert_with(
+        format: &str,
+        image_count: usize,
+        options: &ConvertOptions,
+    ) -> HashMap<String, FileData> {
+        let mut content =
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}"#.to_string();
+        let mut downloaded = HashMap::new();
+        for i in 0..image_count {
+            content.push_str(&format!(
+                "\n{{\"type\":\"image\",\"file\":\"img{:04}.jpg\",\"width\":640,\"height\":480,\"split\":\"train\",\"annotations\":{{\"boxes\":[[0,0.5,0.5,0.2,0.2]]}}}}",
+                i
+            ));
+            downloaded.insert(
+                image_download_key("train", &format!("img{:04}.jpg", i)),
+                FileData::from(vec![0u8]),
+            );
+        }
+        let data = parse_ndjson(&content).unwrap();
+        get_converter(format, options)
+            .unwrap()
+            .convert(&data, &downloaded)
+    }
+
+    #[test]
+    fn yolo_images_and_labels_are_sharded_together() {
+        let mut files = convert("yolo", 2500);
+
+        let shards = shard_image_dirs(
+            &mut files,
+            "yolo",
+            "detect",
+            1000,
+            &ConvertOptions::default(),
+        );
+
+        assert_eq!(shards, Some(3));
+        for (shard, count) in [("0000", 1000), ("0001", 1000), ("0002", 500)] {
+            let images = files
+                .keys()
+                .filter(|path| path.starts_with(&format!("train/images/{}/", shard)))
+                .count();
+            let labels = files
+                .keys()
+                .filter(|path| path.starts_with(&format!("train/labels/{}/", shard)))
+                .count();
+            assert_eq!((images, labels), (count, count));
+        }
+        assert!(files.contains_key("train/images/0002/img2499.jpg"));
+        assert!(files.contains_key("train/labels/0002/img2499.txt"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("export.zip");
+        crate::write_zip_atomically(
+            &zip_path,
+            &files,
+            None,
+            crate::options::CompressionPreset::default(),
+            &tauri::ipc::Channel::new(|_| Ok(())),
+        )
+        .unwrap();
+        let report = crate::verify::verify_export(&zip_path, "yolo").unwrap();
+        assert!(report.valid, "{:?}", report.problems);
+    }
+
+    #[test]
+    fn darknet_list_entries_follow_sharded_images() {
+        let mut files = convert("yolo_darknet", 3);
+
+        assert_eq!(
+            shard_image_dirs(
+                &mut files,
+                "yolo_darknet",
+                "detect",
+                2,
+                &ConvertOptions::default()
+            ),
+            Some(2)
+        );
+
+        assert!(files.contains_key("train/0001/img0002.jpg"));
+        assert!(files.contains_key("train/0001/img0002.txt"));
+        let list = String::from_utf8(files["train.txt"].read().unwrap().into_owned()).unwrap();
+        assert_eq!(
+            list,
+            "train/0000/img0000.jpg\ntrain/0000/img0001.jpg\ntrain/0001/img0002.jpg\n"
+        );
+    }
+
+    #[test]
+    fn coco_file_names_point_into_shards() {
+        let mut files = convert("coco", 5);
+
+        assert_eq!(
+            shard_image_dirs(&mut files, "coco", "detect", 2, &ConvertOptions::default()),
+            Some(3)
+        );
+
+        assert!(files.contains_key("train/0001/img0002.jpg"));
+        let coco: serde_json::Value =
+            serde_json::from_slice(&files["train/_annotations.coco.json"].read().unwrap()).unwrap();
+        assert_eq!(coco["images"][2]["file_name"], "0001/img0002.jpg");
+    }
+
+    #[test]
+    fn single_file_coco_and_voc_paths_point_into_shards() {
+        let options = ConvertOptions {
+            coco_single_file: true,
+            voc_path: crate::options::VocPath::Split,
+            ..Default::default()
+        };
+        let mut coco = convert_with("coco", 3, &options);
+        let mut voc = convert_with("pascal_voc", 3, &options);
+
+        shard_image_dirs(&mut coco, "coco", "detect", 2, &options);
+        shard_image_dirs(&mut voc, "pascal_voc", "detect", 2, &options);
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&coco["_annotations.coco.json"].read().unwrap()).unwrap();
+        assert_eq!(json["images"][2]["file_name"], "train/0001/img0002.jpg");
+        let xml =
+            String::from_utf8(voc["train/0001/img0002.xml"].read().unwrap().into_owned()).unwrap();
+        assert!(xml.contains("<filename>img0002.jpg</filename>"), "{}", xml);
+        assert!(
+            xml.contains("<path>train/0001/img0002.jpg</path>"),
+            "{}",
+            xml
+        );
+    }
+
+    #[test]
+    fn splits_under_the_cap_stay_flat() {
+        let mut files = convert("yolo", 3);
+        let before: Vec<String> = {
+            let mut keys: Vec<String> = files.keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+
+        assert_eq!(
+            shard_image_dirs(
+                &mut files,
+                "yolo",
+                "detect",
+                1000,
+                &ConvertOptions::default()
+            ),
+            Some(0)
+        );
+
+        let mut after: Vec<String> = files.keys().cloned().collect();
+        after.sort();
+        assert_eq!(before, after);
+    }
+}
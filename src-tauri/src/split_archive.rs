@@ -0,0 +1,514 @@
+use crate::manifest::{sha256_hex, ExportManifest, MANIFEST_FILE_NAME};
+use crate::options::OUTPUT_SPLITS;
+use crate::parser::normalize_split;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+/// One archive in `{stem}_index.json`, in the order the archives were
+/// written.
+#[derive(Debug, Serialize)]
+struct Volume {
+    file: String,
+    bytes: u64,
+    entry_count: usize,
+    entries: Vec<String>,
+}
+
+/// The split an archive entry belongs to: its top-level directory, its
+/// `raw/{split}/` sidecars, or a root-level `{split}.txt`/`{split}.json`
+/// (Darknet lists, CreateML files). Other root entries are shared files.
+fn entry_split(path: &str) -> Option<&'static str> {
+    OUTPUT_SPLITS.into_iter().find(|split| {
+        path.starts_with(&format!("{}/", split))
+            || path.starts_with(&format!("raw/{}/", split))
+            || path == format!("{}.txt", split)
+            || path == format!("{}.json", split)
+    })
+}
+
+/// Shared root files that name images, so each archive gets its own
+/// split's part of them rather than a copy.
+const NARROWED_FILES: [&str; 6] = [
+    "data.yaml",
+    "_annotations.coco.json",
+    "_captions.coco.json",
+    "file_name_map.json",
+    "source.ndjson",
+    MANIFEST_FILE_NAME,
+];
+
+/// `data.yaml` with only the path entry for `split`, so each archive is
+/// self-contained. A `val` entry listing both `val/` and `valid/` is
+/// narrowed to the archive's own directory.
+fn yaml_for_split(yaml: &str, split: &str) -> String {
+    let key = if split == "valid" { "val" } else { split };
+    yaml.lines()
+        .filter(|line| {
+            ["train:", "val:", "test:"]
+                .iter()
+                .all(|other| !line.starts_with(other))
+                || line.starts_with(&format!("{}:", key))
+        })
+        .map(|line| match line.strip_prefix(&format!("{}: [", key)) {
+            Some(list) => {
+                let own = list
+                    .trim_end_matches(']')
+                    .split(", ")
+                    .find(|path| *path == split || path.starts_with(&format!("{}/", split)))
+                    .unwrap_or_default();
+                format!("{}: {}\n", key, own)
+            }
+            None => format!("{}\n", line),
+        })
+        .collect()
+}
+
+/// Serializes `value` the way `original` was written, pretty-printed or
+/// compact.
+fn to_json_like(original: &[u8], value: &serde_json::Value) -> Vec<u8> {
+    if original.contains(&b'\n') {
+        serde_json::to_vec_pretty(value).unwrap_or_default()
+    } else {
+        serde_json::to_vec(value).unwrap_or_default()
+    }
+}
+
+/// A single-file COCO (or its captions) with only the images under
+/// `{split}/` and their annotations.
+fn coco_for_split(json: &[u8], split: &str) -> Result<Vec<u8>, String> {
+    let mut coco: serde_json::Value =
+        serde_json::from_slice(json).map_err(|e| format!("Failed to parse COCO file: {}", e))?;
+    let prefix = format!("{}/", split);
+    let mut kept = HashSet::new();
+    if let Some(images) = coco["images"].as_array_mut() {
+        images.retain(|image| {
+            let keep = image["file_name"]
+                .as_str()
+                .is_some_and(|name| name.starts_with(&prefix));
+            if keep {
+                kept.extend(image["id"].as_i64());
+            }
+            keep
+        });
+    }
+    if let Some(annotations) = coco["annotations"].as_array_mut() {
+        annotations.retain(|annotation| {
+            annotation["image_id"]
+                .as_i64()
+                .is_some_and(|id| kept.contains(&id))
+        });
+    }
+    Ok(to_json_like(json, &coco))
+}
+
+/// `file_name_map.json` with only the renames in `split`.
+fn file_name_map_for_split(json: &[u8], split: &str) -> Result<Vec<u8>, String> {
+    let mut map: serde_json::Value = serde_json::from_slice(json)
+        .map_err(|e| format!("Failed to parse file name map: {}", e))?;
+    if let Some(renames) = map.as_array_mut() {
+        renames.retain(|rename| rename["split"] == split);
+    }
+    Ok(to_json_like(json, &map))
+}
+
+/// The embedded source NDJSON with only the image records of `split`. Other
+/// records, and images without a split, are kept. `separate_val` says the
+/// export kept `val` apart from `valid`.
+fn source_for_split(ndjson: &[u8], split: &str, separate_val: bool) -> Vec<u8> {
+    String::from_utf8_lossy(ndjson)
+        .lines()
+        .filter(|line| {
+            let Ok(record) = serde_json::from_str::<serde_json::Value>(line) else {
+                return true;
+            };
+            if record["type"] != "image" {
+                return true;
+            }
+            match record["split"].as_str() {
+                Some("val") if separate_val => split == "val",
+                Some(other) => normalize_split(other) == split,
+                None => true,
+            }
+        })
+        .map(|line| format!("{}\n", line))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// `manifest.json` with only the images of `split` and the files in its
+/// archive, with the sizes and hashes of the files narrowed for it.
+fn manifest_for_split(
+    json: &[u8],
+    split: &str,
+    entries: &[String],
+    narrowed: &HashMap<String, Vec<u8>>,
+) -> Result<Vec<u8>, String> {
+    let mut manifest: ExportManifest = serde_json::from_slice(json)
+        .map_err(|e| format!("Failed to parse '{}': {}", MANIFEST_FILE_NAME, e))?;
+    let prefix = format!("{}/", split);
+    manifest.images.retain(|key, _| key.starts_with(&prefix));
+    manifest.files.retain(|file| entries.contains(&file.path));
+    for file in &mut manifest.files {
+        if let Some(bytes) = narrowed.get(&file.path) {
+            file.size = bytes.len() as u64;
+            file.sha256 = sha256_hex(bytes);
+        }
+    }
+    Ok(manifest.to_json())
+}
+
+/// Writes the archive for `split` to `path`: its own entries, plus every
+/// shared root file, narrowed to the split where it names images. Returns
+/// the entry names.
+fn write_split_archive(
+    archive: &mut ZipArchive<std::fs::File>,
+    names: &[String],
+    split: &str,
+    separate_val: bool,
+    path: &Path,
+) -> Result<Vec<String>, String> {
+    let output = std::fs::File::create(path)
+        .map_err(|e| format!("Failed to create output file '{}': {}", path.display(), e))?;
+    let mut zip = ZipWriter::new(output);
+    let mut entries = Vec::new();
+    let mut narrowed = HashMap::new();
+    let mut manifest = None;
+
+    for name in names {
+        let own = match entry_split(name) {
+            Some(entry) if entry != split => continue,
+            Some(_) => true,
+            None => false,
+        };
+        entries.push(name.clone());
+        let mut entry = archive
+            .by_name(name)
+            .map_err(|e| format!("Failed to read '{}' from ZIP: {}", name, e))?;
+        if own || !NARROWED_FILES.contains(&name.as_str()) {
+            zip.raw_copy_file(entry)
+                .map_err(|e| format!("Failed to add file to ZIP: {}", e))?;
+            continue;
+        }
+
+        let mut bytes = Vec::new();
+        entry
+            .read_to_end(&mut bytes)
+            .map_err(|e| format!("Failed to read '{}' from ZIP: {}", name, e))?;
+        let bytes = match name.as_str() {
+            // The manifest lists the other files, so it is written last.
+            MANIFEST_FILE_NAME => {
+                manifest = Some(bytes);
+                continue;
+            }
+            "data.yaml" => yaml_for_split(&String::from_utf8_lossy(&bytes), split).into_bytes(),
+            "file_name_map.json" => file_name_map_for_split(&bytes, split)?,
+            "source.ndjson" => source_for_split(&bytes, split, separate_val),
+            _ => coco_for_split(&bytes, split)?,
+        };
+        add_file(&mut zip, name, &bytes)?;
+        narrowed.insert(name.clone(), bytes);
+    }
+    if let Some(json) = manifest {
+        let bytes = manifest_for_split(&json, split, &entries, &narrowed)?;
+        add_file(&mut zip, MANIFEST_FILE_NAME, &bytes)?;
+    }
+
+    zip.finish()
+        .map_err(|e| format!("Failed to finish ZIP: {}", e))?;
+    Ok(entries)
+}
+
+fn add_file(zip: &mut ZipWriter<std::fs::File>, name: &str, bytes: &[u8]) -> Result<(), String> {
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    zip.start_file(name, options)
+        .map_err(|e| format!("Failed to add file to ZIP: {}", e))?;
+    zip.write_all(bytes)
+        .map_err(|e| format!("Failed to write file to ZIP: {}", e))
+}
+
+/// Has `write` fill a sibling `.tmp` file, then renames it over `path`, so
+/// an interrupted split never leaves a partial file behind.
+fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&Path) -> Result<(), String>,
+) -> Result<(), String> {
+    let temp_path = crate::temp_output_path(path);
+    let result = write(&temp_path).and_then(|()| {
+        std::fs::rename(&temp_path, path)
+            .map_err(|e| format!("Failed to move '{}' into place: {}", path.display(), e))
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Repartitions a combined export into one archive per split, named
+/// `{stem}_{split}.zip` in `output_dir`. Shared root files go into every
+/// archive, with `data.yaml`, a single-file COCO, the manifest, the file
+/// name map and the embedded source narrowed to the archive's split. A
+/// `{stem}_index.json` lists each archive's size and entries so an uploader
+/// can check it has every part. Every file is written to a temp file and
+/// renamed into place. Returns the archive paths, in split order.
+pub fn split_archive(zip_path: &Path, output_dir: &Path) -> Result<Vec<String>, String> {
+    let file = std::fs::File::open(zip_path)
+        .map_err(|e| format!("Failed to open '{}': {}", zip_path.display(), e))?;
+    let mut archive = ZipArchive::new(file)
+        .map_err(|e| format!("'{}' is not a valid ZIP: {}", zip_path.display(), e))?;
+    let names: Vec<String> = archive.file_names().map(str::to_string).collect();
+    let stem = zip_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "export".to_string());
+    let separate_val = names.iter().any(|name| entry_split(name) == Some("val"));
+
+    let mut written = Vec::new();
+    let mut volumes = Vec::new();
+    for split in OUTPUT_SPLITS {
+        if !names.iter().any(|name| entry_split(name) == Some(split)) {
+            continue;
+        }
+        let output_path = output_dir.join(format!("{}_{}.zip", stem, split));
+        let mut entries = Vec::new();
+        write_atomically(&output_path, |temp_path| {
+            entries = write_split_archive(&mut archive, &names, split, separate_val, temp_path)?;
+            Ok(())
+        })?;
+        let bytes = std::fs::metadata(&output_path)
+            .map_err(|e| format!("Failed to read '{}': {}", output_path.display(), e))?
+            .len();
+        volumes.push(Volume {
+            file: format!("{}_{}.zip", stem, split),
+            bytes,
+            entry_count: entries.len(),
+            entries,
+        });
+        written.push(output_path.to_string_lossy().to_string());
+    }
+
+    let index_path = output_dir.join(format!("{}_index.json", stem));
+    let index = serde_json::to_vec_pretty(&serde_json::json!({ "volumes": volumes }))
+        .map_err(|e| format!("Failed to build archive index: {}", e))?;
+    write_atomically(&index_path, |temp_path| {
+        std::fs::write(temp_path, index)
+            .map_err(|e| format!("Failed to write '{}': {}", temp_path.display(), e))
+    })?;
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converter::{get_converter, FileData};
+    use crate::options::ConvertOptions;
+    use crate::parser::{image_download_key, parse_ndjson};
+    use std::collections::HashMap;
+
+    #[test]
+    fn combined_export_is_split_per_directory_with_config_in_each() {
+        let data = parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"val","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let downloaded: HashMap<String, FileData> = HashMap::from([
+            (image_download_key("train", "a.jpg"), b"a".to_vec().into()),
+            (image_download_key("valid", "b.jpg"), b"b".to_vec().into()),
+        ]);
+        let files = get_converter("yolo", &ConvertOptions::default())
+            .unwrap()
+            .convert(&data, &downloaded);
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("export.zip");
+        crate::write_zip_atomically(
+            &zip_path,
+            &files,
+            None,
+            crate::options::CompressionPreset::default(),
+            &tauri::ipc::Channel::new(|_| Ok(())),
+        )
+        .unwrap();
+
+        let written = split_archive(&zip_path, dir.path()).unwrap();
+
+        assert_eq!(
+            written,
+            [
+                dir.path().join("export_train.zip").to_string_lossy(),
+                dir.path().join("export_valid.zip").to_string_lossy(),
+            ]
+        );
+        let mut train = ZipArchive::new(std::fs::File::open(&written[0]).unwrap()).unwrap();
+        let mut names: Vec<&str> = train.file_names().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "classes.txt",
+                "data.yaml",
+                "train/images/a.jpg",
+                "train/labels/a.txt"
+            ]
+        );
+        let mut yaml = String::new();
+        train
+            .by_name("data.yaml")
+            .unwrap()
+            .read_to_string(&mut yaml)
+            .unwrap();
+        assert!(yaml.contains("train: train/images\n"));
+        assert!(!yaml.contains("val:"));
+
+        let valid = ZipArchive::new(std::fs::File::open(&written[1]).unwrap()).unwrap();
+        assert!(valid.file_names().any(|name| name == "valid/images/b.jpg"));
+        assert!(valid.file_names().any(|name| name == "classes.txt"));
+        assert!(!valid.file_names().any(|name| name.starts_with("train/")));
+
+        let index: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join("export_index.json")).unwrap())
+                .unwrap();
+        let volumes = index["volumes"].as_array().unwrap();
+        assert_eq!(volumes.len(), 2);
+        assert_eq!(volumes[0]["file"], "export_train.zip");
+        assert_eq!(volumes[0]["entry_count"], 4);
+        assert_eq!(volumes[1]["file"], "export_valid.zip");
+        assert_eq!(volumes[1]["entry_count"], valid.len());
+        assert_eq!(
+            volumes[1]["bytes"],
+            std::fs::metadata(&written[1]).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn separate_val_and_valid_get_their_own_archives() {
+        let data = parse_ndjson(
+            r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"val","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"valid","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#,
+        )
+        .unwrap();
+        let options = ConvertOptions {
+            split_normalization: crate::options::SplitNormalization::SeparateVal,
+            ..Default::default()
+        };
+        let files = get_converter("yolo", &options)
+            .unwrap()
+            .convert(&data, &HashMap::new());
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("export.zip");
+        crate::write_zip_atomically(
+            &zip_path,
+            &files,
+            None,
+            crate::options::CompressionPreset::default(),
+            &tauri::ipc::Channel::new(|_| Ok(())),
+        )
+        .unwrap();
+
+        let written = split_archive(&zip_path, dir.path()).unwrap();
+
+        assert_eq!(written.len(), 2);
+        for (path, split) in written.iter().zip(["val", "valid"]) {
+            assert!(path.ends_with(&format!("export_{}.zip", split)), "{}", path);
+            let mut archive = ZipArchive::new(std::fs::File::open(path).unwrap()).unwrap();
+            let mut yaml = String::new();
+            archive
+                .by_name("data.yaml")
+                .unwrap()
+                .read_to_string(&mut yaml)
+                .unwrap();
+            assert!(
+                yaml.contains(&format!("val: {}/images\n", split)),
+                "{}",
+                yaml
+            );
+        }
+    }
+
+    #[test]
+    fn root_files_naming_images_are_narrowed_to_each_split() {
+        let source = r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"val","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#;
+        let data = parse_ndjson(source).unwrap();
+        let downloaded: HashMap<String, FileData> = HashMap::from([
+            (image_download_key("train", "a.jpg"), b"a".to_vec().into()),
+            (image_download_key("valid", "b.jpg"), b"b".to_vec().into()),
+        ]);
+        let options = ConvertOptions {
+            coco_single_file: true,
+            ..Default::default()
+        };
+        let mut files = get_converter("coco", &options)
+            .unwrap()
+            .convert(&data, &downloaded);
+        files.insert(
+            "source.ndjson".to_string(),
+            source.as_bytes().to_vec().into(),
+        );
+        let mut manifest =
+            ExportManifest::for_images(&data.images, crate::options::SplitNormalization::Canonical);
+        manifest.record_files(&files).unwrap();
+        files.insert(MANIFEST_FILE_NAME.to_string(), manifest.to_json().into());
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("export.zip");
+        crate::write_zip_atomically(
+            &zip_path,
+            &files,
+            None,
+            crate::options::CompressionPreset::default(),
+            &tauri::ipc::Channel::new(|_| Ok(())),
+        )
+        .unwrap();
+
+        let written = split_archive(&zip_path, dir.path()).unwrap();
+
+        assert_eq!(written.len(), 2);
+        for (path, (split, file)) in written.iter().zip([("train", "a.jpg"), ("valid", "b.jpg")]) {
+            let mut archive = ZipArchive::new(std::fs::File::open(path).unwrap()).unwrap();
+            let mut read = |name: &str| {
+                let mut bytes = Vec::new();
+                archive
+                    .by_name(name)
+                    .unwrap()
+                    .read_to_end(&mut bytes)
+                    .unwrap();
+                bytes
+            };
+            let coco: serde_json::Value =
+                serde_json::from_slice(&read("_annotations.coco.json")).unwrap();
+            let images = coco["images"].as_array().unwrap();
+            assert_eq!(images.len(), 1, "{}", split);
+            assert_eq!(images[0]["file_name"], format!("{}/{}", split, file));
+            assert_eq!(coco["annotations"].as_array().unwrap().len(), 1);
+
+            let source = String::from_utf8(read("source.ndjson")).unwrap();
+            assert!(source.contains(file), "{}", split);
+            assert_eq!(source.lines().count(), 2, "{}", source);
+
+            let manifest: ExportManifest =
+                serde_json::from_slice(&read(MANIFEST_FILE_NAME)).unwrap();
+            assert_eq!(
+                manifest.images.keys().collect::<Vec<_>>(),
+                [&format!("{}/{}", split, file)]
+            );
+            for listed in &manifest.files {
+                let bytes = read(&listed.path);
+                assert_eq!(listed.size, bytes.len() as u64, "{}", listed.path);
+                assert_eq!(listed.sha256, sha256_hex(&bytes), "{}", listed.path);
+            }
+        }
+        let leftovers: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty(), "{:?}", leftovers);
+    }
+}
@@ -0,0 +1,533 @@
+use crate::manifest::{sha256_hex, ExportManifest, MANIFEST_FILE_NAME};
+use crate::options::OUTPUT_SPLITS;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use std::io::Read;
+use std::path::Path;
+use zip::ZipArchive;
+
+const COCO_SINGLE_FILE: &str = "_annotations.coco.json";
+const IMAGE_EXTENSIONS: [&str; 9] = [
+    "jpg", "jpeg", "png", "bmp", "gif", "webp", "tif", "tiff", "jfif",
+];
+
+/// Outcome of re-opening an exported ZIP. `valid` is true when `problems` is
+/// empty.
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    pub valid: bool,
+    pub entry_count: usize,
+    pub image_count: usize,
+    pub label_count: usize,
+    pub problems: Vec<String>,
+}
+
+pub(crate) fn is_image(path: &str) -> bool {
+    path.rsplit_once('.').is_some_and(|(_, ext)| {
+        IMAGE_EXTENSIONS
+            .iter()
+            .any(|known| ext.eq_ignore_ascii_case(known))
+    })
+}
+
+/// `dir/stem` of a path, ignoring its extension.
+fn stem_key(path: &str) -> &str {
+    let name_start = path.rfind('/').map_or(0, |idx| idx + 1);
+    match path[name_start..].rfind('.') {
+        Some(dot) => &path[..name_start + dot],
+        None => path,
+    }
+}
+
+struct Export<R> {
+    archive: ZipArchive<R>,
+    entries: BTreeSet<String>,
+    images_bundled: bool,
+    report: VerifyReport,
+}
+
+impl<R: Read + std::io::Seek> Export<R> {
+    fn read_text(&mut self, path: &str) -> Option<String> {
+        let mut content = String::new();
+        let result = match self.archive.by_name(path) {
+            Ok(mut file) => file
+                .read_to_string(&mut content)
+                .map_err(|e| format!("{}: unreadable: {}", path, e)),
+            Err(_) => Err(format!("{}: missing", path)),
+        };
+        match result {
+            Ok(_) => Some(content),
+            Err(problem) => {
+                self.problem(problem);
+                None
+            }
+        }
+    }
+
+    fn read_json(&mut self, path: &str) -> Option<serde_json::Value> {
+        let content = self.read_text(path)?;
+        match serde_json::from_str(&content) {
+            Ok(json) => Some(json),
+            Err(e) => {
+                self.problem(format!("{}: invalid JSON: {}", path, e));
+                None
+            }
+        }
+    }
+
+    /// Checks each file listed in `manifest.json` against its recorded size
+    /// and SHA-256.
+    fn check_manifest(&mut self) {
+        if !self.entries.contains(MANIFEST_FILE_NAME) {
+            return;
+        }
+        let Some(json) = self.read_json(MANIFEST_FILE_NAME) else {
+            return;
+        };
+        let manifest: ExportManifest = match serde_json::from_value(json) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                self.problem(format!("{}: invalid manifest: {}", MANIFEST_FILE_NAME, e));
+                return;
+            }
+        };
+
+        for listed in manifest.files {
+            let mut bytes = Vec::new();
+            let result = match self.archive.by_name(&listed.path) {
+                Ok(mut file) if file.size() == listed.size => file
+                    .read_to_end(&mut bytes)
+                    .map(|_| sha256_hex(&bytes) == listed.sha256)
+                    .map_err(|e| format!("{}: unreadable: {}", listed.path, e)),
+                Ok(file) => Err(format!(
+                    "{}: size {} does not match manifest ({})",
+                    listed.path,
+                    file.size(),
+                    listed.size
+                )),
+                Err(_) => Err(format!("{}: listed in manifest but missing", listed.path)),
+            };
+            match result {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.problem(format!("{}: SHA-256 does not match manifest", listed.path))
+                }
+                Err(problem) => self.problem(problem),
+            }
+        }
+    }
+
+    fn problem(&mut self, problem: String) {
+        self.report.problems.push(problem);
+    }
+
+    fn splits(&self) -> Vec<&'static str> {
+        OUTPUT_SPLITS
+            .into_iter()
+            .filter(|split| {
+                let prefix = format!("{}/", split);
+                self.entries.iter().any(|path| path.starts_with(&prefix))
+            })
+            .collect()
+    }
+
+    fn entries_matching(&self, matches: impl Fn(&str) -> bool) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|path| matches(path))
+            .cloned()
+            .collect()
+    }
+
+    /// Reports `image` as missing when images are bundled but it isn't.
+    fn expect_image(&mut self, referenced_by: &str, image: &str) {
+        if self.images_bundled && !self.entries.contains(image) {
+            self.problem(format!(
+                "{}: references missing image '{}'",
+                referenced_by, image
+            ));
+        }
+    }
+
+    /// YOLO labels must pair by stem with an image in `image_dir(label)`,
+    /// which also covers exports sharded into numbered subdirectories.
+    fn check_paired_labels(&mut self, labels: Vec<String>, image_dir: impl Fn(&str) -> String) {
+        let image_stems: HashSet<String> = self
+            .entries
+            .iter()
+            .filter(|path| is_image(path))
+            .map(|path| stem_key(path).to_string())
+            .collect();
+        self.report.label_count += labels.len();
+
+        for label in labels {
+            let stem = &stem_key(&label)[label.rfind('/').map_or(0, |idx| idx + 1)..];
+            let expected = format!("{}/{}", image_dir(&label), stem);
+            if self.images_bundled && !image_stems.contains(&expected) {
+                self.problem(format!("{}: no matching image", label));
+            }
+        }
+    }
+
+    fn check_yolo(&mut self) {
+        for required in ["data.yaml", "classes.txt"] {
+            if !self.entries.contains(required) {
+                self.problem(format!("{}: missing", required));
+            }
+        }
+        if self.entries.contains("data.yaml") {
+            if let Some(yaml) = self.read_text("data.yaml") {
+                match serde_yaml::from_str::<serde_yaml::Value>(&yaml) {
+                    Ok(doc) if doc.get("names").is_some() => {}
+                    Ok(_) => self.problem("data.yaml: no 'names' key".to_string()),
+                    Err(e) => self.problem(format!("data.yaml: invalid YAML: {}", e)),
+                }
+            }
+        }
+
+        let labels = self.entries_matching(|path| {
+            path.contains("/labels/")
+                && path.ends_with(".txt")
+                && !path.ends_with(".legend.txt")
+                && !path.ends_with(".abs.txt")
+        });
+        self.check_paired_labels(labels, |label| {
+            let dir = label.rsplit_once('/').map_or("", |(dir, _)| dir);
+            dir.replacen("/labels", "/images", 1)
+        });
+    }
+
+    fn check_darknet(&mut self) {
+        if !self.entries.contains("_darknet.labels") {
+            self.problem("_darknet.labels: missing".to_string());
+        }
+
+        let labels = self.entries_matching(|path| {
+            OUTPUT_SPLITS
+                .iter()
+                .any(|split| path.starts_with(&format!("{}/", split)))
+                && path.ends_with(".txt")
+                && !path.ends_with(".legend.txt")
+                && !path.ends_with(".abs.txt")
+        });
+        self.check_paired_labels(labels, |label| {
+            label
+                .rsplit_once('/')
+                .map_or("", |(dir, _)| dir)
+                .to_string()
+        });
+    }
+
+    fn check_coco(&mut self) {
+        // A single-file export has one root annotation file whose file names
+        // already include the split directory.
+        let annotation_files: Vec<(String, String)> = if self.entries.contains(COCO_SINGLE_FILE) {
+            vec![(COCO_SINGLE_FILE.to_string(), String::new())]
+        } else {
+            self.splits()
+                .into_iter()
+                .map(|split| {
+                    (
+                        format!("{}/_annotations.coco.json", split),
+                        format!("{}/", split),
+                    )
+                })
+                .collect()
+        };
+        if annotation_files.is_empty() {
+            self.problem("no split directories found".to_string());
+        }
+        for (path, image_dir) in annotation_files {
+            let Some(coco) = self.read_json(&path) else {
+                continue;
+            };
+            let Some(images) = coco.get("images").and_then(|v| v.as_array()).cloned() else {
+                self.problem(format!("{}: no 'images' array", path));
+                continue;
+            };
+            if coco.get("annotations").and_then(|v| v.as_array()).is_none() {
+                self.problem(format!("{}: no 'annotations' array", path));
+            }
+            self.report.label_count += 1;
+            for image in images {
+                match image.get("file_name").and_then(|v| v.as_str()) {
+                    Some(file_name) => {
+                        self.expect_image(&path, &format!("{}{}", image_dir, file_name))
+                    }
+                    None => self.problem(format!("{}: image without 'file_name'", path)),
+                }
+            }
+        }
+    }
+
+    fn check_coco_panoptic(&mut self) {
+        let splits = self.splits();
+        if splits.is_empty() {
+            self.problem("no split directories found".to_string());
+        }
+        for split in splits {
+            let path = format!("{}/_annotations.panoptic.json", split);
+            let Some(panoptic) = self.read_json(&path) else {
+                continue;
+            };
+            self.report.label_count += 1;
+            for image in panoptic["images"].as_array().cloned().unwrap_or_default() {
+                match image.get("file_name").and_then(|v| v.as_str()) {
+                    Some(file_name) => {
+                        self.expect_image(&path, &format!("{}/{}", split, file_name))
+                    }
+                    None => self.problem(format!("{}: image without 'file_name'", path)),
+                }
+            }
+            for annotation in panoptic["annotations"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default()
+            {
+                let mask = annotation
+                    .get("file_name")
+                    .and_then(|v| v.as_str())
+                    .map(|file_name| format!("{}/panoptic/{}", split, file_name));
+                match mask {
+                    Some(mask) if self.entries.contains(&mask) => {}
+                    Some(mask) => self.problem(format!("{}: missing mask {}", path, mask)),
+                    None => self.problem(format!("{}: annotation without 'file_name'", path)),
+                }
+            }
+        }
+    }
+
+    fn check_pascal_voc(&mut self) {
+        let xml_files = self.entries_matching(|path| path.ends_with(".xml"));
+        self.report.label_count += xml_files.len();
+
+        for path in xml_files {
+            let Some(xml) = self.read_text(&path) else {
+                continue;
+            };
+            match voc_filename(&xml) {
+                Ok(Some(file_name)) => {
+                    let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+                    self.expect_image(&path, &format!("{}/{}", dir, file_name));
+                }
+                Ok(None) => self.problem(format!("{}: no <filename>", path)),
+                Err(e) => self.problem(format!("{}: invalid XML: {}", path, e)),
+            }
+        }
+    }
+
+    fn check_createml(&mut self) {
+        let splits: Vec<&str> = OUTPUT_SPLITS
+            .into_iter()
+            .filter(|split| self.entries.contains(&format!("{}.json", split)))
+            .collect();
+        if splits.is_empty() {
+            self.problem("no split annotation files found".to_string());
+        }
+        for split in splits {
+            let path = format!("{}.json", split);
+            let Some(json) = self.read_json(&path) else {
+                continue;
+            };
+            let Some(items) = json.as_array().cloned() else {
+                self.problem(format!("{}: expected an array", path));
+                continue;
+            };
+            self.report.label_count += 1;
+            for item in items {
+                match item.get("image").and_then(|v| v.as_str()) {
+                    Some(image) => self.expect_image(&path, &format!("{}/{}", split, image)),
+                    None => self.problem(format!("{}: entry without 'image'", path)),
+                }
+            }
+        }
+    }
+}
+
+/// Parses a VOC annotation fully, returning its `<filename>`.
+fn voc_filename(xml: &str) -> Result<Option<String>, quick_xml::Error> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut in_filename = false;
+    let mut file_name = None;
+    loop {
+        match reader.read_event()? {
+            quick_xml::events::Event::Start(tag) => {
+                in_filename = tag.name().as_ref() == b"filename";
+            }
+            quick_xml::events::Event::Text(text) if in_filename && file_name.is_none() => {
+                file_name = Some(text.unescape()?.into_owned());
+            }
+            quick_xml::events::Event::End(_) => in_filename = false,
+            quick_xml::events::Event::Eof => return Ok(file_name),
+            _ => {}
+        }
+    }
+}
+
+/// Re-opens an exported ZIP and checks it is complete for `format`: the
+/// format's required files are present, its JSON/YAML/XML parses, and, when
+/// images are bundled, every label points at an image in the archive. Files
+/// listed in a `manifest.json` must match its sizes and hashes.
+pub fn verify_export(zip_path: &Path, format: &str) -> Result<VerifyReport, String> {
+    let file = std::fs::File::open(zip_path)
+        .map_err(|e| format!("Failed to open '{}': {}", zip_path.display(), e))?;
+    let archive = ZipArchive::new(file)
+        .map_err(|e| format!("'{}' is not a valid ZIP: {}", zip_path.display(), e))?;
+    let entries: BTreeSet<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(str::to_string)
+        .collect();
+    let image_count = entries.iter().filter(|path| is_image(path)).count();
+
+    let mut export = Export {
+        archive,
+        images_bundled: image_count > 0,
+        report: VerifyReport {
+            entry_count: entries.len(),
+            image_count,
+            ..Default::default()
+        },
+        entries,
+    };
+    match format.to_lowercase().as_str() {
+        "yolo" => export.check_yolo(),
+        "yolo_darknet" => export.check_darknet(),
+        "coco" => export.check_coco(),
+        "pascal_voc" | "voc" => export.check_pascal_voc(),
+        "createml" => export.check_createml(),
+        "coco_panoptic" => export.check_coco_panoptic(),
+        _ => return Err(format!("Unknown format: {}", format)),
+    }
+    export.check_manifest();
+
+    let mut report = export.report;
+    report.valid = report.problems.is_empty();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converter::{get_converter, FileData};
+    use crate::options::{ConvertOptions, SplitNormalization};
+    use crate::parser::{image_download_key, parse_ndjson};
+    use std::collections::HashMap;
+
+    const CONTENT: &str = r#"{"type":"dataset","name":"test","class_names":{"0":"cat"}}
+{"type":"image","file":"a.jpg","width":640,"height":480,"split":"train","url":"https://example.com/a.jpg","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}
+{"type":"image","file":"b.jpg","width":640,"height":480,"split":"val","url":"https://example.com/b.jpg","annotations":{"boxes":[[0,0.5,0.5,0.2,0.2]]}}"#;
+
+    fn export(format: &str, drop: &[&str]) -> (tempfile::TempDir, std::path::PathBuf) {
+        let data = parse_ndjson(CONTENT).unwrap();
+        let downloaded: HashMap<String, FileData> = HashMap::from([
+            (image_download_key("train", "a.jpg"), b"a".to_vec().into()),
+            (image_download_key("valid", "b.jpg"), b"b".to_vec().into()),
+        ]);
+        let mut files = get_converter(format, &ConvertOptions::default())
+            .unwrap()
+            .convert(&data, &downloaded);
+        for path in drop {
+            files.remove(*path).unwrap();
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("export.zip");
+        crate::write_zip_atomically(
+            &zip_path,
+            &files,
+            None,
+            crate::options::CompressionPreset::default(),
+            &tauri::ipc::Channel::new(|_| Ok(())),
+        )
+        .unwrap();
+        (dir, zip_path)
+    }
+
+    #[test]
+    fn complete_exports_verify_for_every_format() {
+        for format in ["yolo", "yolo_darknet", "coco", "pascal_voc", "createml"] {
+            let (_dir, zip_path) = export(format, &[]);
+
+            let report = verify_export(&zip_path, format).unwrap();
+
+            assert!(report.valid, "{}: {:?}", format, report.problems);
+            assert_eq!(report.image_count, 2);
+            assert!(report.label_count > 0);
+        }
+    }
+
+    #[test]
+    fn incomplete_yolo_export_reports_each_problem() {
+        let (_dir, zip_path) = export("yolo", &["classes.txt", "valid/images/b.jpg"]);
+
+        let report = verify_export(&zip_path, "yolo").unwrap();
+
+        assert!(!report.valid);
+        assert_eq!(
+            report.problems,
+            vec![
+                "classes.txt: missing".to_string(),
+                "valid/labels/b.txt: no matching image".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn coco_export_missing_referenced_image_is_invalid() {
+        let (_dir, zip_path) = export("coco", &["train/a.jpg"]);
+
+        let report = verify_export(&zip_path, "coco").unwrap();
+
+        assert!(!report.valid);
+        assert_eq!(
+            report.problems,
+            vec![
+                "train/_annotations.coco.json: references missing image 'train/a.jpg'".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn files_differing_from_the_manifest_are_reported() {
+        let data = parse_ndjson(CONTENT).unwrap();
+        let mut files = get_converter("yolo", &ConvertOptions::default())
+            .unwrap()
+            .convert(&data, &HashMap::new());
+        let mut manifest = ExportManifest::for_images(&data.images, SplitNormalization::Canonical);
+        manifest.record_files(&files).unwrap();
+        let yaml_size = manifest
+            .files
+            .iter()
+            .find(|file| file.path == "data.yaml")
+            .unwrap()
+            .size;
+        files.insert(MANIFEST_FILE_NAME.to_string(), manifest.to_json().into());
+        files.insert("classes.txt".to_string(), b"dog".to_vec().into());
+        files.insert(
+            "data.yaml".to_string(),
+            b"names: [tampered]\n".to_vec().into(),
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("export.zip");
+        crate::write_zip_atomically(
+            &zip_path,
+            &files,
+            None,
+            crate::options::CompressionPreset::default(),
+            &tauri::ipc::Channel::new(|_| Ok(())),
+        )
+        .unwrap();
+
+        let report = verify_export(&zip_path, "yolo").unwrap();
+
+        assert!(!report.valid);
+        assert_eq!(
+            report.problems,
+            vec![
+                "classes.txt: SHA-256 does not match manifest".to_string(),
+                format!("data.yaml: size 18 does not match manifest ({})", yaml_size),
+            ]
+        );
+    }
+}
@@ -55,7 +55,12 @@ export function ConverterScreen({ onBack }: { onBack: () => void }) {
         if (paths.length > 0) {
           const file = paths[0];
           const lowerFile = file.toLowerCase();
-          if (lowerFile.endsWith(".ndjson") || lowerFile.endsWith(".jsonl")) {
+          const fileName = lowerFile.split(/[\\/]/).pop() ?? "";
+          if (
+            lowerFile.endsWith(".ndjson") ||
+            lowerFile.endsWith(".jsonl") ||
+            !fileName.includes(".")
+          ) {
             setFileFromPath(file);
           }
         }
@@ -168,8 +173,14 @@ export function ConverterScreen({ onBack }: { onBack: () => void }) {
                               ? "Downloading Images"
                               : progress.phase === "converting"
                                 ? "Converting Annotations"
-                                : progress.phase === "zipping"
-                                  ? "Creating ZIP"
+                                : progress.phase === "zipping" || progress.phase === "writing"
+                                  ? progress.stage === "images"
+                                    ? "Writing Images"
+                                    : progress.stage === "labels"
+                                      ? "Writing Labels"
+                                      : progress.phase === "zipping"
+                                        ? "Creating ZIP"
+                                        : "Writing Files"
                                   : progress.phase === "parsing"
                                     ? "Parsing NDJSON"
                                     : "Processing"}
@@ -191,6 +202,9 @@ export function ConverterScreen({ onBack }: { onBack: () => void }) {
                             {progress.phase === "downloading" && (
                               <span>{getDownloadRate()} img/s</span>
                             )}
+                            {progress.eta_seconds != null && (
+                              <span>{formatElapsedTime(progress.eta_seconds)} left</span>
+                            )}
                           </div>
                         </div>
                       </div>
@@ -213,7 +227,9 @@ export function ConverterScreen({ onBack }: { onBack: () => void }) {
                             ? "Converting..."
                             : progress?.phase === "zipping"
                               ? "Creating ZIP..."
-                              : "Starting..."}
+                              : progress?.phase === "writing"
+                                ? "Writing Files..."
+                                : "Starting..."}
                       </>
                     ) : (
                       "Convert to " + selectedFormat.name
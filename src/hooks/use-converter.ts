@@ -114,8 +114,8 @@ export function useConverter() {
   };
 
   const getProgressPercentage = () => {
-    if (!progress || progress.total === 0) return 0;
-    return Math.round((progress.current / progress.total) * 100);
+    if (!progress) return 0;
+    return progress.overall_percent;
   };
 
   const formatElapsedTime = (seconds: number) => {
@@ -1,10 +1,13 @@
-export type ProgressPhase = "downloading" | "converting" | "zipping" | "parsing" | "complete";
+export type ProgressPhase = "downloading" | "converting" | "zipping" | "writing" | "parsing" | "complete";
 
 export interface ProgressEvent {
   phase: ProgressPhase;
   current: number;
   total: number;
   item: string | null;
+  overall_percent: number;
+  stage: string | null;
+  eta_seconds: number | null;
 }
 
 export interface ConvertResult {
@@ -13,6 +16,50 @@ export interface ConvertResult {
   image_count: number;
   download_total: number;
   failed_downloads: number;
+  mirror_downloads: number;
+  timed_out_downloads: number;
+  complete: boolean;
+  incomplete_reason: string | null;
+  orphaned_images: string[];
+  orphaned_labels: string[];
+  deduplicated_images: number;
+  skipped_images: number;
+  sampled_out_images: number;
+  dropped_empty_images: number;
+  withheld_test_labels: number;
+  subpixel_boxes: number;
+  uncompressed_bytes: number;
+  archive_bytes: number;
+  warnings: string[];
+}
+
+export interface VerifyReport {
+  valid: boolean;
+  entry_count: number;
+  image_count: number;
+  label_count: number;
+  problems: string[];
+}
+
+export interface UrlStatus {
+  url: string;
+  reachable: boolean;
+  status: number | null;
+  error: string | null;
+}
+
+export interface ClassChange {
+  id: number;
+  before: string | null;
+  after: string | null;
+}
+
+export interface DiffReport {
+  added_images: string[];
+  removed_images: string[];
+  changed_images: string[];
+  unchanged_images: number;
+  class_changes: ClassChange[];
 }
 
 export interface Format {
@@ -32,6 +79,7 @@ export const formats: Format[] = [
   { id: "yolo", name: "YOLOv5", available: true, desc: "TXT annotations and YAML config", highlight: "YOLOv5" },
   { id: "yolo", name: "YOLOv7", available: true, desc: "TXT annotations and YAML config", highlight: "YOLOv7" },
   { id: "coco", name: "COCO JSON", available: true, desc: "COCO JSON annotations", highlight: "EfficientDet Pytorch and Detectron 2" },
+  { id: "coco_panoptic", name: "COCO Panoptic", available: true, desc: "COCO panoptic PNG masks and JSON", highlight: "Detectron 2 and panoptic segmentation models" },
   { id: "yolo_darknet", name: "YOLO Darknet", available: true, desc: "Darknet TXT annotations", highlight: "YOLO Darknet (both v3 and v4) and YOLOv3 PyTorch" },
   { id: "pascal_voc", name: "Pascal VOC XML", available: true, desc: "Common XML annotation format for local data munging (pioneered by", highlight: "ImageNet" },
   { id: "tfrecord", name: "TFRecord", available: false, desc: "TFRecord binary format", highlight: "Tensorflow 1.5 and Tensorflow 2.0 Object Detection models" },